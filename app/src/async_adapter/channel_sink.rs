@@ -0,0 +1,138 @@
+use std::{
+    io,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+use screen_cap::record::{
+    encoded_buffer::Metadata,
+    sink::{ChunkSink, StopHandle},
+};
+use tokio::sync::{mpsc, Notify};
+use utils::contiguous::{BufferItem, FrameId};
+
+use super::EncodedChunk;
+
+/// Forwards the recorder's chunks into a tokio channel, e.g. for the server, see `Recorder::add_sink`.
+///
+/// Waits for room in the channel until the recorder is gone, the chunks that don't fit after that are dropped.
+#[derive(Debug)]
+pub struct ChannelSink {
+    tx: mpsc::Sender<EncodedChunk>,
+    stop: Arc<Stop>,
+}
+
+#[derive(Debug, Default)]
+struct Stop {
+    stopped: AtomicBool,
+    notify: Notify,
+}
+
+impl ChannelSink {
+    pub fn new(tx: mpsc::Sender<EncodedChunk>) -> Self {
+        Self {
+            tx,
+            stop: Arc::default(),
+        }
+    }
+}
+
+impl ChunkSink for ChannelSink {
+    fn write_chunk(&mut self, id: FrameId, item: BufferItem<'_, Metadata>) -> io::Result<()> {
+        let chunk = EncodedChunk {
+            id,
+            data: item.data().into_owned(),
+            metadata: *item.metadata(),
+        };
+
+        // a receiver that stopped reading would keep the recorder from ever being dropped
+        if self.stop.stopped.load(Ordering::Acquire) {
+            let _ = self.tx.try_send(chunk);
+            return Ok(());
+        }
+
+        // this runs on the sink's own thread, so waiting for room is fine,
+        // the recorder keeps queuing chunks for it in the meantime.
+        // Nobody listening anymore isn't an error either, the chunks just go nowhere
+        futures::executor::block_on(async {
+            tokio::select! {
+                _ = self.tx.send(chunk) => (),
+                // stored as a permit if it comes before the wait starts
+                _ = self.stop.notify.notified() => (),
+            }
+        });
+
+        Ok(())
+    }
+
+    fn stop_handle(&self) -> Option<StopHandle> {
+        let stop = self.stop.clone();
+
+        Some(Box::new(move || {
+            stop.stopped.store(true, Ordering::Release);
+            stop.notify.notify_one();
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::mpsc as std_mpsc, thread, time::Duration};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn chunks_reach_the_channel() {
+        let (tx, mut rx) = mpsc::channel(1);
+        let mut sink = ChannelSink::new(tx);
+
+        let writer = thread::spawn(move || {
+            for i in 0..3u8 {
+                let metadata = Metadata::new(i == 0, i.into());
+                sink.write_chunk(
+                    FrameId::new(i.into()),
+                    BufferItem::from_parts(&[i], &metadata),
+                )
+                .unwrap();
+            }
+        });
+
+        for i in 0..3u8 {
+            let chunk = rx.recv().await.unwrap();
+            assert_eq!(chunk.id, FrameId::new(i.into()));
+            assert_eq!(chunk.data, [i]);
+            assert_eq!(chunk.metadata.pts, i64::from(i));
+        }
+
+        writer.join().unwrap();
+        assert!(rx.recv().await.is_none());
+    }
+
+    #[test]
+    fn stopping_unblocks_a_full_channel() {
+        // kept alive without ever being read
+        let (tx, _rx) = mpsc::channel(1);
+        let mut sink = ChannelSink::new(tx);
+        let stop = sink.stop_handle().unwrap();
+
+        let (written_tx, written_rx) = std_mpsc::channel();
+        thread::spawn(move || {
+            let metadata = Metadata::new(true, 0);
+            for i in 0..3u8 {
+                sink.write_chunk(FrameId::new(i.into()), BufferItem::from_parts(&[i], &metadata))
+                    .unwrap();
+                written_tx.send(i).unwrap();
+            }
+        });
+
+        // the first one fits, the second one waits for room
+        assert_eq!(written_rx.recv_timeout(Duration::from_secs(1)), Ok(0));
+        assert!(written_rx.recv_timeout(Duration::from_millis(50)).is_err());
+
+        stop();
+        assert_eq!(written_rx.recv_timeout(Duration::from_secs(1)), Ok(1));
+        assert_eq!(written_rx.recv_timeout(Duration::from_secs(1)), Ok(2));
+    }
+}
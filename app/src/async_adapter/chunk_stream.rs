@@ -0,0 +1,210 @@
+use std::{collections::VecDeque, sync::Arc};
+
+use futures::{stream, Stream};
+use screen_cap::record::{encoded_buffer::Metadata, RecordError};
+use thiserror::Error;
+use utils::contiguous::FrameId;
+
+use super::RecorderAsyncAdapter;
+
+/// A chunk copied out of the ring buffer
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EncodedChunk {
+    pub id: FrameId,
+    pub data: Vec<u8>,
+    pub metadata: Metadata,
+}
+
+#[derive(Debug, Error, Clone)]
+pub enum ChunkStreamError {
+    /// The chunks with ids from `from` up to `to` got evicted before they could be read.
+    ///
+    /// Not fatal, the stream continues at `to`,
+    /// but a decoder has to wait for the next keyframe before it can pick up again.
+    #[error("chunks {from:?}..{to:?} got evicted before they were read")]
+    Lagged { from: FrameId, to: FrameId },
+    #[error(transparent)]
+    Record(Arc<RecordError>),
+}
+
+struct ChunkCursor {
+    recorder: RecorderAsyncAdapter,
+    next_id: Option<FrameId>,
+    pending: VecDeque<EncodedChunk>,
+    finished: bool,
+}
+
+impl ChunkCursor {
+    async fn next(&mut self) -> Option<Result<EncodedChunk, ChunkStreamError>> {
+        loop {
+            if let Some(chunk) = self.pending.pop_front() {
+                return Some(Ok(chunk));
+            }
+
+            if self.finished {
+                return None;
+            }
+
+            if let Err(lagged) = self.collect_new_chunks().await {
+                return Some(Err(lagged));
+            }
+
+            if !self.pending.is_empty() {
+                continue;
+            }
+
+            if let Err(e) = self.recorder.wait_for_next_flush().await {
+                // the recorder isn't going to produce anything after an error
+                self.finished = true;
+                return Some(Err(ChunkStreamError::Record(e)));
+            }
+        }
+    }
+
+    // the chunks get queued even if some of them were lost, the error only has to be yielded first
+    async fn collect_new_chunks(&mut self) -> Result<(), ChunkStreamError> {
+        let buf = self.recorder.data_buffer().await;
+        let (min_id, max_id) = buf.id_bounds();
+
+        let mut result = Ok(());
+
+        let start_id = match self.next_id {
+            Some(id) if id < min_id => {
+                result = Err(ChunkStreamError::Lagged {
+                    from: id,
+                    to: min_id,
+                });
+                min_id
+            }
+            Some(id) => id,
+            None => min_id,
+        };
+
+        let chunks = FrameId::range(start_id, max_id)
+            .zip(buf.range(start_id, max_id))
+            .map(|(id, item)| EncodedChunk {
+                id,
+                data: item.data().into_owned(),
+                metadata: *item.metadata(),
+            });
+
+        self.pending.extend(chunks);
+        self.next_id = Some(max_id.max(start_id));
+
+        result
+    }
+}
+
+impl RecorderAsyncAdapter {
+    /// Every chunk in the ring buffer in order, starting at the oldest one that's still there.
+    ///
+    /// A consumer that falls so far behind that chunks get evicted before it reads them
+    /// gets a `ChunkStreamError::Lagged` and then continues with the oldest chunk left.
+    /// The stream ends after the first recorder error.
+    pub fn chunk_stream(&self) -> impl Stream<Item = Result<EncodedChunk, ChunkStreamError>> {
+        let cursor = ChunkCursor {
+            recorder: self.clone(),
+            next_id: None,
+            pending: VecDeque::new(),
+            finished: false,
+        };
+
+        stream::unfold(cursor, |mut cursor| async move {
+            let item = cursor.next().await?;
+            Some((item, cursor))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc;
+
+    use futures::StreamExt;
+    use screen_cap::record::{encoded_buffer::EncodedBuffer, SharedHeaders, SharedVideoInfo};
+    use utils::threading::WorkerError;
+
+    use super::*;
+    use crate::async_adapter::RecorderMessage;
+
+    // every flush writes the next batch of (data, is_key) chunks, once they run out the worker "exits"
+    fn fake_recorder(capacity: usize, batches: Vec<Vec<(Vec<u8>, bool)>>) -> RecorderAsyncAdapter {
+        let mut buf = EncodedBuffer::new(capacity);
+        let view = buf.view();
+
+        RecorderAsyncAdapter::with_recorder_thread(
+            view,
+            SharedHeaders::default(),
+            SharedVideoInfo::default(),
+            move |rx: mpsc::Receiver<RecorderMessage>, _| {
+                let mut batches = batches.into_iter();
+
+                for msg in rx.iter() {
+                    let RecorderMessage::WaitForNextFlush(dest) = msg else {
+                        continue;
+                    };
+
+                    let Some(batch) = batches.next() else {
+                        dest.send_result(Err(Arc::new(WorkerError::Exited.into())));
+                        continue;
+                    };
+
+                    for (data, is_key) in batch {
+                        buf.write_flush(&data, Metadata::new(is_key, 0)).unwrap();
+                    }
+                    dest.send_result(Ok(()));
+                }
+            },
+        )
+    }
+
+    #[tokio::test]
+    async fn chunks_in_order() {
+        let recorder = fake_recorder(
+            1024,
+            vec![
+                vec![(vec![0], true), (vec![1], false)],
+                vec![(vec![2], false)],
+            ],
+        );
+
+        let chunks: Vec<_> = recorder.chunk_stream().collect().await;
+
+        assert_eq!(chunks.len(), 4);
+        for (i, chunk) in chunks[..3].iter().enumerate() {
+            let chunk = chunk.as_ref().unwrap();
+            assert_eq!(chunk.id, FrameId::new(i));
+            assert_eq!(chunk.data, [i as u8]);
+            assert_eq!(chunk.metadata.is_key, i == 0);
+        }
+        assert!(matches!(chunks[3], Err(ChunkStreamError::Record(_))));
+    }
+
+    #[tokio::test]
+    async fn evicted_chunks_are_reported() {
+        // room for 4 chunks
+        let recorder = fake_recorder(
+            16,
+            vec![
+                vec![(vec![0; 4], true)],
+                (0..6).map(|i| (vec![i; 4], i == 2)).collect(),
+            ],
+        );
+
+        let mut stream = Box::pin(recorder.chunk_stream());
+
+        assert_eq!(stream.next().await.unwrap().unwrap().id, FrameId::new(0));
+
+        match stream.next().await.unwrap() {
+            Err(ChunkStreamError::Lagged { from, to }) => {
+                assert_eq!((from, to), (FrameId::new(1), FrameId::new(3)));
+            }
+            other => panic!("expected the stream to lag, got {other:?}"),
+        }
+
+        // picks up with the oldest chunk left
+        let chunk = stream.next().await.unwrap().unwrap();
+        assert_eq!(chunk.id, FrameId::new(3));
+        assert!(chunk.metadata.is_key);
+    }
+}
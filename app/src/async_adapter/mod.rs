@@ -1,30 +1,72 @@
+mod channel_sink;
+mod chunk_stream;
+mod single_consumer;
+
 use std::{
+    mem,
+    path::PathBuf,
     sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
         mpsc::{self, Receiver, Sender, TryRecvError},
         Arc,
     },
-    thread,
+    thread::{self, JoinHandle},
+    time::Duration,
 };
 
 use parking_lot::Mutex;
 use screen_cap::record::{
     encoded_buffer::{ArcEncodedDataGuard, EncodedBufferView},
-    EncodeStatus, RecordError, Recorder,
+    DrainedChunks, EncodeStatus, EncoderReconfig, RecordError, RecordStats, Recorder, RecorderMonitor,
+    SharedHeaders, SharedVideoInfo, VideoInfo,
+};
+use tokio::{sync::Notify, task, time};
+use utils::contiguous::FrameId;
+
+pub use self::{
+    channel_sink::ChannelSink,
+    chunk_stream::{ChunkStreamError, EncodedChunk},
+    single_consumer::SingleConsumerAdapter,
 };
-use tokio::sync::Notify;
 
 type NextFlushResult = Result<(), Arc<RecordError>>;
 type NextFrameResult = Result<EncodeStatus, Arc<RecordError>>;
+type DrainResult = Result<DrainedChunks, Arc<RecordError>>;
+type FrameCountResult = Result<FrameId, Arc<RecordError>>;
+type KeyframeResult = Result<FrameId, Arc<RecordError>>;
+type FinishResult = Result<Box<[u8]>, Arc<RecordError>>;
+
+// the latest report the managing thread got, numbered so every clone can tell whether it has seen it yet
+type LatestFrame = Arc<Mutex<(u64, Option<NextFrameResult>)>>;
+
+// how often the recorder managing thread checks for a shutdown while no frames are coming
+const SHUTDOWN_POLL_INTERVAL: Duration = Duration::from_millis(50);
 
 #[derive(Debug, Clone)]
-enum RecorderMessage {
+pub(crate) enum RecorderMessage {
     // RecordError isn't Clone, so Arc it is
     WaitForNextFlush(ReturnDestination<NextFlushResult>),
     WaitForFrame(ReturnDestination<NextFrameResult>),
+    DrainRemaining {
+        since_id: FrameId,
+        dest: ReturnDestination<DrainResult>,
+    },
+    WaitForFramesSince {
+        target_id: FrameId,
+        dest: ReturnDestination<FrameCountResult>,
+    },
+    RequestKeyframe,
+    WaitForNextKeyframe(ReturnDestination<KeyframeResult>),
+    SetBitrate(i32),
+    Reconfigure(EncoderReconfig),
+    PauseRecording,
+    ResumeRecording,
+    // stops the managing thread, which hands the recorder's last bytes back on the way out
+    Finish(ReturnDestination<FinishResult>),
 }
 
 #[derive(Debug, Default)]
-struct ReturnDestination<T> {
+pub(crate) struct ReturnDestination<T> {
     return_dest: Arc<Mutex<Option<T>>>,
     notify: Arc<Notify>,
 }
@@ -64,39 +106,188 @@ pub struct RecorderAsyncAdapter {
 
     next_frame_dest: ReturnDestination<NextFrameResult>,
     next_flush_dest: ReturnDestination<NextFlushResult>,
+    drain_dest: ReturnDestination<DrainResult>,
+    frame_count_dest: ReturnDestination<FrameCountResult>,
+    keyframe_dest: ReturnDestination<KeyframeResult>,
     recorder_tx: Sender<RecorderMessage>,
 
-    headers: Arc<[u8]>,
+    latest_frame: LatestFrame,
+    // the number of the latest report `poll_frame` has returned on this clone
+    polled: AtomicU64,
+
+    data_buffer_view: EncodedBufferView,
+    headers: SharedHeaders,
+    video_info: SharedVideoInfo,
+    // None for a recorder faked in tests
+    monitor: Option<RecorderMonitor>,
+
+    // has to come after the senders, the data buffer managing thread only exits once they're all dropped
+    threads: Arc<ManagingThreads>,
 }
 
 impl RecorderAsyncAdapter {
+    /// Spawns the threads managing the recorder,
+    /// they keep running until the last clone of the adapter is dropped or shut down.
     pub fn new(recorder: Recorder) -> Self {
-        let headers = recorder.headers().into();
+        let headers = recorder.shared_headers();
+        let video_info = recorder.shared_video_info();
+        let data_buffer_view = recorder.data_buffer_view();
+        let monitor = recorder.monitor();
+
+        let latest_frame = LatestFrame::default();
+        let thread_latest_frame = latest_frame.clone();
 
+        let adapter =
+            Self::with_recorder_thread(data_buffer_view, headers, video_info, move |rx, shutdown| {
+                recorder_managing_thread(recorder, rx, &shutdown, &thread_latest_frame)
+            });
+
+        Self {
+            monitor: Some(monitor),
+            latest_frame,
+            ..adapter
+        }
+    }
+
+    /// A lighter adapter for when there's only ever going to be one consumer, e.g. a single client or a file writer.
+    ///
+    /// Skips the message passing and the per-call allocations, see `SingleConsumerAdapter`.
+    pub fn single_consumer(recorder: Recorder) -> SingleConsumerAdapter {
+        SingleConsumerAdapter::new(recorder)
+    }
+
+    /// `recorder_thread` takes the place of the recorder managing thread, so tests can fake a recorder
+    pub(crate) fn with_recorder_thread<F>(
+        data_buffer_view: EncodedBufferView,
+        headers: SharedHeaders,
+        video_info: SharedVideoInfo,
+        recorder_thread: F,
+    ) -> Self
+    where
+        F: FnOnce(Receiver<RecorderMessage>, Arc<AtomicBool>) + Send + 'static,
+    {
         let data_buffer_dest = ReturnDestination::new();
         let next_frame_dest = ReturnDestination::new();
         let next_flush_dest = ReturnDestination::new();
+        let drain_dest = ReturnDestination::new();
+        let frame_count_dest = ReturnDestination::new();
+        let keyframe_dest = ReturnDestination::new();
 
         let (data_buffer_tx, data_buffer_rx) = mpsc::channel();
-        let data_buffer_view = recorder.data_buffer_view();
+        let thread_data_buffer_view = data_buffer_view.clone();
+
+        let data_buffer_thread = thread::spawn(move || {
+            data_buffer_managing_thread(thread_data_buffer_view, data_buffer_rx)
+        });
 
-        thread::spawn(move || data_buffer_managing_thread(data_buffer_view, data_buffer_rx));
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let thread_shutdown = shutdown.clone();
 
         let (recorder_tx, recorder_rx) = mpsc::channel();
-        thread::spawn(move || recorder_managing_thread(recorder, recorder_rx));
+        let recorder_thread = thread::spawn(move || recorder_thread(recorder_rx, thread_shutdown));
+
+        let threads = Arc::new(ManagingThreads {
+            shutdown,
+            handles: vec![data_buffer_thread, recorder_thread],
+        });
 
         Self {
             data_buffer_dest,
             data_buffer_tx,
             next_frame_dest,
             next_flush_dest,
+            drain_dest,
+            frame_count_dest,
+            keyframe_dest,
             recorder_tx,
+            latest_frame: LatestFrame::default(),
+            polled: AtomicU64::new(0),
+            data_buffer_view,
             headers,
+            video_info,
+            monitor: None,
+            threads,
+        }
+    }
+
+    /// Stops the managing threads and waits for them to exit, which drops the recorder.
+    ///
+    /// The threads are shared by all the clones, so they only get stopped if this is the last one,
+    /// returns whether that was the case.
+    /// Dropping the last clone stops the threads as well, but doesn't wait for them.
+    pub fn shutdown(self) -> bool {
+        let Self {
+            data_buffer_tx,
+            recorder_tx,
+            threads,
+            ..
+        } = self;
+
+        drop(data_buffer_tx);
+        drop(recorder_tx);
+
+        match Arc::into_inner(threads) {
+            Some(threads) => {
+                threads.join();
+                true
+            }
+            None => false,
         }
     }
 
-    pub fn headers(&self) -> &[u8] {
-        &self.headers
+    /// The current SPS/PPS headers, see `Recorder::headers`
+    pub fn headers(&self) -> Arc<[u8]> {
+        self.headers.get()
+    }
+
+    /// The current headers and the id of the first chunk they apply to, see `Recorder::headers_since`
+    pub fn headers_since(&self) -> (Arc<[u8]>, FrameId) {
+        self.headers.get_since()
+    }
+
+    /// Saves what's in the ring buffer into a new file at `path`, see `Recorder::save_replay`.
+    ///
+    /// Both copying the buffer and writing the file happen on a blocking thread.
+    pub async fn save_replay(&self, path: impl Into<PathBuf>) -> Result<(), RecordError> {
+        let view = self.data_buffer_view.clone();
+        let headers = self.headers.clone();
+        let path = path.into();
+
+        task::spawn_blocking(move || view.snapshot().save_replay(&headers.get(), &path))
+            .await
+            .expect("saving the replay panicked")
+    }
+
+    /// The current size, timebase and capture rate of the video, see `Recorder::video_info`
+    pub fn video_info(&self) -> VideoInfo {
+        self.video_info.get()
+    }
+
+    /// See `Recorder::stats`
+    pub fn stats(&self) -> RecordStats {
+        self.monitor.as_ref().map(RecorderMonitor::stats).unwrap_or_default()
+    }
+
+    /// See `Recorder::measured_rate`
+    pub fn measured_rate(&self) -> f64 {
+        self.monitor.as_ref().map_or(0.0, RecorderMonitor::measured_rate)
+    }
+
+    /// Whether the recorder is still going, i.e. neither its encoder thread nor the threads managing it have exited
+    pub fn is_running(&self) -> bool {
+        let worker_exited = self.monitor.as_ref().is_some_and(RecorderMonitor::worker_exited);
+
+        !worker_exited && self.threads.handles.iter().all(|handle| !handle.is_finished())
+    }
+
+    /// Gives direct access to the encoded buffer, skipping the data buffer managing thread.
+    ///
+    /// Locking the view blocks the current thread if the encoder is flushing at the moment,
+    /// so this is only meant for latency sensitive readers that know the lock is usually uncontended.
+    /// `data_buffer` should be used otherwise.
+    #[inline]
+    pub fn buffer_view(&self) -> EncodedBufferView {
+        self.data_buffer_view.clone()
     }
 
     pub async fn data_buffer(&self) -> ArcEncodedDataGuard {
@@ -124,6 +315,155 @@ impl RecorderAsyncAdapter {
 
         self.next_flush_dest.recv_result().await
     }
+
+    /// Same as `wait_for_next_flush`, but gives up after `timeout`.
+    ///
+    /// Resolves to `Ok(true)` if there was a flush and `Ok(false)` if the time ran out first.
+    pub async fn wait_for_next_flush_timeout(&self, timeout: Duration) -> Result<bool, Arc<RecordError>> {
+        // a destination of its own, so an answer that arrives after the timeout can't be mistaken
+        // for the answer to a later call
+        let dest = ReturnDestination::new();
+
+        self.recorder_tx
+            .send(RecorderMessage::WaitForNextFlush(dest.clone()))
+            .unwrap();
+
+        match time::timeout(timeout, dest.recv_result()).await {
+            Ok(result) => result.map(|_| true),
+            Err(_) => Ok(false),
+        }
+    }
+
+    /// Resolves once the ring buffer holds at least `n` chunks past `last_id`,
+    /// i.e. when `id_bounds().1 >= last_id + n`.
+    ///
+    /// Returns the upper id bound at that moment.
+    /// Unlike `wait_for_next_flush`, the task only gets woken up once.
+    pub async fn wait_for_frames_since(&self, last_id: FrameId, n: usize) -> FrameCountResult {
+        self.recorder_tx
+            .send(RecorderMessage::WaitForFramesSince {
+                target_id: last_id + n,
+                dest: self.frame_count_dest.clone(),
+            })
+            .unwrap();
+
+        self.frame_count_dest.recv_result().await
+    }
+
+    /// Makes one of the next encoded frames a keyframe, e.g. so that a new client can start decoding right away.
+    ///
+    /// Doesn't wait for anything, the request reaches the recorder after the frame that's currently being encoded.
+    /// See `Recorder::request_keyframe`
+    pub fn request_keyframe(&self) {
+        self.recorder_tx
+            .send(RecorderMessage::RequestKeyframe)
+            .unwrap();
+    }
+
+    /// Resolves with the id of the first keyframe that gets flushed after the call.
+    ///
+    /// The encoder only emits keyframes every so often on its own,
+    /// see `request_keyframe_and_wait` for not having to wait for that.
+    pub async fn wait_for_next_keyframe(&self) -> KeyframeResult {
+        self.recorder_tx
+            .send(RecorderMessage::WaitForNextKeyframe(
+                self.keyframe_dest.clone(),
+            ))
+            .unwrap();
+
+        self.keyframe_dest.recv_result().await
+    }
+
+    /// Forces a keyframe and resolves with its id once it has been flushed,
+    /// e.g. so that a new viewer can start decoding cleanly.
+    pub async fn request_keyframe_and_wait(&self) -> KeyframeResult {
+        // the messages are handled in order, so the keyframe can't be flushed before the wait starts
+        self.request_keyframe();
+        self.wait_for_next_keyframe().await
+    }
+
+    /// Changes the bitrate in kbit/s, see `Recorder::set_bitrate`.
+    ///
+    /// Same as `request_keyframe`, this reaches the recorder after the frame that's currently being encoded.
+    pub fn set_bitrate(&self, kbps: i32) {
+        self.recorder_tx
+            .send(RecorderMessage::SetBitrate(kbps))
+            .unwrap();
+    }
+
+    /// Rebuilds the encoder with different parameters, see `Recorder::reconfigure`.
+    ///
+    /// Consumers notice the change through `headers_since`.
+    pub fn reconfigure(&self, reconfig: EncoderReconfig) {
+        self.recorder_tx
+            .send(RecorderMessage::Reconfigure(reconfig))
+            .unwrap();
+    }
+
+    /// The recorder's latest report if this clone hasn't seen it yet, `None` right away otherwise.
+    ///
+    /// Doesn't go through the managing thread, it looks at the last report the thread got,
+    /// so it doesn't take anything away from `wait_for_frame` and the other way around.
+    /// Only the latest report counts, the ones before it that came in since the last call are skipped,
+    /// e.g. for a render loop that only cares whether anything happened since its last frame.
+    pub fn poll_frame(&self) -> Option<NextFrameResult> {
+        let latest = self.latest_frame.lock();
+        let (number, result) = &*latest;
+
+        if self.polled.swap(*number, Ordering::Relaxed) == *number {
+            return None;
+        }
+
+        result.clone()
+    }
+
+    /// Stops capturing and encoding until `resume_recording`, see `Recorder::pause_recording`.
+    ///
+    /// Reaches the recorder after the frame that's currently being encoded, same as `request_keyframe`.
+    pub fn pause_recording(&self) {
+        self.recorder_tx
+            .send(RecorderMessage::PauseRecording)
+            .unwrap();
+    }
+
+    /// See `Recorder::resume_recording`
+    pub fn resume_recording(&self) {
+        self.recorder_tx
+            .send(RecorderMessage::ResumeRecording)
+            .unwrap();
+    }
+
+    /// Finalizes the recorder and returns every chunk from `since_id` onwards.
+    ///
+    /// See `Recorder::drain_remaining`
+    pub async fn drain_remaining(&self, since_id: FrameId) -> DrainResult {
+        self.recorder_tx
+            .send(RecorderMessage::DrainRemaining {
+                since_id,
+                dest: self.drain_dest.clone(),
+            })
+            .unwrap();
+
+        self.drain_dest.recv_result().await
+    }
+
+    /// Stops recording for good and returns the bytes that never made it into the ring buffer,
+    /// then shuts down like `shutdown`.
+    ///
+    /// See `Recorder::finish`, the ring buffer and the headers don't change after this returns,
+    /// other clones can still read them, but anything that needs the recorder itself panics.
+    pub async fn finish(self) -> FinishResult {
+        let dest = ReturnDestination::new();
+        self.recorder_tx
+            .send(RecorderMessage::Finish(dest.clone()))
+            .unwrap();
+
+        let result = dest.recv_result().await;
+        // the recorder thread is gone already, only the data buffer thread might be left to wait for
+        self.shutdown();
+
+        result
+    }
 }
 
 impl Clone for RecorderAsyncAdapter {
@@ -131,14 +471,48 @@ impl Clone for RecorderAsyncAdapter {
         Self {
             data_buffer_tx: self.data_buffer_tx.clone(),
             recorder_tx: self.recorder_tx.clone(),
+            latest_frame: self.latest_frame.clone(),
+            // a new clone only sees what comes after it
+            polled: AtomicU64::new(self.latest_frame.lock().0),
+            data_buffer_view: self.data_buffer_view.clone(),
             headers: self.headers.clone(),
+            video_info: self.video_info.clone(),
+            monitor: self.monitor.clone(),
+            threads: self.threads.clone(),
             data_buffer_dest: ReturnDestination::new(),
             next_frame_dest: ReturnDestination::new(),
             next_flush_dest: ReturnDestination::new(),
+            drain_dest: ReturnDestination::new(),
+            frame_count_dest: ReturnDestination::new(),
+            keyframe_dest: ReturnDestination::new(),
         }
     }
 }
 
+/// Shared by all the clones of an adapter, signals the managing threads to stop once the last clone is gone
+#[derive(Debug)]
+struct ManagingThreads {
+    shutdown: Arc<AtomicBool>,
+    handles: Vec<JoinHandle<()>>,
+}
+
+impl ManagingThreads {
+    fn join(mut self) {
+        self.shutdown.store(true, Ordering::Release);
+
+        for handle in mem::take(&mut self.handles) {
+            // a panic has already been reported to whoever was waiting on a result
+            _ = handle.join();
+        }
+    }
+}
+
+impl Drop for ManagingThreads {
+    fn drop(&mut self) {
+        self.shutdown.store(true, Ordering::Release);
+    }
+}
+
 // thread that blocks for the lock on the data_buffer so that your async functions don't have to
 // it receives a messages with the reference to the cell where to put the acquired lock guard
 // and a Notify struct that wakes up the task that sent that message
@@ -154,61 +528,301 @@ fn data_buffer_managing_thread(
     }
 }
 
-fn recorder_managing_thread(recorder: Recorder, rx: Receiver<RecorderMessage>) {
-    let mut flush_waiters = Vec::new();
+#[derive(Default)]
+struct Waiters {
+    frame: Vec<ReturnDestination<NextFrameResult>>,
+    flush: Vec<ReturnDestination<NextFlushResult>>,
+    // (target id, destination)
+    frame_count: Vec<(FrameId, ReturnDestination<FrameCountResult>)>,
+    // (first id that counts, destination)
+    keyframe: Vec<(FrameId, ReturnDestination<KeyframeResult>)>,
+    finish: Option<ReturnDestination<FinishResult>>,
+}
+
+fn recorder_managing_thread(
+    recorder: Recorder,
+    rx: Receiver<RecorderMessage>,
+    shutdown: &AtomicBool,
+    latest_frame: &Mutex<(u64, Option<NextFrameResult>)>,
+) {
+    let mut waiters = Waiters::default();
+
+    while !shutdown.load(Ordering::Acquire) && waiters.finish.is_none() {
+        // doesn't block for long so that a shutdown gets noticed even if nothing gets reported,
+        // e.g. while paused or while the capturer has nothing new
+        let result = recorder.wait_for_frame_timeout(SHUTDOWN_POLL_INTERVAL);
 
-    loop {
-        let result = recorder.wait_for_frame().map_err(Arc::new);
+        // the messages get handled either way, the waiters among them get resolved by the next report
         // check if the channel hang up and terminate the loop if it did
         match rx.try_recv() {
-            Ok(msg) => handle_recorder_message(msg, &mut flush_waiters, result.clone()),
+            Ok(msg) => handle_recorder_message(&recorder, msg, &mut waiters),
             Err(TryRecvError::Disconnected) => break,
             Err(TryRecvError::Empty) => (),
         }
 
         for msg in rx.try_iter() {
-            handle_recorder_message(msg, &mut flush_waiters, result.clone());
+            handle_recorder_message(&recorder, msg, &mut waiters);
         }
 
-        // flush the waiters
-        // needed in case we have flush but no messages currently
-        // a bit of code duplication, idk what else to do
+        let Some(result) = result else {
+            continue;
+        };
+        let result = result.map_err(Arc::new);
+
+        {
+            let mut latest = latest_frame.lock();
+            latest.0 += 1;
+            latest.1 = Some(result.clone());
+        }
+
+        waiters
+            .frame
+            .drain(..)
+            .for_each(|d| d.send_result(result.clone()));
+
+        // the recorder only reports flushes, reconfigurations and errors,
+        // everything but a reconfiguration resolves the flush waiters
         if !result
             .as_ref()
             .is_ok_and(|&status| status != EncodeStatus::Flushed)
         {
-            let mapped_result = result.map(|_| ());
+            let mapped_result = result.clone().map(|_| ());
 
-            flush_waiters
+            waiters
+                .flush
                 .drain(..)
                 .for_each(|d: ReturnDestination<_>| d.send_result(mapped_result.clone()));
         }
+
+        resolve_frame_count_waiters(&recorder, &mut waiters, &result);
+        resolve_keyframe_waiters(&recorder.data_buffer_view(), &mut waiters, &result);
+    }
+
+    if let Some(dest) = waiters.finish {
+        dest.send_result(recorder.finish().map_err(Arc::new));
     }
 }
 
-fn handle_recorder_message(
-    msg: RecorderMessage,
-    flush_waiters: &mut Vec<ReturnDestination<NextFlushResult>>,
-    result: NextFrameResult,
+fn resolve_frame_count_waiters(
+    recorder: &Recorder,
+    waiters: &mut Waiters,
+    result: &NextFrameResult,
 ) {
-    match msg {
-        RecorderMessage::WaitForFrame(dest) => dest.send_result(result),
-        RecorderMessage::WaitForNextFlush(dest) => {
-            // if it's not (Flushed or error) push it into the vec of flush waiters
-            if result
-                .as_ref()
-                .is_ok_and(|&status| status != EncodeStatus::Flushed)
-            {
-                flush_waiters.push(dest);
-                return;
-            }
+    if waiters.frame_count.is_empty() {
+        return;
+    }
 
-            let mapped_result = result.map(|_| ());
-            dest.send_result(mapped_result.clone());
+    if let Err(e) = result {
+        waiters
+            .frame_count
+            .drain(..)
+            .for_each(|(_, d)| d.send_result(Err(e.clone())));
 
-            flush_waiters
-                .drain(..)
-                .for_each(|d: ReturnDestination<_>| d.send_result(mapped_result.clone()));
+        return;
+    }
+
+    let (_, id_max) = recorder.data_buffer_view().get().id_bounds();
+
+    let (ready, pending) = waiters
+        .frame_count
+        .drain(..)
+        .partition(|&(target_id, _)| id_max >= target_id);
+
+    waiters.frame_count = pending;
+
+    ready
+        .into_iter()
+        .for_each(|(_, d): (_, ReturnDestination<_>)| d.send_result(Ok(id_max)));
+}
+
+fn resolve_keyframe_waiters(
+    data_buffer_view: &EncodedBufferView,
+    waiters: &mut Waiters,
+    result: &NextFrameResult,
+) {
+    if waiters.keyframe.is_empty() {
+        return;
+    }
+
+    if let Err(e) = result {
+        waiters
+            .keyframe
+            .drain(..)
+            .for_each(|(_, d)| d.send_result(Err(e.clone())));
+
+        return;
+    }
+
+    let buf = data_buffer_view.get();
+    let (_, id_max) = buf.id_bounds();
+
+    waiters.keyframe.retain(|(start_id, dest)| {
+        let keyframe = FrameId::range(*start_id, id_max.max(*start_id))
+            .find(|&id| buf.get(id).is_some_and(|item| item.metadata().is_key));
+
+        match keyframe {
+            Some(id) => {
+                dest.clone().send_result(Ok(id));
+                false
+            }
+            None => true,
+        }
+    });
+}
+
+fn handle_recorder_message(recorder: &Recorder, msg: RecorderMessage, waiters: &mut Waiters) {
+    match msg {
+        // gets resolved by the next report
+        RecorderMessage::WaitForFrame(dest) => waiters.frame.push(dest),
+        RecorderMessage::DrainRemaining { since_id, dest } => {
+            dest.send_result(recorder.drain_remaining(since_id).map_err(Arc::new));
+        }
+        RecorderMessage::WaitForFramesSince { target_id, dest } => {
+            // gets resolved at the end of the current iteration if the frames are already there
+            waiters.frame_count.push((target_id, dest));
+        }
+        RecorderMessage::RequestKeyframe => recorder.request_keyframe(),
+        RecorderMessage::WaitForNextKeyframe(dest) => {
+            // only keyframes flushed from now on count
+            let (_, id_max) = recorder.data_buffer_view().get().id_bounds();
+            waiters.keyframe.push((id_max, dest));
         }
+        RecorderMessage::SetBitrate(kbps) => recorder.set_bitrate(kbps),
+        RecorderMessage::Reconfigure(reconfig) => recorder.reconfigure(reconfig),
+        RecorderMessage::PauseRecording => recorder.pause_recording(),
+        RecorderMessage::ResumeRecording => recorder.resume_recording(),
+        RecorderMessage::WaitForNextFlush(dest) => waiters.flush.push(dest),
+        // the loop stops before the next frame
+        RecorderMessage::Finish(dest) => waiters.finish = Some(dest),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use screen_cap::{
+        record::encoded_buffer::{EncodedBuffer, Metadata},
+        testing::{synthetic_source, test_buffering_settings, test_encoder_settings},
+    };
+
+    use super::*;
+
+    // stands in for a recorder that never produces a frame, reports when it exits
+    fn idle_adapter() -> (RecorderAsyncAdapter, Receiver<()>) {
+        let buf = EncodedBuffer::new(16);
+        let (exited_tx, exited_rx) = mpsc::channel();
+
+        let adapter = RecorderAsyncAdapter::with_recorder_thread(
+            buf.view(),
+            SharedHeaders::default(),
+            SharedVideoInfo::default(),
+            move |_, shutdown| {
+                while !shutdown.load(Ordering::Acquire) {
+                    thread::sleep(SHUTDOWN_POLL_INTERVAL);
+                }
+
+                exited_tx.send(()).unwrap();
+            },
+        );
+
+        (adapter, exited_rx)
+    }
+
+    fn write_chunk(buf: &mut EncodedBuffer, is_key: bool) {
+        buf.write_flush(&[0], Metadata::new(is_key, 0)).unwrap();
+    }
+
+    #[test]
+    fn keyframe_waiter_gets_keyframe_id() {
+        let mut buf = EncodedBuffer::new(1024);
+        let view = buf.view();
+
+        // a keyframe from before the wait started doesn't count
+        write_chunk(&mut buf, true);
+
+        let dest = ReturnDestination::new();
+        let mut waiters = Waiters::default();
+        waiters
+            .keyframe
+            .push((view.get().id_bounds().1, dest.clone()));
+
+        write_chunk(&mut buf, false);
+        resolve_keyframe_waiters(&view, &mut waiters, &Ok(EncodeStatus::Flushed));
+        assert_eq!(waiters.keyframe.len(), 1);
+
+        write_chunk(&mut buf, true);
+        write_chunk(&mut buf, false);
+        resolve_keyframe_waiters(&view, &mut waiters, &Ok(EncodeStatus::Flushed));
+        assert!(waiters.keyframe.is_empty());
+
+        let id = dest.return_dest.lock().take().unwrap().unwrap();
+        assert_eq!(id, FrameId::new(2));
+        assert!(view.get().get(id).unwrap().metadata().is_key);
+    }
+
+    #[test]
+    fn threads_exit_after_last_drop() {
+        let (adapter, exited_rx) = idle_adapter();
+        let clone = adapter.clone();
+
+        drop(adapter);
+        assert!(exited_rx.recv_timeout(4 * SHUTDOWN_POLL_INTERVAL).is_err());
+
+        drop(clone);
+        exited_rx.recv_timeout(Duration::from_secs(1)).unwrap();
+    }
+
+    #[test]
+    fn shutdown_joins_threads() {
+        let (adapter, exited_rx) = idle_adapter();
+
+        // still in use by the original
+        assert!(!adapter.clone().shutdown());
+
+        let start = Instant::now();
+        assert!(adapter.shutdown());
+        assert!(start.elapsed() < Duration::from_secs(1));
+
+        exited_rx.try_recv().unwrap();
+    }
+
+    #[tokio::test]
+    async fn flush_timeout_without_flushes() {
+        let buf = EncodedBuffer::new(16);
+
+        let adapter = RecorderAsyncAdapter::with_recorder_thread(
+            buf.view(),
+            SharedHeaders::default(),
+            SharedVideoInfo::default(),
+            |rx, _| {
+                // never answers, like a recorder whose capture has stalled
+                for _ in rx {}
+            },
+        );
+
+        let flushed = adapter
+            .wait_for_next_flush_timeout(Duration::from_millis(50))
+            .await;
+
+        assert!(matches!(flushed, Ok(false)));
+    }
+
+    #[tokio::test]
+    async fn poll_frame_only_reports_new_frames() {
+        let (source, gate) = synthetic_source(16, 8, usize::MAX).held();
+        let recorder = Recorder::with_source(source, test_buffering_settings(), test_encoder_settings());
+        let adapter = RecorderAsyncAdapter::new(recorder.unwrap());
+
+        thread::sleep(Duration::from_millis(20));
+        assert!(adapter.poll_frame().is_none());
+
+        gate.step(1);
+        // the waiter gets the same report, polling doesn't take it away
+        adapter.wait_for_frame().await.unwrap();
+
+        assert!(matches!(adapter.poll_frame(), Some(Ok(_))));
+        // already seen
+        assert!(adapter.poll_frame().is_none());
     }
 }
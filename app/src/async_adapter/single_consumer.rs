@@ -0,0 +1,307 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicI32, Ordering},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use screen_cap::record::{
+    encoded_buffer::{ArcEncodedDataGuard, EncodedBufferView},
+    EncodeStatus, EncoderReconfig, RecordError, RecordStats, Recorder, RecorderMonitor, SharedHeaders,
+    SharedVideoInfo, VideoInfo,
+};
+use parking_lot::Mutex;
+use tokio::{sync::watch, task, time};
+use utils::{contiguous::FrameId, threading::WorkerError};
+
+use super::{NextFlushResult, SHUTDOWN_POLL_INTERVAL};
+
+// no bitrate change has been asked for, kbit/s are never negative
+const NO_BITRATE_REQUEST: i32 = -1;
+
+/// What the recorder has reported so far, counted so that several reports between two looks don't get lost
+#[derive(Debug, Default)]
+struct Reports {
+    flushes: u64,
+    errors: u64,
+    last_error: Option<Arc<RecordError>>,
+}
+
+// what the consumer asks of the recorder thread, picked up whenever it wakes up
+#[derive(Debug)]
+struct Requests {
+    shutdown: AtomicBool,
+    keyframe: AtomicBool,
+    bitrate: AtomicI32,
+    reconfig: Mutex<Option<EncoderReconfig>>,
+}
+
+/// A `RecorderAsyncAdapter` for when there's only ever one consumer, see `RecorderAsyncAdapter::single_consumer`.
+///
+/// The recorder lives on a thread of its own, which publishes its reports to the consumer as they come.
+/// Waiting for a flush doesn't go through any other thread or allocate anything,
+/// and neither does `data_buffer` unless the encoder is holding the lock at that moment.
+/// Isn't `Clone`, the waits take `&mut self` instead.
+#[derive(Debug)]
+pub struct SingleConsumerAdapter {
+    reports: watch::Receiver<Reports>,
+    requests: Arc<Requests>,
+    thread: Option<JoinHandle<Recorder>>,
+
+    data_buffer_view: EncodedBufferView,
+    headers: SharedHeaders,
+    video_info: SharedVideoInfo,
+    monitor: RecorderMonitor,
+}
+
+impl SingleConsumerAdapter {
+    pub(super) fn new(recorder: Recorder) -> Self {
+        let data_buffer_view = recorder.data_buffer_view();
+        let headers = recorder.shared_headers();
+        let video_info = recorder.shared_video_info();
+        let monitor = recorder.monitor();
+
+        let (reports_tx, reports) = watch::channel(Reports::default());
+        let requests = Arc::new(Requests {
+            shutdown: AtomicBool::new(false),
+            keyframe: AtomicBool::new(false),
+            bitrate: AtomicI32::new(NO_BITRATE_REQUEST),
+            reconfig: Mutex::new(None),
+        });
+        let thread_requests = requests.clone();
+
+        let thread = thread::spawn(move || recorder_thread(recorder, reports_tx, &thread_requests));
+
+        Self {
+            reports,
+            requests,
+            thread: Some(thread),
+            data_buffer_view,
+            headers,
+            video_info,
+            monitor,
+        }
+    }
+
+    /// See `RecorderAsyncAdapter::wait_for_next_flush`
+    pub async fn wait_for_next_flush(&mut self) -> NextFlushResult {
+        // only the reports from now on count
+        let (flushes, errors) = {
+            let reports = self.reports.borrow_and_update();
+            (reports.flushes, reports.errors)
+        };
+
+        loop {
+            let thread_exited = self.reports.changed().await.is_err();
+            let reports = self.reports.borrow_and_update();
+
+            if reports.errors > errors {
+                return Err(reports.last_error.clone().unwrap());
+            }
+            if reports.flushes > flushes {
+                return Ok(());
+            }
+            if thread_exited {
+                return Err(Arc::new(WorkerError::Exited.into()));
+            }
+        }
+    }
+
+    /// See `RecorderAsyncAdapter::wait_for_next_flush_timeout`
+    pub async fn wait_for_next_flush_timeout(&mut self, timeout: Duration) -> Result<bool, Arc<RecordError>> {
+        match time::timeout(timeout, self.wait_for_next_flush()).await {
+            Ok(result) => result.map(|_| true),
+            Err(_) => Ok(false),
+        }
+    }
+
+    /// See `RecorderAsyncAdapter::data_buffer`, only goes to a blocking thread if the lock is taken
+    pub async fn data_buffer(&self) -> ArcEncodedDataGuard {
+        if let Some(guard) = self.data_buffer_view.try_get_arc() {
+            return guard;
+        }
+
+        let view = self.data_buffer_view.clone();
+        task::spawn_blocking(move || view.get_arc())
+            .await
+            .expect("locking the data buffer panicked")
+    }
+
+    /// See `RecorderAsyncAdapter::buffer_view`
+    #[inline]
+    pub fn buffer_view(&self) -> EncodedBufferView {
+        self.data_buffer_view.clone()
+    }
+
+    /// See `RecorderAsyncAdapter::request_keyframe`
+    pub fn request_keyframe(&self) {
+        self.requests.keyframe.store(true, Ordering::Release);
+    }
+
+    /// See `RecorderAsyncAdapter::set_bitrate`
+    pub fn set_bitrate(&self, kbps: i32) {
+        self.requests.bitrate.store(kbps.max(0), Ordering::Release);
+    }
+
+    /// See `RecorderAsyncAdapter::reconfigure`
+    pub fn reconfigure(&self, reconfig: EncoderReconfig) {
+        let mut request = self.requests.reconfig.lock();
+        *request = Some(request.unwrap_or_default().merge(reconfig));
+    }
+
+    /// The current SPS/PPS headers, see `Recorder::headers`
+    pub fn headers(&self) -> Arc<[u8]> {
+        self.headers.get()
+    }
+
+    /// See `Recorder::headers_since`
+    pub fn headers_since(&self) -> (Arc<[u8]>, FrameId) {
+        self.headers.get_since()
+    }
+
+    /// See `Recorder::video_info`
+    pub fn video_info(&self) -> VideoInfo {
+        self.video_info.get()
+    }
+
+    /// See `Recorder::stats`
+    pub fn stats(&self) -> RecordStats {
+        self.monitor.stats()
+    }
+
+    /// See `Recorder::measured_rate`
+    pub fn measured_rate(&self) -> f64 {
+        self.monitor.measured_rate()
+    }
+
+    /// Stops the thread managing the recorder and hands the recorder back,
+    /// e.g. to `Recorder::drain_remaining` and `Recorder::finish` it once the recording is over
+    pub async fn into_recorder(mut self) -> Recorder {
+        self.requests.shutdown.store(true, Ordering::Release);
+        let thread = self.thread.take().unwrap();
+
+        task::spawn_blocking(move || thread.join())
+            .await
+            .unwrap()
+            .expect("the recorder managing thread panicked")
+    }
+}
+
+impl Drop for SingleConsumerAdapter {
+    fn drop(&mut self) {
+        // the thread drops the recorder on its own
+        self.requests.shutdown.store(true, Ordering::Release);
+    }
+}
+
+fn recorder_thread(recorder: Recorder, reports: watch::Sender<Reports>, requests: &Requests) -> Recorder {
+    while !requests.shutdown.load(Ordering::Acquire) {
+        if requests.keyframe.swap(false, Ordering::AcqRel) {
+            recorder.request_keyframe();
+        }
+        let kbps = requests.bitrate.swap(NO_BITRATE_REQUEST, Ordering::AcqRel);
+        if kbps != NO_BITRATE_REQUEST {
+            recorder.set_bitrate(kbps);
+        }
+        let reconfig = requests.reconfig.lock().take();
+        if let Some(reconfig) = reconfig {
+            recorder.reconfigure(reconfig);
+        }
+
+        // doesn't block for long so that a shutdown gets noticed even if nothing gets reported
+        let Some(result) = recorder.wait_for_frame_timeout(SHUTDOWN_POLL_INTERVAL) else {
+            continue;
+        };
+
+        let worker_exited = result.is_err() && recorder.monitor().worker_exited();
+
+        reports.send_modify(|reports| match result {
+            Ok(EncodeStatus::Flushed) => reports.flushes += 1,
+            Ok(_) => (),
+            Err(e) => {
+                reports.errors += 1;
+                reports.last_error = Some(Arc::new(e));
+            }
+        });
+
+        // the error would just get reported over and over
+        if worker_exited {
+            break;
+        }
+    }
+
+    recorder
+}
+
+#[cfg(test)]
+mod tests {
+    use screen_cap::testing::{synthetic_source, test_buffering_settings, test_encoder_settings};
+    use utils::contiguous::FrameId;
+
+    use super::*;
+    use crate::async_adapter::RecorderAsyncAdapter;
+
+    // a new frame every couple of milliseconds, for as long as it's asked for one
+    fn paced_recorder() -> Recorder {
+        let source = synthetic_source(16, 8, usize::MAX);
+        let recorder = Recorder::with_source(source, test_buffering_settings(), test_encoder_settings()).unwrap();
+        recorder.set_capture_rate(500.0);
+
+        recorder
+    }
+
+    // which of the first `n` chunks are there and which are keyframes,
+    // what's in them depends on when the frames got captured
+    fn layout(buf: &ArcEncodedDataGuard, n: usize) -> Vec<(FrameId, bool)> {
+        FrameId::range(FrameId::new(0), FrameId::new(n))
+            .filter_map(|id| buf.get(id).map(|item| (id, item.metadata().is_key)))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn same_chunk_layout_as_the_shared_adapter() {
+        let shared = RecorderAsyncAdapter::new(paced_recorder());
+        let mut single = RecorderAsyncAdapter::single_consumer(paced_recorder());
+
+        while shared.data_buffer().await.id_bounds().1 < FrameId::new(5) {
+            shared.wait_for_next_flush().await.unwrap();
+        }
+        while single.data_buffer().await.id_bounds().1 < FrameId::new(5) {
+            single.wait_for_next_flush().await.unwrap();
+        }
+
+        let expected = layout(&shared.data_buffer().await, 5);
+        assert_eq!(layout(&single.data_buffer().await, 5), expected);
+        assert_eq!(expected.len(), 5);
+        assert!(expected.windows(2).all(|pair| pair[0].0 < pair[1].0));
+        assert!(expected[0].1);
+        assert_eq!(single.headers(), shared.headers());
+
+        // the requests reach the recorder as well
+        let (_, start_id) = single.data_buffer().await.id_bounds();
+        single.request_keyframe();
+
+        let keyframe = time::timeout(Duration::from_secs(5), async {
+            loop {
+                single.wait_for_next_flush().await.unwrap();
+
+                let buf = single.data_buffer().await;
+                let (_, end_id) = buf.id_bounds();
+                if let Some(id) = FrameId::range(start_id, end_id)
+                    .find(|&id| buf.get(id).is_some_and(|item| item.metadata().is_key))
+                {
+                    break id;
+                }
+            }
+        })
+        .await
+        .unwrap();
+        assert!(keyframe >= start_id);
+
+        let recorder = single.into_recorder().await;
+        let remaining = recorder.drain_remaining(keyframe).unwrap();
+        assert!(remaining.iter().count() > 0);
+    }
+}
@@ -0,0 +1,270 @@
+use std::{path::PathBuf, str::FromStr, time::Duration};
+
+use thiserror::Error;
+use x264::{Preset, Tune};
+
+use crate::{BITRATE, PRESET, TARGET_RATE, TUNE};
+
+const DEFAULT_DURATION: Duration = Duration::from_secs(60);
+const DEFAULT_OUTPUT: &str = "thing.h264";
+
+pub const USAGE: &str = "\
+Usage: app [OPTIONS]
+
+Options:
+    --duration <SECONDS>   how long to record for, 0 records until Ctrl-C [default: 60]
+    --output <PATH>        where to write the recording [default: thing.h264]
+    --format <FORMAT>      h264 for the raw stream or mkv for Matroska [default: h264]
+    --bitrate <KBPS>       target bitrate in kbit/s [default: 4000]
+    --preset <PRESET>      x264 preset, ultrafast..placebo [default: ultrafast]
+    --tune <TUNE>          x264 tune, none, film, animation, grain, stillimage, psnr or ssim [default: film]
+    --fps <RATE>           how many frames per second to capture [default: 120]
+    --display <INDEX>      which display to record, as listed by the system [default: the primary one]
+    --checkpoint <SECONDS> sync the file to disk this often, so a crash loses at most that much, 0 never does [default: 0]
+    --help                 print this message";
+
+#[derive(Debug, Error, Clone, PartialEq, Eq)]
+pub enum ArgsError {
+    #[error("unknown argument `{0}`")]
+    UnknownArgument(String),
+    #[error("`{0}` needs a value")]
+    MissingValue(String),
+    #[error("invalid value `{value}` for `{flag}`")]
+    InvalidValue { flag: String, value: String },
+    #[error("unknown preset `{0}`, expected one of ultrafast, superfast, veryfast, faster, fast, medium, slow, slower, veryslow, placebo")]
+    UnknownPreset(String),
+    #[error("unknown tune `{0}`, expected one of none, film, animation, grain, stillimage, psnr, ssim")]
+    UnknownTune(String),
+    #[error("unknown format `{0}`, expected one of h264, mkv")]
+    UnknownFormat(String),
+    /// Not really an error, but the program shouldn't go on either
+    #[error("help requested")]
+    Help,
+}
+
+/// What the recording file holds
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutputFormat {
+    /// The Annex B stream straight out of the encoder
+    #[default]
+    H264,
+    /// The same stream muxed into Matroska, see `screen_cap::mux::MkvMuxer`
+    Mkv,
+}
+
+/// Everything about a recording that can be set from the command line
+#[derive(Debug, Clone, PartialEq)]
+pub struct RunConfig {
+    /// `None` records until the process gets asked to stop
+    pub duration: Option<Duration>,
+    pub output: PathBuf,
+    pub format: OutputFormat,
+    /// In kbit/s
+    pub bitrate: i32,
+    pub preset: Preset,
+    pub tune: Tune,
+    pub target_rate: f64,
+    /// Index into the list of displays, `None` is the primary one
+    pub display: Option<usize>,
+    /// How often the file gets synced to disk, see `file_writer::CheckpointingWriter`, `None` leaves it up to the OS
+    pub checkpoint_interval: Option<Duration>,
+}
+
+impl Default for RunConfig {
+    fn default() -> Self {
+        Self {
+            duration: Some(DEFAULT_DURATION),
+            output: PathBuf::from(DEFAULT_OUTPUT),
+            format: OutputFormat::default(),
+            bitrate: BITRATE,
+            preset: PRESET,
+            tune: TUNE,
+            target_rate: TARGET_RATE,
+            display: None,
+            checkpoint_interval: None,
+        }
+    }
+}
+
+impl RunConfig {
+    /// Parses the arguments without the program name, anything not given keeps its default
+    pub fn from_args<I>(args: I) -> Result<Self, ArgsError>
+    where
+        I: IntoIterator<Item = String>,
+    {
+        let mut config = Self::default();
+        let mut args = args.into_iter();
+
+        while let Some(flag) = args.next() {
+            if flag == "--help" || flag == "-h" {
+                return Err(ArgsError::Help);
+            }
+
+            let mut value = || args.next().ok_or_else(|| ArgsError::MissingValue(flag.clone()));
+
+            match flag.as_str() {
+                "--duration" => {
+                    let seconds: u64 = parse_value(&flag, value()?)?;
+                    config.duration = (seconds != 0).then(|| Duration::from_secs(seconds));
+                }
+                "--output" => config.output = PathBuf::from(value()?),
+                "--format" => config.format = parse_format(&value()?)?,
+                "--bitrate" => config.bitrate = parse_value(&flag, value()?)?,
+                "--preset" => config.preset = parse_preset(&value()?)?,
+                "--tune" => config.tune = parse_tune(&value()?)?,
+                "--fps" => {
+                    let value = value()?;
+                    let rate: f64 = parse_value(&flag, value.clone())?;
+
+                    if rate.is_nan() || rate <= 0.0 {
+                        return Err(ArgsError::InvalidValue { flag, value });
+                    }
+                    config.target_rate = rate;
+                }
+                "--display" => config.display = Some(parse_value(&flag, value()?)?),
+                "--checkpoint" => {
+                    let seconds: u64 = parse_value(&flag, value()?)?;
+                    config.checkpoint_interval = (seconds != 0).then(|| Duration::from_secs(seconds));
+                }
+                _ => return Err(ArgsError::UnknownArgument(flag)),
+            }
+        }
+
+        Ok(config)
+    }
+
+    /// Whether the recording should go on after running for `elapsed`
+    #[inline]
+    pub fn should_continue(&self, elapsed: Duration) -> bool {
+        self.duration.is_none_or(|duration| elapsed < duration)
+    }
+}
+
+fn parse_value<T: FromStr>(flag: &str, value: String) -> Result<T, ArgsError> {
+    value.parse().map_err(|_| ArgsError::InvalidValue {
+        flag: flag.to_string(),
+        value,
+    })
+}
+
+/// Case insensitive, same names as x264's `--preset`
+pub fn parse_preset(name: &str) -> Result<Preset, ArgsError> {
+    let preset = match name.to_ascii_lowercase().as_str() {
+        "ultrafast" => Preset::Ultrafast,
+        "superfast" => Preset::Superfast,
+        "veryfast" => Preset::Veryfast,
+        "faster" => Preset::Faster,
+        "fast" => Preset::Fast,
+        "medium" => Preset::Medium,
+        "slow" => Preset::Slow,
+        "slower" => Preset::Slower,
+        "veryslow" => Preset::Veryslow,
+        "placebo" => Preset::Placebo,
+        _ => return Err(ArgsError::UnknownPreset(name.to_string())),
+    };
+
+    Ok(preset)
+}
+
+/// Case insensitive, same names as x264's `--tune`
+pub fn parse_tune(name: &str) -> Result<Tune, ArgsError> {
+    let tune = match name.to_ascii_lowercase().as_str() {
+        "none" => Tune::None,
+        "film" => Tune::Film,
+        "animation" => Tune::Animation,
+        "grain" => Tune::Grain,
+        "stillimage" => Tune::StillImage,
+        "psnr" => Tune::Psnr,
+        "ssim" => Tune::Ssim,
+        _ => return Err(ArgsError::UnknownTune(name.to_string())),
+    };
+
+    Ok(tune)
+}
+
+/// Case insensitive
+pub fn parse_format(name: &str) -> Result<OutputFormat, ArgsError> {
+    let format = match name.to_ascii_lowercase().as_str() {
+        "h264" => OutputFormat::H264,
+        "mkv" => OutputFormat::Mkv,
+        _ => return Err(ArgsError::UnknownFormat(name.to_string())),
+    };
+
+    Ok(format)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<RunConfig, ArgsError> {
+        RunConfig::from_args(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn preset_names() {
+        assert_eq!(parse_preset("ultrafast"), Ok(Preset::Ultrafast));
+        assert_eq!(parse_preset("VeryFast"), Ok(Preset::Veryfast));
+        assert_eq!(parse_preset("placebo"), Ok(Preset::Placebo));
+        assert_eq!(
+            parse_preset("ludicrous"),
+            Err(ArgsError::UnknownPreset("ludicrous".to_string()))
+        );
+    }
+
+    #[test]
+    fn tune_names() {
+        assert_eq!(parse_tune("film"), Ok(Tune::Film));
+        assert_eq!(parse_tune("StillImage"), Ok(Tune::StillImage));
+        assert_eq!(parse_tune("none"), Ok(Tune::None));
+        assert_eq!(parse_tune("zerolatency"), Err(ArgsError::UnknownTune("zerolatency".to_string())));
+    }
+
+    #[test]
+    fn zero_duration_runs_until_stopped() {
+        let config = parse(&["--duration", "0"]).unwrap();
+
+        assert_eq!(config.duration, None);
+        assert!(config.should_continue(Duration::from_secs(60 * 60 * 24 * 365)));
+
+        let config = parse(&["--duration", "5"]).unwrap();
+        assert!(config.should_continue(Duration::from_secs(4)));
+        assert!(!config.should_continue(Duration::from_secs(5)));
+    }
+
+    #[test]
+    fn all_flags() {
+        let config = parse(&[
+            "--output", "out.h264", "--bitrate", "8000", "--preset", "fast", "--tune", "animation",
+            "--fps", "30", "--display", "1", "--format", "mkv", "--checkpoint", "5",
+        ])
+        .unwrap();
+
+        let expected = RunConfig {
+            output: PathBuf::from("out.h264"),
+            format: OutputFormat::Mkv,
+            bitrate: 8000,
+            preset: Preset::Fast,
+            tune: Tune::Animation,
+            target_rate: 30.0,
+            display: Some(1),
+            checkpoint_interval: Some(Duration::from_secs(5)),
+            ..RunConfig::default()
+        };
+        assert_eq!(config, expected);
+    }
+
+    #[test]
+    fn bad_arguments() {
+        assert_eq!(parse(&["--bitrate"]), Err(ArgsError::MissingValue("--bitrate".to_string())));
+        assert_eq!(
+            parse(&["--fps", "-1"]),
+            Err(ArgsError::InvalidValue {
+                flag: "--fps".to_string(),
+                value: "-1".to_string()
+            })
+        );
+        assert_eq!(parse(&["--format", "avi"]), Err(ArgsError::UnknownFormat("avi".to_string())));
+        assert_eq!(parse(&["--loud"]), Err(ArgsError::UnknownArgument("--loud".to_string())));
+    }
+}
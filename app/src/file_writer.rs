@@ -0,0 +1,410 @@
+use std::{
+    borrow::Cow,
+    future::Future,
+    io,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+
+use screen_cap::{mux::MkvMuxer, record::encoded_buffer::Metadata};
+use thiserror::Error;
+use tokio::io::{AsyncWrite, AsyncWriteExt, BufWriter};
+use utils::contiguous::{BufferItem, FrameId, RingBuffer};
+
+/// The ring buffer evicted chunks before they got written, so the output is missing them.
+///
+/// `lost_to` is exclusive, like the end of `RingBuffer::id_bounds`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+#[error("chunks {lost_from} to {lost_to} got evicted before they could be written")]
+pub struct WriteLagError {
+    pub lost_from: FrameId,
+    pub lost_to: FrameId,
+}
+
+/// Which headers the output has gotten, so the new ones go in right before the first chunk encoded with them.
+///
+/// The encoder gets new headers when it's rebuilt with a different config, see `SharedHeaders::get_since`.
+/// Only the latest change counts, if the headers change twice before the chunks in between get written,
+/// those chunks go out with the latest headers.
+#[derive(Debug, Clone, Default)]
+pub struct HeaderTracker {
+    first_id: FrameId,
+    pending: Option<Arc<[u8]>>,
+}
+
+impl HeaderTracker {
+    /// The output already has the headers that apply from `first_id` on
+    pub fn new(first_id: FrameId) -> Self {
+        Self {
+            first_id,
+            pending: None,
+        }
+    }
+
+    /// Takes note of the current headers, as returned by `SharedHeaders::get_since`.
+    ///
+    /// Has to be called after locking the ring buffer, so the chunks in it can't be ahead of the headers.
+    pub fn update(&mut self, (headers, first_id): (Arc<[u8]>, FrameId)) {
+        if first_id > self.first_id {
+            self.first_id = first_id;
+            self.pending = Some(headers);
+        }
+    }
+
+    // the headers that have to go right before the chunk with `id`
+    fn headers_before(&self, id: FrameId) -> Option<&[u8]> {
+        self.pending.as_deref().filter(|_| id >= self.first_id)
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum DrainError {
+    #[error(transparent)]
+    Lag(#[from] WriteLagError),
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
+
+/// Writes every chunk in `buf` from `last_id` on into `writer`, muxed if there's a muxer,
+/// and moves `last_id` past the written ones.
+/// New headers from `headers` go in front of the first chunk they apply to.
+///
+/// Fails with `DrainError::Lag` without writing anything if the chunk at `last_id` is gone already,
+/// i.e. the writes fell so far behind the encoder that the ring buffer overwrote what hadn't been written yet.
+/// `last_id` is moved past the gap then, so calling this again carries on with the chunks that are left.
+pub async fn drain_to_writer<W>(
+    buf: &RingBuffer<Metadata>,
+    last_id: &mut FrameId,
+    headers: &mut HeaderTracker,
+    muxer: &mut Option<MkvMuxer>,
+    writer: &mut W,
+) -> Result<(), DrainError>
+where
+    W: AsyncWrite + Unpin,
+{
+    let (id_min, id_max) = buf.id_bounds();
+
+    if id_min > *last_id {
+        let error = WriteLagError {
+            lost_from: *last_id,
+            lost_to: id_min,
+        };
+        *last_id = id_min;
+
+        return Err(error.into());
+    }
+
+    for chunk in buf.range(*last_id, id_max) {
+        let new_headers = headers.headers_before(*last_id);
+        writer.write_all(&output_chunk(muxer, &chunk, new_headers)).await?;
+
+        // a failed write can be retried without writing a chunk twice
+        if new_headers.is_some() {
+            headers.pending = None;
+        }
+        *last_id += 1;
+    }
+
+    Ok(())
+}
+
+/// Writes the bytes `Recorder::finish` returned after the last chunk out of the ring buffer,
+/// along with any new headers that didn't have a chunk to go in front of yet.
+///
+/// The tail doesn't come with metadata, so a muxer gets it as a single non-key chunk at `last_pts`,
+/// the pts of the last chunk that was written.
+pub async fn write_tail<W>(
+    tail: &[u8],
+    last_pts: i64,
+    headers: &mut HeaderTracker,
+    muxer: &mut Option<MkvMuxer>,
+    writer: &mut W,
+) -> io::Result<()>
+where
+    W: AsyncWrite + Unpin,
+{
+    if tail.is_empty() {
+        return Ok(());
+    }
+
+    let data = match headers.pending.take() {
+        Some(new_headers) => Cow::Owned([&new_headers, tail].concat()),
+        None => Cow::Borrowed(tail),
+    };
+
+    match muxer {
+        Some(muxer) => writer.write_all(&muxer.wrap_chunk(&data, last_pts, false)).await,
+        None => writer.write_all(&data).await,
+    }
+}
+
+/// The chunk as it goes into the file, muxed if there's a muxer.
+///
+/// `headers` go in front of the chunk's NAL units, muxed along with them,
+/// a muxed stream's own headers can't change after they've been written.
+pub fn output_chunk<'a>(
+    muxer: &mut Option<MkvMuxer>,
+    frame: &BufferItem<'a, Metadata>,
+    headers: Option<&[u8]>,
+) -> Cow<'a, [u8]> {
+    let data = match headers {
+        Some(headers) => Cow::Owned([headers, &frame.data()].concat()),
+        None => frame.data(),
+    };
+
+    match muxer {
+        Some(muxer) => {
+            let metadata = frame.metadata();
+            Cow::Owned(muxer.wrap_chunk(&data, metadata.pts, metadata.is_key))
+        }
+        None => data,
+    }
+}
+
+/// A writer whose data can be made durable, see `CheckpointingWriter`
+pub trait SyncData {
+    /// Flushes whatever is buffered and waits for it to reach the disk, see `tokio::fs::File::sync_data`
+    fn sync_data(&mut self) -> impl Future<Output = io::Result<()>> + Send;
+}
+
+impl SyncData for tokio::fs::File {
+    async fn sync_data(&mut self) -> io::Result<()> {
+        tokio::fs::File::sync_data(self).await
+    }
+}
+
+impl<W> SyncData for BufWriter<W>
+where
+    W: AsyncWrite + SyncData + Unpin + Send,
+{
+    async fn sync_data(&mut self) -> io::Result<()> {
+        self.flush().await?;
+        self.get_mut().sync_data().await
+    }
+}
+
+/// Wraps the recording's writer and syncs it to disk every `interval`, so a crash only loses the last few seconds.
+///
+/// A checkpoint only happens right after a keyframe has been written, so the file cut off after it still plays
+/// from the start of a GOP. Once a checkpoint is due, the writer asks for a keyframe through `request_keyframe`,
+/// the chunks up to it keep getting written as usual until it shows up, see `checkpoint_ready`.
+pub struct CheckpointingWriter<W> {
+    writer: W,
+    interval: Duration,
+    request_keyframe: Box<dyn Fn() + Send>,
+    last_checkpoint: Instant,
+    // the first id that counts as the keyframe that was asked for
+    keyframe_requested: Option<FrameId>,
+}
+
+impl<W> CheckpointingWriter<W>
+where
+    W: AsyncWrite + SyncData + Unpin,
+{
+    /// The first checkpoint is due `interval` from now
+    pub fn new(writer: W, interval: Duration, request_keyframe: impl Fn() + Send + 'static) -> Self {
+        Self {
+            writer,
+            interval,
+            request_keyframe: Box::new(request_keyframe),
+            last_checkpoint: Instant::now(),
+            keyframe_requested: None,
+        }
+    }
+
+    /// Whether a checkpoint is due and its keyframe has been written, asks for the keyframe once it's due.
+    ///
+    /// Meant to be called after writing the chunks from `buf` up to, but not including, `written_to`,
+    /// while the ring buffer is still locked, so the keyframe can be looked up among them.
+    /// Release the lock before taking the checkpoint, see `checkpoint`.
+    pub fn checkpoint_ready(&mut self, buf: &RingBuffer<Metadata>, written_to: FrameId) -> bool {
+        let Some(requested) = self.keyframe_requested else {
+            if self.last_checkpoint.elapsed() >= self.interval {
+                (self.request_keyframe)();
+                self.keyframe_requested = Some(written_to);
+            }
+
+            return false;
+        };
+
+        FrameId::range(requested, written_to.max(requested))
+            .any(|id| buf.get(id).is_some_and(|item| item.metadata().is_key))
+    }
+
+    /// Syncs everything written so far to disk and starts waiting for the next checkpoint to be due.
+    ///
+    /// That can take a while, the encoder can't flush into a ring buffer that's locked in the meantime.
+    pub async fn checkpoint(&mut self) -> io::Result<()> {
+        self.writer.sync_data().await?;
+        self.last_checkpoint = Instant::now();
+        self.keyframe_requested = None;
+
+        Ok(())
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for CheckpointingWriter<W> {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.writer).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.writer).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.writer).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+
+    fn write_chunk(buf: &mut RingBuffer<Metadata>, fill: u8) {
+        let metadata = Metadata::new(true, fill as i64);
+
+        buf.write(&[fill; 3], metadata).unwrap();
+    }
+
+    #[tokio::test]
+    async fn evicted_chunks_are_reported() {
+        // room for 3 chunks at most
+        let mut buf = RingBuffer::new(10);
+        let mut last_id = FrameId::default();
+        let mut headers = HeaderTracker::default();
+        let mut output = Vec::new();
+
+        write_chunk(&mut buf, 0);
+        write_chunk(&mut buf, 1);
+        drain_to_writer(&buf, &mut last_id, &mut headers, &mut None, &mut output).await.unwrap();
+        assert_eq!(last_id, FrameId::new(2));
+        assert_eq!(output, [0, 0, 0, 1, 1, 1]);
+
+        // the writer falls behind
+        for fill in 2..7 {
+            write_chunk(&mut buf, fill);
+        }
+        let (id_min, id_max) = buf.id_bounds();
+        assert!(id_min > last_id);
+
+        let error = drain_to_writer(&buf, &mut last_id, &mut headers, &mut None, &mut output).await;
+        match error {
+            Err(DrainError::Lag(error)) => assert_eq!(
+                error,
+                WriteLagError {
+                    lost_from: FrameId::new(2),
+                    lost_to: id_min,
+                }
+            ),
+            other => panic!("expected a lag error, got {other:?}"),
+        }
+        assert_eq!(output.len(), 6);
+
+        // picks up after the gap
+        drain_to_writer(&buf, &mut last_id, &mut headers, &mut None, &mut output).await.unwrap();
+        assert_eq!(last_id, id_max);
+
+        let expected: Vec<u8> = [0, 1]
+            .into_iter()
+            .chain(id_min.get() as u8..7)
+            .flat_map(|fill| [fill; 3])
+            .collect();
+        assert_eq!(output, expected);
+    }
+
+    // remembers how often it got synced and how much had been written at the time
+    #[derive(Debug, Default)]
+    struct SyncedVec {
+        data: Vec<u8>,
+        synced_len: Vec<usize>,
+    }
+
+    impl AsyncWrite for SyncedVec {
+        fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+            Pin::new(&mut self.data).poll_write(cx, buf)
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_shutdown(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    impl SyncData for SyncedVec {
+        async fn sync_data(&mut self) -> io::Result<()> {
+            self.synced_len.push(self.data.len());
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn checkpoints_land_on_a_keyframe() {
+        let mut buf = RingBuffer::new(64);
+        let mut last_id = FrameId::default();
+        let mut headers = HeaderTracker::default();
+        let requests = Arc::new(AtomicUsize::new(0));
+
+        let counter = requests.clone();
+        let request_keyframe = move || {
+            counter.fetch_add(1, Ordering::Relaxed);
+        };
+        // due right away
+        let mut writer = CheckpointingWriter::new(SyncedVec::default(), Duration::ZERO, request_keyframe);
+
+        write_chunk(&mut buf, 1);
+        drain_to_writer(&buf, &mut last_id, &mut headers, &mut None, &mut writer).await.unwrap();
+        // the keyframe that's already written doesn't count, it was there before the request
+        assert!(!writer.checkpoint_ready(&buf, last_id));
+        assert_eq!(requests.load(Ordering::Relaxed), 1);
+
+        let metadata = Metadata::new(false, 2);
+        buf.write(&[2; 3], metadata).unwrap();
+        drain_to_writer(&buf, &mut last_id, &mut headers, &mut None, &mut writer).await.unwrap();
+        assert!(!writer.checkpoint_ready(&buf, last_id));
+
+        // the requested keyframe
+        write_chunk(&mut buf, 3);
+        drain_to_writer(&buf, &mut last_id, &mut headers, &mut None, &mut writer).await.unwrap();
+        assert!(writer.checkpoint_ready(&buf, last_id));
+        writer.checkpoint().await.unwrap();
+
+        let output = writer.into_inner();
+        assert_eq!(output.data, [1, 1, 1, 2, 2, 2, 3, 3, 3]);
+        assert_eq!(output.synced_len, [9]);
+        // no second request before the next checkpoint was due
+        assert_eq!(requests.load(Ordering::Relaxed), 1);
+    }
+
+    #[tokio::test]
+    async fn new_headers_go_before_their_first_chunk() {
+        let mut buf = RingBuffer::new(64);
+        let mut last_id = FrameId::default();
+        let mut headers = HeaderTracker::new(FrameId::default());
+        let mut output = Vec::new();
+
+        write_chunk(&mut buf, 1);
+        // the encoder got rebuilt after the first chunk, the next one doesn't exist yet
+        headers.update((Arc::from(&[9, 9][..]), FrameId::new(1)));
+        drain_to_writer(&buf, &mut last_id, &mut headers, &mut None, &mut output).await.unwrap();
+        assert_eq!(output, [1, 1, 1]);
+
+        write_chunk(&mut buf, 2);
+        write_chunk(&mut buf, 3);
+        // nothing new
+        headers.update((Arc::from(&[9, 9][..]), FrameId::new(1)));
+        drain_to_writer(&buf, &mut last_id, &mut headers, &mut None, &mut output).await.unwrap();
+        assert_eq!(output, [1, 1, 1, 9, 9, 2, 2, 2, 3, 3, 3]);
+    }
+}
@@ -1,24 +1,39 @@
 pub mod server;
 pub mod async_adapter;
+pub mod cli;
+pub mod file_writer;
 
 use std::{
-    fs::File,
-    io::{BufWriter, Write},
+    process,
     time::{Duration, Instant},
 };
 
 use async_adapter::RecorderAsyncAdapter;
-use scrap::Display;
-use screen_cap::record::{BufferingSettings, CapturerSettings, EncoderSettings, Recorder};
-use spin_sleep::LoopHelper;
-use tokio::{runtime::Builder, io::AsyncWriteExt};
-use x264::{Colorspace, Preset, Setup, Tune};
+use cli::{ArgsError, OutputFormat, RunConfig, USAGE};
+use file_writer::{drain_to_writer, write_tail, CheckpointingWriter, DrainError, HeaderTracker};
+use screen_cap::{
+    mux::MkvMuxer,
+    record::{
+        encoded_buffer::Metadata, BufferingSettings, CapturerSettings, EncoderConfig,
+        EncoderSettings, Recorder,
+    },
+};
+use tokio::{
+    io::{AsyncWrite, AsyncWriteExt},
+    runtime::Builder,
+    signal,
+};
+use utils::{
+    contiguous::{FrameId, RingBuffer},
+    threading::ThreadScheduling,
+};
+use x264::{Colorspace, Preset, Tune};
 
 // it seems that the real update rate is half as large
-// possibly because scrap likes skipping frames
+// possibly because scrap likes skipping frames, see `ThreadedCapturer::capture_stats`
 const TARGET_RATE: f64 = 120.0;
-// 50 MiB
-const BUFFER_CAPACITY: usize = 50 * 8 * 1024 * 1024;
+// how far back the buffer goes, at the configured bitrate
+const BUFFER_SECS: f64 = 100.0;
 const BUFFERED_FRAMES: usize = 0;
 // 4 Mbits/s
 const BITRATE: i32 = 4000;
@@ -30,140 +45,251 @@ const FAST_DECODE: bool = true;
 const ZERO_LATENCY: bool = true;
 
 pub fn run() {
-    // record_to_file();
+    let config = match RunConfig::from_args(std::env::args().skip(1)) {
+        Ok(config) => config,
+        Err(ArgsError::Help) => {
+            println!("{USAGE}");
+            return;
+        }
+        Err(e) => {
+            eprintln!("error: {e}\n\n{USAGE}");
+            process::exit(2);
+        }
+    };
+
     let rt = Builder::new_multi_thread().enable_all().build().unwrap();
-    rt.block_on(record_to_file_async());
+    rt.block_on(record_to_file_async(config));
 }
 
-async fn record_to_file_async() {
-    let display = Display::primary().unwrap();
-    let width = display.width();
-    let height = display.height();
+/// Resolves once the process receives SIGINT (Ctrl-C) or SIGTERM
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        signal::ctrl_c()
+            .await
+            .expect("Couldn't install the Ctrl-C handler");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        signal::unix::signal(signal::unix::SignalKind::terminate())
+            .expect("Couldn't install the SIGTERM handler")
+            .recv()
+            .await;
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => (),
+        _ = terminate => (),
+    }
+}
 
-    let capturer_settings = CapturerSettings {
-        display_factory: || Display::primary().unwrap(),
-        target_rate: TARGET_RATE,
+/// Records the screen into a file until `config.duration` runs out or the process gets asked to stop.
+async fn record_to_file_async(config: RunConfig) {
+    let capturer_settings = match config.display {
+        Some(index) => CapturerSettings::for_display_index(index, config.target_rate),
+        None => CapturerSettings::for_primary(config.target_rate),
+    };
+    let capturer_settings = match capturer_settings {
+        Ok(settings) => settings,
+        Err(e) => {
+            eprintln!("error: couldn't open the display: {e}");
+            process::exit(1);
+        }
     };
 
     let buffering_settings = BufferingSettings {
-        buffer_capacity: BUFFER_CAPACITY,
         buffered_frames: BUFFERED_FRAMES,
+        ..BufferingSettings::from_duration(BUFFER_SECS, config.bitrate)
     };
 
+    let (preset, tune) = (config.preset, config.tune);
     let encoder_settings = EncoderSettings {
-        encoder_factory: move || {
-            Setup::preset(PRESET, TUNE, FAST_DECODE, ZERO_LATENCY)
-                .bitrate(BITRATE)
+        encoder_factory: move |encoder_config: EncoderConfig| {
+            encoder_config
+                .apply_keyframe_interval(encoder_config.setup(preset, tune, FAST_DECODE, ZERO_LATENCY))
+                .bitrate(encoder_config.bitrate)
                 .timebase(1, TIMEBASE as u32)
-                .build(Colorspace::BGRA, width as _, height as _)
+                .build(encoder_config.colorspace, encoder_config.width as _, encoder_config.height as _)
                 .unwrap()
         },
+        bitrate: config.bitrate,
         timebase: TIMEBASE,
+        colorspace: Colorspace::BGRA,
+        keyframe_interval: None,
+        clock: None,
+        thread_scheduling: ThreadScheduling::default(),
+        stall_filler: None,
     };
 
-    let file = tokio::fs::File::create("thing.h264").await.unwrap();
-    //let mut file_buf = BufWriter::with_capacity(8 * 1024 * 1024, file);
+    let file = tokio::fs::File::create(&config.output).await.unwrap();
     
-    let mut file_buf = tokio::io::BufWriter::with_capacity(8 * 1024 * 1024, file);
+    let file_buf = tokio::io::BufWriter::with_capacity(8 * 1024 * 1024, file);
     
-    let recorder = Recorder::new(capturer_settings, buffering_settings, encoder_settings);
+    let recorder = Recorder::new(capturer_settings, buffering_settings, encoder_settings).unwrap();
     let recorder = RecorderAsyncAdapter::new(recorder);
 
-    let mut last_chunk_id = 0;
+    // a checkpoint that's never due
+    let checkpoint_interval = config.checkpoint_interval.unwrap_or(Duration::MAX);
+    let keyframes = recorder.clone();
+    let mut file_buf =
+        CheckpointingWriter::new(file_buf, checkpoint_interval, move || keyframes.request_keyframe());
+
+    let video_info = recorder.video_info();
+    println!(
+        "recording {}x{} at {} fps into {}",
+        video_info.width,
+        video_info.height,
+        video_info.target_rate,
+        config.output.display()
+    );
+
+    let mut muxer = match config.format {
+        OutputFormat::H264 => None,
+        OutputFormat::Mkv => Some(
+            MkvMuxer::new(
+                &recorder.headers(),
+                video_info.width as u16,
+                video_info.height as u16,
+                video_info.timebase as u32,
+            )
+            .unwrap(),
+        ),
+    };
+
+    let mut last_chunk_id = FrameId::default();
 
     let start_time = Instant::now();
 
-    file_buf.write_all(recorder.headers()).await.unwrap();
-    
-    let mut loop_helper = LoopHelper::builder().report_interval_s(1.0).build_without_target_rate();
+    let (first_headers, first_id) = recorder.headers_since();
+    let mut headers = HeaderTracker::new(first_id);
 
-    while start_time.elapsed() < Duration::from_secs(60) {
-        loop_helper.loop_start();
-        
-        if let Some(fps) = loop_helper.report_rate() {
-            dbg!(fps * (BUFFERED_FRAMES + 1) as f64 );
+    match &muxer {
+        Some(muxer) => file_buf.write_all(&muxer.header()).await.unwrap(),
+        None => file_buf.write_all(&first_headers).await.unwrap(),
+    }
+    
+    let shutdown = shutdown_signal();
+    tokio::pin!(shutdown);
+
+    while config.should_continue(start_time.elapsed()) {
+        tokio::select! {
+            result = recorder.wait_for_next_flush() => result.unwrap(),
+            // whatever got encoded in the meantime is written out after the loop
+            _ = &mut shutdown => break,
         }
-        
-        recorder.wait_for_next_flush().await.unwrap();
-        loop_helper.loop_sleep();
 
         let data_buf = recorder.data_buffer().await;
-        let (id_min, id_max) = data_buf.id_bounds();
-
-        let start_id = id_min.max(last_chunk_id);
-
-        for i in start_id..id_max {
-            let frame = data_buf.get(i).unwrap();
-            file_buf.write_all(frame.data()).await.unwrap();
+        headers.update(recorder.headers_since());
+        write_chunks(&data_buf, &mut last_chunk_id, &mut headers, &mut muxer, &mut file_buf).await;
+
+        let checkpoint_ready = file_buf.checkpoint_ready(&data_buf, last_chunk_id);
+        // the encoder can keep flushing while the file syncs
+        drop(data_buf);
+
+        if checkpoint_ready {
+            if let Err(e) = file_buf.checkpoint().await {
+                eprintln!("error: couldn't sync the recording to disk: {e}");
+                process::exit(1);
+            }
         }
-
-        last_chunk_id = id_max;
-        
     }
-    file_buf.flush().await.unwrap();
+    
+    finish_recording(recorder, last_chunk_id, headers, &mut muxer, &mut file_buf).await;
 }
 
-fn record_to_file() {
-    let display = Display::primary().unwrap();
-    let width = display.width();
-    let height = display.height();
-
-    let capturer_settings = CapturerSettings {
-        display_factory: || Display::primary().unwrap(),
-        target_rate: TARGET_RATE,
-    };
-
-    let buffering_settings = BufferingSettings {
-        buffer_capacity: BUFFER_CAPACITY,
-        buffered_frames: BUFFERED_FRAMES,
-    };
-
-    let encoder_settings = EncoderSettings {
-        encoder_factory: move || {
-            Setup::preset(PRESET, TUNE, FAST_DECODE, ZERO_LATENCY)
-                .bitrate(BITRATE)
-                .timebase(1, TIMEBASE as u32)
-                .build(Colorspace::BGRA, width as _, height as _)
-                .unwrap()
-        },
-        timebase: TIMEBASE,
-    };
-
-    let file = File::create("thing.h264").unwrap();
-    let mut file_buf = BufWriter::with_capacity(8 * 1024 * 1024, file);
-
-    let recorder = Recorder::new(capturer_settings, buffering_settings, encoder_settings);
-
-    let mut last_chunk_id = 0;
-
-    let start_time = Instant::now();
+/// Writes out what the recording loop didn't get to and flushes `writer`:
+/// the chunks encoded after the last flush it saw, then whatever x264 was still holding on to.
+async fn finish_recording<W>(
+    recorder: RecorderAsyncAdapter,
+    mut last_chunk_id: FrameId,
+    mut headers: HeaderTracker,
+    muxer: &mut Option<MkvMuxer>,
+    writer: &mut W,
+) where
+    W: AsyncWrite + Unpin,
+{
+    // the encoder keeps going until it's finished, the ring buffer and the headers only stop changing after that,
+    // the clone still gets to read them
+    let finished = recorder.clone();
+    let tail = recorder.finish().await.unwrap();
+
+    let remaining = finished.data_buffer().await;
+    headers.update(finished.headers_since());
+    write_chunks(&remaining, &mut last_chunk_id, &mut headers, muxer, writer).await;
+
+    let (id_min, id_max) = remaining.id_bounds();
+    let last_pts = remaining.range(id_min, id_max).last().map_or(0, |chunk| chunk.metadata().pts);
+    if let Err(e) = write_tail(&tail, last_pts, &mut headers, muxer, writer).await {
+        eprintln!("error: couldn't write the recording: {e}");
+        process::exit(1);
+    }
 
-    file_buf.write_all(recorder.headers()).unwrap();
-    
-    let mut loop_helper = LoopHelper::builder().report_interval_s(1.0).build_without_target_rate();
+    writer.flush().await.unwrap();
+}
 
-    while start_time.elapsed() < Duration::from_secs(60) {
-        loop_helper.loop_start();
-        
-        if let Some(fps) = loop_helper.report_rate() {
-            dbg!(fps * (BUFFERED_FRAMES + 1) as f64 );
+/// `drain_to_writer`, except losing chunks only gets a warning and the recording carries on after the gap
+async fn write_chunks<W>(
+    buf: &RingBuffer<Metadata>,
+    last_id: &mut FrameId,
+    headers: &mut HeaderTracker,
+    muxer: &mut Option<MkvMuxer>,
+    writer: &mut W,
+) where
+    W: AsyncWrite + Unpin,
+{
+    loop {
+        match drain_to_writer(buf, last_id, headers, muxer, writer).await {
+            Ok(()) => return,
+            Err(DrainError::Lag(e)) => {
+                eprintln!("warning: the file can't keep up with the encoder, the recording has a gap: {e}");
+            }
+            Err(DrainError::Io(e)) => {
+                eprintln!("error: couldn't write the recording: {e}");
+                process::exit(1);
+            }
         }
-        
-        recorder.block_until_next_flush().unwrap();
-        loop_helper.loop_sleep();
+    }
+}
 
-        let data_buf = recorder.data_buffer().unwrap();
-        let (id_min, id_max) = data_buf.id_bounds();
+#[cfg(test)]
+mod tests {
+    use screen_cap::testing::{synthetic_source, test_buffering_settings, test_encoder_settings};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn the_tail_of_the_recording_gets_written() {
+        let source = synthetic_source(16, 8, usize::MAX);
+        let recorder = Recorder::with_source(source, test_buffering_settings(), test_encoder_settings()).unwrap();
+        // a frame every couple of milliseconds, for as long as the recorder keeps asking
+        recorder.set_capture_rate(500.0);
+        let recorder = RecorderAsyncAdapter::new(recorder);
+        // keeps the stats around once the recorder is finished
+        let monitor = recorder.clone();
+
+        let (first_headers, first_id) = recorder.headers_since();
+        let mut headers = HeaderTracker::new(first_id);
+        let mut last_chunk_id = FrameId::default();
+        let mut output = first_headers.to_vec();
+
+        while last_chunk_id < FrameId::new(3) {
+            recorder.wait_for_next_flush().await.unwrap();
+
+            let data_buf = recorder.data_buffer().await;
+            headers.update(recorder.headers_since());
+            write_chunks(&data_buf, &mut last_chunk_id, &mut headers, &mut None, &mut output).await;
+        }
+        let written_in_loop = output.len();
 
-        let start_id = id_min.max(last_chunk_id);
+        // the source carries on in the meantime
+        recorder.wait_for_next_flush().await.unwrap();
 
-        for i in start_id..id_max {
-            let frame = data_buf.get(i).unwrap();
-            file_buf.write_all(frame.data()).unwrap();
-        }
+        finish_recording(recorder, last_chunk_id, headers, &mut None, &mut output).await;
 
-        last_chunk_id = id_max;
-        
+        assert!(output.len() > written_in_loop);
+        assert_eq!(output.len() as u64, first_headers.len() as u64 + monitor.stats().bytes_flushed);
     }
-    file_buf.flush().unwrap();
 }
@@ -0,0 +1,196 @@
+use std::{
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use futures::Future;
+use hyper::{header, Request, Response, StatusCode};
+use tower::{Layer, Service};
+
+use super::recordings;
+
+/// Turns away websocket upgrades and recording downloads that don't carry the right token,
+/// everything else goes through untouched.
+///
+/// The token is either in the query, `?token=<token>`, or in an `Authorization: Bearer <token>` header.
+/// Browsers can't set headers on a websocket or a `<video>` source, so the query is the way to go from a page.
+/// The query isn't percent-decoded, so the token should stick to URL-safe characters.
+#[derive(Debug, Clone)]
+pub struct TokenAuth<S> {
+    inner: S,
+    token: Option<Arc<str>>,
+}
+
+impl<S, B> Service<Request<B>> for TokenAuth<S>
+where
+    S: Service<Request<B>, Response = Response<B>>,
+    S::Future: Send + 'static,
+    B: Default + Send + 'static,
+{
+    type Response = S::Response;
+
+    type Error = S::Error;
+
+    type Future = Pin<Box<dyn Future<Output = Result<S::Response, S::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<B>) -> Self::Future {
+        // both give away what's on the screen, unlike the page itself
+        let protected = hyper_tungstenite::is_upgrade_request(&req)
+            || req.uri().path().starts_with(recordings::PATH_PREFIX);
+
+        let authorized = match &self.token {
+            Some(token) if protected => request_token(&req)
+                .is_some_and(|given| constant_time_eq(given.as_bytes(), token.as_bytes())),
+            _ => true,
+        };
+
+        if authorized {
+            return Box::pin(self.inner.call(req));
+        }
+
+        let response = Response::builder()
+            .status(StatusCode::UNAUTHORIZED)
+            .header(header::WWW_AUTHENTICATE, "Bearer")
+            .body(B::default())
+            .unwrap();
+
+        Box::pin(async { Ok(response) })
+    }
+}
+
+/// See `TokenAuth`, a `None` token lets everyone in
+#[derive(Debug, Clone)]
+pub struct TokenAuthLayer {
+    token: Option<Arc<str>>,
+}
+
+impl TokenAuthLayer {
+    pub fn new(token: Option<&str>) -> Self {
+        Self {
+            token: token.map(Arc::from),
+        }
+    }
+}
+
+impl<S> Layer<S> for TokenAuthLayer {
+    type Service = TokenAuth<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        TokenAuth {
+            inner,
+            token: self.token.clone(),
+        }
+    }
+}
+
+// the query parameter wins if both are there
+fn request_token<B>(req: &Request<B>) -> Option<&str> {
+    let from_query = req.uri().query().and_then(|query| {
+        query
+            .split('&')
+            .find_map(|pair| pair.strip_prefix("token="))
+    });
+
+    from_query.or_else(|| {
+        req.headers()
+            .get(header::AUTHORIZATION)?
+            .to_str()
+            .ok()?
+            .strip_prefix("Bearer ")
+    })
+}
+
+// takes as long for a token that's almost right as for one that's completely wrong,
+// only the length can be told apart
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+
+    let difference = a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y));
+    std::hint::black_box(difference) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+
+    use tower::{service_fn, ServiceExt};
+
+    use super::*;
+
+    const TOKEN: &str = "s3cret-token";
+
+    async fn status(req: hyper::http::request::Builder) -> StatusCode {
+        let inner = service_fn(|_req: Request<String>| async {
+            Ok::<_, Infallible>(Response::new(String::new()))
+        });
+
+        let svc = TokenAuthLayer::new(Some(TOKEN)).layer(inner);
+        svc.oneshot(req.body(String::new()).unwrap())
+            .await
+            .unwrap()
+            .status()
+    }
+
+    fn upgrade_request(uri: &str) -> hyper::http::request::Builder {
+        Request::get(uri)
+            .header(header::CONNECTION, "Upgrade")
+            .header(header::UPGRADE, "websocket")
+    }
+
+    #[test]
+    fn token_comparison() {
+        assert!(constant_time_eq(b"abc", b"abc"));
+        assert!(!constant_time_eq(b"abc", b"abd"));
+        assert!(!constant_time_eq(b"abc", b"abcd"));
+    }
+
+    #[tokio::test]
+    async fn accepted_upgrade() {
+        let from_query = upgrade_request("/websocket?quality=high&token=s3cret-token");
+        assert_eq!(status(from_query).await, StatusCode::OK);
+
+        let from_header =
+            upgrade_request("/websocket").header(header::AUTHORIZATION, "Bearer s3cret-token");
+        assert_eq!(status(from_header).await, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn rejected_upgrade() {
+        let wrong_query = upgrade_request("/websocket?token=s3cret-tokem");
+        assert_eq!(status(wrong_query).await, StatusCode::UNAUTHORIZED);
+
+        let wrong_header =
+            upgrade_request("/websocket").header(header::AUTHORIZATION, "Bearer nope");
+        assert_eq!(status(wrong_header).await, StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn missing_token() {
+        assert_eq!(
+            status(upgrade_request("/websocket")).await,
+            StatusCode::UNAUTHORIZED
+        );
+
+        // the page itself doesn't need one
+        assert_eq!(status(Request::get("/index.html")).await, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn recordings_need_the_token() {
+        assert_eq!(
+            status(Request::get("/recordings/session.mkv")).await,
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            status(Request::get("/recordings/session.mkv?token=s3cret-token")).await,
+            StatusCode::OK
+        );
+    }
+}
@@ -0,0 +1,123 @@
+use std::sync::Arc;
+
+use bytes::Bytes;
+use hyper::body::Sender;
+use screen_cap::record::{encoded_buffer::BootstrapInfo, RecordError};
+use utils::contiguous::FrameId;
+
+use crate::async_adapter::RecorderAsyncAdapter;
+
+/// Streams the encoded frames into a hyper `Body` with back-pressure.
+///
+/// Sending waits for the body to have capacity instead of buffering,
+/// so a client that doesn't keep up falls behind the encoder.
+/// Once it's too far behind, or the frames it needs got evicted from the ring buffer,
+/// it gets skipped ahead to the latest keyframe without sending anything in between.
+#[derive(Debug)]
+pub struct FrameBodySink {
+    sender: Sender,
+    recorder: RecorderAsyncAdapter,
+    next_id: Option<FrameId>,
+    max_lag: usize,
+    // the id the headers the client has gotten last apply from
+    headers_since: FrameId,
+    // headers that go out before the first chunk from that id on
+    new_headers: Option<(FrameId, Arc<[u8]>)>,
+}
+
+impl FrameBodySink {
+    const DEFAULT_MAX_LAG: usize = 60;
+
+    pub fn new(sender: Sender, recorder: RecorderAsyncAdapter) -> Self {
+        Self {
+            sender,
+            recorder,
+            next_id: None,
+            max_lag: Self::DEFAULT_MAX_LAG,
+            headers_since: FrameId::default(),
+            new_headers: None,
+        }
+    }
+
+    /// How many frames the client is allowed to fall behind before it gets skipped ahead to a keyframe
+    pub fn with_max_lag(mut self, max_lag: usize) -> Self {
+        self.max_lag = max_lag;
+        self
+    }
+
+    /// The id of the next frame that is going to be sent, `None` if nothing has been sent yet
+    #[inline]
+    pub fn next_id(&self) -> Option<FrameId> {
+        self.next_id
+    }
+
+    /// Streams frames until the client disconnects.
+    pub async fn run(mut self) -> Result<(), Arc<RecordError>> {
+        // so the client doesn't have to wait for the encoder to emit one on its own
+        self.recorder.request_keyframe();
+
+        let (headers, headers_since) = self.recorder.headers_since();
+        self.headers_since = headers_since;
+        if self.sender.send_data(Bytes::copy_from_slice(&headers)).await.is_err() {
+            return Ok(());
+        }
+
+        loop {
+            self.recorder.wait_for_next_flush().await?;
+
+            for chunk in self.collect_new_chunks().await {
+                if self.sender.send_data(chunk).await.is_err() {
+                    // the client has disconnected
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    // copies the chunks out so the buffer isn't locked while waiting on the client
+    async fn collect_new_chunks(&mut self) -> Vec<Bytes> {
+        let buf = self.recorder.data_buffer().await;
+        let info = BootstrapInfo::from_buffer(&buf);
+
+        // the encoder got rebuilt with a different config, read after locking the buffer so the chunks can't be ahead
+        let (headers, first_id) = self.recorder.headers_since();
+        if first_id > self.headers_since {
+            self.headers_since = first_id;
+            self.new_headers = Some((first_id, headers));
+        }
+
+        let start_id = match self.next_id {
+            Some(id) if id >= info.min_id => {
+                let lagging = info.max_id - id > self.max_lag;
+
+                match info.latest_keyframe {
+                    Some(keyframe) if lagging && keyframe > id => keyframe,
+                    _ => id,
+                }
+            }
+            // either a fresh start or the frames were evicted,
+            // a decoder can only pick up the stream at a keyframe
+            _ => match info.latest_keyframe {
+                Some(keyframe) => keyframe,
+                None => {
+                    self.next_id = None;
+                    return Vec::new();
+                }
+            },
+        };
+
+        self.next_id = Some(info.max_id);
+
+        let mut chunks = Vec::new();
+        for (id, item) in FrameId::range(start_id, info.max_id).zip(buf.range(start_id, info.max_id)) {
+            if self.new_headers.as_ref().is_some_and(|(first_id, _)| id >= *first_id) {
+                let (_, headers) = self.new_headers.take().unwrap();
+                chunks.push(Bytes::copy_from_slice(&headers));
+            }
+
+            chunks.push(Bytes::copy_from_slice(&item.data()));
+        }
+
+        chunks
+    }
+}
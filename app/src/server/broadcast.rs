@@ -0,0 +1,895 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use parking_lot::Mutex;
+use screen_cap::record::encoded_buffer::{BootstrapInfo, EncodedBufferView, Metadata};
+use tokio::{
+    runtime,
+    sync::{
+        broadcast::{self, error::RecvError},
+        watch, Notify,
+    },
+    time,
+};
+use utils::contiguous::FrameId;
+
+use crate::async_adapter::RecorderAsyncAdapter;
+
+/// Feeds any number of clients from a single recorder.
+///
+/// Every flushed chunk id gets published to all the subscribers,
+/// which then read the chunks themselves straight from the ring buffer.
+#[derive(Debug, Clone)]
+pub struct BroadcastHub {
+    recorder: RecorderAsyncAdapter,
+    chunk_tx: broadcast::Sender<FrameId>,
+    // true once the clients should disconnect
+    closing_tx: Arc<watch::Sender<bool>>,
+    clients: Arc<ClientRegistry>,
+}
+
+#[derive(Debug, Default)]
+struct ClientRegistry {
+    queues: Mutex<HashMap<u64, Arc<ClientQueue>>>,
+    next_id: AtomicU64,
+    // only for a hub that pauses the recorder while nobody's connected
+    idle: Option<Arc<IdlePause>>,
+}
+
+// pauses the recorder once the last client has been gone for a while, see `BroadcastHub::with_idle_grace`
+#[derive(Debug)]
+struct IdlePause {
+    recorder: RecorderAsyncAdapter,
+    grace: Duration,
+    // the clients go away in `Drop`, which might not be running on the runtime
+    runtime: runtime::Handle,
+    state: Mutex<IdleState>,
+}
+
+#[derive(Debug, Default)]
+struct IdleState {
+    recording: bool,
+    // bumped whenever a client connects or the last one leaves,
+    // a pause only goes through if nothing happened since it was scheduled
+    generation: u64,
+}
+
+impl IdlePause {
+    // both get called with the queues locked, so they can't race each other
+    fn client_connected(&self) {
+        let mut state = self.state.lock();
+        state.generation += 1;
+
+        if !state.recording {
+            self.recorder.resume_recording();
+            state.recording = true;
+        }
+    }
+
+    fn last_client_gone(self: &Arc<Self>) {
+        let generation = {
+            let mut state = self.state.lock();
+            state.generation += 1;
+            state.generation
+        };
+
+        let this = self.clone();
+        self.runtime.spawn(async move {
+            time::sleep(this.grace).await;
+
+            let mut state = this.state.lock();
+            if state.generation == generation && state.recording {
+                this.recorder.pause_recording();
+                state.recording = false;
+            }
+        });
+    }
+}
+
+/// How a connected client is doing, see `BroadcastHub::client_stats`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClientStats {
+    pub id: u64,
+    /// Chunks that never went out to the client because its queue overflowed
+    pub dropped_chunks: u64,
+}
+
+impl BroadcastHub {
+    // a subscriber falling this far behind doesn't lose anything as long as the chunks are still in the buffer
+    const CHANNEL_CAPACITY: usize = 256;
+    /// How many chunks can wait to be sent to a single client before it gets resynced
+    pub const CLIENT_QUEUE_CAPACITY: usize = 128;
+
+    /// Spawns the task publishing the chunk ids, so it has to be called from within a tokio runtime.
+    pub fn new(recorder: RecorderAsyncAdapter) -> Self {
+        let (chunk_tx, _) = broadcast::channel(Self::CHANNEL_CAPACITY);
+
+        tokio::spawn(publish_chunks(recorder.clone(), chunk_tx.clone()));
+
+        let (closing_tx, _) = watch::channel(false);
+
+        Self {
+            recorder,
+            chunk_tx,
+            closing_tx: Arc::new(closing_tx),
+            clients: Arc::default(),
+        }
+    }
+
+    /// Like `new`, except the recorder only runs while there are clients to watch it.
+    ///
+    /// The recorder gets paused right away and resumed when the first client registers.
+    /// Once the last client is gone, it's paused again after `idle_grace`, unless another client shows up first.
+    /// A paused recorder keeps its ring buffer and its encoder, so the subscriptions carry on once it resumes.
+    pub fn with_idle_grace(recorder: RecorderAsyncAdapter, idle_grace: Duration) -> Self {
+        recorder.pause_recording();
+
+        let idle = IdlePause {
+            recorder: recorder.clone(),
+            grace: idle_grace,
+            runtime: runtime::Handle::current(),
+            state: Mutex::default(),
+        };
+        let clients = ClientRegistry {
+            idle: Some(Arc::new(idle)),
+            ..ClientRegistry::default()
+        };
+
+        Self {
+            clients: Arc::new(clients),
+            ..Self::new(recorder)
+        }
+    }
+
+    /// Keeps track of a connected client, it should hold on to the handle until it has disconnected
+    pub fn register_client(&self) -> ClientHandle {
+        let id = self.clients.next_id.fetch_add(1, Ordering::Relaxed);
+        let queue = Arc::new(ClientQueue::new(Self::CLIENT_QUEUE_CAPACITY));
+
+        let mut queues = self.clients.queues.lock();
+        queues.insert(id, queue.clone());
+        if let Some(idle) = &self.clients.idle {
+            idle.client_connected();
+        }
+        drop(queues);
+
+        ClientHandle {
+            id,
+            queue,
+            closing_rx: self.closing_tx.subscribe(),
+            clients: self.clients.clone(),
+        }
+    }
+
+    /// The currently connected clients, ordered by id
+    pub fn client_stats(&self) -> Vec<ClientStats> {
+        let mut stats: Vec<_> = self
+            .clients
+            .queues
+            .lock()
+            .iter()
+            .map(|(&id, queue)| ClientStats {
+                id,
+                dropped_chunks: queue.dropped_chunks(),
+            })
+            .collect();
+        stats.sort_unstable_by_key(|stats| stats.id);
+
+        stats
+    }
+
+    /// Whether the recorder is running, always the case unless the hub was made `with_idle_grace`
+    pub fn is_recording(&self) -> bool {
+        self.clients.idle.as_ref().is_none_or(|idle| idle.state.lock().recording)
+    }
+
+    /// Tells every client to disconnect and waits until all of their handles are dropped.
+    ///
+    /// Clients registering after this get told to disconnect right away.
+    pub async fn close_all(&self) {
+        self.closing_tx.send_replace(true);
+        self.closing_tx.closed().await;
+    }
+
+    /// The subscription starts at the latest keyframe in the buffer
+    pub fn subscribe(&self) -> Subscription {
+        Subscription::new(self.chunk_tx.subscribe(), self.recorder.buffer_view())
+    }
+
+    /// The recorder the clients are fed from
+    #[inline]
+    pub fn recorder(&self) -> &RecorderAsyncAdapter {
+        &self.recorder
+    }
+
+    /// See `RecorderAsyncAdapter::headers`
+    pub fn headers(&self) -> Arc<[u8]> {
+        self.recorder.headers()
+    }
+
+    /// See `RecorderAsyncAdapter::headers_since`
+    pub fn headers_since(&self) -> (Arc<[u8]>, FrameId) {
+        self.recorder.headers_since()
+    }
+
+    /// See `RecorderAsyncAdapter::request_keyframe`
+    pub fn request_keyframe(&self) {
+        self.recorder.request_keyframe();
+    }
+}
+
+// publishes every chunk that gets flushed until the recorder fails
+async fn publish_chunks(recorder: RecorderAsyncAdapter, chunk_tx: broadcast::Sender<FrameId>) {
+    let view = recorder.buffer_view();
+    let (_, mut next_id) = view.get().id_bounds();
+
+    while recorder.wait_for_next_flush().await.is_ok() {
+        let (_, max_id) = view.get().id_bounds();
+
+        for id in FrameId::range(next_id, max_id) {
+            // no subscribers at the moment, that's fine
+            let _ = chunk_tx.send(id);
+        }
+
+        next_id = max_id;
+    }
+}
+
+/// A client connected to a `BroadcastHub`, see `BroadcastHub::register_client`
+#[derive(Debug)]
+pub struct ClientHandle {
+    id: u64,
+    queue: Arc<ClientQueue>,
+    closing_rx: watch::Receiver<bool>,
+    clients: Arc<ClientRegistry>,
+}
+
+impl ClientHandle {
+    #[inline]
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Where the chunks for this client wait until they can be sent
+    pub fn queue(&self) -> Arc<ClientQueue> {
+        self.queue.clone()
+    }
+
+    /// Resolves once the hub wants the client to disconnect
+    pub async fn closing(&mut self) {
+        while !*self.closing_rx.borrow_and_update() {
+            // the hub is gone, nobody is going to ask anymore
+            if self.closing_rx.changed().await.is_err() {
+                std::future::pending::<()>().await;
+            }
+        }
+    }
+}
+
+impl Drop for ClientHandle {
+    fn drop(&mut self) {
+        let mut queues = self.clients.queues.lock();
+        queues.remove(&self.id);
+
+        if let Some(idle) = self.clients.idle.as_ref().filter(|_| queues.is_empty()) {
+            idle.last_client_gone();
+        }
+    }
+}
+
+/// What goes out to a client next, see `ClientQueue::pop`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outgoing {
+    /// The chunks after this don't continue from the ones the client got before, the first one has this id
+    Resync(FrameId),
+    /// The CRC-32 of the chunk that comes right after, if the recorder computes them
+    Checksum(u32),
+    Chunk(Vec<u8>),
+    /// New SPS/PPS headers, the chunks that come after this need them to be decoded
+    Headers(Vec<u8>),
+    /// A keepalive ping, it skips ahead of the chunks
+    Ping,
+}
+
+#[derive(Debug)]
+struct QueuedChunk {
+    id: FrameId,
+    data: Vec<u8>,
+    is_key: bool,
+    // taken once it's been sent
+    crc32: Option<u32>,
+}
+
+#[derive(Debug, Default)]
+struct QueueState {
+    chunks: VecDeque<QueuedChunk>,
+    // the id the client thinks the next chunk has
+    expected_id: Option<FrameId>,
+    // the queue overflowed, nothing gets queued until the next keyframe
+    needs_resync: bool,
+    // headers that go out before the first chunk from that id on
+    new_headers: Option<(FrameId, Vec<u8>)>,
+    ping_pending: bool,
+    closed: bool,
+}
+
+/// A single client's chunks on their way out, so a slow connection doesn't hold up the rest.
+///
+/// The queue is bounded, once it overflows every non-keyframe chunk in it gets dropped
+/// and nothing new is queued until the next keyframe, since the client couldn't decode it anyway.
+/// It's the same thing the ring buffer does when it evicts chunks a subscriber hasn't read yet,
+/// but for a single client.
+#[derive(Debug)]
+pub struct ClientQueue {
+    state: Mutex<QueueState>,
+    notify: Notify,
+    capacity: usize,
+    dropped: AtomicU64,
+}
+
+impl ClientQueue {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            state: Mutex::default(),
+            notify: Notify::new(),
+            capacity: capacity.max(1),
+            dropped: AtomicU64::new(0),
+        }
+    }
+
+    /// Queues the chunks, returns whether the client has to wait for a new keyframe,
+    /// in which case it's best to ask the encoder for one.
+    pub fn push(&self, chunks: Chunks) -> bool {
+        let mut state = self.state.lock();
+        let mut dropped = 0;
+
+        let ids = FrameId::range(chunks.start_id, chunks.start_id + chunks.data.len());
+        for ((id, data), metadata) in ids.zip(chunks.data).zip(chunks.metadata) {
+            if state.needs_resync && !metadata.is_key {
+                dropped += 1;
+                continue;
+            }
+            state.needs_resync = false;
+
+            state.chunks.push_back(QueuedChunk {
+                id,
+                data,
+                is_key: metadata.is_key,
+                crc32: metadata.crc32,
+            });
+
+            if state.chunks.len() > self.capacity {
+                let len = state.chunks.len();
+                state.chunks.retain(|chunk| chunk.is_key);
+                // in case it's all keyframes
+                let excess = state.chunks.len().saturating_sub(self.capacity);
+                state.chunks.drain(..excess);
+
+                dropped += len - state.chunks.len();
+                // whatever comes next depends on the chunks that just got dropped
+                state.needs_resync = !metadata.is_key;
+            }
+        }
+
+        self.dropped.fetch_add(dropped as u64, Ordering::Relaxed);
+        self.notify.notify_one();
+
+        state.needs_resync
+    }
+
+    /// Drops whatever is queued, the client expects the next chunk to have `expected_id`
+    /// and gets a resync if it doesn't, see `Subscription::resume_from`.
+    pub fn resume(&self, expected_id: Option<FrameId>) {
+        let mut state = self.state.lock();
+        state.chunks.clear();
+        state.expected_id = expected_id;
+        state.needs_resync = false;
+    }
+
+    /// Sends `headers` before the first chunk with an id from `first_id` on, see `SharedHeaders::get_since`.
+    ///
+    /// Replaces headers that haven't gone out yet, the chunks that needed them can't be decoded without these anyway.
+    pub fn push_headers(&self, headers: Vec<u8>, first_id: FrameId) {
+        self.state.lock().new_headers = Some((first_id, headers));
+        self.notify.notify_one();
+    }
+
+    /// Sends a ping before whatever is queued, pings that haven't gone out yet get merged
+    pub fn ping(&self) {
+        self.state.lock().ping_pending = true;
+        self.notify.notify_one();
+    }
+
+    /// Whatever is queued still goes out, after that `pop` returns `None`
+    pub fn close(&self) {
+        self.state.lock().closed = true;
+        self.notify.notify_one();
+    }
+
+    /// Waits for the next thing to send, `None` once the queue is closed and empty
+    pub async fn pop(&self) -> Option<Outgoing> {
+        loop {
+            {
+                let mut state = self.state.lock();
+
+                if std::mem::take(&mut state.ping_pending) {
+                    return Some(Outgoing::Ping);
+                }
+
+                if let Some(id) = state.chunks.front().map(|chunk| chunk.id) {
+                    if state.expected_id != Some(id) {
+                        state.expected_id = Some(id);
+                        return Some(Outgoing::Resync(id));
+                    }
+
+                    if state.new_headers.as_ref().is_some_and(|(first_id, _)| id >= *first_id) {
+                        let (_, headers) = state.new_headers.take()?;
+                        return Some(Outgoing::Headers(headers));
+                    }
+
+                    if let Some(crc) = state.chunks.front_mut().and_then(|chunk| chunk.crc32.take()) {
+                        return Some(Outgoing::Checksum(crc));
+                    }
+
+                    let chunk = state.chunks.pop_front()?;
+                    state.expected_id = Some(chunk.id + 1);
+                    return Some(Outgoing::Chunk(chunk.data));
+                }
+
+                if state.closed {
+                    return None;
+                }
+            }
+
+            // the permit is kept if the push happens before this is reached
+            self.notify.notified().await;
+        }
+    }
+
+    /// How many chunks have been dropped in total
+    #[inline]
+    pub fn dropped_chunks(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+/// The chunks a subscriber got since the last time
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Chunks {
+    /// The chunks the subscriber needed got evicted, so it got skipped ahead to a keyframe.
+    ///
+    /// Whatever the client was decoding before doesn't continue into these chunks.
+    pub resynced: bool,
+    /// The id of the first chunk in `data`, the rest follow in order
+    pub start_id: FrameId,
+    pub data: Vec<Vec<u8>>,
+    /// The metadata of each chunk in `data`
+    pub metadata: Vec<Metadata>,
+}
+
+/// What `Subscription::resume_from` ended up doing
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResumeOutcome {
+    /// The chunks are still there, the subscription continues from the requested id
+    Resumed,
+    /// The requested chunk is gone, the subscription continues from the latest keyframe instead,
+    /// `None` if there isn't one in the buffer yet and it waits for the next one
+    Resynced { keyframe: Option<FrameId> },
+}
+
+/// A single client's position in the broadcast
+#[derive(Debug)]
+pub struct Subscription {
+    chunk_rx: broadcast::Receiver<FrameId>,
+    view: EncodedBufferView,
+    next_id: Option<FrameId>,
+}
+
+impl Subscription {
+    fn new(chunk_rx: broadcast::Receiver<FrameId>, view: EncodedBufferView) -> Self {
+        Self {
+            chunk_rx,
+            view,
+            next_id: None,
+        }
+    }
+
+    /// The id of the next chunk that is going to be returned, `None` if nothing has been returned yet
+    #[inline]
+    pub fn next_id(&self) -> Option<FrameId> {
+        self.next_id
+    }
+
+    /// Makes the subscription continue from `id`, e.g. for a client that reconnected after losing the connection.
+    ///
+    /// If `id` has been evicted already, or isn't in the buffer for some other reason,
+    /// the subscription skips to the latest keyframe instead.
+    pub fn resume_from(&mut self, id: FrameId) -> ResumeOutcome {
+        let info = self.view.bootstrap_info();
+
+        if (info.min_id..=info.max_id).contains(&id) {
+            self.next_id = Some(id);
+            return ResumeOutcome::Resumed;
+        }
+
+        // a `None` makes the next call to `next_chunks` wait for a keyframe
+        self.next_id = info.latest_keyframe;
+
+        ResumeOutcome::Resynced {
+            keyframe: info.latest_keyframe,
+        }
+    }
+
+    /// Waits for new chunks and returns all of them,
+    /// the first call returns everything since the latest keyframe right away.
+    ///
+    /// Returns `None` once the hub stops publishing.
+    pub async fn next_chunks(&mut self) -> Option<Chunks> {
+        loop {
+            if let Some(chunks) = self.collect_new_chunks() {
+                return Some(chunks);
+            }
+
+            match self.chunk_rx.recv().await {
+                // the ids are only a wakeup, the chunks are read from the buffer,
+                // so missing some of them doesn't matter as long as the chunks are still there
+                Ok(_) | Err(RecvError::Lagged(_)) => (),
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    }
+
+    // copies the chunks out so the buffer isn't locked while waiting on the client
+    fn collect_new_chunks(&mut self) -> Option<Chunks> {
+        let buf = self.view.get();
+        let info = BootstrapInfo::from_buffer(&buf);
+
+        let (start_id, resynced) = match self.next_id {
+            Some(id) if id >= info.min_id => (id, false),
+            // either a fresh start or the chunks were evicted,
+            // a decoder can only pick up the stream at a keyframe
+            stale => (info.latest_keyframe?, stale.is_some()),
+        };
+
+        if start_id >= info.max_id {
+            return None;
+        }
+
+        self.next_id = Some(info.max_id);
+
+        let (data, metadata) = buf
+            .range(start_id, info.max_id)
+            .map(|item| (item.data().into_owned(), *item.metadata()))
+            .unzip();
+
+        Some(Chunks {
+            resynced,
+            start_id,
+            data,
+            metadata,
+        })
+    }
+}
+
+/// Parses the control message a client sends to pick up where it left off, `{"resume_from": <id>}`
+pub fn parse_resume_from(message: &str) -> Option<FrameId> {
+    let fields = message.trim().strip_prefix('{')?.strip_suffix('}')?;
+    let (key, value) = fields.split_once(':')?;
+
+    if key.trim() != "\"resume_from\"" {
+        return None;
+    }
+
+    value.trim().parse().ok().map(FrameId::new)
+}
+
+/// The reply telling a client that the chunks after it start at `id`, `{"resync": <id>}`
+pub fn resync_message(id: FrameId) -> String {
+    format!("{{\"resync\": {id}}}")
+}
+
+/// Sent right before a chunk when the recorder computes checksums, `{"crc32": <crc>}`
+pub fn checksum_message(crc: u32) -> String {
+    format!("{{\"crc32\": {crc}}}")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc;
+
+    use futures::FutureExt;
+    use screen_cap::record::{encoded_buffer::EncodedBuffer, SharedHeaders, SharedVideoInfo};
+
+    use super::*;
+    use crate::async_adapter::RecorderMessage;
+
+    fn publish(
+        buf: &mut EncodedBuffer,
+        tx: &broadcast::Sender<FrameId>,
+        data: &[u8],
+        is_key: bool,
+    ) {
+        let id = buf.write_flush(data, Metadata::new(is_key, 0)).unwrap();
+        // nobody might be subscribed yet
+        let _ = tx.send(id);
+    }
+
+    #[tokio::test]
+    async fn subscribers_get_the_same_chunks() {
+        let mut buf = EncodedBuffer::new(1024);
+        let (tx, _) = broadcast::channel(16);
+
+        // the first one starts at the keyframe, not before it
+        publish(&mut buf, &tx, &[0], false);
+        publish(&mut buf, &tx, &[1], true);
+
+        let mut first = Subscription::new(tx.subscribe(), buf.view());
+        let mut second = Subscription::new(tx.subscribe(), buf.view());
+
+        publish(&mut buf, &tx, &[2], false);
+        publish(&mut buf, &tx, &[3], false);
+
+        let expected = Chunks {
+            resynced: false,
+            start_id: FrameId::new(1),
+            data: vec![vec![1], vec![2], vec![3]],
+            metadata: [true, false, false]
+                .map(|is_key| Metadata::new(is_key, 0))
+                .to_vec(),
+        };
+        assert_eq!(first.next_chunks().await, Some(expected.clone()));
+        assert_eq!(second.next_chunks().await, Some(expected));
+
+        publish(&mut buf, &tx, &[4], true);
+        drop(tx);
+
+        for subscription in [&mut first, &mut second] {
+            let chunks = subscription.next_chunks().await.unwrap();
+            assert_eq!(chunks.data, [vec![4]]);
+
+            assert_eq!(subscription.next_chunks().await, None);
+        }
+    }
+
+    #[tokio::test]
+    async fn evicted_subscriber_resyncs() {
+        let mut buf = EncodedBuffer::new(16);
+        let (tx, _) = broadcast::channel(16);
+
+        publish(&mut buf, &tx, &[0; 4], true);
+        let mut subscription = Subscription::new(tx.subscribe(), buf.view());
+        assert_eq!(subscription.next_chunks().await.unwrap().data.len(), 1);
+
+        // pushes everything the subscriber hasn't read yet out of the buffer
+        publish(&mut buf, &tx, &[1; 4], false);
+        publish(&mut buf, &tx, &[2; 4], true);
+        for _ in 0..3 {
+            publish(&mut buf, &tx, &[3; 4], false);
+        }
+
+        let chunks = subscription.next_chunks().await.unwrap();
+        assert!(chunks.resynced);
+        assert_eq!(chunks.data[0], [2; 4]);
+        assert_eq!(subscription.next_id(), Some(FrameId::new(6)));
+    }
+
+    #[test]
+    fn resume_control_messages() {
+        assert_eq!(
+            parse_resume_from(r#"{"resume_from": 12345}"#),
+            Some(FrameId::new(12345))
+        );
+        assert_eq!(
+            parse_resume_from(r#" {"resume_from":7} "#),
+            Some(FrameId::new(7))
+        );
+        assert_eq!(parse_resume_from(r#"{"resume_from": -1}"#), None);
+        assert_eq!(parse_resume_from(r#"{"resync": 1}"#), None);
+        assert_eq!(parse_resume_from("resume_from"), None);
+
+        assert_eq!(resync_message(FrameId::new(42)), r#"{"resync": 42}"#);
+    }
+
+    #[tokio::test]
+    async fn resume_from_buffered_chunk() {
+        let mut buf = EncodedBuffer::new(1024);
+        let (tx, _) = broadcast::channel(16);
+
+        for i in 0..4 {
+            publish(&mut buf, &tx, &[i], i == 0);
+        }
+
+        let mut subscription = Subscription::new(tx.subscribe(), buf.view());
+        let message = parse_resume_from(r#"{"resume_from": 2}"#).unwrap();
+
+        assert_eq!(subscription.resume_from(message), ResumeOutcome::Resumed);
+
+        let chunks = subscription.next_chunks().await.unwrap();
+        assert_eq!(chunks.start_id, FrameId::new(2));
+        assert_eq!(chunks.data, [vec![2], vec![3]]);
+    }
+
+    #[tokio::test]
+    async fn resume_from_evicted_chunk() {
+        // room for 4 chunks
+        let mut buf = EncodedBuffer::new(16);
+        let (tx, _) = broadcast::channel(16);
+
+        for i in 0..6 {
+            publish(&mut buf, &tx, &[i; 4], i % 3 == 0);
+        }
+        assert_eq!(buf.view().get().id_bounds().0, FrameId::new(2));
+
+        let mut subscription = Subscription::new(tx.subscribe(), buf.view());
+        let message = parse_resume_from(r#"{"resume_from": 1}"#).unwrap();
+
+        let keyframe = FrameId::new(3);
+        assert_eq!(
+            subscription.resume_from(message),
+            ResumeOutcome::Resynced {
+                keyframe: Some(keyframe)
+            }
+        );
+
+        let chunks = subscription.next_chunks().await.unwrap();
+        assert_eq!(chunks.start_id, keyframe);
+        assert_eq!(chunks.data[0], [3; 4]);
+    }
+
+    #[tokio::test]
+    async fn slow_client_resyncs_fast_client_loses_nothing() {
+        let mut buf = EncodedBuffer::new(1024);
+        let (tx, _) = broadcast::channel(64);
+
+        let fast_queue = ClientQueue::new(4);
+        let slow_queue = ClientQueue::new(4);
+        let mut fast = Subscription::new(tx.subscribe(), buf.view());
+        let mut slow = Subscription::new(tx.subscribe(), buf.view());
+
+        let mut fast_received = Vec::new();
+
+        // a keyframe every 4 chunks, the fast client sends out everything right away
+        // while the slow one doesn't get to send anything until the end
+        for i in 0..14 {
+            publish(&mut buf, &tx, &[i], i % 4 == 0);
+
+            fast_queue.push(fast.next_chunks().await.unwrap());
+            slow_queue.push(slow.next_chunks().await.unwrap());
+
+            while let Some(outgoing) = fast_queue.pop().now_or_never().flatten() {
+                if let Outgoing::Chunk(chunk) = outgoing {
+                    fast_received.push(chunk);
+                }
+            }
+        }
+        fast_queue.close();
+        slow_queue.close();
+
+        assert_eq!(fast_received, (0..14).map(|i| vec![i]).collect::<Vec<_>>());
+        assert_eq!(fast_queue.dropped_chunks(), 0);
+
+        let mut slow_sent = Vec::new();
+        while let Some(outgoing) = slow_queue.pop().await {
+            slow_sent.push(outgoing);
+        }
+
+        // only the keyframes survive the overflows, 13 overflows the full queue once more
+        let expected = [0, 4, 8, 12].map(|i| {
+            [
+                Outgoing::Resync(FrameId::new(i)),
+                Outgoing::Chunk(vec![i as u8]),
+            ]
+        });
+        assert_eq!(slow_sent, expected.concat());
+        assert_eq!(slow_queue.dropped_chunks(), 10);
+    }
+
+    #[tokio::test]
+    async fn checksum_goes_out_before_its_chunk() {
+        let mut buf = EncodedBuffer::new(1024).with_checksums();
+        let (tx, _) = broadcast::channel(64);
+        let queue = ClientQueue::new(4);
+        let mut subscription = Subscription::new(tx.subscribe(), buf.view());
+
+        publish(&mut buf, &tx, &[1, 2, 3], true);
+        queue.push(subscription.next_chunks().await.unwrap());
+        queue.close();
+
+        let crc = buf.view().get().iter().next().unwrap().metadata().crc32.unwrap();
+
+        let mut sent = Vec::new();
+        while let Some(outgoing) = queue.pop().await {
+            sent.push(outgoing);
+        }
+
+        assert_eq!(
+            sent,
+            [
+                Outgoing::Resync(FrameId::new(0)),
+                Outgoing::Checksum(crc),
+                Outgoing::Chunk(vec![1, 2, 3]),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn new_headers_go_out_before_their_first_chunk() {
+        let mut buf = EncodedBuffer::new(1024);
+        let (tx, _) = broadcast::channel(64);
+        let queue = ClientQueue::new(8);
+        let mut subscription = Subscription::new(tx.subscribe(), buf.view());
+
+        publish(&mut buf, &tx, &[1], true);
+        queue.push(subscription.next_chunks().await.unwrap());
+        // the encoder got rebuilt, the chunks from id 2 on need these
+        queue.push_headers(vec![9, 9], FrameId::new(2));
+
+        publish(&mut buf, &tx, &[2], false);
+        publish(&mut buf, &tx, &[3], true);
+        queue.push(subscription.next_chunks().await.unwrap());
+        queue.close();
+
+        let mut sent = Vec::new();
+        while let Some(outgoing) = queue.pop().await {
+            sent.push(outgoing);
+        }
+
+        assert_eq!(
+            sent,
+            [
+                Outgoing::Resync(FrameId::new(0)),
+                Outgoing::Chunk(vec![1]),
+                Outgoing::Chunk(vec![2]),
+                Outgoing::Headers(vec![9, 9]),
+                Outgoing::Chunk(vec![3]),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn reconnecting_within_the_grace_period_keeps_recording() {
+        // true for every pause, false for every resume
+        let (paused_tx, paused_rx) = mpsc::channel();
+        let buf = EncodedBuffer::new(16);
+        let recorder = RecorderAsyncAdapter::with_recorder_thread(
+            buf.view(),
+            SharedHeaders::default(),
+            SharedVideoInfo::default(),
+            move |rx, _| {
+                for msg in rx {
+                    match msg {
+                        RecorderMessage::PauseRecording => paused_tx.send(true).unwrap(),
+                        RecorderMessage::ResumeRecording => paused_tx.send(false).unwrap(),
+                        _ => (),
+                    }
+                }
+            },
+        );
+
+        let grace = Duration::from_millis(100);
+        let hub = BroadcastHub::with_idle_grace(recorder, grace);
+        assert!(!hub.is_recording());
+
+        let client = hub.register_client();
+        assert!(hub.is_recording());
+
+        drop(client);
+        time::sleep(grace / 4).await;
+        // back before the grace period is over
+        let client = hub.register_client();
+        time::sleep(grace * 2).await;
+        assert!(hub.is_recording());
+
+        drop(client);
+        time::sleep(grace * 2).await;
+        assert!(!hub.is_recording());
+
+        assert_eq!(paused_rx.try_iter().collect::<Vec<_>>(), [true, false, true]);
+    }
+}
@@ -0,0 +1,107 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+use hyper::{header, Request};
+use hyper_tungstenite::tungstenite::Message;
+
+/// How the headers and the chunks go out on a websocket, chosen by the client when it connects.
+///
+/// Some proxies and clients mangle binary frames, base64 in text frames gets through them
+/// at the cost of about a third more bandwidth, so it's only used when asked for.
+/// The control messages are JSON text frames either way, a base64 chunk never starts with `{`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Framing {
+    #[default]
+    Binary,
+    Base64,
+}
+
+impl Framing {
+    /// The subprotocols the client can ask for in `Sec-WebSocket-Protocol`
+    pub const BINARY_SUBPROTOCOL: &'static str = "transscreen.binary";
+    pub const BASE64_SUBPROTOCOL: &'static str = "transscreen.base64";
+
+    /// Picks the framing for an upgrade request, along with the subprotocol the response has to echo, if any.
+    ///
+    /// The first of the client's subprotocols that's known wins, otherwise `?encoding=base64` in the query
+    /// asks for base64, e.g. for a browser that can't pick subprotocols.
+    /// Anything else gets binary.
+    pub fn negotiate<B>(req: &Request<B>) -> (Self, Option<&'static str>) {
+        let offered = req
+            .headers()
+            .get_all(header::SEC_WEBSOCKET_PROTOCOL)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .map(str::trim);
+
+        for protocol in offered {
+            match protocol {
+                Self::BINARY_SUBPROTOCOL => return (Self::Binary, Some(Self::BINARY_SUBPROTOCOL)),
+                Self::BASE64_SUBPROTOCOL => return (Self::Base64, Some(Self::BASE64_SUBPROTOCOL)),
+                _ => (),
+            }
+        }
+
+        let base64_query = req
+            .uri()
+            .query()
+            .is_some_and(|query| query.split('&').any(|pair| pair == "encoding=base64"));
+
+        match base64_query {
+            true => (Self::Base64, None),
+            false => (Self::Binary, None),
+        }
+    }
+
+    /// The websocket message carrying `data`
+    pub fn message(self, data: Vec<u8>) -> Message {
+        match self {
+            Self::Binary => Message::Binary(data),
+            Self::Base64 => Message::Text(STANDARD.encode(data)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn upgrade_request(uri: &str, protocols: Option<&str>) -> Request<()> {
+        let mut builder = Request::get(uri)
+            .header(header::CONNECTION, "Upgrade")
+            .header(header::UPGRADE, "websocket");
+        if let Some(protocols) = protocols {
+            builder = builder.header(header::SEC_WEBSOCKET_PROTOCOL, protocols);
+        }
+
+        builder.body(()).unwrap()
+    }
+
+    #[test]
+    fn binary_by_default() {
+        let negotiated = |uri, protocols| Framing::negotiate(&upgrade_request(uri, protocols));
+
+        assert_eq!(negotiated("/websocket", None), (Framing::Binary, None));
+        assert_eq!(negotiated("/websocket?encoding=hex", None), (Framing::Binary, None));
+        // nothing the server knows, so nothing to echo back
+        assert_eq!(negotiated("/websocket", Some("chat, mqtt")), (Framing::Binary, None));
+        assert_eq!(
+            negotiated("/websocket?encoding=base64", Some("transscreen.binary")),
+            (Framing::Binary, Some(Framing::BINARY_SUBPROTOCOL))
+        );
+    }
+
+    #[test]
+    fn base64_when_asked_for() {
+        let negotiated = |uri, protocols| Framing::negotiate(&upgrade_request(uri, protocols));
+
+        assert_eq!(negotiated("/websocket?token=abc&encoding=base64", None), (Framing::Base64, None));
+        // the client's first choice wins
+        assert_eq!(
+            negotiated("/websocket", Some("chat, transscreen.base64, transscreen.binary")),
+            (Framing::Base64, Some(Framing::BASE64_SUBPROTOCOL))
+        );
+
+        assert_eq!(Framing::Base64.message(vec![0, 0, 0, 1]), Message::Text("AAAAAQ==".into()));
+        assert_eq!(Framing::Binary.message(vec![0, 0, 0, 1]), Message::Binary(vec![0, 0, 0, 1]));
+    }
+}
@@ -0,0 +1,285 @@
+use std::{
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+
+use futures::Future;
+use hyper::{header, Request, Response, StatusCode};
+use parking_lot::Mutex;
+use tower::{load_shed::error::Overloaded, BoxError, Layer, Service};
+
+/// The address of the client a request came from, put into the request's extensions when the connection is accepted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClientAddr(pub SocketAddr);
+
+/// How many websocket upgrades a single IP gets in a window of time, see `UpgradeRateLimit`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UpgradeLimit {
+    pub upgrades: u32,
+    pub per: Duration,
+}
+
+impl Default for UpgradeLimit {
+    fn default() -> Self {
+        Self {
+            upgrades: 10,
+            per: Duration::from_secs(30),
+        }
+    }
+}
+
+// the window an IP is in and how many upgrades it's used up in it
+type Windows = HashMap<IpAddr, (Instant, u32)>;
+
+/// Turns away websocket upgrades with `429` once a client has used up its `UpgradeLimit`.
+///
+/// Unlike tower's `RateLimit`, every IP gets its own budget, so a client reconnecting in a loop
+/// doesn't lock everyone else out. Everything that isn't an upgrade goes through untouched,
+/// and so do requests without a `ClientAddr`.
+#[derive(Debug, Clone)]
+pub struct UpgradeRateLimit<S> {
+    inner: S,
+    limit: Option<UpgradeLimit>,
+    windows: Arc<Mutex<Windows>>,
+}
+
+impl<S> UpgradeRateLimit<S> {
+    // whether the client still has an upgrade left in its current window, using it up if so
+    fn try_acquire(&self, ip: IpAddr, limit: UpgradeLimit, now: Instant) -> bool {
+        let mut windows = self.windows.lock();
+
+        // forgetting the clients that haven't come back in a while, so the map doesn't grow forever
+        windows.retain(|_, (started, _)| now.duration_since(*started) < limit.per);
+
+        let (_, used) = windows.entry(ip).or_insert((now, 0));
+        if *used >= limit.upgrades {
+            return false;
+        }
+
+        *used += 1;
+        true
+    }
+}
+
+impl<S, B> Service<Request<B>> for UpgradeRateLimit<S>
+where
+    S: Service<Request<B>, Response = Response<B>>,
+    S::Future: Send + 'static,
+    B: Default + Send + 'static,
+{
+    type Response = S::Response;
+
+    type Error = S::Error;
+
+    type Future = Pin<Box<dyn Future<Output = Result<S::Response, S::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<B>) -> Self::Future {
+        let client = req.extensions().get::<ClientAddr>().copied();
+
+        let exceeded = match (self.limit, client) {
+            (Some(limit), Some(ClientAddr(addr)))
+                if hyper_tungstenite::is_upgrade_request(&req) =>
+            {
+                (!self.try_acquire(addr.ip(), limit, Instant::now())).then_some(limit)
+            }
+            _ => None,
+        };
+
+        let Some(limit) = exceeded else {
+            return Box::pin(self.inner.call(req));
+        };
+
+        let response = Response::builder()
+            .status(StatusCode::TOO_MANY_REQUESTS)
+            .header(header::RETRY_AFTER, limit.per.as_secs().max(1))
+            .body(B::default())
+            .unwrap();
+
+        Box::pin(async { Ok(response) })
+    }
+}
+
+/// See `UpgradeRateLimit`, the budgets are shared between all the services the layer makes.
+///
+/// A `None` limit lets every upgrade through.
+#[derive(Debug, Clone)]
+pub struct UpgradeRateLimitLayer {
+    limit: Option<UpgradeLimit>,
+    windows: Arc<Mutex<Windows>>,
+}
+
+impl UpgradeRateLimitLayer {
+    pub fn new(limit: Option<UpgradeLimit>) -> Self {
+        Self {
+            limit,
+            windows: Arc::default(),
+        }
+    }
+}
+
+impl<S> Layer<S> for UpgradeRateLimitLayer {
+    type Service = UpgradeRateLimit<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        UpgradeRateLimit {
+            inner,
+            limit: self.limit,
+            windows: self.windows.clone(),
+        }
+    }
+}
+
+/// Answers with `503` when `LoadShed` turns a request away, instead of failing the connection.
+///
+/// Any other error goes through as it is.
+#[derive(Debug, Clone)]
+pub struct OverloadResponse<S> {
+    inner: S,
+}
+
+impl<S, B> Service<Request<B>> for OverloadResponse<S>
+where
+    S: Service<Request<B>, Response = Response<B>, Error = BoxError>,
+    S::Future: Send + 'static,
+    B: Default + Send + 'static,
+{
+    type Response = S::Response;
+
+    type Error = S::Error;
+
+    type Future = Pin<Box<dyn Future<Output = Result<S::Response, S::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<B>) -> Self::Future {
+        let fut = self.inner.call(req);
+
+        Box::pin(async move {
+            match fut.await {
+                Err(e) if e.is::<Overloaded>() => Ok(Response::builder()
+                    .status(StatusCode::SERVICE_UNAVAILABLE)
+                    .body(B::default())
+                    .unwrap()),
+                result => result,
+            }
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct OverloadResponseLayer;
+
+impl<S> Layer<S> for OverloadResponseLayer {
+    type Service = OverloadResponse<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        OverloadResponse { inner }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+
+    use tokio::sync::oneshot;
+    use tower::{
+        limit::ConcurrencyLimitLayer, load_shed::LoadShedLayer, service_fn, ServiceBuilder,
+        ServiceExt,
+    };
+
+    use super::*;
+
+    fn upgrade_from(ip: [u8; 4]) -> Request<String> {
+        let mut req = Request::get("/websocket")
+            .header(header::CONNECTION, "Upgrade")
+            .header(header::UPGRADE, "websocket")
+            .body(String::new())
+            .unwrap();
+
+        req.extensions_mut()
+            .insert(ClientAddr(SocketAddr::from((ip, 4000))));
+        req
+    }
+
+    #[tokio::test]
+    async fn upgrades_limited_per_ip() {
+        let inner = service_fn(|_req: Request<String>| async {
+            Ok::<_, Infallible>(Response::new(String::new()))
+        });
+        let layer = UpgradeRateLimitLayer::new(Some(UpgradeLimit {
+            upgrades: 2,
+            per: Duration::from_secs(60),
+        }));
+        // every connection gets its own service, the budget still has to be shared
+        let status = |req| layer.layer(inner).oneshot(req);
+
+        for _ in 0..2 {
+            let response = status(upgrade_from([10, 0, 0, 1])).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+
+        let response = status(upgrade_from([10, 0, 0, 1])).await.unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()[header::RETRY_AFTER], "60");
+
+        // someone else isn't affected
+        let response = status(upgrade_from([10, 0, 0, 2])).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // and neither is the page
+        let mut page = Request::get("/").body(String::new()).unwrap();
+        page.extensions_mut()
+            .insert(ClientAddr(SocketAddr::from(([10, 0, 0, 1], 4000))));
+        let response = status(page).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn overload_is_shed() {
+        let (release_tx, release_rx) = oneshot::channel::<()>();
+        let release_rx = Arc::new(Mutex::new(Some(release_rx)));
+
+        // the first request holds on to the only slot until it's released
+        let inner = service_fn(move |_req: Request<String>| {
+            let release_rx = release_rx.lock().take();
+            async move {
+                if let Some(release_rx) = release_rx {
+                    _ = release_rx.await;
+                }
+                Ok::<_, BoxError>(Response::new(String::new()))
+            }
+        });
+
+        let svc = ServiceBuilder::new()
+            .layer(OverloadResponseLayer)
+            .layer(LoadShedLayer::new())
+            .layer(ConcurrencyLimitLayer::new(1))
+            .service(inner);
+
+        let request = || Request::get("/").body(String::new()).unwrap();
+
+        let held = tokio::spawn(svc.clone().oneshot(request()));
+        // gives the first request time to take the slot
+        tokio::task::yield_now().await;
+
+        let response = svc.clone().oneshot(request()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        release_tx.send(()).unwrap();
+        assert_eq!(held.await.unwrap().unwrap().status(), StatusCode::OK);
+
+        // there's room again
+        let response = svc.oneshot(request()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+}
@@ -1,95 +1,160 @@
+pub mod auth;
+pub mod body_sink;
+pub mod broadcast;
+pub mod framing;
+pub mod limits;
+mod recordings;
+pub mod stats;
+mod static_files;
+
 use std::{
     convert::Infallible,
     fmt::Debug,
     net::SocketAddr,
+    path::PathBuf,
     pin::Pin,
+    sync::Arc,
     task::{Context, Poll},
-    time::Duration, borrow::Cow,
+    time::{Duration, Instant},
 };
 
-use futures::{Future, SinkExt};
+use futures::{Future, Sink, SinkExt, StreamExt};
 use hyper::{
+    server::conn::AddrStream,
+    header::{self, HeaderValue},
     service::{self, Service},
-    Body, Method, Request, Response, Server, StatusCode,
+    Request, Response, Server, StatusCode,
+};
+use hyper_tungstenite::{
+    tungstenite::{
+        self,
+        protocol::{frame::coding::CloseCode, CloseFrame},
+        Message,
+    },
+    HyperWebsocket,
+};
+use tokio::time;
+use tower::{
+    limit::ConcurrencyLimitLayer, load_shed::LoadShedLayer, timeout::TimeoutLayer, Layer,
+    ServiceBuilder,
+};
+use tracing::Instrument;
+
+use self::{
+    auth::TokenAuthLayer,
+    limits::{ClientAddr, OverloadResponseLayer, UpgradeLimit, UpgradeRateLimitLayer},
+    broadcast::{BroadcastHub, Chunks, ClientHandle, ClientQueue, Outgoing, ResumeOutcome},
+    framing::Framing,
+    static_files::StaticPageService,
 };
-use hyper_tungstenite::{HyperWebsocket, tungstenite::{Message, protocol::{CloseFrame, frame::coding::CloseCode}}};
-use tower::{timeout::TimeoutLayer, Layer, ServiceBuilder};
 
-#[derive(Debug, Clone, Copy)]
-struct StaticState {
-    index_html: &'static [u8],
-    stylesheet: &'static [u8],
-    script: &'static [u8],
+#[derive(Debug, Clone)]
+pub struct ServerConfig {
+    pub addr: SocketAddr,
+    /// Where to serve the page from, the assets embedded in the binary are used if it's not set
+    /// or a file is missing from it
+    pub static_dir: Option<PathBuf>,
+    /// Websocket clients and recording downloads have to present this token, see `TokenAuth`,
+    /// `None` lets anyone connect
+    pub auth_token: Option<String>,
+    /// Finished recordings in here can be downloaded from `/recordings/<name>`, `None` serves none
+    pub recordings_dir: Option<PathBuf>,
+    /// How often a single IP can open a websocket, see `UpgradeRateLimit`, `None` doesn't limit it
+    pub upgrade_limit: Option<UpgradeLimit>,
+    /// Requests past this many in flight get a `503` right away instead of waiting their turn
+    pub max_concurrent_requests: usize,
+    /// How websocket clients that silently went away get noticed, `None` leaves it to the OS
+    pub keepalive: Option<Keepalive>,
 }
 
-struct InstantFuture<T>(Option<T>);
-
-impl<T> Unpin for InstantFuture<T> {}
-
-impl<T> Future for InstantFuture<T> {
-    type Output = T;
-
-    fn poll(mut self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Self::Output> {
-        Poll::Ready(self.0.take().expect("InstantFuture got polled twice"))
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            addr: SocketAddr::from(([0, 0, 0, 0], 9090)),
+            static_dir: None,
+            auth_token: None,
+            recordings_dir: None,
+            upgrade_limit: Some(UpgradeLimit::default()),
+            max_concurrent_requests: 256,
+            keepalive: Some(Keepalive::default()),
+        }
     }
 }
 
-#[derive(Debug, Clone)]
-struct StaticPageService {
-    state: StaticState,
+/// Websocket clients get pinged every `interval`,
+/// one that hasn't sent anything, pongs included, for `timeout` is considered gone and disconnected
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Keepalive {
+    pub interval: Duration,
+    pub timeout: Duration,
 }
 
-impl Service<Request<Body>> for StaticPageService {
-    type Response = Response<Body>;
-
-    type Error = Infallible;
-
-    type Future = InstantFuture<Result<Self::Response, Self::Error>>;
-
-    fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
-        Poll::Ready(Ok(()))
-    }
-
-    fn call(&mut self, req: Request<Body>) -> Self::Future {
-        let response = match (req.method(), req.uri().path()) {
-            (&Method::GET, "/") => Response::new(self.state.index_html.into()),
-            (&Method::GET, "/stylesheet") => Response::new(self.state.stylesheet.into()),
-            (&Method::GET, "/script") => Response::new(self.state.script.into()),
-
-            _ => Response::builder().status(404).body(Body::empty()).unwrap(),
-        };
-
-        InstantFuture(Some(Ok(response)))
+impl Default for Keepalive {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(15),
+            timeout: Duration::from_secs(45),
+        }
     }
 }
 
-pub async fn run() {
-    let state = StaticState {
-        index_html: include_bytes!("../static/index.html"),
-        stylesheet: &[],
-        script: include_bytes!("../static/main.js"),
-    };
-
-    let svc = StaticPageService { state };
+/// Serves the page and the stream until `shutdown` resolves,
+/// then tells the connected websocket clients to go away and waits for them to disconnect.
+///
+/// Logs through `tracing`, to stdout unless the embedding app has set up a subscriber of its own beforehand.
+pub async fn run(config: ServerConfig, hub: BroadcastHub, shutdown: impl Future<Output = ()>) {
+    // fails if there's a subscriber already, which is the one that should be used then,
+    // the tests capture the logs with one of their own
+    #[cfg(not(test))]
+    let _ = tracing_subscriber::fmt().try_init();
+
+    let websocket_hub = hub.clone();
+    let keepalive = config.keepalive;
+
+    let svc = StaticPageService::new(config.static_dir)
+        .with_hub(hub.clone())
+        .with_recordings(config.recordings_dir);
     let full_svc = ServiceBuilder::new()
         .layer(LogLayer)
+        .layer(OverloadResponseLayer)
+        .layer(LoadShedLayer::new())
+        .layer(ConcurrencyLimitLayer::new(config.max_concurrent_requests))
         .layer(TimeoutLayer::new(Duration::from_secs(10)))
-        .layer(WebSocketUpgradeLayer::new(handle_websocket))
-        // .layer(LoadShedLayer::new())
-        // .layer(BufferLayer::new(1))
-        // .layer(RateLimitLayer::new(10, Duration::from_secs(30)))
+        // before the token check, so guessing tokens is limited as well
+        .layer(UpgradeRateLimitLayer::new(config.upgrade_limit))
+        .layer(TokenAuthLayer::new(config.auth_token.as_deref()))
+        .layer(WebSocketUpgradeLayer::new(move |ws, framing| {
+            handle_websocket(websocket_hub.clone(), keepalive, framing, ws)
+        }))
         .service(svc);
 
-    let make_svc = service::make_service_fn(|_conn| {
-        let svc = full_svc.clone();
+    let make_svc = service::make_service_fn(|conn: &AddrStream| {
+        let client = ClientAddr(conn.remote_addr());
+        let svc = ServiceBuilder::new()
+            .map_request(move |mut req: Request<hyper::Body>| {
+                req.extensions_mut().insert(client);
+                req
+            })
+            .service(full_svc.clone());
 
         async { Ok::<_, Infallible>(svc) }
     });
 
-    let addr: SocketAddr = "0.0.0.0:9090".parse().unwrap();
-
-    let server = Server::bind(&addr).serve(make_svc);
+    let server = Server::bind(&config.addr)
+        .serve(make_svc)
+        .with_graceful_shutdown(shutdown);
     _ = server.await;
+
+    // upgraded connections aren't hyper's business anymore, so it doesn't wait for them
+    hub.close_all().await;
+}
+
+/// `run` until the process gets a Ctrl-C
+pub async fn run_until_ctrl_c(config: ServerConfig, hub: BroadcastHub) {
+    run(config, hub, async {
+        _ = tokio::signal::ctrl_c().await;
+    })
+    .await;
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -115,18 +180,26 @@ where
     }
 
     fn call(&mut self, req: Request<B>) -> Self::Future {
-        println!("REQUEST:  {} {}", req.method(), req.uri());
-        let mut this = self.clone();
+        let span = tracing::info_span!("request", method = %req.method(), uri = %req.uri());
+        // the service that was polled ready is the one that has to take the request,
+        // load shedding turns the request away otherwise
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+
+        let fut = async move {
+            let start = Instant::now();
+            let resp = inner.call(req).await;
+            let elapsed = start.elapsed();
 
-        Box::pin(async move {
-            let resp = this.inner.call(req).await;
             match &resp {
-                Ok(resp) => println!("RESPONSE: {:?}", resp.status()),
-                Err(e) => println!("RESPONSE ERROR: {e:?}"),
+                Ok(resp) => tracing::info!(status = %resp.status(), ?elapsed, "response"),
+                Err(e) => tracing::warn!(error = ?e, ?elapsed, "request failed"),
             };
 
             resp
-        })
+        };
+        // the inner services get polled within the span too, so whatever they log is tied to the request
+        Box::pin(fut.instrument(span))
     }
 }
 
@@ -143,7 +216,7 @@ impl<S> Layer<S> for LogLayer {
 #[derive(Debug)]
 struct WebSocketUpgrade<S, F, Fut>
 where
-    F: FnMut(HyperWebsocket) -> Fut,
+    F: FnMut(HyperWebsocket, Framing) -> Fut,
     Fut: Future<Output = ()>,
 {
     inner: S,
@@ -152,7 +225,7 @@ where
 
 impl<S, F, Fut> WebSocketUpgrade<S, F, Fut>
 where
-    F: FnMut(HyperWebsocket) -> Fut,
+    F: FnMut(HyperWebsocket, Framing) -> Fut,
     Fut: Future<Output = ()>,
 {
     fn new(service: S, websocket_handler: F) -> Self {
@@ -166,7 +239,7 @@ where
 // implementing manually because derive macro gets confused when Fut isn't Clone
 impl<S, F, Fut> Clone for WebSocketUpgrade<S, F, Fut>
 where
-    F: FnMut(HyperWebsocket) -> Fut + Clone,
+    F: FnMut(HyperWebsocket, Framing) -> Fut + Clone,
     Fut: Future<Output = ()>, // this one doesn't have to be clone, it's returned by F
     S: Clone,
 {
@@ -181,7 +254,7 @@ where
 // same story as with Clone
 impl<S, F, Fut> Copy for WebSocketUpgrade<S, F, Fut>
 where
-    F: FnMut(HyperWebsocket) -> Fut + Copy,
+    F: FnMut(HyperWebsocket, Framing) -> Fut + Copy,
     Fut: Future<Output = ()>, // this one doesn't have to be copy
     S: Copy,
 {
@@ -189,7 +262,7 @@ where
 
 impl<S, F, Fut, B> Service<Request<B>> for WebSocketUpgrade<S, F, Fut>
 where
-    F: FnMut(HyperWebsocket) -> Fut + Send + 'static,
+    F: FnMut(HyperWebsocket, Framing) -> Fut + Send + 'static,
     Fut: Future<Output = ()> + Send + 'static,
     S: Service<Request<B>, Response = Response<B>> + Send,
     S::Future: Send + 'static,
@@ -212,9 +285,10 @@ where
             return Box::pin(self.inner.call(req));
         }
 
+        let (framing, subprotocol) = Framing::negotiate(&req);
         let mut this = self.clone();
         Box::pin(async move {
-            let (response, websocket) = match hyper_tungstenite::upgrade(req, None) {
+            let (mut response, websocket) = match hyper_tungstenite::upgrade(req, None) {
                 Err(_) => {
                     return Ok(Response::builder()
                         .status(StatusCode::BAD_REQUEST)
@@ -224,7 +298,14 @@ where
                 Ok(pair) => pair,
             };
 
-            let handler_fut = (this.websocket_handler)(websocket);
+            // the client only accepts the subprotocol it got if it's one of those it asked for
+            if let Some(subprotocol) = subprotocol {
+                response
+                    .headers_mut()
+                    .insert(header::SEC_WEBSOCKET_PROTOCOL, HeaderValue::from_static(subprotocol));
+            }
+
+            let handler_fut = (this.websocket_handler)(websocket, framing);
             tokio::spawn(handler_fut);
             
             // I want this Service to be a bit more flexible over the type of body, so instead of returning the
@@ -240,7 +321,7 @@ where
 
 struct WebSocketUpgradeLayer<F, Fut>
 where
-    F: FnMut(HyperWebsocket) -> Fut,
+    F: FnMut(HyperWebsocket, Framing) -> Fut,
     Fut: Future<Output = ()>,
 {
     websocket_handler: F,
@@ -248,7 +329,7 @@ where
 
 impl<F, Fut> WebSocketUpgradeLayer<F, Fut>
 where
-    F: FnMut(HyperWebsocket) -> Fut,
+    F: FnMut(HyperWebsocket, Framing) -> Fut,
     Fut: Future<Output = ()>,
 {
     fn new(f: F) -> Self {
@@ -260,7 +341,7 @@ where
 
 impl<F, Fut, S> Layer<S> for WebSocketUpgradeLayer<F, Fut>
 where
-    F: FnMut(HyperWebsocket) -> Fut + Clone,
+    F: FnMut(HyperWebsocket, Framing) -> Fut + Clone,
     Fut: Future<Output = ()>,
 {
     type Service = WebSocketUpgrade<S, F, Fut>;
@@ -270,10 +351,487 @@ where
     }
 }
 
-async fn handle_websocket(ws: HyperWebsocket) {
-    println!("Got a websocket");
-    let mut socket = ws.await.unwrap();
-    socket.send(Message::Text("Hellooo from a websocket".to_string())).await.unwrap();
-    socket.send(Message::Binary(vec![1, 2, 3, 4])).await.unwrap();
-    socket.send(Message::Close(Some(CloseFrame { code: CloseCode::Normal, reason: Cow::Borrowed("fuck you") }))).await.unwrap();
+// what woke up the websocket handler
+enum ClientEvent {
+    Chunks(Option<Chunks>),
+    Message(Option<Result<Message, tungstenite::Error>>),
+    Closing,
+    Ping,
+    Unresponsive,
+}
+
+// when to ping a client and when to give up on it, see `Keepalive`
+struct KeepaliveTimer {
+    pings: time::Interval,
+    deadline: Pin<Box<time::Sleep>>,
+    timeout: Duration,
+}
+
+impl KeepaliveTimer {
+    fn new(keepalive: Keepalive) -> Self {
+        let start = time::Instant::now();
+
+        Self {
+            // the first tick would be right away, there's no point in pinging a client that just connected
+            pings: time::interval_at(start + keepalive.interval, keepalive.interval),
+            deadline: Box::pin(time::sleep_until(start + keepalive.timeout)),
+            timeout: keepalive.timeout,
+        }
+    }
+
+    fn heard_from_client(&mut self) {
+        let deadline = time::Instant::now() + self.timeout;
+        self.deadline.as_mut().reset(deadline);
+    }
+
+    // never resolves without a keepalive
+    async fn next(timer: &mut Option<Self>) -> ClientEvent {
+        let Some(timer) = timer else {
+            return std::future::pending().await;
+        };
+
+        tokio::select! {
+            _ = &mut timer.deadline => ClientEvent::Unresponsive,
+            _ = timer.pings.tick() => ClientEvent::Ping,
+        }
+    }
+}
+
+/// Streams the recording to the client, starting at the latest keyframe.
+///
+/// The headers go first, followed by the chunks as binary messages,
+/// or as base64 text messages if the client asked for them, see `Framing`.
+/// After the encoder gets reconfigured, the new headers go out the same way, right before the chunks that need them.
+/// Whenever the chunks don't continue from the previous ones, e.g. at the start or because the client
+/// fell too far behind, they're preceded by a `{"resync": <id>}` text message with the id of the first one.
+/// A client that got disconnected can send `{"resume_from": <id>}` after reconnecting,
+/// if that chunk is gone already it gets a resync to the latest keyframe instead.
+/// A client too slow to keep up with its queue loses the chunks up to the next keyframe, see `ClientQueue`.
+/// Once the hub is closing, the client gets a close frame with `CloseCode::Away`.
+/// A client that stops answering the keepalive pings gets disconnected, see `Keepalive`.
+///
+/// Everything that happens to the client is logged within a `websocket` span with its id.
+async fn handle_websocket(hub: BroadcastHub, keepalive: Option<Keepalive>, framing: Framing, ws: HyperWebsocket) {
+    let client = hub.register_client();
+    let span = tracing::info_span!("websocket", client = client.id());
+
+    async move {
+        stream_to_client(hub, client, keepalive, framing, ws).await;
+        tracing::info!("websocket client disconnected");
+    }
+    .instrument(span)
+    .await;
+}
+
+async fn stream_to_client(
+    hub: BroadcastHub,
+    mut client: ClientHandle,
+    keepalive: Option<Keepalive>,
+    framing: Framing,
+    ws: HyperWebsocket,
+) {
+    let mut socket = match ws.await {
+        Ok(socket) => socket,
+        Err(e) => {
+            tracing::warn!(error = %e, "websocket handshake failed");
+            return;
+        }
+    };
+    tracing::info!(?framing, "websocket client connected");
+
+    let mut subscription = hub.subscribe();
+    // so the client doesn't have to wait for the encoder to emit one on its own
+    hub.request_keyframe();
+
+    let (headers, mut headers_since) = hub.headers_since();
+    if let Err(e) = socket.send(framing.message(headers.to_vec())).await {
+        tracing::warn!(error = %e, "couldn't send the headers");
+        return;
+    }
+
+    // the socket gets written on its own task, so a slow client only fills up its own queue
+    let queue = client.queue();
+    let (sink, mut stream) = socket.split();
+    let mut writer = tokio::spawn(write_queue(sink, queue.clone(), framing));
+    let mut keepalive = keepalive.map(KeepaliveTimer::new);
+
+    loop {
+        let event = tokio::select! {
+            chunks = subscription.next_chunks() => ClientEvent::Chunks(chunks),
+            message = stream.next() => ClientEvent::Message(message),
+            _ = client.closing() => ClientEvent::Closing,
+            event = KeepaliveTimer::next(&mut keepalive) => event,
+            // couldn't send something, the client is gone
+            _ = &mut writer => {
+                tracing::debug!("couldn't write to the websocket");
+                return;
+            }
+        };
+
+        if let (ClientEvent::Message(Some(Ok(_))), Some(keepalive)) = (&event, &mut keepalive) {
+            keepalive.heard_from_client();
+        }
+
+        match event {
+            ClientEvent::Chunks(Some(chunks)) => {
+                // the encoder got rebuilt with a different config, read after the chunks so they can't be ahead
+                let (headers, first_id) = hub.headers_since();
+                if first_id > headers_since {
+                    headers_since = first_id;
+                    queue.push_headers(headers.to_vec(), first_id);
+                }
+
+                if queue.push(chunks) {
+                    hub.request_keyframe();
+                }
+            }
+            ClientEvent::Message(Some(Ok(Message::Text(text)))) => {
+                if let Some(id) = broadcast::parse_resume_from(&text) {
+                    let expected_id = match subscription.resume_from(id) {
+                        ResumeOutcome::Resumed => Some(id),
+                        // the resync message goes out with the next chunks
+                        ResumeOutcome::Resynced { .. } => None,
+                    };
+                    queue.resume(expected_id);
+                }
+            }
+            // pings get answered by tungstenite on its own
+            ClientEvent::Message(Some(Ok(_))) => (),
+            ClientEvent::Message(Some(Err(e))) => {
+                tracing::warn!(error = %e, "websocket error");
+                return;
+            }
+            // the client has disconnected
+            ClientEvent::Message(None) => return,
+            ClientEvent::Ping => queue.ping(),
+            // most likely gone without saying goodbye, the socket gets dropped along with the writer
+            ClientEvent::Unresponsive => {
+                tracing::info!("websocket client stopped answering pings");
+                writer.abort();
+                return;
+            }
+            // the recorder has stopped or the server is shutting down
+            ClientEvent::Chunks(None) | ClientEvent::Closing => break,
+        }
+    }
+
+    queue.close();
+    let Ok(Some(mut sink)) = writer.await else {
+        return;
+    };
+
+    let close_frame = CloseFrame {
+        code: CloseCode::Away,
+        reason: "the stream has ended".into(),
+    };
+    _ = sink.send(Message::Close(Some(close_frame))).await;
+}
+
+// sends whatever shows up in the queue until it's closed, `None` if the client has gone away
+async fn write_queue<S>(mut sink: S, queue: Arc<ClientQueue>, framing: Framing) -> Option<S>
+where
+    S: Sink<Message> + Unpin,
+{
+    while let Some(outgoing) = queue.pop().await {
+        let message = match outgoing {
+            Outgoing::Resync(id) => Message::Text(broadcast::resync_message(id)),
+            Outgoing::Checksum(crc) => Message::Text(broadcast::checksum_message(crc)),
+            Outgoing::Chunk(chunk) | Outgoing::Headers(chunk) => framing.message(chunk),
+            Outgoing::Ping => Message::Ping(Vec::new()),
+        };
+
+        sink.send(message).await.ok()?;
+    }
+
+    Some(sink)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::TcpListener, sync::mpsc};
+
+    use hyper::{client::conn, upgrade};
+    use hyper_tungstenite::{tungstenite::protocol::Role, WebSocketStream};
+    use screen_cap::record::{encoded_buffer::EncodedBuffer, SharedHeaders, SharedVideoInfo};
+    use tokio::{net::TcpStream, sync::oneshot, time};
+    use tower::{service_fn, ServiceExt};
+    use tracing_test::traced_test;
+
+    use super::*;
+    use crate::async_adapter::{RecorderAsyncAdapter, RecorderMessage};
+
+    const HEADERS: [u8; 5] = [0, 0, 0, 1, 0x67];
+
+    // a recorder that never produces anything
+    fn idle_hub() -> BroadcastHub {
+        hub_with_recorder_thread(|rx| {
+            // keeps the requests unanswered until the adapter is gone
+            for _ in rx {}
+        })
+    }
+
+    fn hub_with_recorder_thread<F>(recorder_thread: F) -> BroadcastHub
+    where
+        F: FnOnce(mpsc::Receiver<RecorderMessage>) + Send + 'static,
+    {
+        let buf = EncodedBuffer::new(16);
+        let headers = SharedHeaders::new(Arc::from(&HEADERS[..]));
+
+        let video_info = SharedVideoInfo::default();
+
+        let recorder =
+            RecorderAsyncAdapter::with_recorder_thread(buf.view(), headers, video_info, |rx, _| {
+                recorder_thread(rx)
+            });
+
+        BroadcastHub::new(recorder)
+    }
+
+    // starts the server on a free port
+    fn serve(hub: BroadcastHub) -> SocketAddr {
+        let addr = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+        let config = ServerConfig {
+            addr,
+            ..ServerConfig::default()
+        };
+
+        tokio::spawn(run(config, hub, std::future::pending()));
+        addr
+    }
+
+    async fn get(addr: SocketAddr, path: &str) -> (StatusCode, bytes::Bytes) {
+        let uri: hyper::Uri = format!("http://{addr}{path}").parse().unwrap();
+
+        // the server might not be listening yet
+        let response = loop {
+            match hyper::Client::new().get(uri.clone()).await {
+                Ok(response) => break response,
+                Err(_) => time::sleep(Duration::from_millis(10)).await,
+            }
+        };
+
+        let status = response.status();
+        (status, hyper::body::to_bytes(response.into_body()).await.unwrap())
+    }
+
+    async fn connect_websocket(addr: SocketAddr) -> WebSocketStream<upgrade::Upgraded> {
+        connect_websocket_with_protocol(addr, None).await.0
+    }
+
+    // also returns the subprotocol the server picked
+    async fn connect_websocket_with_protocol(
+        addr: SocketAddr,
+        subprotocol: Option<&str>,
+    ) -> (WebSocketStream<upgrade::Upgraded>, Option<HeaderValue>) {
+        // the server might not be listening yet
+        let stream = loop {
+            match TcpStream::connect(addr).await {
+                Ok(stream) => break stream,
+                Err(_) => time::sleep(Duration::from_millis(10)).await,
+            }
+        };
+
+        let (mut sender, connection) = conn::handshake(stream).await.unwrap();
+        tokio::spawn(connection);
+
+        let mut request = Request::get("/websocket")
+            .header(header::HOST, addr.to_string())
+            .header(header::CONNECTION, "Upgrade")
+            .header(header::UPGRADE, "websocket")
+            .header("Sec-WebSocket-Version", "13")
+            .header("Sec-WebSocket-Key", "dGhlIHNhbXBsZSBub25jZQ==");
+        if let Some(subprotocol) = subprotocol {
+            request = request.header(header::SEC_WEBSOCKET_PROTOCOL, subprotocol);
+        }
+        let request = request.body(hyper::Body::empty()).unwrap();
+
+        let response = sender.send_request(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::SWITCHING_PROTOCOLS);
+        let chosen = response.headers().get(header::SEC_WEBSOCKET_PROTOCOL).cloned();
+
+        let upgraded = upgrade::on(response).await.unwrap();
+        (WebSocketStream::from_raw_socket(upgraded, Role::Client, None).await, chosen)
+    }
+
+    #[tokio::test]
+    async fn websocket_closes_on_shutdown() {
+        let addr = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+        let config = ServerConfig {
+            addr,
+            ..ServerConfig::default()
+        };
+
+        let (shutdown_tx, shutdown_rx) = oneshot::channel();
+        let server = tokio::spawn(run(config, idle_hub(), async {
+            _ = shutdown_rx.await;
+        }));
+
+        let mut socket = connect_websocket(addr).await;
+
+        let headers = socket.next().await.unwrap().unwrap();
+        assert_eq!(headers, Message::Binary(HEADERS.to_vec()));
+
+        shutdown_tx.send(()).unwrap();
+
+        let close = time::timeout(Duration::from_secs(5), socket.next()).await.unwrap();
+        match close.unwrap().unwrap() {
+            Message::Close(Some(frame)) => assert_eq!(frame.code, CloseCode::Away),
+            other => panic!("expected a close frame, got {other:?}"),
+        }
+
+        drop(socket);
+        time::timeout(Duration::from_secs(5), server).await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn base64_framing_is_opt_in() {
+        let addr = serve(idle_hub());
+
+        let (mut socket, chosen) = connect_websocket_with_protocol(addr, None).await;
+        assert_eq!(chosen, None);
+        let headers = socket.next().await.unwrap().unwrap();
+        assert_eq!(headers, Message::Binary(HEADERS.to_vec()));
+
+        let (mut socket, chosen) = connect_websocket_with_protocol(addr, Some("chat, transscreen.base64")).await;
+        assert_eq!(chosen.unwrap(), Framing::BASE64_SUBPROTOCOL);
+        let headers = socket.next().await.unwrap().unwrap();
+        assert_eq!(headers, Message::Text("AAAAAWc=".into()));
+    }
+
+    #[tokio::test]
+    async fn unresponsive_client_is_dropped() {
+        let addr = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+        let keepalive = Keepalive {
+            interval: Duration::from_millis(50),
+            timeout: Duration::from_millis(300),
+        };
+        let config = ServerConfig {
+            addr,
+            keepalive: Some(keepalive),
+            ..ServerConfig::default()
+        };
+
+        let hub = idle_hub();
+        tokio::spawn(run(config, hub.clone(), std::future::pending()));
+
+        let mut socket = connect_websocket(addr).await;
+        let headers = socket.next().await.unwrap().unwrap();
+        assert_eq!(headers, Message::Binary(HEADERS.to_vec()));
+
+        // reading is what gets the pings answered, the client stays for as long as it keeps doing that
+        let mut pings = 0;
+        _ = time::timeout(keepalive.timeout * 2, async {
+            while let Some(Ok(message)) = socket.next().await {
+                if let Message::Ping(_) = message {
+                    pings += 1;
+                }
+            }
+        })
+        .await;
+        assert!(pings > 0);
+        assert_eq!(hub.client_stats().len(), 1);
+
+        // still connected, but not answering anymore
+        let dropped = time::timeout(keepalive.timeout * 2, async {
+            while !hub.client_stats().is_empty() {
+                time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await;
+        assert!(dropped.is_ok(), "the client should've been dropped");
+
+        drop(socket);
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn requests_are_logged_in_a_span() {
+        let inner = service_fn(|_req: Request<String>| async {
+            let response = Response::builder()
+                .status(StatusCode::NOT_FOUND)
+                .body(String::new())
+                .unwrap();
+            Ok::<_, Infallible>(response)
+        });
+
+        let request = Request::get("/missing?page=2").body(String::new()).unwrap();
+        let response = LogLayer.layer(inner).oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        assert!(logs_contain("request{method=GET uri=/missing?page=2}"));
+        assert!(logs_contain("status=404 Not Found"));
+        assert!(logs_contain("elapsed="));
+    }
+
+    #[tokio::test]
+    async fn stats_endpoint() {
+        let addr = serve(idle_hub());
+
+        let (status, body) = get(addr, "/stats").await;
+        assert_eq!(status, StatusCode::OK);
+
+        let stats: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(stats["frames_encoded"], 0);
+        assert_eq!(stats["buffer_bytes_used"], 0);
+        assert_eq!(stats["buffer_capacity"], 16);
+        assert_eq!(stats["encode_rate"], 0.0);
+        assert_eq!(stats["connected_clients"], 0);
+        assert_eq!(stats["clients"], serde_json::json!([]));
+
+        let (status, body) = get(addr, "/healthz").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, "ok");
+    }
+
+    #[tokio::test]
+    async fn metrics_endpoint() {
+        let addr = serve(idle_hub());
+
+        let (status, body) = get(addr, "/metrics").await;
+        assert_eq!(status, StatusCode::OK);
+
+        let text = std::str::from_utf8(&body).unwrap();
+        let samples: Vec<(&str, f64)> = text
+            .lines()
+            .filter(|line| !line.starts_with('#'))
+            .map(|line| {
+                let (name, value) = line.split_once(' ').unwrap();
+                (name, value.parse().unwrap())
+            })
+            .collect();
+
+        assert_eq!(samples.len(), 8);
+        assert!(samples.contains(&("transscreen_frames_encoded_total", 0.0)));
+        assert!(samples.contains(&("transscreen_buffer_capacity_bytes", 16.0)));
+        assert!(text.contains("# TYPE transscreen_buffer_capacity_bytes gauge\n"));
+
+        let uri = format!("http://{addr}/metrics").parse().unwrap();
+        let response = hyper::Client::new().get(uri).await.unwrap();
+        assert_eq!(
+            response.headers()[header::CONTENT_TYPE],
+            "text/plain; version=0.0.4"
+        );
+    }
+
+    #[tokio::test]
+    async fn healthz_after_recorder_stopped() {
+        let (stop_tx, stop_rx) = mpsc::channel::<()>();
+        let hub = hub_with_recorder_thread(move |rx| {
+            _ = stop_rx.recv();
+            drop(rx);
+        });
+        let addr = serve(hub);
+
+        stop_tx.send(()).unwrap();
+        // the thread takes a moment to actually finish
+        let status = loop {
+            let (status, _) = get(addr, "/healthz").await;
+            if status != StatusCode::OK {
+                break status;
+            }
+            time::sleep(Duration::from_millis(10)).await;
+        };
+
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    }
 }
@@ -0,0 +1,264 @@
+use std::{
+    io::SeekFrom,
+    ops::Range,
+    path::{Component, Path},
+};
+
+use hyper::{body::Sender, header, Body, HeaderMap, Response, StatusCode};
+use tokio::{
+    fs::File,
+    io::{AsyncReadExt, AsyncSeekExt},
+};
+
+use super::static_files;
+
+// how much of the file gets read into memory at a time
+const READ_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Where the recordings are served, followed by the file name
+pub(super) const PATH_PREFIX: &str = "/recordings/";
+
+/// Serves the recording `name` from `dir`, or just the part of it asked for with a `Range` header.
+///
+/// Only single byte ranges are supported, anything else in the header gets the whole file.
+/// The file is streamed, so it can be as large as it likes.
+pub(super) async fn serve(dir: &Path, name: &str, headers: &HeaderMap) -> Response<Body> {
+    // only plain file names, recordings don't live in subdirectories
+    let is_file_name = matches!(
+        Path::new(name).components().collect::<Vec<_>>()[..],
+        [Component::Normal(_)]
+    );
+    if !is_file_name || name.contains('\\') {
+        return static_files::status_response(StatusCode::NOT_FOUND);
+    }
+
+    let path = dir.join(name);
+    let file = match File::open(&path).await {
+        Ok(file) => file,
+        Err(_) => return static_files::status_response(StatusCode::NOT_FOUND),
+    };
+    let len = match file.metadata().await {
+        Ok(metadata) if metadata.is_file() => metadata.len(),
+        _ => return static_files::status_response(StatusCode::NOT_FOUND),
+    };
+
+    let range = headers
+        .get(header::RANGE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(parse_range);
+
+    let response = Response::builder()
+        .header(header::CONTENT_TYPE, static_files::content_type(&path))
+        .header(header::ACCEPT_RANGES, "bytes");
+
+    let (response, range) = match range.map(|range| range.resolve(len)) {
+        None => (response, 0..len),
+        Some(Some(range)) => {
+            let content_range = format!("bytes {}-{}/{len}", range.start, range.end - 1);
+            let response = response
+                .status(StatusCode::PARTIAL_CONTENT)
+                .header(header::CONTENT_RANGE, content_range);
+
+            (response, range)
+        }
+        Some(None) => {
+            let response = response
+                .status(StatusCode::RANGE_NOT_SATISFIABLE)
+                .header(header::CONTENT_RANGE, format!("bytes */{len}"))
+                .body(Body::empty())
+                .unwrap();
+
+            return response;
+        }
+    };
+
+    let (sender, body) = Body::channel();
+    tokio::spawn(stream_file(file, range.clone(), sender));
+
+    response
+        .header(header::CONTENT_LENGTH, range.end - range.start)
+        .body(body)
+        .unwrap()
+}
+
+// the client going away or the file getting cut short just ends the body early
+async fn stream_file(mut file: File, range: Range<u64>, mut sender: Sender) {
+    if file.seek(SeekFrom::Start(range.start)).await.is_err() {
+        sender.abort();
+        return;
+    }
+
+    let mut remaining = range.end - range.start;
+    let mut buf = vec![0; READ_CHUNK_SIZE];
+
+    while remaining > 0 {
+        let to_read = remaining.min(buf.len() as u64) as usize;
+
+        let read = match file.read(&mut buf[..to_read]).await {
+            Ok(0) | Err(_) => {
+                // shorter than the promised length, the client has to know it didn't get everything
+                sender.abort();
+                return;
+            }
+            Ok(read) => read,
+        };
+
+        if sender.send_data(buf[..read].to_vec().into()).await.is_err() {
+            return;
+        }
+        remaining -= read as u64;
+    }
+}
+
+/// A single range out of a `Range: bytes=...` header, before it's known how long the file is
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ByteRange {
+    /// `start-end`, both inclusive, `end` is `None` for `start-`
+    From { start: u64, end: Option<u64> },
+    /// `-len`, the last `len` bytes
+    Suffix(u64),
+}
+
+impl ByteRange {
+    /// The half-open range of the file to send, `None` if none of it is in the file
+    fn resolve(self, file_len: u64) -> Option<Range<u64>> {
+        let range = match self {
+            ByteRange::From { start, end } => {
+                let end = end.map_or(file_len, |end| end.saturating_add(1).min(file_len));
+                start..end
+            }
+            ByteRange::Suffix(len) => file_len.saturating_sub(len)..file_len,
+        };
+
+        (range.start < range.end).then_some(range)
+    }
+}
+
+// `None` for anything that isn't a single, well-formed byte range, the header gets ignored then
+fn parse_range(value: &str) -> Option<ByteRange> {
+    let spec = value.trim().strip_prefix("bytes=")?;
+    if spec.contains(',') {
+        return None;
+    }
+
+    let (start, end) = spec.trim().split_once('-')?;
+    let (start, end) = (start.trim(), end.trim());
+
+    if start.is_empty() {
+        return end.parse().ok().map(ByteRange::Suffix);
+    }
+
+    let start = start.parse().ok()?;
+    let end = match end {
+        "" => None,
+        end => Some(end.parse().ok()?),
+    };
+
+    if end.is_some_and(|end| end < start) {
+        return None;
+    }
+
+    Some(ByteRange::From { start, end })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use hyper::header::HeaderValue;
+
+    use super::*;
+
+    async fn body_of(response: Response<Body>) -> Vec<u8> {
+        hyper::body::to_bytes(response.into_body())
+            .await
+            .unwrap()
+            .to_vec()
+    }
+
+    fn with_range(range: &'static str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::RANGE, HeaderValue::from_static(range));
+        headers
+    }
+
+    fn header_of(response: &Response<Body>, name: header::HeaderName) -> &str {
+        response.headers()[name].to_str().unwrap()
+    }
+
+    #[test]
+    fn range_parsing() {
+        assert_eq!(
+            parse_range("bytes=10-19"),
+            Some(ByteRange::From {
+                start: 10,
+                end: Some(19)
+            })
+        );
+        assert_eq!(
+            parse_range("bytes=10-"),
+            Some(ByteRange::From {
+                start: 10,
+                end: None
+            })
+        );
+        assert_eq!(parse_range("bytes=-5"), Some(ByteRange::Suffix(5)));
+
+        assert_eq!(parse_range("bytes=5-1"), None);
+        assert_eq!(parse_range("bytes=0-1,4-5"), None);
+        assert_eq!(parse_range("items=0-1"), None);
+
+        assert_eq!(
+            parse_range("bytes=90-200").unwrap().resolve(100),
+            Some(90..100)
+        );
+        assert_eq!(
+            parse_range("bytes=-500").unwrap().resolve(100),
+            Some(0..100)
+        );
+        assert_eq!(parse_range("bytes=100-").unwrap().resolve(100), None);
+    }
+
+    #[tokio::test]
+    async fn downloads() {
+        let dir = std::env::temp_dir().join(format!("recordings_downloads_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        // larger than a read chunk, so it takes a few sends
+        let contents: Vec<u8> = (0..READ_CHUNK_SIZE * 2 + 100).map(|i| i as u8).collect();
+        fs::write(dir.join("thing.mp4"), &contents).unwrap();
+
+        let response = serve(&dir, "thing.mp4", &HeaderMap::new()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            header_of(&response, header::CONTENT_LENGTH),
+            contents.len().to_string()
+        );
+        assert_eq!(header_of(&response, header::ACCEPT_RANGES), "bytes");
+        assert_eq!(header_of(&response, header::CONTENT_TYPE), "video/mp4");
+        assert_eq!(body_of(response).await, contents);
+
+        let response = serve(&dir, "thing.mp4", &with_range("bytes=100-199")).await;
+        assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(header_of(&response, header::CONTENT_LENGTH), "100");
+        assert_eq!(
+            header_of(&response, header::CONTENT_RANGE),
+            format!("bytes 100-199/{}", contents.len())
+        );
+        assert_eq!(body_of(response).await, &contents[100..200]);
+
+        let response = serve(&dir, "thing.mp4", &with_range("bytes=1000000-")).await;
+        assert_eq!(response.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+        assert_eq!(
+            header_of(&response, header::CONTENT_RANGE),
+            format!("bytes */{}", contents.len())
+        );
+
+        let response = serve(&dir, "missing.mp4", &HeaderMap::new()).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let response = serve(&dir, "../thing.mp4", &HeaderMap::new()).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
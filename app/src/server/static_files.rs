@@ -0,0 +1,284 @@
+use std::{
+    convert::Infallible,
+    path::{Component, Path, PathBuf},
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use futures::Future;
+use hyper::{header, service::Service, Body, Method, Request, Response, StatusCode};
+
+use super::{broadcast::BroadcastHub, recordings, stats::ServerStats};
+
+// served when there's no static directory or the file isn't in it
+const EMBEDDED_ASSETS: [(&str, &[u8]); 3] = [
+    ("index.html", include_bytes!("../static/index.html")),
+    ("style.css", include_bytes!("../static/style.css")),
+    ("main.js", include_bytes!("../static/main.js")),
+];
+
+// the version of the text exposition format `ServerStats::to_prometheus` produces
+const PROMETHEUS_CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+
+/// Serves the page, either from `static_dir` or from the assets baked into the binary.
+///
+/// With a hub it also serves `GET /stats` and `GET /metrics` for Prometheus, see `ServerStats`,
+/// and `GET /healthz`, which fails with `503` once the recorder has stopped.
+/// With a recordings directory it serves the files in it at `GET /recordings/<name>`,
+/// see `recordings::serve`.
+#[derive(Debug, Clone)]
+pub(super) struct StaticPageService {
+    static_dir: Option<Arc<Path>>,
+    hub: Option<BroadcastHub>,
+    recordings_dir: Option<Arc<Path>>,
+}
+
+impl StaticPageService {
+    pub(super) fn new(static_dir: Option<PathBuf>) -> Self {
+        Self {
+            static_dir: static_dir.map(Arc::from),
+            hub: None,
+            recordings_dir: None,
+        }
+    }
+
+    pub(super) fn with_hub(mut self, hub: BroadcastHub) -> Self {
+        self.hub = Some(hub);
+        self
+    }
+
+    pub(super) fn with_recordings(mut self, recordings_dir: Option<PathBuf>) -> Self {
+        self.recordings_dir = recordings_dir.map(Arc::from);
+        self
+    }
+}
+
+impl Service<Request<Body>> for StaticPageService {
+    type Response = Response<Body>;
+
+    type Error = Infallible;
+
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: Request<Body>) -> Self::Future {
+        let static_dir = self.static_dir.clone();
+        let hub = self.hub.clone();
+        let recordings_dir = self.recordings_dir.clone();
+
+        Box::pin(async move {
+            if req.method() != Method::GET {
+                return Ok(status_response(StatusCode::NOT_FOUND));
+            }
+
+            match (req.uri().path(), hub) {
+                ("/stats", Some(hub)) => {
+                    let json = ServerStats::collect(&hub).to_json();
+                    return Ok(asset_response(Path::new("stats.json"), json.into()));
+                }
+                ("/metrics", Some(hub)) => {
+                    let text = ServerStats::collect(&hub).to_prometheus();
+                    let response = Response::builder()
+                        .header(header::CONTENT_TYPE, PROMETHEUS_CONTENT_TYPE)
+                        .body(text.into())
+                        .unwrap();
+                    return Ok(response);
+                }
+                ("/healthz", hub) => {
+                    if hub.is_some_and(|hub| !hub.recorder().is_running()) {
+                        return Ok(text_response(StatusCode::SERVICE_UNAVAILABLE, "recorder stopped"));
+                    }
+                    return Ok(text_response(StatusCode::OK, "ok"));
+                }
+                _ => (),
+            }
+
+            let recording = req.uri().path().strip_prefix(recordings::PATH_PREFIX);
+            if let (Some(name), Some(dir)) = (recording, recordings_dir) {
+                return Ok(recordings::serve(&dir, name, req.headers()).await);
+            }
+
+            let Some(relative_path) = asset_path(req.uri().path()) else {
+                return Ok(status_response(StatusCode::FORBIDDEN));
+            };
+
+            if let Some(static_dir) = static_dir {
+                if let Ok(contents) = tokio::fs::read(static_dir.join(&relative_path)).await {
+                    return Ok(asset_response(&relative_path, contents.into()));
+                }
+            }
+
+            let embedded = EMBEDDED_ASSETS
+                .iter()
+                .find(|(name, _)| relative_path == Path::new(name));
+
+            let response = match embedded {
+                Some(&(_, contents)) => asset_response(&relative_path, contents.into()),
+                None => status_response(StatusCode::NOT_FOUND),
+            };
+
+            Ok(response)
+        })
+    }
+}
+
+/// Maps the request path to a file path relative to the static directory,
+/// `None` if it tries to get out of it.
+fn asset_path(request_path: &str) -> Option<PathBuf> {
+    let relative_path = match request_path {
+        "/" => "index.html",
+        "/stylesheet" => "style.css",
+        "/script" => "main.js",
+        path => path.trim_start_matches('/'),
+    };
+
+    // only plain file names, no `..`, no roots and no drive prefixes
+    let is_contained = Path::new(relative_path)
+        .components()
+        .all(|component| matches!(component, Component::Normal(_)));
+
+    // a backslash is a separator on windows
+    (is_contained && !relative_path.contains('\\')).then(|| PathBuf::from(relative_path))
+}
+
+pub(super) fn content_type(path: &Path) -> &'static str {
+    let extension = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(str::to_ascii_lowercase);
+
+    match extension.as_deref() {
+        Some("html" | "htm") => "text/html; charset=utf-8",
+        Some("css") => "text/css; charset=utf-8",
+        Some("js" | "mjs") => "text/javascript; charset=utf-8",
+        Some("json") => "application/json",
+        Some("txt") => "text/plain; charset=utf-8",
+        Some("svg") => "image/svg+xml",
+        Some("png") => "image/png",
+        Some("jpg" | "jpeg") => "image/jpeg",
+        Some("ico") => "image/x-icon",
+        Some("wasm") => "application/wasm",
+        Some("mp4") => "video/mp4",
+        Some("mkv") => "video/x-matroska",
+        Some("h264") => "video/h264",
+        _ => "application/octet-stream",
+    }
+}
+
+fn asset_response(path: &Path, body: Body) -> Response<Body> {
+    Response::builder()
+        .header(header::CONTENT_TYPE, content_type(path))
+        .body(body)
+        .unwrap()
+}
+
+fn text_response(status: StatusCode, text: &'static str) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header(header::CONTENT_TYPE, "text/plain; charset=utf-8")
+        .body(text.into())
+        .unwrap()
+}
+
+pub(super) fn status_response(status: StatusCode) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .body(Body::empty())
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    async fn get(service: &mut StaticPageService, path: &str) -> Response<Body> {
+        let req = Request::get(path).body(Body::empty()).unwrap();
+        service.call(req).await.unwrap()
+    }
+
+    fn content_type_of(response: &Response<Body>) -> &str {
+        response.headers()[header::CONTENT_TYPE].to_str().unwrap()
+    }
+
+    #[test]
+    fn content_type_inference() {
+        assert_eq!(
+            content_type(Path::new("index.html")),
+            "text/html; charset=utf-8"
+        );
+        assert_eq!(
+            content_type(Path::new("a/b/main.JS")),
+            "text/javascript; charset=utf-8"
+        );
+        assert_eq!(
+            content_type(Path::new("style.css")),
+            "text/css; charset=utf-8"
+        );
+        assert_eq!(
+            content_type(Path::new("no_extension")),
+            "application/octet-stream"
+        );
+    }
+
+    #[test]
+    fn traversal_is_rejected() {
+        assert_eq!(asset_path("/../secret"), None);
+        assert_eq!(asset_path("/assets/../../secret"), None);
+        assert_eq!(asset_path("/./secret"), None);
+        assert_eq!(asset_path("/..\\secret"), None);
+
+        assert_eq!(
+            asset_path("/assets/logo.png"),
+            Some(PathBuf::from("assets/logo.png"))
+        );
+    }
+
+    #[tokio::test]
+    async fn embedded_assets() {
+        let mut service = StaticPageService::new(None);
+
+        let response = get(&mut service, "/stylesheet").await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(content_type_of(&response), "text/css; charset=utf-8");
+
+        let response = get(&mut service, "/nothing_here.js").await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn static_dir_assets() {
+        let static_dir =
+            std::env::temp_dir().join(format!("static_dir_assets_{}", std::process::id()));
+        fs::create_dir_all(static_dir.join("assets")).unwrap();
+        fs::write(static_dir.join("assets/data.json"), "{}").unwrap();
+        fs::write(static_dir.join("main.js"), "// from disk").unwrap();
+
+        let mut service = StaticPageService::new(Some(static_dir.clone()));
+
+        let response = get(&mut service, "/assets/data.json").await;
+        assert_eq!(content_type_of(&response), "application/json");
+
+        // overrides the embedded one
+        let response = get(&mut service, "/script").await;
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        assert_eq!(body, "// from disk");
+
+        // falls back to the embedded one
+        let response = get(&mut service, "/").await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = get(&mut service, "/../secret").await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        let response = get(&mut service, "/missing.png").await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        fs::remove_dir_all(static_dir).unwrap();
+    }
+}
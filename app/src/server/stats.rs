@@ -0,0 +1,181 @@
+use std::fmt::Write;
+
+use screen_cap::record::RecordStats;
+
+use super::broadcast::{BroadcastHub, ClientStats};
+
+/// What `GET /stats` and `GET /metrics` report about the recorder and the clients
+#[derive(Debug, Clone, PartialEq)]
+pub struct ServerStats {
+    pub recorder: RecordStats,
+    pub buffer_bytes_used: usize,
+    pub buffer_capacity: usize,
+    /// Frames per second the encoder has been handling, see `Recorder::measured_rate`
+    pub encode_rate: f64,
+    pub clients: Vec<ClientStats>,
+}
+
+impl ServerStats {
+    pub fn collect(hub: &BroadcastHub) -> Self {
+        let recorder = hub.recorder();
+        let view = recorder.buffer_view();
+
+        Self {
+            recorder: recorder.stats(),
+            buffer_bytes_used: view.bytes_used(),
+            buffer_capacity: view.capacity(),
+            encode_rate: recorder.measured_rate(),
+            clients: hub.client_stats(),
+        }
+    }
+
+    /// Everything is a number, so there's nothing to escape
+    pub fn to_json(&self) -> String {
+        let RecordStats {
+            frames_encoded,
+            frames_skipped,
+            keyframes,
+            bytes_flushed,
+        } = self.recorder;
+
+        let clients = self
+            .clients
+            .iter()
+            .fold(String::new(), |mut out, client| {
+                if !out.is_empty() {
+                    out.push(',');
+                }
+                _ = write!(
+                    out,
+                    "{{\"id\":{},\"dropped_chunks\":{}}}",
+                    client.id, client.dropped_chunks
+                );
+                out
+            });
+
+        let encode_rate = self.finite_encode_rate();
+
+        format!(
+            "{{\"frames_encoded\":{frames_encoded},\"frames_skipped\":{frames_skipped},\
+             \"keyframes\":{keyframes},\"bytes_flushed\":{bytes_flushed},\
+             \"buffer_bytes_used\":{},\"buffer_capacity\":{},\"encode_rate\":{encode_rate},\
+             \"connected_clients\":{},\"clients\":[{clients}]}}",
+            self.buffer_bytes_used,
+            self.buffer_capacity,
+            self.clients.len(),
+        )
+    }
+    /// The Prometheus text exposition format, for `GET /metrics`
+    pub fn to_prometheus(&self) -> String {
+        let RecordStats {
+            frames_encoded,
+            frames_skipped,
+            keyframes,
+            bytes_flushed,
+        } = self.recorder;
+
+        let metrics: [(&str, &str, &str, f64); 8] = [
+            (
+                "transscreen_frames_encoded_total",
+                "counter",
+                "Frames handed to the encoder",
+                frames_encoded as f64,
+            ),
+            (
+                "transscreen_frames_skipped_total",
+                "counter",
+                "Frames the capturer had nothing new for",
+                frames_skipped as f64,
+            ),
+            (
+                "transscreen_keyframes_total",
+                "counter",
+                "Encoded frames that were keyframes",
+                keyframes as f64,
+            ),
+            (
+                "transscreen_bytes_flushed_total",
+                "counter",
+                "Encoded bytes that made it into the ring buffer",
+                bytes_flushed as f64,
+            ),
+            (
+                "transscreen_buffer_bytes_used",
+                "gauge",
+                "Bytes taken up by the chunks in the ring buffer",
+                self.buffer_bytes_used as f64,
+            ),
+            (
+                "transscreen_buffer_capacity_bytes",
+                "gauge",
+                "Size of the ring buffer",
+                self.buffer_capacity as f64,
+            ),
+            (
+                "transscreen_connected_clients",
+                "gauge",
+                "Clients connected over the websocket",
+                self.clients.len() as f64,
+            ),
+            (
+                "transscreen_encode_rate_fps",
+                "gauge",
+                "Frames per second the encoder has been handling",
+                self.finite_encode_rate(),
+            ),
+        ];
+
+        metrics
+            .iter()
+            .fold(String::new(), |mut out, (name, kind, help, value)| {
+                _ = writeln!(out, "# HELP {name} {help}");
+                _ = writeln!(out, "# TYPE {name} {kind}");
+                _ = writeln!(out, "{name} {value}");
+                out
+            })
+    }
+
+    // infinity and NaN don't fit in JSON, and the rate is 0 until it's been measured anyway
+    fn finite_encode_rate(&self) -> f64 {
+        if self.encode_rate.is_finite() {
+            self.encode_rate
+        } else {
+            0.0
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prometheus_format() {
+        let stats = ServerStats {
+            recorder: RecordStats {
+                frames_encoded: 120,
+                frames_skipped: 3,
+                keyframes: 2,
+                bytes_flushed: 65536,
+            },
+            buffer_bytes_used: 4096,
+            buffer_capacity: 8192,
+            encode_rate: 59.5,
+            clients: vec![ClientStats {
+                id: 1,
+                dropped_chunks: 0,
+            }],
+        };
+
+        let text = stats.to_prometheus();
+
+        assert!(text.contains(
+            "# TYPE transscreen_frames_encoded_total counter\ntransscreen_frames_encoded_total 120\n"
+        ));
+        assert!(text.contains("\ntransscreen_bytes_flushed_total 65536\n"));
+        assert!(text.contains("# TYPE transscreen_encode_rate_fps gauge\ntransscreen_encode_rate_fps 59.5\n"));
+        assert!(text.contains("\ntransscreen_connected_clients 1\n"));
+        // every sample has a type
+        assert_eq!(text.lines().filter(|line| line.starts_with("# TYPE")).count(), 8);
+    }
+}
@@ -1,7 +1,8 @@
 "use strict";
 const message_container = document.getElementById("message_container");
 const host = document.location.host;
-const socket = new WebSocket(`ws://${host}/websocket`);
+// passes the token along when the page is opened as /?token=...
+const socket = new WebSocket(`ws://${host}/websocket${location.search}`);
 socket.onmessage = (event) => {
     const message = document.createElement("p");
     if (typeof event.data !== "string") {
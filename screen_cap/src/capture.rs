@@ -1,35 +1,509 @@
 use scrap::{Capturer, Display};
-use std::{io, ops::Deref};
+use std::{
+    io::{self, ErrorKind},
+    mem,
+    ops::Deref,
+    panic,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc, Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+use parking_lot::Mutex;
 use utils::{
-    multibuffer::{MultiBuffer, MultiBufferView},
-    threading::{ThreadLoop, ThreadWork},
+    multibuffer::{TripleBuffer, TripleBufferView},
+    threading::{ThreadLoop, ThreadLoopBuilder, ThreadLoopControl, ThreadScheduling, ThreadWork},
+};
+
+use crate::{
+    frame::{self, AreaScaler, FrameError, FrameFormat, FrameGuard, FrameSource},
+    record::clock::{Clock, RecordClock},
 };
 
-use crate::frame::{FrameError, FrameGuard};
+/// A rectangle of the display to capture instead of the whole thing, in pixels
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CaptureRegion {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl CaptureRegion {
+    /// Fails if the region is empty or doesn't fit inside a display of the given size
+    pub fn validate(&self, display_width: usize, display_height: usize) -> io::Result<()> {
+        let fits = self.x + self.width <= display_width && self.y + self.height <= display_height;
+
+        if self.width == 0 || self.height == 0 || !fits {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("{self:?} doesn't fit inside a {display_width}x{display_height} display"),
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Copies the region out of a BGRA frame row by row into `dst`, leaving it tightly packed.
+    ///
+    /// `stride` is the length of a row of `frame` in bytes,
+    /// which may be larger than `4 * width` (e.g. on macos).
+    /// `dst` gets overwritten, it's only reallocated if it's too small for the region.
+    pub fn crop_bgra(&self, frame: &[u8], stride: usize, dst: &mut Vec<u8>) {
+        let row_len = self.width * 4;
+        let start = self.x * 4;
+
+        dst.resize(row_len * self.height, 0);
+
+        let rows = frame.chunks(stride).skip(self.y).take(self.height);
+        for (dst_row, row) in dst.chunks_exact_mut(row_len).zip(rows) {
+            dst_row.copy_from_slice(&row[start..start + row_len]);
+        }
+    }
+}
+
+/// The size to scale the frames to before they get encoded, e.g. to encode a 4K display at 1080p.
+///
+/// The scaling happens on the capture thread after cropping to the region, see `AreaScaler`.
+/// Trades picture quality for a lot less work for the encoder.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ScalingSettings {
+    pub target_width: usize,
+    pub target_height: usize,
+}
+
+impl ScalingSettings {
+    /// Fails if the target size is empty
+    pub fn validate(&self) -> io::Result<()> {
+        if self.target_width == 0 || self.target_height == 0 {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("can't scale the frames down to {self:?}"),
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+/// How the capture thread recovers when the capturer fails, instead of failing on every frame from then on.
+///
+/// The capturer gets dropped and recreated with the display factory, waiting `backoff` before every attempt.
+/// `frame` returns `FrameError::Restarting` while that's going on, `FrameError::Restarted` once it worked,
+/// and `FrameError::RestartsExhausted` for good once `max_attempts` attempts in a row have failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RestartPolicy {
+    pub max_attempts: u32,
+    pub backoff: Duration,
+}
+
+impl Default for RestartPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            backoff: Duration::from_millis(500),
+        }
+    }
+}
+
+// keeps track of the attempts to bring back a failed capturer
+#[derive(Debug, Clone, Copy)]
+struct Restarter {
+    policy: RestartPolicy,
+    // attempts made since the capturer failed
+    attempts: u32,
+}
+
+impl Restarter {
+    fn new(policy: RestartPolicy) -> Self {
+        Self { policy, attempts: 0 }
+    }
+
+    // what to report after the capturer or an attempt to recreate it failed with `error`
+    fn failed(&self, error: io::Error) -> FrameError {
+        if self.attempts >= self.policy.max_attempts {
+            return FrameError::RestartsExhausted {
+                attempts: self.attempts,
+            };
+        }
+
+        FrameError::Restarting {
+            attempt: self.attempts + 1,
+            source: error,
+        }
+    }
+
+    // waits out the backoff and tries `recreate`, which fails the same way `CaptureWorker::reinit` does
+    fn restart(&mut self, recreate: impl FnOnce() -> Result<(), FrameError>) -> Result<(), FrameError> {
+        if self.attempts >= self.policy.max_attempts {
+            return Err(FrameError::RestartsExhausted {
+                attempts: self.attempts,
+            });
+        }
+
+        thread::sleep(self.policy.backoff);
+        self.attempts += 1;
+
+        match recreate() {
+            Err(FrameError::Error(e) | FrameError::Disconnected(e)) => Err(self.failed(e)),
+            Ok(()) | Err(FrameError::Skipped) => Err(FrameError::Restarted {
+                attempts: mem::take(&mut self.attempts),
+            }),
+            // a resize means a fresh start for the recorder anyway
+            result => {
+                self.attempts = 0;
+                result
+            }
+        }
+    }
+}
+
+/// A snapshot of the capture thread's counters, see `ThreadedCapturer::capture_stats`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CaptureStats {
+    /// Frames scrap handed over, whether they made it into the frame buffer or not
+    pub captured: u64,
+    /// Frames that made it into the frame buffer, whether anyone got to see them or not.
+    ///
+    /// Fewer than `captured`, a frame that's the same as the one before doesn't get published again,
+    /// and neither does one that comes while every other buffer is being read.
+    pub published: u64,
+    /// Updates scrap had no new frame for, see `FrameError::Skipped`
+    pub skipped: u64,
+}
+
+// updated by the capture thread, read by anyone holding the capturer
+#[derive(Debug, Default)]
+struct CaptureCounters {
+    captured: AtomicU64,
+    published: AtomicU64,
+    skipped: AtomicU64,
+}
+
+impl CaptureCounters {
+    fn record(&self, result: &Result<(), FrameError>) {
+        match result {
+            Ok(()) => self.captured.fetch_add(1, Ordering::Relaxed),
+            Err(FrameError::Skipped) => self.skipped.fetch_add(1, Ordering::Relaxed),
+            Err(_) => return,
+        };
+    }
+
+    fn published(&self) {
+        self.published.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn snapshot(&self) -> CaptureStats {
+        CaptureStats {
+            captured: self.captured.load(Ordering::Relaxed),
+            published: self.published.load(Ordering::Relaxed),
+            skipped: self.skipped.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// A frame as the capture thread publishes it, see `ThreadedCapturer::frame_view`.
+///
+/// Derefs into the frame's data.
+#[derive(Debug, Clone, Default)]
+pub struct CapturedFrame {
+    pub data: Vec<u8>,
+    /// When the frame was first captured, `None` before the first frame.
+    ///
+    /// An unchanged screen doesn't get published again, so this can be a while ago on a static screen.
+    pub captured_at: Option<Instant>,
+}
+
+impl Deref for CapturedFrame {
+    type Target = [u8];
+
+    #[inline]
+    fn deref(&self) -> &Self::Target {
+        &self.data
+    }
+}
+
+// where the capture times come from, and when the front frame was last captured again, for `frame_with_age`.
+// An unchanged frame doesn't get published again but it's still as fresh as the capture that produced it,
+// that's only known after it's been published, so it's kept out here along with the capture time of the frame
+// it applies to, instead of going to whichever frame is in front by the time it's read
+#[derive(Debug, Default)]
+struct FrameTimes {
+    clock: Mutex<RecordClock>,
+    // (when the front frame was first captured, when it was captured again)
+    recaptured: Mutex<Option<(Instant, Instant)>>,
+}
+
+impl FrameTimes {
+    fn now(&self) -> Instant {
+        self.clock.lock().now()
+    }
+
+    fn recaptured(&self, captured_at: Instant, again_at: Instant) {
+        *self.recaptured.lock() = Some((captured_at, again_at));
+    }
+
+    // zero until the first frame
+    fn age(&self, frame: &CapturedFrame) -> Duration {
+        let Some(captured_at) = frame.captured_at else {
+            return Duration::ZERO;
+        };
+
+        let latest = match *self.recaptured.lock() {
+            Some((first, again)) if first == captured_at => again,
+            _ => captured_at,
+        };
+
+        self.now().saturating_duration_since(latest)
+    }
+}
+
+// whether a frame this old gets skipped
+fn too_old(age: Duration, max_age: Option<Duration>) -> bool {
+    max_age.is_some_and(|max_age| age > max_age)
+}
+
+/// The size of the frames captured from a display of the given size, the region's if there is one.
+///
+/// Fails if the region doesn't fit inside the display.
+pub fn frame_size(
+    display_width: usize,
+    display_height: usize,
+    region: Option<CaptureRegion>,
+) -> io::Result<(usize, usize)> {
+    match region {
+        Some(region) => {
+            region.validate(display_width, display_height)?;
+            Ok((region.width, region.height))
+        }
+        None => Ok((display_width, display_height)),
+    }
+}
+
+/// A display factory with its type erased, see `CapturerSettings::for_primary` and `CapturerSettings::for_display_index`
+pub type BoxedDisplayFactory = Box<dyn FnMut() -> io::Result<Display> + Send>;
+
+// Whether a frame of `len` bytes means the display got resized.
+// The stride isn't known up front (it's larger than the width on macos),
+// so the length of the first frame after creating the capturer is the baseline.
+fn frame_size_changed(len: usize, baseline: Option<usize>, width: usize, height: usize) -> bool {
+    len < width * height * 4 || baseline.is_some_and(|baseline| baseline != len)
+}
+
+// Copies a frame into a buffer that gets reused for every frame.
+// The length only changes along with the frame size, so apart from that this is a plain memcpy
+// that leaves the allocation alone.
+// Copying can't be avoided entirely, scrap's frames borrow the capturer until the next one is taken.
+fn copy_frame(frame: &[u8], dst: &mut Vec<u8>) {
+    dst.resize(frame.len(), 0);
+    dst.copy_from_slice(frame);
+}
 
 // capturer that will be working in the ThreadLoop
 struct CaptureWorker {
-    capturer: Capturer,
-    frame_buf: MultiBuffer<Vec<u8>>,
+    // only None if recreating it after a resize has failed, it's retried on the next update
+    capturer: Option<Capturer>,
+    display_factory: BoxedDisplayFactory,
+    frame_buf: TripleBuffer<CapturedFrame>,
+    format: FrameFormat,
+    // of the display, not the region
+    width: usize,
+    height: usize,
+    // length of the first frame since the capturer was created
+    frame_len: Option<usize>,
+    region: Option<CaptureRegion>,
+    scaling: Option<ScalingSettings>,
+    // built for the current size of the cropped frame, None until the first frame
+    scaler: Option<AreaScaler>,
+    // the cropped or repacked frame before it gets scaled or converted to I420
+    crop_buf: Vec<u8>,
+    // the scaled frame before it gets converted to I420
+    scale_buf: Vec<u8>,
+    counters: Arc<CaptureCounters>,
+    frame_times: Arc<FrameTimes>,
+    // None leaves failed capturers alone, except for disconnects
+    restarter: Option<Restarter>,
 }
 
 impl CaptureWorker {
-    fn new(display: Display, frame_buf: MultiBuffer<Vec<u8>>) -> io::Result<Self> {
+    #[allow(clippy::too_many_arguments)]
+    fn new(
+        mut display_factory: BoxedDisplayFactory,
+        frame_buf: TripleBuffer<CapturedFrame>,
+        format: FrameFormat,
+        region: Option<CaptureRegion>,
+        scaling: Option<ScalingSettings>,
+        counters: Arc<CaptureCounters>,
+        frame_times: Arc<FrameTimes>,
+        restart: Option<RestartPolicy>,
+    ) -> io::Result<Self> {
+        let display = display_factory()?;
+        let width = display.width();
+        let height = display.height();
+
         Ok(Self {
-            capturer: Capturer::new(display)?,
+            capturer: Some(Capturer::new(display)?),
+            display_factory,
             frame_buf,
+            format,
+            width,
+            height,
+            frame_len: None,
+            region,
+            scaling,
+            scaler: None,
+            crop_buf: Vec::new(),
+            scale_buf: Vec::new(),
+            counters,
+            frame_times,
+            restarter: restart.map(Restarter::new),
         })
     }
 
+    // recreates the capturer for the display's current resolution
+    fn reinit(&mut self) -> Result<(), FrameError> {
+        // some platforms only allow one capturer per display
+        self.capturer = None;
+        self.frame_len = None;
+
+        // e.g. the monitor got unplugged, retried the same as a capturer that can't be created
+        let display = (self.display_factory)().map_err(FrameError::Disconnected)?;
+        let width = display.width();
+        let height = display.height();
+
+        if let Some(region) = self.region {
+            region.validate(width, height).map_err(FrameError::Error)?;
+        }
+
+        // the display may still be gone, in which case this is retried on the next update
+        self.capturer = Some(Capturer::new(display)?);
+
+        let resized = (width, height) != (self.width, self.height);
+        self.width = width;
+        self.height = height;
+
+        match (self.region, self.scaling) {
+            (None, None) if resized => Err(FrameError::Resized { width, height }),
+            // the frames stay the size of the region or the scaled size
+            _ => Err(FrameError::Skipped),
+        }
+    }
+
     fn update(&mut self) -> Result<(), FrameError> {
-        let frame = match self.capturer.frame() {
+        let Some(capturer) = &mut self.capturer else {
+            let Some(mut restarter) = self.restarter else {
+                return self.reinit();
+            };
+
+            let result = restarter.restart(|| self.reinit());
+            self.restarter = Some(restarter);
+
+            return result;
+        };
+
+        let frame = match capturer.frame() {
             Ok(f) => f,
-            Err(e) => return Err(e.into()),
+            Err(e) => {
+                // the capturer is useless now, a new one is created on the next update
+                return match (FrameError::from(e), &self.restarter) {
+                    (FrameError::Error(e) | FrameError::Disconnected(e), Some(restarter)) => {
+                        self.capturer = None;
+                        Err(restarter.failed(e))
+                    }
+                    (e @ FrameError::Disconnected(_), None) => {
+                        self.capturer = None;
+                        Err(e)
+                    }
+                    (e, _) => Err(e),
+                };
+            }
         };
 
-        self.frame_buf.back_mut().clear();
-        self.frame_buf.back_mut().extend_from_slice(&frame);
-        self.frame_buf.swap();
+        if frame_size_changed(frame.len(), self.frame_len, self.width, self.height) {
+            return self.reinit();
+        }
+        self.frame_len = Some(frame.len());
+        let captured_at = self.frame_times.now();
+
+        // the stride may be larger than the width on macos
+        // https://github.com/quadrupleslap/scrap/issues/44#issuecomment-1486345836
+        let stride = frame.len() / self.height;
+
+        if let Some(scaling) = self.scaling {
+            // cropping first, so only the region gets scaled
+            let (src, src_stride, src_size) = match self.region {
+                Some(region) => {
+                    region.crop_bgra(&frame, stride, &mut self.crop_buf);
+                    (&self.crop_buf[..], region.width * 4, (region.width, region.height))
+                }
+                None => (&frame[..], stride, (self.width, self.height)),
+            };
+
+            let dst_size = (scaling.target_width, scaling.target_height);
+            let scaler = match &mut self.scaler {
+                Some(scaler) if scaler.src_size() == src_size => scaler,
+                // the display got resized
+                scaler => scaler.insert(AreaScaler::new(src_size, dst_size)),
+            };
+
+            match self.format {
+                FrameFormat::Bgra => scaler.scale_bgra(src, src_stride, &mut self.frame_buf.back_mut().data),
+                format => {
+                    scaler.scale_bgra(src, src_stride, &mut self.scale_buf);
+
+                    let (width, height) = dst_size;
+                    format.convert_bgra(&self.scale_buf, width, height, &mut self.frame_buf.back_mut().data);
+                }
+            }
+        } else {
+            match (self.format, self.region) {
+                (FrameFormat::Bgra, None) => {
+                    copy_frame(&frame, &mut self.frame_buf.back_mut().data);
+                }
+                (FrameFormat::Bgra, Some(region)) => {
+                    region.crop_bgra(&frame, stride, &mut self.frame_buf.back_mut().data);
+                }
+                (format, None) => {
+                    let frame_data = frame::packed_bgra(&frame, self.width, self.height, &mut self.crop_buf);
+
+                    format.convert_bgra(frame_data, self.width, self.height, &mut self.frame_buf.back_mut().data);
+                }
+                (format, Some(region)) => {
+                    region.crop_bgra(&frame, stride, &mut self.crop_buf);
+
+                    format.convert_bgra(
+                        &self.crop_buf,
+                        region.width,
+                        region.height,
+                        &mut self.frame_buf.back_mut().data,
+                    );
+                }
+            }
+        }
+
+        self.frame_buf.back_mut().captured_at = Some(captured_at);
+
+        // A static screen keeps producing the same frame, there's no point in publishing it again.
+        // The first frame always is, even a black one that's the same as the zeroed buffer it replaces
+        let front = self.frame_buf.view();
+        let unchanged_front = front
+            .try_front()
+            .filter(|front| front.data == self.frame_buf.back().data)
+            .and_then(|front| front.captured_at);
+
+        match unchanged_front {
+            // just as fresh as the new frame
+            Some(front_captured_at) => self.frame_times.recaptured(front_captured_at, captured_at),
+            // never blocks on readers, if all the other buffers are being read
+            // the frame is simply dropped and the readers keep seeing the previous one
+            None => {
+                if self.frame_buf.swap() {
+                    self.counters.published();
+                }
+            }
+        }
 
         Ok(())
     }
@@ -39,62 +513,698 @@ impl ThreadWork for CaptureWorker {
     type WorkResult = Result<(), FrameError>;
 
     #[inline]
-    fn work(&mut self) -> Self::WorkResult {
-        self.update()
+    fn work(&mut self) -> Option<Self::WorkResult> {
+        let result = self.update();
+        self.counters.record(&result);
+
+        // `frame` waits for every iteration, even the skipped ones
+        Some(result)
+    }
+}
+
+/// Builds a display factory that always resolves to the display at `index` in the list returned by `list_displays`.
+///
+/// The list is queried again on every call, since the factory gets called again whenever the worker restarts.
+/// Fails if there is no display at `index` right now,
+/// the factory fails the same way if the display goes away later on.
+pub fn display_index_factory<D, L>(
+    index: usize,
+    mut list_displays: L,
+) -> io::Result<impl FnMut() -> io::Result<D> + Send + 'static>
+where
+    L: FnMut() -> io::Result<Vec<D>> + Send + 'static,
+{
+    let display_count = list_displays()?.len();
+
+    let missing = move |display_count| {
+        io::Error::new(
+            ErrorKind::NotFound,
+            format!("there is no display with index {index}, only {display_count} displays found"),
+        )
+    };
+
+    if index >= display_count {
+        return Err(missing(display_count));
     }
+
+    Ok(move || {
+        let mut displays = list_displays()?;
+        if index >= displays.len() {
+            return Err(missing(displays.len()));
+        }
+
+        Ok(displays.swap_remove(index))
+    })
 }
 
 pub struct ThreadedCapturer {
     thread_loop: ThreadLoop<CaptureWorker>,
-    frame_buf: MultiBufferView<Vec<u8>>,
+    frame_buf: TripleBufferView<CapturedFrame>,
+    format: FrameFormat,
+    width: usize,
+    height: usize,
+    counters: Arc<CaptureCounters>,
+    frame_times: Arc<FrameTimes>,
+    max_frame_age: Option<Duration>,
+}
+
+// `Capturer` isn't `Send`, so it has to be created on the capture thread.
+// Waits for `worker_factory` to finish there, so its error can be returned on this thread instead of getting lost.
+fn start_capture_loop<W, F>(
+    worker_factory: F,
+    target_rate: f64,
+    scheduling: ThreadScheduling,
+) -> io::Result<ThreadLoop<W>>
+where
+    W: ThreadWork + 'static,
+    F: FnOnce() -> io::Result<W> + Send + 'static,
+{
+    let (init_tx, init_rx) = mpsc::sync_channel(1);
+
+    let worker_factory = move || match worker_factory() {
+        Ok(worker) => {
+            let _ = init_tx.send(Ok(()));
+            worker
+        }
+        Err(e) => {
+            let _ = init_tx.send(Err(e));
+            // the error has already been delivered, so the thread just has to stop
+            // resume_unwind skips the panic hook, so nothing gets printed
+            panic::resume_unwind(Box::new("couldn't create the capturer"))
+        }
+    };
+
+    let thread_loop = ThreadLoopBuilder::new(worker_factory)
+        .name("screen-capture")
+        .scheduling(scheduling)
+        .start_loop(target_rate)?;
+
+    match init_rx.recv() {
+        Ok(result) => result.map(|_| thread_loop),
+        // the factory panicked before it could report anything
+        Err(_) => Err(io::Error::other("the capture thread exited while creating the capturer")),
+    }
 }
 
 impl ThreadedCapturer {
-    pub fn new<F>(mut display_factory: F, target_rate: f64) -> Self
+    /// Panics if the capturer couldn't be created, see `try_new`
+    pub fn new<F>(display_factory: F, target_rate: f64) -> Self
     where
-        F: FnMut() -> Display + Send + 'static,
+        F: FnMut() -> io::Result<Display> + Send + 'static,
     {
-        let display = display_factory();
-        let width = display.width();
-        let height = display.height();
+        Self::try_new(display_factory, target_rate).expect("Couldn't create the capturer")
+    }
+
+    /// Fails if the capturer couldn't be created,
+    /// e.g. when the screen recording permission hasn't been granted
+    pub fn try_new<F>(display_factory: F, target_rate: f64) -> io::Result<Self>
+    where
+        F: FnMut() -> io::Result<Display> + Send + 'static,
+    {
+        Self::with_region(display_factory, target_rate, FrameFormat::Bgra, None)
+    }
+
+    /// Same as `new`, except the frames are converted into `format` on the capture thread.
+    ///
+    /// Converting to `FrameFormat::I420` or `FrameFormat::Nv12` here takes the color conversion off of the encoding thread,
+    /// letting it overlap with the encoding of the previous frame.
+    pub fn with_format<F>(display_factory: F, target_rate: f64, format: FrameFormat) -> Self
+    where
+        F: FnMut() -> io::Result<Display> + Send + 'static,
+    {
+        Self::with_region(display_factory, target_rate, format, None)
+            .expect("Couldn't create the capturer")
+    }
+
+    /// Same as `with_format`, except only `region` of the display gets captured if it's set.
+    ///
+    /// The frames are the size of the region then.
+    /// Fails if the region doesn't fit inside the display or if the capturer couldn't be created.
+    pub fn with_region<F>(
+        display_factory: F,
+        target_rate: f64,
+        format: FrameFormat,
+        region: Option<CaptureRegion>,
+    ) -> io::Result<Self>
+    where
+        F: FnMut() -> io::Result<Display> + Send + 'static,
+    {
+        Self::with_options(
+            display_factory,
+            target_rate,
+            format,
+            region,
+            None,
+            None,
+            ThreadScheduling::default(),
+        )
+    }
+
+    /// Same as `with_region`, except the frames get scaled to the target size of `scaling` if it's set,
+    /// and the capturer gets recreated according to `restart` when it fails, see `RestartPolicy`.
+    /// The capture thread gets `scheduling` applied before anything else.
+    ///
+    /// The frames are the target size then, whatever the size of the display or the region.
+    /// Also fails if the target size is empty.
+    pub fn with_options<F>(
+        mut display_factory: F,
+        target_rate: f64,
+        format: FrameFormat,
+        region: Option<CaptureRegion>,
+        scaling: Option<ScalingSettings>,
+        restart: Option<RestartPolicy>,
+        scheduling: ThreadScheduling,
+    ) -> io::Result<Self>
+    where
+        F: FnMut() -> io::Result<Display> + Send + 'static,
+    {
+        let display = display_factory()?;
 
-        let frame_buf = vec![0_u8; width * height * 4];
-        let frame_buf = MultiBuffer::new(frame_buf);
+        let (width, height) = frame_size(display.width(), display.height(), region)?;
+        let (width, height) = match scaling {
+            Some(scaling) => {
+                scaling.validate()?;
+                (scaling.target_width, scaling.target_height)
+            }
+            None => (width, height),
+        };
+
+        let frame_buf = CapturedFrame {
+            data: vec![0_u8; format.frame_len(width, height)],
+            captured_at: None,
+        };
+        let frame_buf = TripleBuffer::new(frame_buf);
         let frame_buf_reader = frame_buf.view();
 
+        let counters = Arc::new(CaptureCounters::default());
+        let worker_counters = counters.clone();
+
+        let frame_times = Arc::new(FrameTimes::default());
+        let worker_frame_times = frame_times.clone();
+
         let worker_factory = move || {
-            // no way to propagate that error for now
-            // so we just halt and catch fire
-            CaptureWorker::new(display_factory(), frame_buf).unwrap()
+            CaptureWorker::new(
+                Box::new(display_factory),
+                frame_buf,
+                format,
+                region,
+                scaling,
+                worker_counters,
+                worker_frame_times,
+                restart,
+            )
         };
 
-        let thread_loop = ThreadLoop::new(worker_factory, target_rate);
+        let thread_loop = start_capture_loop(worker_factory, target_rate, scheduling)?;
 
-        Self {
+        Ok(Self {
             thread_loop,
             frame_buf: frame_buf_reader,
-        }
+            format,
+            width,
+            height,
+            counters,
+            frame_times,
+            max_frame_age: None,
+        })
+    }
+
+    /// Width of the captured frames in pixels, the scaled or the region's if set
+    #[inline]
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    /// Height of the captured frames in pixels, the scaled or the region's if set
+    #[inline]
+    pub fn height(&self) -> usize {
+        self.height
     }
 
+    /// Read-only access to the captured frames, independent of `frame`.
+    ///
+    /// Allows other consumers to look at the latest frame on their own schedule,
+    /// since a display can only be captured by one `Capturer` at a time.
+    #[inline]
+    pub fn frame_view(&self) -> TripleBufferView<CapturedFrame> {
+        self.frame_buf.clone()
+    }
+
+    /// Changes how often the screen gets captured, starting from the next frame
+    #[inline]
+    pub fn set_target_rate(&self, target_rate: f64) {
+        self.thread_loop.set_rate(target_rate);
+    }
+
+    /// Controls the capture thread, even after the capturer has been moved into another worker
+    #[inline]
+    pub fn control(&self) -> ThreadLoopControl {
+        self.thread_loop.control()
+    }
+
+    /// How many frames the capture thread got from scrap, how many of them it published
+    /// and how many times it got nothing, since it started.
+    ///
+    /// Counted on the capture thread, so frames nobody waited for in `frame` are included.
+    #[inline]
+    pub fn capture_stats(&self) -> CaptureStats {
+        self.counters.snapshot()
+    }
+
+    /// Where the capture times come from, `None` is the system's monotonic clock, see `EncoderSettings::clock`
+    pub fn set_clock(&self, clock: Option<Arc<dyn Clock>>) {
+        *self.frame_times.clock.lock() = RecordClock::new(clock);
+    }
+
+    /// Makes `next_frame` skip frames that were captured more than `max_age` ago, `None` takes any frame.
+    ///
+    /// For a low-latency stream, a late frame is better dropped than encoded, see `frame_with_age`.
+    #[inline]
+    pub fn set_max_frame_age(&mut self, max_age: Option<Duration>) {
+        self.max_frame_age = max_age;
+    }
+
+    /// Waits for the next frame.
+    ///
+    /// Returns `FrameError::Resized` once if the display's resolution has changed,
+    /// `width` and `height` return the new size after that.
+    /// `FrameError::Disconnected` isn't fatal, frames keep coming once the display is back.
+    /// Neither are `FrameError::Restarting` and `FrameError::Restarted`, see `RestartPolicy`.
     pub fn frame(&mut self) -> Result<impl Deref<Target = [u8]> + '_, FrameError> {
+        self.frame_coalesced().map(|(frame, _)| frame)
+    }
+
+    /// Same as `frame`, along with how long ago the frame was captured.
+    ///
+    /// The capture thread runs on its own schedule, so the frame can be as old as the last capture that worked,
+    /// e.g. when the consumer had to wait for the frame buffer's lock and the capture thread dropped the newer ones.
+    /// A frame that stayed the same counts as captured again every time.
+    pub fn frame_with_age(&mut self) -> Result<(impl Deref<Target = [u8]> + '_, Duration), FrameError> {
+        let frame_times = self.frame_times.clone();
+        let (frame, _) = self.front_coalesced()?;
+
+        // the capture time comes with the frame, so it's this frame's age even if a newer one got published since
+        let age = frame_times.age(&frame);
+
+        Ok((FrameGuard::new(frame), age))
+    }
+
+    /// Same as `frame`, along with how many captured frames got replaced by a newer one since the last call,
+    /// i.e. frames that were captured but never seen by the caller
+    pub fn frame_coalesced(&mut self) -> Result<(impl Deref<Target = [u8]> + '_, u64), FrameError> {
+        self.front_coalesced()
+            .map(|(frame, coalesced)| (FrameGuard::new(frame), coalesced))
+    }
+
+    // `frame_coalesced` with the frame's capture time
+    fn front_coalesced(&mut self) -> Result<(impl Deref<Target = CapturedFrame> + '_, u64), FrameError> {
         // waits for the frame and bubbles up the error if there is one
-        self.thread_loop.work_recv().unwrap()?;
+        let result = self.thread_loop.work_recv()?;
+        if let Err(FrameError::Resized { width, height }) = result {
+            self.width = width;
+            self.height = height;
+        }
+        result?;
 
         // lock the frame buf
-        let frame_guard = FrameGuard::new(self.frame_buf.front());
+        let frame_guard = self.frame_buf.front();
 
         // clear the backlog of messages and get the last error if any
-        let error_iter = self.thread_loop.work_try_iter().filter_map(|message| {
-            message.err().filter(|e| {
-                // don't count skipped frames
-                matches!(e, FrameError::Error(_))
-            })
-        });
-
-        if let Some(e) = error_iter.last() {
+        let drained = self.thread_loop.drain_collect_errors();
+        let mut last_error = None;
+        for error in drained.errors {
+            match error {
+                FrameError::Skipped => (),
+                FrameError::Resized { width, height } => {
+                    self.width = width;
+                    self.height = height;
+                    last_error = Some(FrameError::Resized { width, height });
+                }
+                e => last_error = Some(e),
+            }
+        }
+
+        if let Some(e) = last_error {
             return Err(e);
         }
 
-        Ok(frame_guard)
+        Ok((frame_guard, drained.successes))
+    }
+}
+
+impl FrameSource for ThreadedCapturer {
+    #[inline]
+    fn next_frame(&mut self) -> Result<impl Deref<Target = [u8]> + '_, FrameError> {
+        let max_age = self.max_frame_age;
+        let (frame, age) = self.frame_with_age()?;
+
+        // the frame is more likely to be late than missing, the next one comes soon enough
+        if too_old(age, max_age) {
+            return Err(FrameError::Skipped);
+        }
+
+        Ok(frame)
+    }
+
+    #[inline]
+    fn size(&self) -> (usize, usize) {
+        (self.width, self.height)
+    }
+
+    #[inline]
+    fn format(&self) -> FrameFormat {
+        self.format
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{HashSet, VecDeque};
+
+    use super::*;
+    use crate::record::clock::ManualClock;
+
+    #[test]
+    fn display_index_factory_picks_index() {
+        let list_displays = || Ok(vec!["first", "second"]);
+
+        let mut factory = display_index_factory(1, list_displays).unwrap();
+
+        assert_eq!(factory().unwrap(), "second");
+        // re-resolved to the same display every time
+        assert_eq!(factory().unwrap(), "second");
+    }
+
+    #[test]
+    fn display_index_factory_fails_once_the_display_is_gone() {
+        let mut displays = vec!["first", "second"];
+        let list_displays = move || Ok(std::mem::take(&mut displays));
+
+        let mut factory = display_index_factory(1, list_displays).unwrap();
+
+        // the second call lists no displays at all
+        assert_eq!(factory().unwrap_err().kind(), ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn crop_with_stride() {
+        // 3x3 frame with a padded stride of 4 pixels, every byte of a pixel is its index
+        let stride = 4 * 4;
+        let frame: Vec<u8> = (0..3 * 4).flat_map(|i| [i as u8; 4]).collect();
+
+        let region = CaptureRegion {
+            x: 1,
+            y: 1,
+            width: 2,
+            height: 2,
+        };
+
+        let mut dst = Vec::new();
+        region.crop_bgra(&frame, stride, &mut dst);
+
+        let expected: Vec<u8> = [5_u8, 6, 9, 10].iter().flat_map(|&i| [i; 4]).collect();
+        assert_eq!(dst, expected);
+    }
+
+    #[test]
+    fn frame_buffers_are_reused() {
+        let frame = vec![7_u8; 64 * 48 * 4];
+        let mut frame_buf = TripleBuffer::new(vec![0_u8; frame.len()]);
+
+        let region = CaptureRegion {
+            x: 8,
+            y: 8,
+            width: 32,
+            height: 16,
+        };
+        let mut crop_buf = Vec::new();
+        region.crop_bgra(&frame, 64 * 4, &mut crop_buf);
+        let crop_allocation = (crop_buf.as_ptr(), crop_buf.capacity());
+
+        let mut allocations = HashSet::new();
+
+        for _ in 0..1000 {
+            copy_frame(&frame, frame_buf.back_mut());
+            let back = frame_buf.back();
+            allocations.insert((back.as_ptr(), back.capacity()));
+            frame_buf.swap();
+
+            region.crop_bgra(&frame, 64 * 4, &mut crop_buf);
+            assert_eq!((crop_buf.as_ptr(), crop_buf.capacity()), crop_allocation);
+        }
+
+        // at most one allocation per buffer of the triple buffer, and none of them ever grew
+        assert!(allocations.len() <= 3);
+        assert!(allocations.iter().all(|&(_, capacity)| capacity == frame.len()));
+    }
+
+    #[test]
+    fn capture_counters() {
+        let counters = CaptureCounters::default();
+        let script = [
+            Ok(()),
+            Err(FrameError::Skipped),
+            Err(FrameError::Skipped),
+            Ok(()),
+            Err(FrameError::Resized {
+                width: 640,
+                height: 480,
+            }),
+            Err(FrameError::Skipped),
+            Ok(()),
+        ];
+
+        for result in &script {
+            counters.record(result);
+        }
+        // one of the captured frames was the same as the one before
+        counters.published();
+        counters.published();
+
+        // resizes don't count as either
+        assert_eq!(
+            counters.snapshot(),
+            CaptureStats {
+                captured: 3,
+                published: 2,
+                skipped: 3
+            }
+        );
+    }
+
+    #[test]
+    fn late_frames_are_too_old() {
+        let clock = ManualClock::new();
+        let frame_times = FrameTimes::default();
+        let max_age = Some(Duration::from_millis(50));
+
+        // no frame yet
+        assert_eq!(frame_times.age(&CapturedFrame::default()), Duration::ZERO);
+
+        *frame_times.clock.lock() = RecordClock::new(Some(Arc::new(clock.clone())));
+        let frame = CapturedFrame {
+            data: vec![1; 4],
+            captured_at: Some(frame_times.now()),
+        };
+        clock.advance(Duration::from_millis(50));
+        assert_eq!(frame_times.age(&frame), Duration::from_millis(50));
+        assert!(!too_old(frame_times.age(&frame), max_age));
+
+        clock.advance(Duration::from_millis(1));
+        assert!(too_old(frame_times.age(&frame), max_age));
+        // unless anything goes
+        assert!(!too_old(frame_times.age(&frame), None));
+
+        // captured again without changing
+        frame_times.recaptured(frame.captured_at.unwrap(), frame_times.now());
+        assert!(!too_old(frame_times.age(&frame), max_age));
+    }
+
+    #[test]
+    fn recapture_only_refreshes_its_own_frame() {
+        let clock = ManualClock::new();
+        let frame_times = FrameTimes::default();
+        *frame_times.clock.lock() = RecordClock::new(Some(Arc::new(clock.clone())));
+
+        let mut frame_buf = TripleBuffer::new(CapturedFrame::default());
+        let view = frame_buf.view();
+
+        frame_buf.back_mut().captured_at = Some(frame_times.now());
+        frame_buf.swap();
+        let old = view.front();
+
+        clock.advance(Duration::from_millis(100));
+        frame_buf.back_mut().data = vec![1; 4];
+        frame_buf.back_mut().captured_at = Some(frame_times.now());
+        frame_buf.swap();
+
+        // the new frame stays the same for a while
+        clock.advance(Duration::from_millis(30));
+        frame_times.recaptured(view.front().captured_at.unwrap(), frame_times.now());
+
+        // a reader that's still on the old frame sees how old it really is
+        assert_eq!(frame_times.age(&old), Duration::from_millis(130));
+        assert_eq!(frame_times.age(&view.front()), Duration::ZERO);
+    }
+
+    #[test]
+    fn frame_size_follows_display() {
+        assert_eq!(frame_size(1920, 1080, None).unwrap(), (1920, 1080));
+
+        let region = CaptureRegion {
+            x: 100,
+            y: 50,
+            width: 640,
+            height: 480,
+        };
+        assert_eq!(frame_size(1920, 1080, Some(region)).unwrap(), (640, 480));
+        assert!(frame_size(700, 1080, Some(region)).is_err());
+    }
+
+    #[test]
+    fn region_outside_display() {
+        let region = CaptureRegion {
+            x: 10,
+            y: 0,
+            width: 20,
+            height: 20,
+        };
+
+        assert!(region.validate(40, 20).is_ok());
+        assert!(region.validate(29, 20).is_err());
+        assert!(region.validate(40, 19).is_err());
+
+        let empty = CaptureRegion { width: 0, ..region };
+        assert!(empty.validate(40, 20).is_err());
+    }
+
+    struct Idle;
+
+    impl ThreadWork for Idle {
+        type WorkResult = ();
+
+        fn work(&mut self) -> Option<Self::WorkResult> {
+            Some(())
+        }
+    }
+
+    #[test]
+    fn failed_init_is_returned() {
+        let worker_factory = || -> io::Result<Idle> {
+            Err(io::Error::new(ErrorKind::PermissionDenied, "screen recording permission denied"))
+        };
+
+        let error = start_capture_loop(worker_factory, 100.0, ThreadScheduling::default()).err().unwrap();
+
+        assert_eq!(error.kind(), ErrorKind::PermissionDenied);
+    }
+
+    #[test]
+    fn successful_init_starts_the_loop() {
+        let thread_loop = start_capture_loop(|| Ok(Idle), 100.0, ThreadScheduling::default()).unwrap();
+
+        assert!(thread_loop.work_recv().is_ok());
+    }
+
+    // stands in for the capture worker, `recreated` scripts how the attempts to bring the capturer back go
+    struct FlakyWorker {
+        connected: bool,
+        fail_next: bool,
+        recreated: VecDeque<bool>,
+        restarter: Restarter,
+    }
+
+    impl ThreadWork for FlakyWorker {
+        type WorkResult = Result<(), FrameError>;
+
+        fn work(&mut self) -> Option<Self::WorkResult> {
+            Some(self.capture())
+        }
+    }
+
+    impl FlakyWorker {
+        fn capture(&mut self) -> Result<(), FrameError> {
+            if !self.connected {
+                let (connected, recreated) = (&mut self.connected, &mut self.recreated);
+
+                return self.restarter.restart(|| {
+                    *connected = recreated.pop_front().unwrap_or(true);
+                    match connected {
+                        true => Err(FrameError::Skipped),
+                        false => Err(FrameError::Error(io::Error::other("no display"))),
+                    }
+                });
+            }
+
+            if mem::take(&mut self.fail_next) {
+                self.connected = false;
+                return Err(self.restarter.failed(io::Error::other("capture failed")));
+            }
+
+            Ok(())
+        }
+    }
+
+    fn flaky_worker(recreated: &[bool], max_attempts: u32) -> FlakyWorker {
+        FlakyWorker {
+            connected: true,
+            fail_next: true,
+            recreated: recreated.iter().copied().collect(),
+            restarter: Restarter::new(RestartPolicy {
+                max_attempts,
+                backoff: Duration::from_millis(1),
+            }),
+        }
+    }
+
+    #[test]
+    fn capture_resumes_after_restart() {
+        let worker = flaky_worker(&[false, false, true], 3);
+        let thread_loop = start_capture_loop(move || Ok(worker), 1000.0, ThreadScheduling::default()).unwrap();
+
+        let next = || thread_loop.work_recv().unwrap();
+
+        // the failure itself and the two failed attempts
+        for expected in 1..=3 {
+            assert!(matches!(next(), Err(FrameError::Restarting { attempt, .. }) if attempt == expected));
+        }
+        assert!(matches!(next(), Err(FrameError::Restarted { attempts: 3 })));
+
+        assert!(next().is_ok());
+        assert!(next().is_ok());
+    }
+
+    #[test]
+    fn restarts_run_out() {
+        let mut worker = flaky_worker(&[false, false, true], 2);
+
+        assert!(matches!(worker.capture(), Err(FrameError::Restarting { attempt: 1, .. })));
+        assert!(matches!(worker.capture(), Err(FrameError::Restarting { attempt: 2, .. })));
+
+        // and it stays that way, even though the next attempt would have worked
+        for _ in 0..3 {
+            assert!(matches!(worker.capture(), Err(FrameError::RestartsExhausted { attempts: 2 })));
+        }
+    }
+
+    #[test]
+    fn frame_size_change() {
+        // the first frame only has to be large enough
+        assert!(!frame_size_changed(4 * 4 * 4, None, 4, 4));
+        assert!(!frame_size_changed(5 * 4 * 4, None, 4, 4));
+        assert!(frame_size_changed(3 * 4 * 4, None, 4, 4));
+
+        // after that, any change in length is a resize
+        assert!(!frame_size_changed(5 * 4 * 4, Some(5 * 4 * 4), 4, 4));
+        assert!(frame_size_changed(6 * 4 * 4, Some(5 * 4 * 4), 4, 4));
+    }
+
+    #[test]
+    fn display_index_out_of_range() {
+        let list_displays = || Ok(vec!["first", "second"]);
+
+        let error = display_index_factory(2, list_displays).err().unwrap();
+
+        assert_eq!(error.kind(), ErrorKind::NotFound);
     }
 }
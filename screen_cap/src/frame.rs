@@ -1,6 +1,8 @@
 use std::{ops::Deref, io::{self, ErrorKind}};
 
 use thiserror::Error;
+use utils::threading::WorkerError;
+use x264::Colorspace;
 
 /// A convenience type to go from "something that derefs into something else that derefs into `[u8]`"
 /// into just something that derefs into `[u8]`.
@@ -39,19 +41,542 @@ where
     }
 }
 
+/// Pixel layout of the frames handed out by the capturer.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FrameFormat {
+    /// What the display produces, 4 bytes per pixel
+    #[default]
+    Bgra,
+    /// Planar YUV 4:2:0, what x264 encodes internally.
+    ///
+    /// The Y plane is followed by the U and V planes, each subsampled by 2 in both directions
+    I420,
+    /// Semi-planar YUV 4:2:0, what hardware encoders tend to want.
+    ///
+    /// The Y plane is followed by a single plane of interleaved U and V samples, the same size as both I420 chroma planes
+    Nv12,
+}
+
+impl FrameFormat {
+    /// The size of a tightly packed frame in this format
+    #[inline]
+    pub fn frame_len(self, width: usize, height: usize) -> usize {
+        match self {
+            FrameFormat::Bgra => width * height * 4,
+            FrameFormat::I420 | FrameFormat::Nv12 => {
+                let (chroma_width, chroma_height) = i420_chroma_size(width, height);
+                width * height + 2 * chroma_width * chroma_height
+            }
+        }
+    }
+
+    /// A tightly packed frame that's all black, limited range for the YUV formats like the converters produce
+    pub fn black_frame(self, width: usize, height: usize) -> Vec<u8> {
+        match self {
+            FrameFormat::Bgra => vec![0; self.frame_len(width, height)],
+            FrameFormat::I420 | FrameFormat::Nv12 => {
+                // the chroma planes are in the same place in both, just interleaved in NV12
+                let mut frame = vec![128; self.frame_len(width, height)];
+                frame[..width * height].fill(16);
+                frame
+            }
+        }
+    }
+
+    /// What the encoder has to be built with to take frames in this format
+    #[inline]
+    pub fn colorspace(self) -> Colorspace {
+        match self {
+            FrameFormat::Bgra => Colorspace::BGRA,
+            FrameFormat::I420 => Colorspace::I420,
+            FrameFormat::Nv12 => Colorspace::NV12,
+        }
+    }
+
+    /// The format the capturer has to produce for an encoder built with `colorspace`,
+    /// `None` if the capturer can't produce it
+    #[inline]
+    pub fn from_colorspace(colorspace: Colorspace) -> Option<Self> {
+        match colorspace {
+            Colorspace::BGRA => Some(FrameFormat::Bgra),
+            Colorspace::I420 => Some(FrameFormat::I420),
+            Colorspace::NV12 => Some(FrameFormat::Nv12),
+            _ => None,
+        }
+    }
+
+    /// Converts a tightly packed BGRA frame into this format, writing the result into `dst`.
+    ///
+    /// `dst` is cleared first, reusing it between frames avoids reallocating.
+    pub fn convert_bgra(self, bgra: &[u8], width: usize, height: usize, dst: &mut Vec<u8>) {
+        match self {
+            FrameFormat::Bgra => {
+                dst.clear();
+                dst.extend_from_slice(&bgra[..width * height * 4]);
+            }
+            FrameFormat::I420 => bgra_to_i420(bgra, width, height, dst),
+            FrameFormat::Nv12 => bgra_to_nv12(bgra, width, height, dst),
+        }
+    }
+}
+
+/// Dimensions of the U and V planes of an I420 frame
+#[inline]
+pub fn i420_chroma_size(width: usize, height: usize) -> (usize, usize) {
+    (width.div_ceil(2), height.div_ceil(2))
+}
+
+/// Converts a tightly packed BGRA frame into I420 (BT.601, limited range), writing the result into `dst`.
+///
+/// `dst` is cleared first, reusing it between frames avoids reallocating.
+pub fn bgra_to_i420(bgra: &[u8], width: usize, height: usize, dst: &mut Vec<u8>) {
+    let (chroma_width, chroma_height) = i420_chroma_size(width, height);
+
+    // every byte gets written below, so the old contents can stay
+    dst.resize(FrameFormat::I420.frame_len(width, height), 0);
+
+    let (y_plane, chroma) = dst.split_at_mut(width * height);
+    let (u_plane, v_plane) = chroma.split_at_mut(chroma_width * chroma_height);
+
+    bgra_to_yuv420(bgra, width, height, y_plane, |i, u, v| {
+        u_plane[i] = u;
+        v_plane[i] = v;
+    });
+}
+
+/// Same as `bgra_to_i420`, except the U and V samples are interleaved into one plane
+pub fn bgra_to_nv12(bgra: &[u8], width: usize, height: usize, dst: &mut Vec<u8>) {
+    dst.resize(FrameFormat::Nv12.frame_len(width, height), 0);
+
+    let (y_plane, uv_plane) = dst.split_at_mut(width * height);
+
+    bgra_to_yuv420(bgra, width, height, y_plane, |i, u, v| {
+        uv_plane[2 * i] = u;
+        uv_plane[2 * i + 1] = v;
+    });
+}
+
+// writes the luma into `y_plane` and hands every chroma sample to `write_chroma` along with its index
+fn bgra_to_yuv420(
+    bgra: &[u8],
+    width: usize,
+    height: usize,
+    y_plane: &mut [u8],
+    mut write_chroma: impl FnMut(usize, u8, u8),
+) {
+    let (chroma_width, chroma_height) = i420_chroma_size(width, height);
+
+    let pixel = |x: usize, y: usize| {
+        let i = (y * width + x) * 4;
+        (bgra[i + 2] as i32, bgra[i + 1] as i32, bgra[i] as i32)
+    };
+
+    for y in 0..height {
+        for x in 0..width {
+            let (r, g, b) = pixel(x, y);
+            y_plane[y * width + x] = (((66 * r + 129 * g + 25 * b + 128) >> 8) + 16) as u8;
+        }
+    }
+
+    for cy in 0..chroma_height {
+        for cx in 0..chroma_width {
+            // average the 2x2 block, clamping at the edges for odd dimensions
+            let (mut r, mut g, mut b) = (0, 0, 0);
+            for (dx, dy) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+                let x = (cx * 2 + dx).min(width - 1);
+                let y = (cy * 2 + dy).min(height - 1);
+                let (pr, pg, pb) = pixel(x, y);
+                r += pr;
+                g += pg;
+                b += pb;
+            }
+            let (r, g, b) = (r / 4, g / 4, b / 4);
+
+            let u = (((-38 * r - 74 * g + 112 * b + 128) >> 8) + 128) as u8;
+            let v = (((112 * r - 94 * g - 18 * b + 128) >> 8) + 128) as u8;
+            write_chroma(cy * chroma_width + cx, u, v);
+        }
+    }
+}
+
+/// Gets the visible part of a BGRA frame with its rows back to back, the way `Image::bgra` and `bgra_to_i420` want it.
+///
+/// Depending on the platform scrap can pad every row of a frame,
+/// so the stride is derived from the length of the frame rather than assumed to be `width * 4`.
+/// https://github.com/quadrupleslap/scrap/issues/44#issuecomment-1486345836
+/// Tightly packed frames are returned as they are, padded ones get repacked into `scratch`,
+/// reusing it between frames avoids reallocating.
+pub fn packed_bgra<'a>(frame: &'a [u8], width: usize, height: usize, scratch: &'a mut Vec<u8>) -> &'a [u8] {
+    let row_len = width * 4;
+    let stride = frame.len() / height.max(1);
+
+    if stride <= row_len {
+        return &frame[..row_len * height];
+    }
+
+    scratch.resize(row_len * height, 0);
+    for (dst_row, row) in scratch.chunks_exact_mut(row_len).zip(frame.chunks(stride)) {
+        dst_row.copy_from_slice(&row[..row_len]);
+    }
+
+    scratch
+}
+
+/// Resizes BGRA frames by averaging the area of the source that every destination pixel covers.
+///
+/// Works for any ratio, including non-integer ones and upscaling, but it's meant for downscaling,
+/// e.g. encoding a 4K display at 1080p. The weights are computed once for a pair of sizes,
+/// so the scaler should be kept around for as long as the source size stays the same.
+#[derive(Debug, Clone)]
+pub struct AreaScaler {
+    src_size: (usize, usize),
+    dst_size: (usize, usize),
+    // for every destination column/row, the source columns/rows it covers and how much of each
+    x_weights: Vec<Vec<(usize, f32)>>,
+    y_weights: Vec<Vec<(usize, f32)>>,
+}
+
+impl AreaScaler {
+    /// Both sizes are `(width, height)` in pixels and mustn't be empty
+    pub fn new(src_size: (usize, usize), dst_size: (usize, usize)) -> Self {
+        Self {
+            src_size,
+            dst_size,
+            x_weights: area_weights(src_size.0, dst_size.0),
+            y_weights: area_weights(src_size.1, dst_size.1),
+        }
+    }
+
+    /// The source size this scaler was built for
+    #[inline]
+    pub fn src_size(&self) -> (usize, usize) {
+        self.src_size
+    }
+
+    #[inline]
+    pub fn dst_size(&self) -> (usize, usize) {
+        self.dst_size
+    }
+
+    /// Scales `src` into `dst`, leaving it tightly packed.
+    ///
+    /// `stride` is the length of a row of `src` in bytes, which may be larger than `4 * width` (e.g. on macos).
+    /// `dst` gets overwritten, it's only reallocated if it's too small.
+    pub fn scale_bgra(&self, src: &[u8], stride: usize, dst: &mut Vec<u8>) {
+        let (dst_width, dst_height) = self.dst_size;
+
+        dst.resize(dst_width * dst_height * 4, 0);
+
+        for (dst_row, y_weights) in dst.chunks_exact_mut(dst_width * 4).zip(&self.y_weights) {
+            for (dst_pixel, x_weights) in dst_row.chunks_exact_mut(4).zip(&self.x_weights) {
+                let mut sum = [0.0_f32; 4];
+
+                for &(y, y_weight) in y_weights {
+                    let row = &src[y * stride..];
+
+                    for &(x, x_weight) in x_weights {
+                        let weight = y_weight * x_weight;
+                        let pixel = &row[x * 4..x * 4 + 4];
+
+                        for (sum, &channel) in sum.iter_mut().zip(pixel) {
+                            *sum += weight * f32::from(channel);
+                        }
+                    }
+                }
+
+                for (dst, sum) in dst_pixel.iter_mut().zip(sum) {
+                    *dst = sum.round().clamp(0.0, 255.0) as u8;
+                }
+            }
+        }
+    }
+}
+
+// which source pixels each destination pixel covers along one axis, the weights of each add up to 1
+fn area_weights(src_len: usize, dst_len: usize) -> Vec<Vec<(usize, f32)>> {
+    let ratio = src_len as f64 / dst_len as f64;
+
+    (0..dst_len)
+        .map(|i| {
+            let start = i as f64 * ratio;
+            let end = (start + ratio).min(src_len as f64);
+
+            let first = start.floor() as usize;
+            let last = (end.ceil() as usize).min(src_len);
+
+            (first..last)
+                .map(|src| {
+                    let covered = end.min(src as f64 + 1.0) - start.max(src as f64);
+                    (src, (covered / ratio) as f32)
+                })
+                .filter(|&(_, weight)| weight > 0.0)
+                .collect()
+        })
+        .collect()
+}
+
+/// Cheaply estimates how much of a frame changed since the previous one
+/// by comparing a sparse sample of its bytes.
+#[derive(Debug, Clone)]
+pub struct ChangeDetector {
+    step: usize,
+    samples: Vec<u8>,
+}
+
+impl ChangeDetector {
+    // not a multiple of 4 so that different color channels get sampled
+    const DEFAULT_STEP: usize = 4 * 61 + 1;
+
+    #[inline]
+    pub fn new() -> Self {
+        Self::with_step(Self::DEFAULT_STEP)
+    }
+
+    /// `step` is the distance in bytes between the sampled bytes
+    #[inline]
+    pub fn with_step(step: usize) -> Self {
+        assert!(step > 0, "step must be positive");
+
+        Self {
+            step,
+            samples: Vec::new(),
+        }
+    }
+
+    /// Returns the fraction of sampled bytes that differ from the previous frame,
+    /// and remembers this frame for the next call.
+    ///
+    /// The first frame is compared against nothing and returns 0,
+    /// a frame of a different size than the previous one counts as fully changed.
+    pub fn change_ratio(&mut self, frame: &[u8]) -> f32 {
+        let sample_count = frame.len().div_ceil(self.step);
+
+        if self.samples.len() != sample_count {
+            let ratio = if self.samples.is_empty() { 0.0 } else { 1.0 };
+
+            self.samples.clear();
+            self.samples.extend(frame.iter().step_by(self.step));
+
+            return ratio;
+        }
+
+        if sample_count == 0 {
+            return 0.0;
+        }
+
+        let mut changed = 0;
+        for (sample, &byte) in self.samples.iter_mut().zip(frame.iter().step_by(self.step)) {
+            if *sample != byte {
+                changed += 1;
+                *sample = byte;
+            }
+        }
+
+        changed as f32 / sample_count as f32
+    }
+}
+
+impl Default for ChangeDetector {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Where a `Recorder` gets its frames from, see `Recorder::with_source`.
+///
+/// `ThreadedCapturer` is the one `Recorder::new` uses.
+pub trait FrameSource {
+    /// Waits for the next frame, `size` pixels in `format`.
+    ///
+    /// The recorder encodes the frames as fast as they come, so this is what paces the recording.
+    /// `FrameError::Skipped` means there's nothing new, and `FrameError::Resized` rebuilds the encoder for the new size,
+    /// the same as with a `ThreadedCapturer`.
+    fn next_frame(&mut self) -> Result<impl Deref<Target = [u8]> + '_, FrameError>;
+
+    /// The width and height of the frames, until `next_frame` returns `FrameError::Resized`
+    fn size(&self) -> (usize, usize);
+
+    fn format(&self) -> FrameFormat;
+}
+
 #[derive(Debug, Error)]
 pub enum FrameError {
     #[error("the frame is skipped")]
     Skipped,
     #[error(transparent)]
     Error(io::Error),
+    /// The display or the session went away, e.g. the screen got locked or an RDP session disconnected.
+    ///
+    /// Not fatal, the capturer gets recreated on the next frame and capturing resumes once the display is back
+    #[error("the display has been disconnected")]
+    Disconnected(#[source] io::Error),
+    #[error("capture {0}")]
+    Worker(#[from] WorkerError),
+    /// The display changed its resolution and the capturer got recreated,
+    /// the frames from now on have the new size
+    #[error("the display has been resized to {width}x{height}")]
+    Resized { width: usize, height: usize },
+    /// The capturer failed and is going to be recreated, see `RestartPolicy`.
+    ///
+    /// Not fatal, `attempt` counts the attempts from 1
+    #[error("the capturer failed, restart attempt {attempt} coming up")]
+    Restarting {
+        attempt: u32,
+        #[source]
+        source: io::Error,
+    },
+    /// The capturer got recreated after failing, frames were missed in the meantime
+    #[error("the capturer has been restarted after {attempts} attempts")]
+    Restarted { attempts: u32 },
+    /// Every attempt `RestartPolicy` allows has failed, no more frames are coming
+    #[error("gave up restarting the capturer after {attempts} attempts")]
+    RestartsExhausted { attempts: u32 },
 }
 
 impl From<io::Error> for FrameError {
     fn from(value: io::Error) -> Self {
         match value.kind() {
             ErrorKind::WouldBlock => Self::Skipped,
+            // what scrap turns DXGI_ERROR_ACCESS_LOST and DXGI_ERROR_SESSION_DISCONNECTED into on windows
+            ErrorKind::ConnectionReset | ErrorKind::ConnectionAborted | ErrorKind::NotConnected => {
+                Self::Disconnected(value)
+            }
+            _ if is_disconnect_code(&value) => Self::Disconnected(value),
             _ => Self::Error(value),
         }
     }
 }
+
+// in case the HRESULT gets passed through as is
+#[cfg(windows)]
+fn is_disconnect_code(error: &io::Error) -> bool {
+    const DXGI_ERROR_ACCESS_LOST: u32 = 0x887A0026;
+    const DXGI_ERROR_SESSION_DISCONNECTED: u32 = 0x887A0028;
+
+    error.raw_os_error().is_some_and(|code| {
+        let code = code as u32;
+        code == DXGI_ERROR_ACCESS_LOST || code == DXGI_ERROR_SESSION_DISCONNECTED
+    })
+}
+
+#[cfg(not(windows))]
+fn is_disconnect_code(_: &io::Error) -> bool {
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn padded_rows_get_repacked() {
+        // 3x2 frame padded to a stride of 5 pixels, the padding is 0xff
+        let (width, height, stride) = (3, 2, 5 * 4);
+        let expected: Vec<u8> = (0..width * height * 4).map(|i| i as u8).collect();
+
+        let mut padded = vec![0xff_u8; stride * height];
+        for (row, expected_row) in padded.chunks_mut(stride).zip(expected.chunks(width * 4)) {
+            row[..width * 4].copy_from_slice(expected_row);
+        }
+
+        let mut scratch = Vec::new();
+        assert_eq!(packed_bgra(&padded, width, height, &mut scratch), &expected[..]);
+
+        // nothing to repack
+        let mut scratch = Vec::new();
+        assert_eq!(packed_bgra(&expected, width, height, &mut scratch), &expected[..]);
+        assert!(scratch.is_empty());
+    }
+
+    #[test]
+    fn i420_white_and_black() {
+        let mut frame = vec![255_u8; 4 * 2 * 4];
+        // bottom row is black
+        frame[4 * 4..].fill(0);
+
+        let mut dst = Vec::new();
+        bgra_to_i420(&frame, 4, 2, &mut dst);
+
+        assert_eq!(dst.len(), FrameFormat::I420.frame_len(4, 2));
+        assert_eq!(&dst[..4], &[235; 4]);
+        assert_eq!(&dst[4..8], &[16; 4]);
+        // no color anywhere
+        assert!(dst[8..].iter().all(|&c| c == 128));
+    }
+
+    #[test]
+    fn change_ratio() {
+        let mut detector = ChangeDetector::with_step(1);
+
+        assert_eq!(detector.change_ratio(&[0, 0, 0, 0]), 0.0);
+        assert_eq!(detector.change_ratio(&[0, 0, 0, 0]), 0.0);
+        assert_eq!(detector.change_ratio(&[1, 1, 0, 0]), 0.5);
+        assert_eq!(detector.change_ratio(&[1, 1, 0, 0, 0, 0]), 1.0);
+    }
+
+    #[test]
+    fn would_block_is_skipped() {
+        let error = FrameError::from(io::Error::from(ErrorKind::WouldBlock));
+
+        assert!(matches!(error, FrameError::Skipped));
+    }
+
+    #[test]
+    fn lost_access_is_disconnected() {
+        let error = FrameError::from(io::Error::from(ErrorKind::ConnectionReset));
+        assert!(matches!(error, FrameError::Disconnected(_)));
+
+        let error = FrameError::from(io::Error::from(ErrorKind::PermissionDenied));
+        assert!(matches!(error, FrameError::Error(_)));
+    }
+
+    #[test]
+    fn area_scaling() {
+        // 4x2 with a padded stride of 5 pixels, the left half black and the right half white
+        let stride = 5 * 4;
+        let mut frame = vec![0xAA_u8; stride * 2];
+        for row in frame.chunks_mut(stride) {
+            row[8..16].fill(255);
+            row[..8].fill(0);
+        }
+
+        let mut dst = Vec::new();
+        AreaScaler::new((4, 2), (2, 1)).scale_bgra(&frame, stride, &mut dst);
+        assert_eq!(dst, [0, 0, 0, 0, 255, 255, 255, 255]);
+
+        // 4 pixels into 3, the middle one covers half a black and half a white pixel
+        AreaScaler::new((4, 2), (3, 2)).scale_bgra(&frame, stride, &mut dst);
+        assert_eq!(dst.len(), 3 * 2 * 4);
+        assert_eq!(dst[..12], [0, 0, 0, 0, 128, 128, 128, 128, 255, 255, 255, 255]);
+        assert_eq!(dst[..12], dst[12..]);
+    }
+
+    #[test]
+    fn nv12_interleaves_i420_chroma() {
+        let frame: Vec<u8> = (0..5 * 3 * 4).map(|i| (i * 7) as u8).collect();
+
+        let mut i420 = Vec::new();
+        bgra_to_i420(&frame, 5, 3, &mut i420);
+        let mut nv12 = Vec::new();
+        FrameFormat::Nv12.convert_bgra(&frame, 5, 3, &mut nv12);
+
+        assert_eq!(nv12.len(), i420.len());
+        let (i420_y, i420_chroma) = i420.split_at(5 * 3);
+        let (nv12_y, nv12_chroma) = nv12.split_at(5 * 3);
+        assert_eq!(nv12_y, i420_y);
+
+        let (u_plane, v_plane) = i420_chroma.split_at(i420_chroma.len() / 2);
+        let interleaved: Vec<u8> = u_plane.iter().zip(v_plane).flat_map(|(&u, &v)| [u, v]).collect();
+        assert_eq!(nv12_chroma, &interleaved[..]);
+    }
+
+    #[test]
+    fn i420_odd_dimensions() {
+        let frame = vec![0_u8; 3 * 3 * 4];
+
+        let mut dst = Vec::new();
+        bgra_to_i420(&frame, 3, 3, &mut dst);
+
+        assert_eq!(dst.len(), 9 + 2 * 4);
+    }
+}
@@ -1,3 +1,6 @@
 pub mod frame;
 pub mod capture;
 pub mod record;
+pub mod mux;
+pub mod snapshot;
+pub mod testing;
@@ -0,0 +1,673 @@
+use thiserror::Error;
+
+// NAL unit types that matter here
+const NAL_SPS: u8 = 7;
+const NAL_PPS: u8 = 8;
+const NAL_AUD: u8 = 9;
+
+const TRACK_ID: u32 = 1;
+
+// sample_depends_on = 2, i.e. an IDR
+const KEY_SAMPLE_FLAGS: u32 = 0x0200_0000;
+// sample_depends_on = 1 and sample_is_non_sync_sample
+const DELTA_SAMPLE_FLAGS: u32 = 0x0101_0000;
+
+// EBML and Matroska element ids
+const EBML_HEADER: u32 = 0x1A45_DFA3;
+const EBML_VERSION: u32 = 0x4286;
+const EBML_READ_VERSION: u32 = 0x42F7;
+const EBML_MAX_ID_LENGTH: u32 = 0x42F2;
+const EBML_MAX_SIZE_LENGTH: u32 = 0x42F3;
+const DOC_TYPE: u32 = 0x4282;
+const DOC_TYPE_VERSION: u32 = 0x4287;
+const DOC_TYPE_READ_VERSION: u32 = 0x4285;
+const SEGMENT: u32 = 0x1853_8067;
+const INFO: u32 = 0x1549_A966;
+const TIMECODE_SCALE: u32 = 0x2A_D7B1;
+const MUXING_APP: u32 = 0x4D80;
+const WRITING_APP: u32 = 0x5741;
+const TRACKS: u32 = 0x1654_AE6B;
+const TRACK_ENTRY: u32 = 0xAE;
+const TRACK_NUMBER: u32 = 0xD7;
+const TRACK_UID: u32 = 0x73C5;
+const TRACK_TYPE: u32 = 0x83;
+const CODEC_ID: u32 = 0x86;
+const CODEC_PRIVATE: u32 = 0x63A2;
+const VIDEO: u32 = 0xE0;
+const PIXEL_WIDTH: u32 = 0xB0;
+const PIXEL_HEIGHT: u32 = 0xBA;
+const CLUSTER: u32 = 0x1F43_B675;
+const CLUSTER_TIMECODE: u32 = 0xE7;
+const SIMPLE_BLOCK: u32 = 0xA3;
+
+const TRACK_TYPE_VIDEO: u64 = 1;
+const SIMPLE_BLOCK_KEYFRAME: u8 = 0x80;
+// timestamps in milliseconds
+const TIMECODE_SCALE_NS: u64 = 1_000_000;
+// the length marker of an 8 byte size
+const SIZE_MARKER: u64 = 0x0100_0000_0000_0000;
+// an 8 byte size with all the value bits set
+const UNKNOWN_SIZE: u64 = 0x01FF_FFFF_FFFF_FFFF;
+
+#[derive(Debug, Error, Clone, Copy, PartialEq, Eq)]
+pub enum MuxError {
+    #[error("the headers don't contain an SPS")]
+    MissingSps,
+    #[error("the headers don't contain a PPS")]
+    MissingPps,
+}
+
+/// Wraps the Annex B stream coming out of the `Recorder` into fragmented MP4,
+/// which is what browsers accept through Media Source Extensions.
+///
+/// A client needs the `init_segment` first, followed by fragments starting at a keyframe.
+#[derive(Debug, Clone)]
+pub struct Fmp4Muxer {
+    sps: Vec<u8>,
+    pps: Vec<u8>,
+    width: u16,
+    height: u16,
+    timescale: u32,
+    sequence_number: u32,
+    last_pts: Option<i64>,
+    // the duration of a sample isn't known until the next one arrives,
+    // so the previous distance between samples is used instead
+    last_duration: u32,
+}
+
+impl Fmp4Muxer {
+    /// `headers` are the SPS/PPS from `Recorder::headers`,
+    /// `timescale` is the number of timestamp ticks per second, i.e. `EncoderSettings::timebase`.
+    pub fn new(headers: &[u8], width: u16, height: u16, timescale: u32) -> Result<Self, MuxError> {
+        let (sps, pps) = parameter_sets(headers)?;
+
+        Ok(Self {
+            sps,
+            pps,
+            width,
+            height,
+            timescale,
+            sequence_number: 0,
+            last_pts: None,
+            // 30 fps until we know better
+            last_duration: (timescale / 30).max(1),
+        })
+    }
+
+    /// The `ftyp` and `moov` boxes describing the single video track
+    pub fn init_segment(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+
+        write_box(&mut out, b"ftyp", |out| {
+            out.extend_from_slice(b"isom");
+            out.extend_from_slice(&0x200_u32.to_be_bytes());
+            out.extend_from_slice(b"isomiso6avc1mp41");
+        });
+
+        write_box(&mut out, b"moov", |out| {
+            write_full_box(out, b"mvhd", 0, 0, |out| {
+                // creation and modification time
+                out.extend_from_slice(&[0; 8]);
+                out.extend_from_slice(&self.timescale.to_be_bytes());
+                // duration, unknown
+                out.extend_from_slice(&0_u32.to_be_bytes());
+                // rate 1.0, volume 1.0, reserved
+                out.extend_from_slice(&0x0001_0000_u32.to_be_bytes());
+                out.extend_from_slice(&0x0100_u16.to_be_bytes());
+                out.extend_from_slice(&[0; 10]);
+                write_matrix(out);
+                // pre_defined
+                out.extend_from_slice(&[0; 24]);
+                // next_track_ID
+                out.extend_from_slice(&(TRACK_ID + 1).to_be_bytes());
+            });
+
+            write_box(out, b"trak", |out| {
+                // enabled, in movie, in preview
+                write_full_box(out, b"tkhd", 0, 0x7, |out| {
+                    out.extend_from_slice(&[0; 8]);
+                    out.extend_from_slice(&TRACK_ID.to_be_bytes());
+                    // reserved, duration
+                    out.extend_from_slice(&[0; 8]);
+                    // reserved, layer, alternate_group, volume, reserved
+                    out.extend_from_slice(&[0; 16]);
+                    write_matrix(out);
+                    // 16.16 fixed point
+                    out.extend_from_slice(&(u32::from(self.width) << 16).to_be_bytes());
+                    out.extend_from_slice(&(u32::from(self.height) << 16).to_be_bytes());
+                });
+
+                write_box(out, b"mdia", |out| {
+                    write_full_box(out, b"mdhd", 0, 0, |out| {
+                        out.extend_from_slice(&[0; 8]);
+                        out.extend_from_slice(&self.timescale.to_be_bytes());
+                        out.extend_from_slice(&0_u32.to_be_bytes());
+                        // language "und", pre_defined
+                        out.extend_from_slice(&0x55C4_u16.to_be_bytes());
+                        out.extend_from_slice(&[0; 2]);
+                    });
+
+                    write_full_box(out, b"hdlr", 0, 0, |out| {
+                        out.extend_from_slice(&[0; 4]);
+                        out.extend_from_slice(b"vide");
+                        out.extend_from_slice(&[0; 12]);
+                        out.extend_from_slice(b"VideoHandler\0");
+                    });
+
+                    write_box(out, b"minf", |out| {
+                        write_full_box(out, b"vmhd", 0, 1, |out| out.extend_from_slice(&[0; 8]));
+
+                        write_box(out, b"dinf", |out| {
+                            write_full_box(out, b"dref", 0, 0, |out| {
+                                out.extend_from_slice(&1_u32.to_be_bytes());
+                                // the media data is in the same file
+                                write_full_box(out, b"url ", 0, 1, |_| ());
+                            });
+                        });
+
+                        write_box(out, b"stbl", |out| {
+                            write_full_box(out, b"stsd", 0, 0, |out| {
+                                out.extend_from_slice(&1_u32.to_be_bytes());
+                                self.write_avc1(out);
+                            });
+
+                            // the samples are all in the fragments
+                            for kind in [b"stts", b"stsc", b"stco"] {
+                                write_full_box(out, kind, 0, 0, |out| out.extend_from_slice(&[0; 4]));
+                            }
+                            write_full_box(out, b"stsz", 0, 0, |out| out.extend_from_slice(&[0; 8]));
+                        });
+                    });
+                });
+            });
+
+            write_box(out, b"mvex", |out| {
+                write_full_box(out, b"trex", 0, 0, |out| {
+                    out.extend_from_slice(&TRACK_ID.to_be_bytes());
+                    // default sample description index, duration, size, flags
+                    out.extend_from_slice(&1_u32.to_be_bytes());
+                    out.extend_from_slice(&[0; 12]);
+                });
+            });
+        });
+
+        out
+    }
+
+    /// Wraps a flushed chunk into a `moof` + `mdat` fragment holding a single sample.
+    ///
+    /// `data` is the Annex B chunk out of the ring buffer, `pts` and `is_key` come from its `Metadata`.
+    /// Parameter sets in the chunk are dropped since they're already in the init segment.
+    pub fn wrap_chunk(&mut self, data: &[u8], pts: i64, is_key: bool) -> Vec<u8> {
+        if let Some(last_pts) = self.last_pts {
+            if let Ok(duration) = u32::try_from(pts - last_pts) {
+                self.last_duration = duration.max(1);
+            }
+        }
+        self.last_pts = Some(pts);
+        self.sequence_number = self.sequence_number.wrapping_add(1);
+
+        let sample = length_prefixed(data);
+
+        let sample_flags = if is_key {
+            KEY_SAMPLE_FLAGS
+        } else {
+            DELTA_SAMPLE_FLAGS
+        };
+
+        let mut out = Vec::with_capacity(sample.len() + 128);
+
+        let mut data_offset_position = 0;
+        write_box(&mut out, b"moof", |out| {
+            write_full_box(out, b"mfhd", 0, 0, |out| {
+                out.extend_from_slice(&self.sequence_number.to_be_bytes());
+            });
+
+            write_box(out, b"traf", |out| {
+                // default-base-is-moof
+                write_full_box(out, b"tfhd", 0, 0x02_0000, |out| {
+                    out.extend_from_slice(&TRACK_ID.to_be_bytes());
+                });
+
+                write_full_box(out, b"tfdt", 1, 0, |out| {
+                    out.extend_from_slice(&(pts.max(0) as u64).to_be_bytes());
+                });
+
+                // data offset, sample duration, size and flags present
+                write_full_box(out, b"trun", 0, 0x0701, |out| {
+                    out.extend_from_slice(&1_u32.to_be_bytes());
+                    data_offset_position = out.len();
+                    out.extend_from_slice(&0_u32.to_be_bytes());
+                    out.extend_from_slice(&self.last_duration.to_be_bytes());
+                    out.extend_from_slice(&(sample.len() as u32).to_be_bytes());
+                    out.extend_from_slice(&sample_flags.to_be_bytes());
+                });
+            });
+        });
+
+        // the sample starts right after the mdat header, counting from the start of the moof
+        let data_offset = (out.len() + 8) as u32;
+        out[data_offset_position..data_offset_position + 4].copy_from_slice(&data_offset.to_be_bytes());
+
+        write_box(&mut out, b"mdat", |out| out.extend_from_slice(&sample));
+
+        out
+    }
+
+    fn write_avc1(&self, out: &mut Vec<u8>) {
+        write_box(out, b"avc1", |out| {
+            // reserved, data reference index
+            out.extend_from_slice(&[0; 6]);
+            out.extend_from_slice(&1_u16.to_be_bytes());
+            // pre_defined, reserved
+            out.extend_from_slice(&[0; 16]);
+            out.extend_from_slice(&self.width.to_be_bytes());
+            out.extend_from_slice(&self.height.to_be_bytes());
+            // 72 dpi
+            out.extend_from_slice(&0x0048_0000_u32.to_be_bytes());
+            out.extend_from_slice(&0x0048_0000_u32.to_be_bytes());
+            out.extend_from_slice(&[0; 4]);
+            // frame count
+            out.extend_from_slice(&1_u16.to_be_bytes());
+            // compressor name
+            out.extend_from_slice(&[0; 32]);
+            // depth, pre_defined
+            out.extend_from_slice(&0x0018_u16.to_be_bytes());
+            out.extend_from_slice(&(-1_i16).to_be_bytes());
+
+            write_box(out, b"avcC", |out| write_avc_config(out, &self.sps, &self.pps));
+        });
+    }
+}
+
+/// Wraps the Annex B stream coming out of the `Recorder` into Matroska,
+/// so a recording can be played and seeked (slowly, there's no index) by regular players.
+///
+/// The `header` goes first, followed by the wrapped chunks in order.
+/// The segment is written with an unknown size, so the file stays valid wherever the recording stops.
+#[derive(Debug, Clone)]
+pub struct MkvMuxer {
+    sps: Vec<u8>,
+    pps: Vec<u8>,
+    width: u16,
+    height: u16,
+    timescale: u32,
+    last_timecode: u64,
+}
+
+impl MkvMuxer {
+    /// Same arguments as `Fmp4Muxer::new`
+    pub fn new(headers: &[u8], width: u16, height: u16, timescale: u32) -> Result<Self, MuxError> {
+        let (sps, pps) = parameter_sets(headers)?;
+
+        Ok(Self {
+            sps,
+            pps,
+            width,
+            height,
+            timescale: timescale.max(1),
+            last_timecode: 0,
+        })
+    }
+
+    /// The EBML header, followed by the start of the segment with its `Info` and `Tracks`
+    pub fn header(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+
+        write_element(&mut out, EBML_HEADER, |out| {
+            write_uint(out, EBML_VERSION, 1);
+            write_uint(out, EBML_READ_VERSION, 1);
+            write_uint(out, EBML_MAX_ID_LENGTH, 4);
+            write_uint(out, EBML_MAX_SIZE_LENGTH, 8);
+            // H.264 isn't allowed in WebM
+            write_bytes(out, DOC_TYPE, b"matroska");
+            write_uint(out, DOC_TYPE_VERSION, 4);
+            write_uint(out, DOC_TYPE_READ_VERSION, 2);
+        });
+
+        write_id(&mut out, SEGMENT);
+        out.extend_from_slice(&UNKNOWN_SIZE.to_be_bytes());
+
+        write_element(&mut out, INFO, |out| {
+            write_uint(out, TIMECODE_SCALE, TIMECODE_SCALE_NS);
+            write_bytes(out, MUXING_APP, b"transscreen");
+            write_bytes(out, WRITING_APP, b"transscreen");
+        });
+
+        write_element(&mut out, TRACKS, |out| {
+            write_element(out, TRACK_ENTRY, |out| {
+                write_uint(out, TRACK_NUMBER, TRACK_ID.into());
+                write_uint(out, TRACK_UID, TRACK_ID.into());
+                write_uint(out, TRACK_TYPE, TRACK_TYPE_VIDEO);
+                write_bytes(out, CODEC_ID, b"V_MPEG4/ISO/AVC");
+                write_element(out, CODEC_PRIVATE, |out| write_avc_config(out, &self.sps, &self.pps));
+                write_element(out, VIDEO, |out| {
+                    write_uint(out, PIXEL_WIDTH, self.width.into());
+                    write_uint(out, PIXEL_HEIGHT, self.height.into());
+                });
+            });
+        });
+
+        out
+    }
+
+    /// Wraps a flushed chunk into a `Cluster` holding a single `SimpleBlock`, arguments as in `Fmp4Muxer::wrap_chunk`.
+    ///
+    /// Timestamps never go backwards, a `pts` earlier than the previous one gets the previous timestamp.
+    pub fn wrap_chunk(&mut self, data: &[u8], pts: i64, is_key: bool) -> Vec<u8> {
+        let timecode = (pts.max(0) as u128 * 1_000_000_000 / u128::from(self.timescale)
+            / u128::from(TIMECODE_SCALE_NS)) as u64;
+        self.last_timecode = self.last_timecode.max(timecode);
+
+        let frame = length_prefixed(data);
+        let mut out = Vec::with_capacity(frame.len() + 32);
+
+        write_element(&mut out, CLUSTER, |out| {
+            write_uint(out, CLUSTER_TIMECODE, self.last_timecode);
+
+            write_element(out, SIMPLE_BLOCK, |out| {
+                // the track number as a 1 byte vint
+                out.push(0x80 | TRACK_ID as u8);
+                // relative to the cluster, which only holds this block
+                out.extend_from_slice(&0_i16.to_be_bytes());
+                out.push(if is_key { SIMPLE_BLOCK_KEYFRAME } else { 0 });
+                out.extend_from_slice(&frame);
+            });
+        });
+
+        out
+    }
+}
+
+/// Splits an Annex B byte stream into NAL units, without the start codes
+pub fn nal_units(data: &[u8]) -> impl Iterator<Item = &[u8]> {
+    let mut rest = match find_start_code(data) {
+        Some(start) => &data[start + 3..],
+        None => &[][..],
+    };
+
+    std::iter::from_fn(move || {
+        if rest.is_empty() {
+            return None;
+        }
+
+        let (nal, next) = match find_start_code(rest) {
+            Some(end) => (&rest[..end], &rest[end + 3..]),
+            None => (rest, &[][..]),
+        };
+        rest = next;
+
+        // the leading zero of a 4 byte start code, NAL units never end with a zero byte
+        let trimmed_len = nal.len() - nal.iter().rev().take_while(|&&b| b == 0).count();
+
+        Some(&nal[..trimmed_len])
+    })
+    .filter(|nal| !nal.is_empty())
+}
+
+// the SPS and PPS out of the encoder headers
+fn parameter_sets(headers: &[u8]) -> Result<(Vec<u8>, Vec<u8>), MuxError> {
+    let mut sps = None;
+    let mut pps = None;
+
+    for nal in nal_units(headers) {
+        match nal_type(nal) {
+            NAL_SPS => sps = Some(nal.to_vec()),
+            NAL_PPS => pps = Some(nal.to_vec()),
+            _ => (),
+        }
+    }
+
+    let sps = sps.filter(|sps| sps.len() >= 4).ok_or(MuxError::MissingSps)?;
+    let pps = pps.ok_or(MuxError::MissingPps)?;
+
+    Ok((sps, pps))
+}
+
+// AVCC, every NAL unit prefixed with its length, without the parameter sets and delimiters
+fn length_prefixed(data: &[u8]) -> Vec<u8> {
+    let mut sample = Vec::with_capacity(data.len());
+    for nal in nal_units(data) {
+        if matches!(nal_type(nal), NAL_SPS | NAL_PPS | NAL_AUD) {
+            continue;
+        }
+
+        sample.extend_from_slice(&(nal.len() as u32).to_be_bytes());
+        sample.extend_from_slice(nal);
+    }
+
+    sample
+}
+
+// AVCDecoderConfigurationRecord, the contents of `avcC` and Matroska's `CodecPrivate`
+fn write_avc_config(out: &mut Vec<u8>, sps: &[u8], pps: &[u8]) {
+    out.push(1);
+    // profile, compatibility and level, straight out of the SPS
+    out.extend_from_slice(&sps[1..4]);
+    // 4 byte NAL unit lengths
+    out.push(0xFF);
+    out.push(0xE1);
+    out.extend_from_slice(&(sps.len() as u16).to_be_bytes());
+    out.extend_from_slice(sps);
+    out.push(1);
+    out.extend_from_slice(&(pps.len() as u16).to_be_bytes());
+    out.extend_from_slice(pps);
+}
+
+fn find_start_code(data: &[u8]) -> Option<usize> {
+    data.windows(3).position(|window| window == [0, 0, 1])
+}
+
+#[inline]
+fn nal_type(nal: &[u8]) -> u8 {
+    nal.first().map_or(0, |header| header & 0x1F)
+}
+
+fn write_box(out: &mut Vec<u8>, kind: &[u8; 4], contents: impl FnOnce(&mut Vec<u8>)) {
+    let start = out.len();
+    // the size gets filled in once the contents are written
+    out.extend_from_slice(&[0; 4]);
+    out.extend_from_slice(kind);
+
+    contents(out);
+
+    let size = (out.len() - start) as u32;
+    out[start..start + 4].copy_from_slice(&size.to_be_bytes());
+}
+
+fn write_full_box(
+    out: &mut Vec<u8>,
+    kind: &[u8; 4],
+    version: u8,
+    flags: u32,
+    contents: impl FnOnce(&mut Vec<u8>),
+) {
+    write_box(out, kind, |out| {
+        out.push(version);
+        out.extend_from_slice(&flags.to_be_bytes()[1..]);
+
+        contents(out);
+    });
+}
+
+// the identity matrix
+fn write_matrix(out: &mut Vec<u8>) {
+    for value in [0x0001_0000_u32, 0, 0, 0, 0x0001_0000, 0, 0, 0, 0x4000_0000] {
+        out.extend_from_slice(&value.to_be_bytes());
+    }
+}
+
+// the ids already include their length marker
+fn write_id(out: &mut Vec<u8>, id: u32) {
+    let len = 4 - id.leading_zeros() as usize / 8;
+    out.extend_from_slice(&id.to_be_bytes()[4 - len..]);
+}
+
+fn write_element(out: &mut Vec<u8>, id: u32, contents: impl FnOnce(&mut Vec<u8>)) {
+    write_id(out, id);
+    // always an 8 byte size, filled in once the contents are written
+    let start = out.len();
+    out.extend_from_slice(&[0; 8]);
+
+    contents(out);
+
+    let size = (out.len() - start - 8) as u64 | SIZE_MARKER;
+    out[start..start + 8].copy_from_slice(&size.to_be_bytes());
+}
+
+fn write_bytes(out: &mut Vec<u8>, id: u32, bytes: &[u8]) {
+    write_element(out, id, |out| out.extend_from_slice(bytes));
+}
+
+fn write_uint(out: &mut Vec<u8>, id: u32, value: u64) {
+    // as few bytes as possible, but at least one
+    let len = (8 - value.leading_zeros() as usize / 8).max(1);
+    write_bytes(out, id, &value.to_be_bytes()[8 - len..]);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SPS: [u8; 6] = [0x67, 0x42, 0xC0, 0x1F, 0xAB, 0xCD];
+    const PPS: [u8; 4] = [0x68, 0xCE, 0x3C, 0x80];
+
+    fn headers() -> Vec<u8> {
+        let mut headers = vec![0, 0, 0, 1];
+        headers.extend_from_slice(&SPS);
+        headers.extend_from_slice(&[0, 0, 1]);
+        headers.extend_from_slice(&PPS);
+        headers
+    }
+
+    // the contents of the first box of the given kind, found by its fourcc
+    fn find_box<'a>(data: &'a [u8], kind: &[u8; 4]) -> &'a [u8] {
+        let position = data.windows(4).position(|window| window == kind).unwrap();
+        let size_start = position - 4;
+        let size = u32::from_be_bytes(data[size_start..position].try_into().unwrap()) as usize;
+
+        &data[position + 4..size_start + size]
+    }
+
+    #[test]
+    fn nal_unit_splitting() {
+        let stream = [0, 0, 0, 1, 0x65, 1, 2, 0, 0, 1, 0x41, 3, 0, 0, 0, 1, 0x41, 4];
+
+        let nals: Vec<&[u8]> = nal_units(&stream).collect();
+
+        assert_eq!(nals, [&[0x65, 1, 2][..], &[0x41, 3], &[0x41, 4]]);
+    }
+
+    #[test]
+    fn avcc_embeds_parameter_sets() {
+        let muxer = Fmp4Muxer::new(&headers(), 1920, 1080, 1000).unwrap();
+        let init = muxer.init_segment();
+
+        let avcc = find_box(&init, b"avcC");
+
+        let mut expected = vec![1, 0x42, 0xC0, 0x1F, 0xFF, 0xE1, 0, SPS.len() as u8];
+        expected.extend_from_slice(&SPS);
+        expected.extend_from_slice(&[1, 0, PPS.len() as u8]);
+        expected.extend_from_slice(&PPS);
+
+        assert_eq!(avcc, expected);
+    }
+
+    #[test]
+    fn missing_parameter_sets() {
+        let only_pps = [0, 0, 1, 0x68, 0xCE];
+
+        assert_eq!(Fmp4Muxer::new(&only_pps, 16, 16, 1000).err(), Some(MuxError::MissingSps));
+        assert_eq!(Fmp4Muxer::new(&headers()[..10], 16, 16, 1000).err(), Some(MuxError::MissingPps));
+    }
+
+    #[test]
+    fn chunk_is_length_prefixed() {
+        let mut muxer = Fmp4Muxer::new(&headers(), 16, 16, 1000).unwrap();
+
+        // the parameter sets in front of the keyframe get dropped
+        let mut chunk = headers();
+        chunk.extend_from_slice(&[0, 0, 1, 0x65, 0xAA, 0xBB]);
+
+        let fragment = muxer.wrap_chunk(&chunk, 40, true);
+
+        assert_eq!(find_box(&fragment, b"mdat"), [0, 0, 0, 3, 0x65, 0xAA, 0xBB]);
+        // version 1, no flags, then the 64 bit decode time
+        assert_eq!(find_box(&fragment, b"tfdt"), [1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 40]);
+
+        // the data offset points at the sample
+        let trun = find_box(&fragment, b"trun");
+        let data_offset = u32::from_be_bytes(trun[8..12].try_into().unwrap()) as usize;
+        assert_eq!(&fragment[data_offset..], [0, 0, 0, 3, 0x65, 0xAA, 0xBB]);
+    }
+
+    // splits off the first element, returning its id, its contents and what comes after it
+    fn read_element(data: &[u8]) -> (u32, &[u8], &[u8]) {
+        let id_len = data[0].leading_zeros() as usize + 1;
+        let id = data[..id_len].iter().fold(0, |acc, &b| acc << 8 | u32::from(b));
+
+        let size_len = data[id_len].leading_zeros() as usize + 1;
+        let size_bytes = &data[id_len..id_len + size_len];
+        // without the length marker
+        let size = size_bytes[1..]
+            .iter()
+            .fold(u64::from(size_bytes[0]) & (0xFF >> size_len), |acc, &b| acc << 8 | u64::from(b));
+
+        let rest = &data[id_len + size_len..];
+        let (contents, rest) = rest.split_at(size as usize);
+
+        (id, contents, rest)
+    }
+
+    // (timecode, keyframe) of every block in the clusters following the header
+    fn mkv_blocks(mut data: &[u8]) -> Vec<(u64, bool)> {
+        let mut blocks = Vec::new();
+
+        while !data.is_empty() {
+            let (id, cluster, rest) = read_element(data);
+            data = rest;
+            assert_eq!(id, CLUSTER);
+
+            let (id, timecode, cluster) = read_element(cluster);
+            assert_eq!(id, CLUSTER_TIMECODE);
+            let timecode = timecode.iter().fold(0, |acc, &b| acc << 8 | u64::from(b));
+
+            let (id, block, _) = read_element(cluster);
+            assert_eq!(id, SIMPLE_BLOCK);
+            let relative = i16::from_be_bytes([block[1], block[2]]);
+
+            blocks.push((timecode.saturating_add_signed(relative.into()), block[3] == SIMPLE_BLOCK_KEYFRAME));
+        }
+
+        blocks
+    }
+
+    #[test]
+    fn mkv_starts_with_ebml_magic() {
+        let muxer = MkvMuxer::new(&headers(), 1920, 1080, 1000).unwrap();
+        let header = muxer.header();
+
+        assert_eq!(header[..4], [0x1A, 0x45, 0xDF, 0xA3]);
+
+        // the codec private data is the same decoder configuration as in MP4
+        let mut avc_config = Vec::new();
+        write_avc_config(&mut avc_config, &SPS, &PPS);
+        assert!(header.windows(avc_config.len()).any(|window| window == avc_config));
+    }
+
+    #[test]
+    fn mkv_timestamps_dont_decrease() {
+        let mut muxer = MkvMuxer::new(&headers(), 16, 16, 90_000).unwrap();
+        let chunk = [0, 0, 1, 0x65, 0xAA];
+
+        let mut file = Vec::new();
+        // 0 ms, 100 ms, back to 50 ms which gets clamped, 1 s
+        for (pts, is_key) in [(0, true), (9_000, false), (4_500, false), (90_000, true)] {
+            file.extend_from_slice(&muxer.wrap_chunk(&chunk, pts, is_key));
+        }
+
+        let blocks = mkv_blocks(&file);
+        assert_eq!(blocks, [(0, true), (100, false), (100, false), (1000, true)]);
+        assert!(blocks.windows(2).all(|pair| pair[0].0 <= pair[1].0));
+    }
+}
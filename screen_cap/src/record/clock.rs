@@ -0,0 +1,82 @@
+use std::{
+    fmt,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use parking_lot::Mutex;
+
+/// Where the recorder gets the time for the timestamps from, see `EncoderSettings::clock`
+pub trait Clock: Send + Sync {
+    fn now(&self) -> Instant;
+}
+
+/// The system's monotonic clock, same as leaving `EncoderSettings::clock` empty
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    #[inline]
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// Only moves when told to, so the timestamps come out exactly as expected in tests.
+///
+/// Clones share the same time.
+#[derive(Debug, Clone)]
+pub struct ManualClock {
+    now: Arc<Mutex<Instant>>,
+}
+
+impl ManualClock {
+    /// Starts at the current time and stays there
+    pub fn new() -> Self {
+        Self {
+            now: Arc::new(Mutex::new(Instant::now())),
+        }
+    }
+
+    pub fn advance(&self, by: Duration) {
+        *self.now.lock() += by;
+    }
+}
+
+impl Default for ManualClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for ManualClock {
+    #[inline]
+    fn now(&self) -> Instant {
+        *self.now.lock()
+    }
+}
+
+// `None` calls `Instant::now` directly instead of going through the trait object
+#[derive(Clone, Default)]
+pub(crate) struct RecordClock(Option<Arc<dyn Clock>>);
+
+impl RecordClock {
+    pub(crate) fn new(clock: Option<Arc<dyn Clock>>) -> Self {
+        Self(clock)
+    }
+
+    #[inline]
+    pub(crate) fn now(&self) -> Instant {
+        match &self.0 {
+            Some(clock) => clock.now(),
+            None => Instant::now(),
+        }
+    }
+}
+
+impl fmt::Debug for RecordClock {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = if self.0.is_some() { "custom" } else { "system" };
+        f.debug_tuple("RecordClock").field(&kind).finish()
+    }
+}
@@ -1,64 +1,318 @@
-use std::{sync::Arc, ops::Deref};
+use std::{
+    cell::{Ref, RefCell, RefMut},
+    fs::File,
+    io::{self, BufWriter, Write},
+    ops::{Deref, DerefMut},
+    path::Path,
+    rc::Rc,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Weak,
+    },
+};
 
-use parking_lot::{RwLock, RwLockReadGuard, lock_api::ArcRwLockReadGuard, RawRwLock};
-use utils::contiguous::{RingBuffer, GrowableBuffer, self};
+use parking_lot::{Mutex, RwLock, RwLockReadGuard, RwLockWriteGuard, lock_api::ArcRwLockReadGuard, RawRwLock};
+use utils::contiguous::{RingBuffer, GrowableBuffer, FrameId, OverflowPolicy, BufferItem, self};
 
-#[derive(Debug)]
+use super::RecordError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Metadata {
     pub is_key: bool,
+    /// Presentation timestamp in `EncoderSettings::timebase` units, as reported by the encoder
+    pub pts: i64,
+    /// CRC-32 of the chunk's data, only there if the buffer computes them, see `EncodedBuffer::with_checksums`
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Option::is_none"))]
+    pub crc32: Option<u32>,
+    /// Which track the chunk belongs to when several streams are interleaved in the one buffer, see `TrackQuery`
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "is_screen_track"))]
+    pub track_id: u8,
+}
+
+// keeps single-track metadata serialized the same as before there were tracks
+#[cfg(feature = "serde")]
+fn is_screen_track(track_id: &u8) -> bool {
+    *track_id == Metadata::SCREEN_TRACK
+}
+
+impl Metadata {
+    /// The track the recorder's own encoder writes to
+    pub const SCREEN_TRACK: u8 = 0;
+    
+    /// A chunk of `SCREEN_TRACK`, the checksum gets filled in by the buffer if it's computing them
+    pub fn new(is_key: bool, pts: i64) -> Self {
+        Self {
+            is_key,
+            pts,
+            crc32: None,
+            track_id: Self::SCREEN_TRACK,
+        }
+    }
+}
+
+/// How the ring buffer is shared between an `EncodedBuffer` and whatever reads it.
+///
+/// `SharedRing` lets the views read from other threads, `LocalRing` skips the atomics and the locking
+/// for when the chunks get read on the thread that writes them.
+/// Either way, the guards deref to the ring buffer.
+pub trait RingLock: Clone {
+    type ReadGuard<'a>: Deref<Target = RingBuffer<Metadata>>
+    where
+        Self: 'a;
+    type WriteGuard<'a>: DerefMut<Target = RingBuffer<Metadata>>
+    where
+        Self: 'a;
+    
+    fn new(buf: RingBuffer<Metadata>) -> Self;
+    
+    fn read(&self) -> Self::ReadGuard<'_>;
+    
+    fn write(&self) -> Self::WriteGuard<'_>;
+}
+
+/// The ring buffer behind an `EncodedBuffer` that's read from other threads through an `EncodedBufferView`
+pub type SharedRing = Arc<RwLock<RingBuffer<Metadata>>>;
+
+/// The ring buffer behind a `LocalEncodedBuffer`, borrowing it mutably while a guard is around panics
+pub type LocalRing = Rc<RefCell<RingBuffer<Metadata>>>;
+
+impl RingLock for SharedRing {
+    type ReadGuard<'a> = RwLockReadGuard<'a, RingBuffer<Metadata>>;
+    type WriteGuard<'a> = RwLockWriteGuard<'a, RingBuffer<Metadata>>;
+    
+    fn new(buf: RingBuffer<Metadata>) -> Self {
+        Arc::new(RwLock::new(buf))
+    }
+    
+    fn read(&self) -> Self::ReadGuard<'_> {
+        RwLock::read(self)
+    }
+    
+    fn write(&self) -> Self::WriteGuard<'_> {
+        RwLock::write(self)
+    }
+}
+
+impl RingLock for LocalRing {
+    type ReadGuard<'a> = Ref<'a, RingBuffer<Metadata>>;
+    type WriteGuard<'a> = RefMut<'a, RingBuffer<Metadata>>;
+    
+    fn new(buf: RingBuffer<Metadata>) -> Self {
+        Rc::new(RefCell::new(buf))
+    }
+    
+    fn read(&self) -> Self::ReadGuard<'_> {
+        self.borrow()
+    }
+    
+    fn write(&self) -> Self::WriteGuard<'_> {
+        self.borrow_mut()
+    }
 }
 
 #[derive(Debug)]
-pub struct EncodedBuffer {
-    ring_buf: Arc<RwLock<RingBuffer<Metadata>>>,
+pub struct EncodedBuffer<L: RingLock = SharedRing> {
+    ring_buf: L,
+    cursors: CursorRegistry,
     write_buf: GrowableBuffer<Metadata>,
+    checksums: bool,
+    growable: bool,
 }
 
+/// An `EncodedBuffer` for reading the chunks on the same thread that writes them, e.g. an in-thread pipeline.
+///
+/// Its views can't leave the thread, but reading them doesn't take a lock, see `LocalEncodedBufferView`.
+pub type LocalEncodedBuffer = EncodedBuffer<LocalRing>;
+
 impl EncodedBuffer {
     pub fn new(capacity: usize) -> Self {
-        let ring_buf = RingBuffer::new(capacity);
-        let ring_buf = Arc::new(RwLock::new(ring_buf));
+        Self::with_policy(capacity, OverflowPolicy::default())
+    }
+    
+    /// With `OverflowPolicy::Reject`, flushing fails with `WriteDataError::WouldEvict` once the ring buffer is full
+    /// and the chunks that didn't fit stay in the write buffer until the next flush.
+    pub fn with_policy(capacity: usize, policy: OverflowPolicy) -> Self {
+        Self::with_ring_lock(capacity, policy)
+    }
+    
+    pub fn view(&self) -> EncodedBufferView {
+        let buf = self.ring_buf.clone();
+        let cursors = self.cursors.clone();
+        EncodedBufferView { buf, cursors }
+    }
+}
+
+impl LocalEncodedBuffer {
+    /// Same as `EncodedBuffer::new`
+    pub fn local(capacity: usize) -> Self {
+        Self::local_with_policy(capacity, OverflowPolicy::default())
+    }
+    
+    /// Same as `EncodedBuffer::with_policy`
+    pub fn local_with_policy(capacity: usize, policy: OverflowPolicy) -> Self {
+        Self::with_ring_lock(capacity, policy)
+    }
+    
+    pub fn view(&self) -> LocalEncodedBufferView {
+        LocalEncodedBufferView { buf: self.ring_buf.clone() }
+    }
+}
+
+impl<L: RingLock> EncodedBuffer<L> {
+    fn with_ring_lock(capacity: usize, policy: OverflowPolicy) -> Self {
+        let ring_buf = L::new(RingBuffer::with_policy(capacity, policy));
         
         let write_buf = GrowableBuffer::new();
         
         Self {
             ring_buf,
+            cursors: CursorRegistry::default(),
             write_buf,
+            checksums: false,
+            growable: false,
         }
     }
     
+    /// Makes every write fill in `Metadata::crc32`, so readers can tell whether a chunk got corrupted on the way.
+    ///
+    /// Off by default, hashing every chunk takes time on the encoder thread.
+    pub fn with_checksums(mut self) -> Self {
+        self.checksums = true;
+        self
+    }
+    
+    /// Makes chunks that are larger than the whole ring buffer grow it to fit, see `make_room_for`.
+    ///
+    /// The ring buffer never shrinks back, so a single huge chunk keeps the memory it took.
+    pub fn growable(mut self) -> Self {
+        self.growable = true;
+        self
+    }
+    
+    /// Checks that a chunk of `len` bytes can fit into the ring buffer at all,
+    /// growing the ring buffer if it's `growable` and failing with `WriteDataError::DataTooLarge` otherwise.
+    ///
+    /// Flushing a chunk that doesn't fit fails every flush after it as well, since the chunk stays in the write buffer,
+    /// so it's best to check before `write`.
+    pub fn make_room_for(&mut self, len: usize) -> Result<(), contiguous::WriteDataError> {
+        if len <= self.capacity() {
+            return Ok(());
+        }
+        
+        if !self.growable {
+            return Err(contiguous::WriteDataError::DataTooLarge);
+        }
+        
+        self.ring_buf.write().ensure_capacity(len);
+        Ok(())
+    }
+    
     pub fn write(&mut self, data: &[u8], metadata: Metadata) {
+        let metadata = self.checksummed(data, metadata);
         self.write_buf.write(data, metadata);
     }
     
-    pub fn write_flush(&mut self, data: &[u8], metadata: Metadata) -> Result<(), contiguous::WriteDataError> {
-        self.flush()?;
-        self.ring_buf.write().write(data, metadata)?;
+    /// Makes room in the write buffer for `chunks` more chunks of `bytes` bytes in total,
+    /// see `GrowableBuffer::reserve`
+    pub fn reserve(&mut self, bytes: usize, chunks: usize) {
+        self.write_buf.reserve(bytes, chunks);
+    }
+    
+    /// Flushes the write buffer and writes the chunk after it, returns the id of the chunk
+    pub fn write_flush(&mut self, data: &[u8], metadata: Metadata) -> Result<FrameId, contiguous::WriteDataError> {
+        let metadata = self.checksummed(data, metadata);
+        let flushed = self.flush().and_then(|_| self.ring_buf.read().check_write(data.len()));
         
-        Ok(())
+        if let Err(e) = flushed {
+            // keep the chunk around for the next flush instead of losing it
+            self.write_buf.write(data, metadata);
+            return Err(e);
+        }
+        
+        let mut ring_buf = self.ring_buf.write();
+        pin_slowest_cursor(&self.cursors, &mut ring_buf);
+        ring_buf.write(data, metadata)
+    }
+    
+    // whatever the caller passed in stays if checksums are off
+    fn checksummed(&self, data: &[u8], mut metadata: Metadata) -> Metadata {
+        if self.checksums {
+            metadata.crc32 = Some(crc32fast::hash(data));
+        }
+        
+        metadata
     }
     
-    pub fn flush(&mut self)  -> Result<(), contiguous::WriteDataError> {
-        self.write_buf.dump_into_ring_buffer(&mut self.ring_buf.write())
+    /// Returns the range of ids the flushed chunks got
+    pub fn flush(&mut self)  -> Result<(FrameId, FrameId), contiguous::WriteDataError> {
+        let mut ring_buf = self.ring_buf.write();
+        pin_slowest_cursor(&self.cursors, &mut ring_buf);
+        self.write_buf.dump_into_ring_buffer(&mut ring_buf)
     }
     
-    pub fn view(&self) -> EncodedBufferView {
-        let buf = self.ring_buf.clone();
-        EncodedBufferView { buf }
+    /// See `RingBuffer::bytes_used`
+    pub fn bytes_used(&self) -> usize {
+        self.ring_buf.read().bytes_used()
+    }
+    
+    /// See `RingBuffer::capacity`
+    pub fn capacity(&self) -> usize {
+        self.ring_buf.read().capacity()
     }
     
     pub fn write_buf_len(&self) -> usize {
         self.write_buf.len()
     }
     
+    /// How many bytes are waiting in the local buffer to be flushed
+    pub fn write_buf_bytes(&self) -> usize {
+        self.write_buf.iter().map(|item| item.len()).sum()
+    }
+    
     pub fn write_buf_is_empty(&self) -> bool {
         self.write_buf.is_empty()
     }
+    
+    /// The id the next chunk written is going to get, after the ones waiting in the write buffer
+    pub fn next_id(&self) -> FrameId {
+        self.ring_buf.read().id_bounds().1 + self.write_buf_len()
+    }
+    
+    /// Empties the local buffer without flushing it, returning the chunks in it back to back
+    pub fn take_pending(&mut self) -> Vec<u8> {
+        let mut pending = Vec::with_capacity(self.write_buf_bytes());
+        for item in self.write_buf.iter() {
+            pending.extend_from_slice(&item.data());
+        }
+        
+        // keeps the room reserved for the pre-buffered frames
+        self.write_buf.clear();
+        
+        pending
+    }
+}
+
+// the cursors only move when they read, so it's enough for the pin to catch up before anything gets written
+fn pin_slowest_cursor(cursors: &CursorRegistry, ring_buf: &mut RingBuffer<Metadata>) {
+    let cursors = cursors.lock();
+    if cursors.pinned {
+        ring_buf.set_pin(cursors.min_position());
+    }
+}
+
+// makes sure pre-buffered frames still reach the shared ring buffer when the worker goes away
+impl<L: RingLock> Drop for EncodedBuffer<L> {
+    fn drop(&mut self) {
+        // nowhere to report the error to at this point
+        let _ = self.flush();
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct EncodedBufferView {
     buf: Arc<RwLock<RingBuffer<Metadata>>>,
+    cursors: CursorRegistry,
 }
 
 impl EncodedBufferView {
@@ -69,15 +323,460 @@ impl EncodedBufferView {
     pub fn get_arc(&self) -> ArcEncodedDataGuard {
         ArcEncodedDataGuard { inner: self.buf.read_arc() }
     }
+    
+    /// Same as `get`, except it gives up right away if the encoder is in the middle of a flush
+    /// instead of waiting for it, so the caller can skip a frame rather than fall behind.
+    pub fn try_get(&self) -> Option<EncodedDataGuard<'_>> {
+        Some(EncodedDataGuard { inner: self.buf.try_read()? })
+    }
+    
+    /// Same as `get_arc`, except it doesn't block, see `try_get`
+    pub fn try_get_arc(&self) -> Option<ArcEncodedDataGuard> {
+        Some(ArcEncodedDataGuard { inner: self.buf.try_read_arc()? })
+    }
+    
+    /// Returns the id bounds together with the latest keyframe, all read under a single lock
+    /// so a write can't slip in between.
+    pub fn bootstrap_info(&self) -> BootstrapInfo {
+        BootstrapInfo::from_buffer(&self.buf.read())
+    }
+    
+    /// Copies everything in the buffer out under a single lock.
+    ///
+    /// Unlike holding a guard, the snapshot doesn't keep the encoder from flushing,
+    /// so it's the way to go for anything slow like writing the buffer to disk.
+    pub fn snapshot(&self) -> BufferSnapshot {
+        BufferSnapshot::from_buffer(&self.buf.read())
+    }
+    
+    /// Copies the newest chunk out along with its id, `None` if the buffer is empty.
+    ///
+    /// Like `snapshot`, the lock is only held for the copy, e.g. for polling thumbnails.
+    pub fn latest(&self) -> Option<(FrameId, OwnedChunk)> {
+        let buf = self.buf.read();
+        if buf.is_empty() {
+            return None;
+        }
+        
+        let id = buf.id_bounds().1 - 1;
+        
+        Some((id, buf.get_owned(id)?))
+    }
+    
+    /// Copies the chunk `id` out, `None` if it's not in the buffer (anymore).
+    ///
+    /// The lock is only held for the copy, so the chunk can be sent somewhere slow
+    /// without keeping the encoder from flushing.
+    pub fn get_owned(&self, id: FrameId) -> Option<OwnedChunk> {
+        self.buf.read().get_owned(id)
+    }
+    
+    /// How many chunks are in the buffer, see `RingBuffer::len`
+    pub fn len(&self) -> usize {
+        self.buf.read().len()
+    }
+    
+    pub fn is_empty(&self) -> bool {
+        self.buf.read().is_empty()
+    }
+    
+    /// See `RingBuffer::bytes_used`
+    pub fn bytes_used(&self) -> usize {
+        self.buf.read().bytes_used()
+    }
+    
+    /// See `RingBuffer::capacity`
+    pub fn capacity(&self) -> usize {
+        self.buf.read().capacity()
+    }
+    
+    /// See `KeyframeSeek::latest_keyframe_id`
+    pub fn latest_keyframe_id(&self) -> Option<FrameId> {
+        self.buf.read().latest_keyframe_id()
+    }
+    
+    /// See `KeyframeSeek::keyframe_id_at_or_before`
+    pub fn keyframe_id_at_or_before(&self, id: FrameId) -> Option<FrameId> {
+        self.buf.read().keyframe_id_at_or_before(id)
+    }
+    
+    /// The ids from the oldest keyframe to the end, what a new viewer can play, see `RingBuffer::decodable_bounds`
+    pub fn decodable_bounds(&self) -> Option<(FrameId, FrameId)> {
+        self.buf.read().decodable_bounds(|metadata| metadata.is_key)
+    }
+    
+    /// Copies the chunks of `track` out of `TrackQuery::track_range`, along with their ids
+    pub fn range_for_track(&self, track: u8, start_id: FrameId, end_id: FrameId) -> Vec<(FrameId, OwnedChunk)> {
+        self.buf
+            .read()
+            .track_range(track, start_id, end_id)
+            .map(|(id, item)| (id, item.to_owned()))
+            .collect()
+    }
+    
+    /// Keeps chunks from `pin` onwards from being evicted, see `RingBuffer::set_pin`.
+    ///
+    /// Along with the high-water callback, this is the only thing a view can change about the buffer,
+    /// it briefly takes the write lock, so it waits for all the guards to be dropped.
+    pub fn set_pin(&self, pin: Option<FrameId>) {
+        self.buf.write().set_pin(pin);
+    }
+    
+    /// See `RingBuffer::set_high_water`, takes the write lock like `set_pin`.
+    ///
+    /// The callback gets called with the write lock held, so it mustn't lock the buffer through a view itself.
+    pub fn set_high_water(&self, fraction: f32, callback: impl Fn() + Send + Sync + 'static) {
+        self.buf.write().set_high_water(fraction, callback);
+    }
+    
+    /// See `RingBuffer::clear_high_water`
+    pub fn clear_high_water(&self) {
+        self.buf.write().clear_high_water();
+    }
+    
+    /// A cursor that reads the chunks flushed from now on, see `ReaderCursor`
+    pub fn new_cursor(&self) -> ReaderCursor {
+        let position = self.buf.read().id_bounds().1;
+        self.new_cursor_at(position)
+    }
+    
+    /// A cursor whose first chunk is going to be `id`, e.g. the latest keyframe
+    pub fn new_cursor_at(&self, id: FrameId) -> ReaderCursor {
+        let shared_position = Arc::new(AtomicUsize::new(id.get()));
+        
+        let mut cursors = self.cursors.lock();
+        // the dropped cursors go away whenever a new one comes
+        cursors.positions.retain(|cursor| cursor.strong_count() > 0);
+        cursors.positions.push(Arc::downgrade(&shared_position));
+        
+        ReaderCursor {
+            buf: self.buf.clone(),
+            state: Mutex::new(CursorState { position: id, missed: None }),
+            shared_position,
+        }
+    }
+    
+    /// Where the slowest of the cursors over this buffer is, `None` if there are none.
+    ///
+    /// Passing it to `set_pin` keeps the chunks any cursor still has to read from being evicted.
+    /// Doesn't lock the buffer, so it can be called from the high-water callback.
+    pub fn min_cursor_position(&self) -> Option<FrameId> {
+        self.cursors.lock().min_position()
+    }
+    
+    /// Keeps the chunks the slowest cursor hasn't read yet from being evicted, the ring buffer grows instead.
+    ///
+    /// The pin follows the cursors every time the buffer gets flushed.
+    /// A cursor that falls more than `max_pinned_chunks` behind the newest chunk stops holding the buffer back
+    /// and gets skipped ahead like without the pin, see `ReaderCursor::take_missed`,
+    /// so a reader that stalls can't grow the buffer forever. `None` turns the pinning off.
+    pub fn pin_cursors(&self, max_pinned_chunks: Option<usize>) {
+        let mut buf = self.buf.write();
+        let mut cursors = self.cursors.lock();
+        cursors.pinned = max_pinned_chunks.is_some();
+        
+        buf.set_max_pinned(max_pinned_chunks);
+        buf.set_pin(max_pinned_chunks.and(cursors.min_position()));
+    }
 }
 
-type Guard<'a> = RwLockReadGuard<'a, RingBuffer<Metadata>>;
+/// A view of a `LocalEncodedBuffer`, for the thread that owns the buffer.
+///
+/// Only has the basic reads, the rest work through the guard just the same,
+/// e.g. `BufferSnapshot::from_buffer` or `KeyframeSeek`.
+#[derive(Debug, Clone)]
+pub struct LocalEncodedBufferView {
+    buf: LocalRing,
+}
 
-pub struct EncodedDataGuard<'a> {
-    inner: Guard<'a>,
+impl LocalEncodedBufferView {
+    /// Never waits, the buffer only gets written through the `LocalEncodedBuffer` on the same thread
+    pub fn get(&self) -> EncodedDataGuard<'_, LocalRing> {
+        EncodedDataGuard { inner: self.buf.read() }
+    }
+    
+    /// See `EncodedBufferView::bootstrap_info`
+    pub fn bootstrap_info(&self) -> BootstrapInfo {
+        BootstrapInfo::from_buffer(&self.buf.read())
+    }
+    
+    /// See `RingBuffer::len`
+    pub fn len(&self) -> usize {
+        self.buf.read().len()
+    }
+    
+    pub fn is_empty(&self) -> bool {
+        self.buf.read().is_empty()
+    }
+}
+
+// the positions of every live cursor over one buffer, shared by the buffer and all its views
+type CursorRegistry = Arc<Mutex<Cursors>>;
+
+#[derive(Debug, Default)]
+struct Cursors {
+    positions: Vec<Weak<AtomicUsize>>,
+    // whether the buffer pins the slowest cursor, see `EncodedBufferView::pin_cursors`
+    pinned: bool,
+}
+
+impl Cursors {
+    fn min_position(&self) -> Option<FrameId> {
+        self.positions
+            .iter()
+            .filter_map(Weak::upgrade)
+            .map(|position| FrameId::new(position.load(Ordering::Acquire)))
+            .min()
+    }
+}
+
+/// A reader that remembers how far it's gotten, issued by `EncodedBufferView::new_cursor`.
+///
+/// Every call to `next_chunks` hands out the chunks flushed since the previous one,
+/// so the reader doesn't have to keep track of the last id it's seen on its own.
+/// Chunks that got evicted before the cursor got to them are skipped, see `take_missed`.
+/// The cursor's position counts towards `EncodedBufferView::min_cursor_position` until it's dropped.
+#[derive(Debug)]
+pub struct ReaderCursor {
+    buf: Arc<RwLock<RingBuffer<Metadata>>>,
+    state: Mutex<CursorState>,
+    // the copy of the position the registry sees, without locking the state
+    shared_position: Arc<AtomicUsize>,
+}
+
+#[derive(Debug)]
+struct CursorState {
+    position: FrameId,
+    missed: Option<(FrameId, FrameId)>,
 }
 
-impl Deref for EncodedDataGuard<'_> {
+impl ReaderCursor {
+    /// Copies out every chunk from the cursor's position on and moves it past them.
+    ///
+    /// The lock is only held for the copy, like with `EncodedBufferView::snapshot`.
+    pub fn next_chunks(&self) -> Vec<OwnedChunk> {
+        let mut state = self.state.lock();
+        let buf = self.buf.read();
+        let (id_min, id_max) = buf.id_bounds();
+        
+        if id_min > state.position {
+            // adds on to what's been missed before, if it hasn't been taken yet
+            let lost_from = state.missed.map_or(state.position, |(lost_from, _)| lost_from);
+            state.missed = Some((lost_from, id_min));
+            state.position = id_min;
+        }
+        
+        let chunks: Vec<_> = buf.range(state.position, id_max).map(|item| item.to_owned()).collect();
+        state.position = state.position.max(id_max);
+        self.shared_position.store(state.position.get(), Ordering::Release);
+        
+        chunks
+    }
+    
+    /// The id of the chunk the next `next_chunks` starts with
+    pub fn position(&self) -> FrameId {
+        self.state.lock().position
+    }
+    
+    /// The ids of the chunks that got evicted before `next_chunks` could hand them out, if there were any,
+    /// from the first one up to, but not including, the first one that was still there.
+    ///
+    /// Clears it, so the same gap is only reported once.
+    pub fn take_missed(&self) -> Option<(FrameId, FrameId)> {
+        self.state.lock().missed.take()
+    }
+}
+
+/// Finding GOP boundaries, a decoder can only start decoding the stream at a keyframe.
+///
+/// Implemented for the ring buffer, so it also works through the read guards.
+pub trait KeyframeSeek {
+    /// Id of the most recent keyframe in the buffer
+    fn latest_keyframe_id(&self) -> Option<FrameId>;
+    
+    /// Id of the newest keyframe that isn't after `id`,
+    /// `None` if the keyframe that starts the GOP of `id` has been evicted already
+    fn keyframe_id_at_or_before(&self, id: FrameId) -> Option<FrameId>;
+}
+
+impl KeyframeSeek for RingBuffer<Metadata> {
+    fn latest_keyframe_id(&self) -> Option<FrameId> {
+        self.rfind_id(|item| item.metadata().is_key)
+    }
+    
+    fn keyframe_id_at_or_before(&self, id: FrameId) -> Option<FrameId> {
+        let (min_id, max_id) = self.id_bounds();
+        let end_id = (id + 1).min(max_id);
+        
+        FrameId::range(min_id, end_id.max(min_id))
+            .rev()
+            .find(|&id| self.get(id).is_some_and(|item| item.metadata().is_key))
+    }
+}
+
+/// The lookups for buffers holding the chunks of several tracks, see `Metadata::track_id`.
+///
+/// The tracks share the ids, so the chunks of a single track don't have consecutive ones.
+/// `KeyframeSeek` doesn't care about tracks, which is fine as long as there's only the one.
+pub trait TrackQuery {
+    /// The chunks of `track` with ids from `start_id` up to, but not including, `end_id`, oldest first.
+    ///
+    /// The range gets clamped the same as in `RingBuffer::range`.
+    fn track_range(
+        &self,
+        track: u8,
+        start_id: FrameId,
+        end_id: FrameId,
+    ) -> impl Iterator<Item = (FrameId, BufferItem<'_, Metadata>)>;
+    
+    /// Same as `KeyframeSeek::latest_keyframe_id`, for `track` only
+    fn latest_track_keyframe_id(&self, track: u8) -> Option<FrameId>;
+    
+    /// Same as `KeyframeSeek::keyframe_id_at_or_before`, for `track` only, `id` can be from any track
+    fn track_keyframe_id_at_or_before(&self, track: u8, id: FrameId) -> Option<FrameId>;
+}
+
+impl TrackQuery for RingBuffer<Metadata> {
+    fn track_range(
+        &self,
+        track: u8,
+        start_id: FrameId,
+        end_id: FrameId,
+    ) -> impl Iterator<Item = (FrameId, BufferItem<'_, Metadata>)> {
+        let (min_id, max_id) = self.id_bounds();
+        let start = start_id.clamp(min_id, max_id);
+        let end = end_id.clamp(min_id, max_id).max(start);
+        
+        FrameId::range(start, end)
+            .zip(self.range(start, end))
+            .filter(move |(_, item)| item.metadata().track_id == track)
+    }
+    
+    fn latest_track_keyframe_id(&self, track: u8) -> Option<FrameId> {
+        self.rfind_id(|item| item.metadata().is_key && item.metadata().track_id == track)
+    }
+    
+    fn track_keyframe_id_at_or_before(&self, track: u8, id: FrameId) -> Option<FrameId> {
+        let (min_id, _) = self.id_bounds();
+        
+        self.track_range(track, min_id, id + 1)
+            .filter(|(_, item)| item.metadata().is_key)
+            .last()
+            .map(|(id, _)| id)
+    }
+}
+
+/// A consistent snapshot of what a new viewer needs to know to start reading the buffer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BootstrapInfo {
+    pub min_id: FrameId,
+    pub max_id: FrameId,
+    pub latest_keyframe: Option<FrameId>,
+}
+
+impl BootstrapInfo {
+    /// Useful when the buffer is already locked, e.g. through an `EncodedDataGuard`
+    pub fn from_buffer(buf: &RingBuffer<Metadata>) -> Self {
+        let (min_id, max_id) = buf.id_bounds();
+        
+        Self {
+            min_id,
+            max_id,
+            latest_keyframe: buf.latest_keyframe_id(),
+        }
+    }
+}
+
+/// An owned copy of the chunks in the buffer, see `EncodedBufferView::snapshot`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BufferSnapshot {
+    /// The data of all the chunks back to back, oldest first
+    pub bytes: Vec<u8>,
+    /// One per chunk, in the same order as their data
+    pub items: Vec<SnapshotItem>,
+}
+
+/// A single chunk copied out of the buffer, see `EncodedBufferView::latest` and `EncodedBufferView::get_owned`
+pub type OwnedChunk = contiguous::OwnedBufferItem<Metadata>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SnapshotItem {
+    pub id: FrameId,
+    /// Where the chunk's data starts in `BufferSnapshot::bytes`, it ends where the next one starts
+    pub offset: usize,
+    pub metadata: Metadata,
+}
+
+impl BufferSnapshot {
+    /// Useful when the buffer is already locked, e.g. through an `EncodedDataGuard`
+    pub fn from_buffer(buf: &RingBuffer<Metadata>) -> Self {
+        let mut bytes = Vec::with_capacity(buf.bytes_used());
+        
+        let items = buf
+            .iter_ids()
+            .map(|(id, item)| {
+                let offset = bytes.len();
+                let (first, second) = item.as_slices();
+                bytes.extend_from_slice(first);
+                bytes.extend_from_slice(second);
+                
+                SnapshotItem {
+                    id,
+                    offset,
+                    metadata: *item.metadata(),
+                }
+            })
+            .collect();
+        
+        Self { bytes, items }
+    }
+    
+    /// The data from the oldest keyframe on, where a decoder can start, `None` if there's no keyframe
+    pub fn decodable_bytes(&self) -> Option<&[u8]> {
+        let keyframe = self.items.iter().find(|item| item.metadata.is_key)?;
+        
+        Some(&self.bytes[keyframe.offset..])
+    }
+    
+    /// Writes `headers` followed by `decodable_bytes` into a new file at `path`, see `Recorder::save_replay`.
+    ///
+    /// The headers have to be the ones the keyframe was encoded with,
+    /// so a replay that reaches back past a resize won't play.
+    pub fn save_replay(&self, headers: &[u8], path: &Path) -> Result<(), RecordError> {
+        let data = self.decodable_bytes().ok_or(RecordError::NoKeyframe)?;
+        
+        let write = || -> io::Result<()> {
+            let mut file = BufWriter::new(File::create(path)?);
+            file.write_all(headers)?;
+            file.write_all(data)?;
+            file.flush()
+        };
+        
+        write().map_err(RecordError::SaveReplay)
+    }
+    
+    /// Every chunk along with its data, oldest first
+    pub fn iter(&self) -> impl Iterator<Item = (&SnapshotItem, &[u8])> + '_ {
+        let ends = self
+            .items
+            .iter()
+            .skip(1)
+            .map(|item| item.offset)
+            .chain([self.bytes.len()]);
+        
+        self.items
+            .iter()
+            .zip(ends)
+            .map(|(item, end)| (item, &self.bytes[item.offset..end]))
+    }
+}
+
+/// A read lock on the ring buffer, `SharedRing`'s unless it comes out of a `LocalEncodedBufferView`
+pub struct EncodedDataGuard<'a, L: RingLock + 'a = SharedRing> {
+    inner: L::ReadGuard<'a>,
+}
+
+impl<L: RingLock> Deref for EncodedDataGuard<'_, L> {
     type Target = RingBuffer<Metadata>;
 
     fn deref(&self) -> &Self::Target {
@@ -99,3 +798,393 @@ impl Deref for ArcEncodedDataGuard {
         &self.inner
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::mpsc, thread};
+
+    use super::*;
+
+    fn write_chunk(buf: &mut EncodedBuffer, is_key: bool) -> FrameId {
+        buf.write_flush(&[0; 4], Metadata::new(is_key, 0)).unwrap()
+    }
+
+    #[test]
+    fn take_pending_skips_ring_buffer() {
+        let mut buf = EncodedBuffer::new(16);
+        let view = buf.view();
+
+        buf.write(&[1, 2], Metadata::new(true, 0));
+        buf.write(&[3], Metadata::new(false, 1));
+
+        assert_eq!(buf.take_pending(), [1, 2, 3]);
+        assert!(buf.write_buf_is_empty());
+
+        // nothing left for the drop to flush either
+        drop(buf);
+        assert_eq!(view.get().id_bounds(), (FrameId::default(), FrameId::default()));
+    }
+
+    #[test]
+    fn no_keyframes() {
+        let mut buf = EncodedBuffer::new(16);
+        let view = buf.view();
+
+        assert_eq!(view.latest_keyframe_id(), None);
+
+        for _ in 0..3 {
+            write_chunk(&mut buf, false);
+        }
+
+        assert_eq!(view.latest_keyframe_id(), None);
+        assert_eq!(view.keyframe_id_at_or_before(FrameId::new(2)), None);
+    }
+
+    #[test]
+    fn keyframe_seek() {
+        let mut buf = EncodedBuffer::new(64);
+        let view = buf.view();
+
+        let first_key = write_chunk(&mut buf, true);
+        let delta = write_chunk(&mut buf, false);
+        let second_key = write_chunk(&mut buf, true);
+        write_chunk(&mut buf, false);
+
+        assert_eq!(view.latest_keyframe_id(), Some(second_key));
+        assert_eq!(view.keyframe_id_at_or_before(delta), Some(first_key));
+        assert_eq!(view.keyframe_id_at_or_before(second_key), Some(second_key));
+        assert_eq!(view.keyframe_id_at_or_before(FrameId::new(100)), Some(second_key));
+    }
+
+    #[test]
+    fn try_get_during_flush() {
+        let mut buf = EncodedBuffer::new(16);
+        let view = buf.view();
+        write_chunk(&mut buf, true);
+
+        let (locked_tx, locked_rx) = mpsc::channel();
+        let (release_tx, release_rx) = mpsc::channel::<()>();
+
+        thread::scope(|scope| {
+            // stands in for the encoder thread holding the lock while flushing
+            let ring_buf = &buf.ring_buf;
+            scope.spawn(move || {
+                let _write_guard = ring_buf.write();
+                locked_tx.send(()).unwrap();
+                let _ = release_rx.recv();
+            });
+
+            locked_rx.recv().unwrap();
+            assert!(view.try_get().is_none());
+            assert!(view.try_get_arc().is_none());
+
+            drop(release_tx);
+        });
+
+        assert_eq!(view.try_get().unwrap().id_bounds(), (FrameId::new(0), FrameId::new(1)));
+        assert!(view.try_get_arc().is_some());
+    }
+
+    #[test]
+    fn checksums_match_data() {
+        let mut buf = EncodedBuffer::new(64).with_checksums();
+        let view = buf.view();
+
+        let mut data = vec![1_u8, 2, 3, 4, 5];
+        buf.write(&data, Metadata::new(true, 0));
+        data[2] ^= 0x10;
+        buf.write_flush(&data, Metadata::new(false, 1)).unwrap();
+
+        let guard = view.get();
+        let crcs: Vec<u32> = guard.iter().map(|item| item.metadata().crc32.unwrap()).collect();
+
+        assert_eq!(crcs[0], crc32fast::hash(&[1, 2, 3, 4, 5]));
+        assert_eq!(crcs[1], crc32fast::hash(&data));
+        // a single flipped bit changes it
+        assert_ne!(crcs[0], crcs[1]);
+
+        // off by default
+        let mut buf = EncodedBuffer::new(64);
+        buf.write_flush(&data, Metadata::new(true, 0)).unwrap();
+        assert_eq!(buf.view().get().iter().next().unwrap().metadata().crc32, None);
+    }
+
+    #[test]
+    fn cursors_read_from_their_own_positions() {
+        // room for 4 chunks
+        let mut buf = EncodedBuffer::new(16);
+        let view = buf.view();
+        assert_eq!(view.min_cursor_position(), None);
+
+        let first = write_chunk(&mut buf, true);
+        let early = view.new_cursor_at(first);
+        write_chunk(&mut buf, false);
+        let late = view.new_cursor();
+        assert_eq!(late.position(), FrameId::new(2));
+        assert_eq!(view.min_cursor_position(), Some(first));
+
+        write_chunk(&mut buf, false);
+        assert_eq!(early.next_chunks().len(), 3);
+        assert_eq!(late.next_chunks().len(), 1);
+        // nothing new since
+        assert!(early.next_chunks().is_empty());
+        assert_eq!(view.min_cursor_position(), Some(FrameId::new(3)));
+
+        // the late one falls behind, ids 3 and 4 get evicted
+        for _ in 0..6 {
+            write_chunk(&mut buf, false);
+        }
+        assert_eq!(late.next_chunks().len(), 4);
+        assert_eq!(late.take_missed(), Some((FrameId::new(3), FrameId::new(5))));
+        assert_eq!(late.take_missed(), None);
+        assert_eq!(late.position(), FrameId::new(9));
+
+        // a dropped cursor doesn't hold anything back
+        drop(early);
+        assert_eq!(view.min_cursor_position(), Some(FrameId::new(9)));
+    }
+
+    #[test]
+    fn pinned_cursors_dont_miss_chunks() {
+        let mut buf = EncodedBuffer::new(16);
+        let view = buf.view();
+        view.pin_cursors(Some(100));
+
+        let fast = view.new_cursor();
+        let slow = view.new_cursor();
+        for _ in 0..10 {
+            write_chunk(&mut buf, false);
+            assert_eq!(fast.next_chunks().len(), 1);
+        }
+
+        // the buffer grew instead of evicting what the slow one hadn't read
+        assert_eq!(slow.next_chunks().len(), 10);
+        assert_eq!(slow.take_missed(), None);
+        assert_eq!(view.min_cursor_position(), Some(FrameId::new(10)));
+
+        // once it's caught up, the buffer doesn't hold on to more than it has to
+        let capacity = view.capacity();
+        for _ in 0..10 {
+            write_chunk(&mut buf, false);
+            fast.next_chunks();
+            slow.next_chunks();
+        }
+        assert_eq!(view.capacity(), capacity);
+    }
+
+    #[test]
+    fn stalled_cursor_stops_being_pinned() {
+        let mut buf = EncodedBuffer::new(16);
+        let view = buf.view();
+        view.pin_cursors(Some(4));
+
+        // the only reader never reads
+        let stalled = view.new_cursor();
+        for _ in 0..1000 {
+            write_chunk(&mut buf, false);
+        }
+
+        // it only grew while the cursor was close enough, then the buffer wrapped around as usual
+        assert_eq!(view.capacity(), 32);
+        assert_eq!(stalled.next_chunks().len(), view.len());
+        assert_eq!(stalled.take_missed().unwrap().0, FrameId::new(0));
+    }
+
+    #[test]
+    fn local_buffer_reads_like_the_shared_one() {
+        let mut local = LocalEncodedBuffer::local(64);
+        let mut shared = EncodedBuffer::new(64);
+        let view = local.view();
+        
+        local.write(&[1, 2], Metadata::new(true, 0));
+        local.write(&[3], Metadata::new(false, 1));
+        shared.write(&[1, 2], Metadata::new(true, 0));
+        shared.write(&[3], Metadata::new(false, 1));
+        assert!(view.is_empty());
+        
+        assert_eq!(local.flush().unwrap(), shared.flush().unwrap());
+        let id = local.write_flush(&[4, 5, 6], Metadata::new(true, 2)).unwrap();
+        assert_eq!(id, write_chunk(&mut shared, true));
+        
+        assert_eq!(view.len(), 3);
+        let shared_view = shared.view();
+        assert_eq!(view.bootstrap_info(), shared_view.bootstrap_info());
+        // the guards deref to the same thing
+        let (local_guard, shared_guard) = (view.get(), shared_view.get());
+        assert_eq!(local_guard.latest_keyframe_id(), shared_guard.latest_keyframe_id());
+        assert_eq!(BufferSnapshot::from_buffer(&local_guard).bytes, [1, 2, 3, 4, 5, 6]);
+    }
+    
+    #[test]
+    fn latest_is_newest_chunk() {
+        let mut buf = EncodedBuffer::new(16);
+        let view = buf.view();
+
+        assert!(view.is_empty());
+        assert_eq!(view.latest(), None);
+
+        // the first ones get evicted on the way
+        for i in 0..6u8 {
+            buf.write_flush(&[i; 4], Metadata::new(i == 5, i.into())).unwrap();
+        }
+
+        let (id, chunk) = view.latest().unwrap();
+        assert_eq!(id, FrameId::new(5));
+        assert_eq!(
+            chunk,
+            OwnedChunk {
+                data: vec![5; 4],
+                metadata: Metadata::new(true, 5),
+            }
+        );
+        assert_eq!(view.len(), 4);
+        assert!(!view.is_empty());
+
+        assert_eq!(view.get_owned(FrameId::new(4)).unwrap().data, [4; 4]);
+        assert_eq!(view.get_owned(FrameId::new(0)), None);
+    }
+
+    #[test]
+    fn only_keyframe_evicted() {
+        let mut buf = EncodedBuffer::new(16);
+        let view = buf.view();
+
+        write_chunk(&mut buf, true);
+        for _ in 0..4 {
+            write_chunk(&mut buf, false);
+        }
+
+        assert_eq!(view.get().id_bounds().0, FrameId::new(1));
+        assert_eq!(view.latest_keyframe_id(), None);
+        assert_eq!(view.bootstrap_info().latest_keyframe, None);
+    }
+
+    #[test]
+    fn oversized_chunk_grows_or_gets_rejected() {
+        let metadata = Metadata::new(true, 0);
+
+        let mut fixed = EncodedBuffer::new(8);
+        write_chunk(&mut fixed, true);
+        assert!(matches!(fixed.make_room_for(16), Err(contiguous::WriteDataError::DataTooLarge)));
+        assert_eq!(fixed.capacity(), 8);
+        // still usable for the chunks that fit
+        fixed.make_room_for(4).unwrap();
+        fixed.write_flush(&[1; 4], metadata).unwrap();
+
+        let mut growable = EncodedBuffer::new(8).growable();
+        let view = growable.view();
+        write_chunk(&mut growable, true);
+        growable.make_room_for(16).unwrap();
+        assert_eq!(growable.capacity(), 16);
+
+        let id = growable.write_flush(&[2; 16], metadata).unwrap();
+        assert_eq!(view.get_owned(id).unwrap().data, [2; 16]);
+    }
+
+    #[test]
+    fn tracks_are_separate() {
+        let mut buf = EncodedBuffer::new(64);
+        let view = buf.view();
+
+        // the screen gets a keyframe every other chunk, the second track only on its first one
+        for i in 0..8u8 {
+            let track_id = i % 2;
+            let is_key = if track_id == 0 { i % 4 == 0 } else { i == 1 };
+            buf.write_flush(&[i; 4], Metadata { is_key, pts: i.into(), crc32: None, track_id }).unwrap();
+        }
+
+        let chunks = |track| -> Vec<(usize, u8, bool)> {
+            view.range_for_track(track, FrameId::new(0), FrameId::new(100))
+                .into_iter()
+                .map(|(id, chunk)| (id.get(), chunk.data[0], chunk.metadata.is_key))
+                .collect()
+        };
+
+        assert_eq!(chunks(0), [(0, 0, true), (2, 2, false), (4, 4, true), (6, 6, false)]);
+        assert_eq!(chunks(1), [(1, 1, true), (3, 3, false), (5, 5, false), (7, 7, false)]);
+        assert_eq!(chunks(2), []);
+
+        let guard = view.get();
+        assert_eq!(guard.latest_keyframe_id(), Some(FrameId::new(4)));
+        assert_eq!(guard.latest_track_keyframe_id(1), Some(FrameId::new(1)));
+        assert_eq!(guard.track_keyframe_id_at_or_before(0, FrameId::new(3)), Some(FrameId::new(0)));
+        assert_eq!(guard.track_keyframe_id_at_or_before(1, FrameId::new(6)), Some(FrameId::new(1)));
+        assert_eq!(guard.track_keyframe_id_at_or_before(1, FrameId::new(0)), None);
+
+        let partial: Vec<_> = guard.track_range(0, FrameId::new(3), FrameId::new(6)).map(|(id, _)| id.get()).collect();
+        assert_eq!(partial, [4]);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn metadata_serde_round_trip() {
+        let metadata = Metadata::new(true, -42);
+
+        let json = serde_json::to_string(&metadata).unwrap();
+        assert_eq!(json, r#"{"is_key":true,"pts":-42}"#);
+        assert_eq!(serde_json::from_str::<Metadata>(&json).unwrap(), metadata);
+    }
+
+    #[test]
+    fn snapshot_matches_buffer() {
+        // 5 byte chunks don't divide the capacity, so some of them wrap around
+        let mut buf = EncodedBuffer::new(16);
+        let view = buf.view();
+
+        for i in 0..7u8 {
+            buf.write_flush(&[i; 5], Metadata::new(i % 3 == 0, i.into())).unwrap();
+        }
+
+        let snapshot = view.snapshot();
+        let guard = view.get();
+        let (min_id, max_id) = guard.id_bounds();
+
+        let concatenated: Vec<u8> = FrameId::range(min_id, max_id)
+            .flat_map(|id| guard.get(id).unwrap().data().into_owned())
+            .collect();
+        assert_eq!(snapshot.bytes, concatenated);
+
+        assert_eq!(snapshot.items.len(), max_id - min_id);
+        for ((item, data), id) in snapshot.iter().zip(FrameId::range(min_id, max_id)) {
+            let chunk = guard.get(id).unwrap();
+
+            assert_eq!(item.id, id);
+            assert_eq!(&item.metadata, chunk.metadata());
+            assert_eq!(data, &*chunk.data());
+        }
+    }
+
+    #[test]
+    fn replay_starts_at_keyframe() {
+        const HEADERS: [u8; 5] = [0, 0, 0, 1, 0x67];
+
+        let mut buf = EncodedBuffer::new(64);
+        let view = buf.view();
+
+        buf.write_flush(&[1; 4], Metadata::new(false, 0)).unwrap();
+        buf.write_flush(&[2; 4], Metadata::new(true, 1)).unwrap();
+        buf.write_flush(&[3; 4], Metadata::new(false, 2)).unwrap();
+
+        let path = std::env::temp_dir().join(format!("replay_starts_at_keyframe_{}.h264", std::process::id()));
+        view.snapshot().save_replay(&HEADERS, &path).unwrap();
+
+        let saved = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(saved[..HEADERS.len()], HEADERS);
+        // the chunk before the keyframe can't be decoded, so it's left out
+        assert_eq!(saved[HEADERS.len()..], [[2; 4], [3; 4]].concat());
+    }
+
+    #[test]
+    fn replay_without_keyframe() {
+        let mut buf = EncodedBuffer::new(64);
+        write_chunk(&mut buf, false);
+
+        let path = std::env::temp_dir().join("replay_without_keyframe.h264");
+        let result = buf.view().snapshot().save_replay(&[], &path);
+
+        assert!(matches!(result, Err(RecordError::NoKeyframe)));
+        assert!(!path.exists());
+    }
+}
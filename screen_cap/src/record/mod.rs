@@ -1,82 +1,425 @@
+pub mod clock;
 pub mod encoded_buffer;
+pub mod sink;
 
-use std::{io, sync::Arc, time::Instant};
+use std::{
+    collections::VecDeque,
+    fmt, io, mem,
+    ops::Deref,
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, AtomicI32, AtomicU64, Ordering},
+        mpsc::RecvTimeoutError,
+        Arc,
+    },
+    time::{Duration, Instant},
+};
 
 use parking_lot::{Condvar, Mutex};
 use scrap::Display;
 use thiserror::Error;
 use utils::{
-    contiguous::WriteDataError,
-    threading::{ThreadLoop, ThreadWork},
+    contiguous::{BufferItem, FrameId, OverflowPolicy, RingBuffer, WriteDataError},
+    multibuffer::{TripleBuffer, TripleBufferView},
+    threading::{
+        ThreadLoop, ThreadLoopBuilder, ThreadLoopControl, ThreadScheduling, ThreadWork, WorkerError,
+    },
 };
-use x264::{Encoder, Image};
+use x264::{Colorspace, Encoder, Image, Plane, Preset, Setup, Tune};
 
-use crate::{capture::ThreadedCapturer, frame::FrameError, record::encoded_buffer::Metadata};
+use crate::{
+    capture::{
+        self, BoxedDisplayFactory, CaptureRegion, CapturedFrame, RestartPolicy, ScalingSettings,
+        ThreadedCapturer,
+    },
+    frame::{self, ChangeDetector, FrameError, FrameFormat, FrameSource},
+    record::encoded_buffer::Metadata,
+};
 
-use self::encoded_buffer::{
-    ArcEncodedDataGuard, EncodedBuffer, EncodedBufferView, EncodedDataGuard,
+use self::{
+    clock::{Clock, RecordClock},
+    encoded_buffer::{
+        ArcEncodedDataGuard, EncodedBuffer, EncodedBufferView, EncodedDataGuard, OwnedChunk,
+    },
+    sink::{ChunkSink, SinkDispatcher, SinkSet, SINK_QUEUE_CAPACITY},
 };
 
+type EncoderFactory = Box<dyn FnMut(EncoderConfig) -> Encoder + Send>;
+
+/// What `EncoderSettings::encoder_factory` has to build the encoder with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EncoderConfig {
+    /// The size of the frames, the target size of `CapturerSettings::scaling`
+    /// or the one of `CapturerSettings::region` if either is set
+    pub width: usize,
+    pub height: usize,
+    /// In kbit/s, see `Recorder::set_bitrate`
+    pub bitrate: i32,
+    /// What the encoder has to be built with, `EncoderSettings::colorspace`
+    pub colorspace: Colorspace,
+    /// How far apart the keyframes have to be, `EncoderSettings::keyframe_interval`, see `apply_keyframe_interval`
+    pub keyframe_interval: KeyframeInterval,
+    /// The preset asked for by `Recorder::reconfigure`, `None` leaves it up to `encoder_factory`, see `setup`
+    pub preset: Option<Preset>,
+    /// The tune asked for by `Recorder::reconfigure`, same as `preset`
+    pub tune: Option<Tune>,
+}
+
+impl EncoderConfig {
+    /// Sets the keyframe interval of `setup` to `keyframe_interval`,
+    /// so `VideoInfo::keyframe_interval` matches what the encoder actually does
+    pub fn apply_keyframe_interval(&self, setup: Setup) -> Setup {
+        let KeyframeInterval { min, max } = self.keyframe_interval;
+
+        setup
+            .min_keyframe_interval(min as i32)
+            .max_keyframe_interval(max as i32)
+    }
+
+    /// `Setup::preset` with `preset` and `tune`, unless `Recorder::reconfigure` has asked for others
+    pub fn setup(&self, preset: Preset, tune: Tune, fast_decode: bool, zero_latency: bool) -> Setup {
+        Setup::preset(
+            self.preset.unwrap_or(preset),
+            self.tune.unwrap_or(tune),
+            fast_decode,
+            zero_latency,
+        )
+    }
+}
+
+/// New parameters for the encoder, see `Recorder::reconfigure`.
+///
+/// Whatever is left at `None` stays as it is.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct EncoderReconfig {
+    /// Passed on to `encoder_factory` as `EncoderConfig::preset`
+    pub preset: Option<Preset>,
+    /// Passed on to `encoder_factory` as `EncoderConfig::tune`
+    pub tune: Option<Tune>,
+    /// In kbit/s, like `Recorder::set_bitrate`
+    pub bitrate: Option<i32>,
+}
+
+impl EncoderReconfig {
+    /// Both at once, whatever `later` sets wins
+    pub fn merge(self, later: Self) -> Self {
+        Self {
+            preset: later.preset.or(self.preset),
+            tune: later.tune.or(self.tune),
+            bitrate: later.bitrate.or(self.bitrate),
+        }
+    }
+
+    fn apply(self, config: &mut EncoderConfig) {
+        config.preset = self.preset.or(config.preset);
+        config.tune = self.tune.or(config.tune);
+        // the lowest bitrate x264 accepts is 1
+        config.bitrate = self.bitrate.map_or(config.bitrate, |kbps| kbps.max(1));
+    }
+}
+
+/// How many frames apart the keyframes are, see `EncoderSettings::keyframe_interval`.
+///
+/// `max` bounds how long a new viewer waits for a keyframe to start decoding at,
+/// and how far back a ring buffer of a given size can start a replay.
+/// Requested keyframes and scene cuts can come sooner than `min`, they rebuild the encoder.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyframeInterval {
+    pub min: u32,
+    pub max: u32,
+}
+
+impl KeyframeInterval {
+    /// What x264 goes with when it isn't told otherwise, its minimum is a tenth of the maximum
+    pub const X264_DEFAULT: Self = Self { min: 25, max: 250 };
+
+    /// Fails if either bound is 0 or if `min` is above `max`
+    pub fn validate(&self) -> io::Result<()> {
+        if self.min == 0 || self.min > self.max {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{self:?} isn't a valid keyframe interval"),
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for KeyframeInterval {
+    fn default() -> Self {
+        Self::X264_DEFAULT
+    }
+}
+
+// `FrameSource` with the frame boxed up, so the worker doesn't have to be generic over the source
+trait DynFrameSource: Send {
+    fn next_frame(&mut self) -> Result<Box<dyn Deref<Target = [u8]> + '_>, FrameError>;
+}
+
+impl<S: FrameSource + Send> DynFrameSource for S {
+    #[inline]
+    fn next_frame(&mut self) -> Result<Box<dyn Deref<Target = [u8]> + '_>, FrameError> {
+        FrameSource::next_frame(self).map(|frame| Box::new(frame) as _)
+    }
+}
+
+// what `Recorder::start` needs to know about the source apart from the frames themselves
+struct SourceInfo {
+    target_rate: f64,
+    scene_cut_threshold: Option<f32>,
+    // None if the source doesn't keep the latest frame around
+    raw_frames: Option<TripleBufferView<CapturedFrame>>,
+    // None if the source has no thread of its own, the encoder thread gets paused and throttled instead
+    control: Option<ThreadLoopControl>,
+}
+
 struct RecordWorker {
-    capturer: ThreadedCapturer,
+    source: Box<dyn DynFrameSource>,
     encoder: Encoder,
-    width: i32,
-    height: i32,
+    encoder_factory: EncoderFactory,
+    config: EncoderConfig,
+    frame_format: FrameFormat,
     data_buf: EncodedBuffer,
     timebase: f64,
     record_start_time: Instant,
+    // x264 wants the timestamps to be strictly increasing
+    last_pts: Option<i64>,
+    // how many frames have been handed to the encoder, for the context of encoding errors
+    frame_index: u64,
+    pause_clock: Arc<Mutex<PauseClock>>,
     buffered_frames: usize,
+    flush_policy: FlushPolicy,
+    // when the pre-buffered frames last went into the ring buffer, on the recording's clock
+    last_flush: Instant,
+    flush_requested: Arc<AtomicBool>,
+    keyframe_requested: Arc<AtomicBool>,
+    bitrate_request: Arc<BitrateRequest>,
+    reconfig_request: Arc<Mutex<Option<EncoderReconfig>>>,
+    scene_cut_threshold: Option<f32>,
+    change_detector: ChangeDetector,
+    // BGRA frames with padded rows get repacked in here before encoding
+    pack_buf: Vec<u8>,
+    counters: Arc<RecordCounters>,
+    headers: SharedHeaders,
+    video_info: SharedVideoInfo,
+    sinks: SinkDispatcher,
+    filler: Filler,
+    // the largest chunk a restarted encoder left behind that didn't fit, reported on the next update
+    restart_too_large: Option<usize>,
 }
 
 impl RecordWorker {
     fn update(&mut self) -> Result<EncodeStatus, RecordError> {
+        if let Some(bytes) = self.restart_too_large.take() {
+            return Ok(EncodeStatus::ChunkTooLarge { bytes });
+        }
+
+        // push out whatever is pre-buffered if the recorder is being finalized
+        let now = self.pause_clock.lock().now();
+
+        if self.flush_requested.swap(false, Ordering::AcqRel) {
+            self.last_flush = now;
+            flush_counted(&mut self.data_buf, &self.counters)?;
+
+            return Ok(EncodeStatus::Flushed);
+        }
+
+        // frames can sit in the write buffer for as long as the capturer has nothing new
+        let flush_overdue = self.flush_policy.overdue(now.saturating_duration_since(self.last_flush));
+        if self.data_buf.write_buf_len() > 0 && flush_overdue {
+            self.last_flush = now;
+            flush_counted(&mut self.data_buf, &self.counters)?;
+
+            return Ok(EncodeStatus::Flushed);
+        }
+
+        // errors that don't come from encoding a particular frame get attributed to the one that's up next
+        let last_pts = self.last_pts.unwrap_or_default();
+        let next_frame = self.frame_index;
+
+        let reconfig = self.reconfig_request.lock().take();
+        if let Some(reconfig) = reconfig {
+            reconfig.apply(&mut self.config);
+
+            let too_large = restart_encoder(
+                &mut self.encoder,
+                &mut self.encoder_factory,
+                self.config,
+                &mut self.data_buf,
+            )
+            .map_err(RecordError::encode(EncodeErrorKind::Encode, last_pts, next_frame))?;
+            self.restart_too_large = self.restart_too_large.max(too_large);
+
+            // the chunks of the old encoder go out first, so every chunk from `first_id` on is in the new headers
+            self.last_flush = now;
+            flush_counted(&mut self.data_buf, &self.counters)?;
+
+            let first_id = self.data_buf.next_id();
+            let headers = self.encoder.headers();
+            let headers = headers.map_err(RecordError::encode(EncodeErrorKind::Headers, last_pts, next_frame))?;
+            self.headers.set(headers.entirety().into(), first_id);
+
+            return Ok(EncodeStatus::HeadersChanged { first_id });
+        }
+
         // get the frame
-        let frame = match self.capturer.frame() {
+        let frame = match self.source.next_frame() {
             Ok(f) => f,
-            // ignore skipped frames
+            // ignore skipped frames, unless it's been long enough for a filler
             Err(e) => match e {
                 FrameError::Skipped => {
-                    return Ok(EncodeStatus::Skipped);
+                    self.counters.frame_skipped();
+                    match self.filler.frame(now, self.frame_format, self.config.width, self.config.height) {
+                        Some(filler) => Box::new(filler),
+                        None => return Ok(EncodeStatus::Skipped),
+                    }
                 }
                 FrameError::Error(e) => return Err(e.into()),
+                // the capture thread recreates the capturer on its own, there's just nothing to encode until then
+                FrameError::Disconnected(_) | FrameError::Restarting { .. } => {
+                    self.counters.frame_skipped();
+                    match self.filler.frame(now, self.frame_format, self.config.width, self.config.height) {
+                        Some(filler) => Box::new(filler),
+                        None => return Ok(EncodeStatus::Skipped),
+                    }
+                }
+                // there's a gap before the next frame, it's better off starting with a keyframe
+                FrameError::Restarted { .. } => {
+                    self.keyframe_requested.store(true, Ordering::Release);
+                    self.counters.frame_skipped();
+                    return Ok(EncodeStatus::Skipped);
+                }
+                FrameError::RestartsExhausted { attempts } => {
+                    return Err(RecordError::CaptureRestartsExhausted { attempts })
+                }
+                FrameError::Worker(e) => return Err(RecordError::CaptureWorker(e)),
+                FrameError::Resized { width, height } => {
+                    // the new encoder starts with fresh SPS/PPS
+                    self.config.width = width;
+                    self.config.height = height;
+
+                    let too_large = restart_encoder(
+                        &mut self.encoder,
+                        &mut self.encoder_factory,
+                        self.config,
+                        &mut self.data_buf,
+                    )
+                    .map_err(RecordError::encode(EncodeErrorKind::Encode, last_pts, next_frame))?;
+                    self.restart_too_large = self.restart_too_large.max(too_large);
+
+                    let headers = self.encoder.headers();
+                    let headers = headers.map_err(RecordError::encode(EncodeErrorKind::Headers, last_pts, next_frame))?;
+                    self.headers.set(headers.entirety().into(), self.data_buf.next_id());
+                    self.video_info.set_size(width, height);
+                    // the old frame can't be repeated at the new size
+                    self.filler.forget_frame();
+
+                    return Ok(EncodeStatus::Reconfigured { width, height });
+                }
             },
         };
 
-        let frame_data = if cfg!(target_os = "macos") {
-            // stride is different on macos
-            // https://github.com/quadrupleslap/scrap/issues/44#issuecomment-1486345836
-            let w = self.width as usize;
-            let h = self.height as usize;
+        // a frame from before a resize can still be around if the capturer couldn't swap in a new one
+        if frame.len() < self.frame_format.frame_len(self.config.width, self.config.height) {
+            self.counters.frame_skipped();
+            return Ok(EncodeStatus::Skipped);
+        }
+
+        let mut force_keyframe = self.keyframe_requested.swap(false, Ordering::AcqRel);
 
-            &frame[..w * h * 4]
-        } else {
-            &frame
+        if let Some(threshold) = self.scene_cut_threshold {
+            force_keyframe |= self.change_detector.change_ratio(&frame) > threshold;
+        }
+
+        let new_bitrate = self.bitrate_request.take();
+        if let Some(bitrate) = new_bitrate {
+            self.config.bitrate = bitrate;
+        }
+
+        if force_keyframe || new_bitrate.is_some() {
+            let too_large = restart_encoder(
+                &mut self.encoder,
+                &mut self.encoder_factory,
+                self.config,
+                &mut self.data_buf,
+            )
+            .map_err(RecordError::encode(EncodeErrorKind::Encode, last_pts, next_frame))?;
+            self.restart_too_large = self.restart_too_large.max(too_large);
+        }
+
+        if new_bitrate.is_some() {
+            let headers = self.encoder.headers();
+            let headers = headers.map_err(RecordError::encode(EncodeErrorKind::Headers, last_pts, next_frame))?;
+            self.headers.set(headers.entirety().into(), self.data_buf.next_id());
+        }
+
+        let width = self.config.width as i32;
+        let height = self.config.height as i32;
+
+        let frame_data = match self.frame_format {
+            // the rows may be padded, the stride is derived from every frame rather than kept from the first one
+            // since the filler frames are packed either way
+            FrameFormat::Bgra => frame::packed_bgra(
+                &frame,
+                self.config.width,
+                self.config.height,
+                &mut self.pack_buf,
+            ),
+            // already converted on the capture thread, the stride is taken care of there as well
+            FrameFormat::I420 | FrameFormat::Nv12 => &frame,
         };
 
-        let image = Image::bgra(self.width, self.height, frame_data);
+        let (planes, plane_count) = frame_planes(self.frame_format, width, height, frame_data);
+        let image = Image::new(self.frame_format.colorspace(), width, height, &planes[..plane_count]);
+
+        self.filler.encoded(now, frame_data);
 
         // actually encoding
-        let elapsed = self.record_start_time.elapsed().as_secs_f64();
-        let timestamp = (elapsed * self.timebase) as i64;
+        // the time spent paused is left out so the timestamps don't jump after resuming
+        let elapsed = self.pause_clock.lock().recording_time(self.record_start_time);
+        let timestamp = next_pts(elapsed, self.timebase, self.last_pts);
+        self.last_pts = Some(timestamp);
 
-        let (data, picture) = self.encoder.encode(timestamp, image)?;
+        self.frame_index += 1;
+
+        let (data, picture) = self
+            .encoder
+            .encode(timestamp, image)
+            .map_err(RecordError::encode(EncodeErrorKind::Encode, timestamp, next_frame))?;
 
         // update the buffer
-        let metadata = Metadata {
-            is_key: picture.keyframe(),
-        };
+        let metadata = Metadata::new(picture.keyframe(), picture.pts());
+
+        self.counters.frame_encoded(metadata.is_key);
+
+        let bytes = data.entirety().len();
+        if let Err(WriteDataError::DataTooLarge) = self.data_buf.make_room_for(bytes) {
+            if !metadata.is_key {
+                self.keyframe_requested.store(true, Ordering::Release);
+            }
+
+            return Ok(EncodeStatus::ChunkTooLarge { bytes });
+        }
 
         if self.buffered_frames == 0 {
             // write flush is a bit more efficient since it immediately writes to the shared ring buffer
-            self.data_buf.write_flush(data.entirety(), metadata)?;
+            let pending = self.data_buf.write_buf_bytes() + data.entirety().len();
+            let result = self.data_buf.write_flush(data.entirety(), metadata);
+            self.last_flush = now;
+            self.counters
+                .bytes_flushed(pending - self.data_buf.write_buf_bytes());
+            result?;
 
             Ok(EncodeStatus::Flushed)
         } else {
             // write into a local buffer
             self.data_buf.write(data.entirety(), metadata);
-            // only copy data from the local buffer once its length reaches self.buffered_frames
-            if self.buffered_frames < self.data_buf.write_buf_len() {
-                self.data_buf.flush()?;
+            // only copy data from the local buffer once its length reaches self.buffered_frames,
+            // or once the flush policy says they've waited long enough
+            if self.buffered_frames < self.data_buf.write_buf_len() || flush_overdue {
+                self.last_flush = now;
+                flush_counted(&mut self.data_buf, &self.counters)?;
 
                 Ok(EncodeStatus::Flushed)
             } else {
@@ -86,226 +429,2338 @@ impl RecordWorker {
     }
 }
 
-impl ThreadWork for RecordWorker {
-    type WorkResult = Result<EncodeStatus, RecordError>;
+// what the encoder thread needs for `StallFiller`
+struct Filler {
+    settings: Option<StallFiller>,
+    // the last frame that was encoded, packed, only kept for `FillerFrame::Repeat`
+    last_frame: Vec<u8>,
+    // when the last frame went to the encoder, captured or filler, on the recording's clock
+    last_encoded: Instant,
+}
 
-    fn work(&mut self) -> Self::WorkResult {
-        self.update()
+impl Filler {
+    fn new(settings: Option<StallFiller>, start: Instant) -> Self {
+        Self {
+            settings,
+            last_frame: Vec::new(),
+            last_encoded: start,
+        }
     }
-}
 
-#[derive(Debug, Error)]
-pub enum RecordError {
-    #[error(transparent)]
-    FrameError(#[from] io::Error),
-    // x264::Error is zero sized and doesn't even implement the Error trait
-    #[error("there has been an error while encoding a frame")]
-    EncodeError,
+    // the frame to encode in place of a captured one, if the capturer has been stalled for long enough
+    fn frame(&self, now: Instant, format: FrameFormat, width: usize, height: usize) -> Option<Vec<u8>> {
+        let settings = self.settings?;
+        if now.saturating_duration_since(self.last_encoded) < settings.timeout {
+            return None;
+        }
 
-    #[error(transparent)]
-    WriteDataError(#[from] WriteDataError),
-}
+        let frame = match settings.frame {
+            FillerFrame::Repeat if !self.last_frame.is_empty() => self.last_frame.clone(),
+            FillerFrame::Repeat | FillerFrame::Black => format.black_frame(width, height),
+        };
+
+        Some(frame)
+    }
 
-// can't do this with a macro because x264::Error doesn't implement the Error trait
-impl From<x264::Error> for RecordError {
-    fn from(_: x264::Error) -> Self {
-        Self::EncodeError
+    fn encoded(&mut self, now: Instant, frame: &[u8]) {
+        self.last_encoded = now;
+
+        if let Some(StallFiller { frame: FillerFrame::Repeat, .. }) = self.settings {
+            self.last_frame.clear();
+            self.last_frame.extend_from_slice(frame);
+        }
+    }
+
+    // after a resize, the frame is the wrong size
+    fn forget_frame(&mut self) {
+        self.last_frame.clear();
     }
 }
 
-pub struct Recorder {
-    thread_loop: ThreadLoop<RecordWorker>,
-    data_buf: EncodedBufferView,
-    headers: Box<[u8]>,
+// a free function so it can be called while the encoder's output is still borrowed
+fn flush_counted(data_buf: &mut EncodedBuffer, counters: &RecordCounters) -> Result<(), RecordError> {
+    let pending = data_buf.write_buf_bytes();
+    let result = data_buf.flush();
+    // a failed flush can still get some of the chunks through
+    counters.bytes_flushed(pending - data_buf.write_buf_bytes());
+    result?;
+
+    Ok(())
 }
 
-impl Recorder {
-    pub fn new<F, G>(
-        capturer_settings: CapturerSettings<F>,
-        buffering_settings: BufferingSettings,
-        encoder_settings: EncoderSettings<G>,
-    ) -> Self
-    where
-        F: FnMut() -> Display + Send + 'static,
-        G: FnOnce() -> Encoder + Send + 'static,
-    {
-        // destructuring arguments arguments
-        let CapturerSettings {
-            mut display_factory,
-            target_rate,
-        } = capturer_settings;
+impl RecordWorker {
+    // everything that hasn't reached the ring buffer, including the pictures x264 was still holding on to
+    fn finish(self) -> Result<Box<[u8]>, RecordError> {
+        let flush_error = RecordError::encode(
+            EncodeErrorKind::Encode,
+            self.last_pts.unwrap_or_default(),
+            self.frame_index,
+        );
 
-        let BufferingSettings {
-            buffer_capacity,
-            buffered_frames,
-        } = buffering_settings;
+        let RecordWorker {
+            encoder,
+            mut data_buf,
+            counters,
+            ..
+        } = self;
 
-        let EncoderSettings {
-            encoder_factory,
-            timebase,
-        } = encoder_settings;
+        let mut tail = data_buf.take_pending();
 
-        let display = display_factory();
+        let mut flush = encoder.flush();
+        while let Some(result) = flush.next() {
+            let (data, picture) = result.map_err(flush_error)?;
 
-        let width = display.width() as i32;
-        let height = display.height() as i32;
+            counters.frame_encoded(picture.keyframe());
+            tail.extend_from_slice(data.entirety());
+        }
 
-        let capturer = ThreadedCapturer::new(display_factory, target_rate);
+        Ok(tail.into_boxed_slice())
+    }
+}
 
-        let data_buf = EncodedBuffer::new(buffer_capacity);
-        let data_buf_view = data_buf.view();
+// x264 doesn't let us mark a single picture as an IDR,
+// but the first picture out of a new encoder always is one.
+// The headers only change along with the config.
+// Returns the size of the largest of the old encoder's chunks that didn't fit into the ring buffer.
+fn restart_encoder(
+    encoder: &mut Encoder,
+    encoder_factory: &mut EncoderFactory,
+    config: EncoderConfig,
+    data_buf: &mut EncodedBuffer,
+) -> Result<Option<usize>, x264::Error> {
+    let old_encoder = mem::replace(encoder, build_encoder(encoder_factory, config));
 
-        // getting the headers from the thread with the encoder
-        type MutexHeaders = Mutex<Option<Box<[u8]>>>;
-        let headers_dest: Arc<(MutexHeaders, Condvar)> = Arc::default();
-        let headers_dest_cloned = headers_dest.clone();
+    // don't lose the pictures the old encoder was still holding on to
+    let mut too_large = None;
+    let mut flush = old_encoder.flush();
+    while let Some(result) = flush.next() {
+        let (data, picture) = result?;
+        let metadata = Metadata::new(picture.keyframe(), picture.pts());
 
-        let worker_factory = move || {
-            let (headers_dest, condvar) = &*headers_dest_cloned;
+        // no keyframe request for these, the new encoder starts with one anyway
+        let bytes = data.entirety().len();
+        match data_buf.make_room_for(bytes) {
+            Ok(()) => data_buf.write(data.entirety(), metadata),
+            Err(_) => too_large = too_large.max(Some(bytes)),
+        }
+    }
 
-            let mut encoder = encoder_factory();
+    Ok(too_large)
+}
 
-            let mut headers = Vec::new();
-            headers.extend_from_slice(
-                encoder
-                    .headers()
-                    .expect("Couldn't get x264 headers")
-                    .entirety(),
-            );
+// The write buffer holds up to `buffered_frames + 1` chunks before it gets flushed,
+// sized for frames of average size at the bitrate.
+// Keyframes are larger, but the buffer keeps its allocation once the first one has grown it.
+fn write_buf_capacity(buffered_frames: usize, bitrate: i32, target_rate: f64) -> (usize, usize) {
+    // the chunks go straight into the ring buffer then
+    if buffered_frames == 0 {
+        return (0, 0);
+    }
 
-            *headers_dest.lock() = Some(headers.into_boxed_slice());
-            condvar.notify_one();
+    let chunks = buffered_frames + 1;
+    // kbit/s into bytes per frame, an infinite rate doesn't tell us anything
+    let frame_bytes = f64::from(bitrate.max(0)) * 125.0 / target_rate;
+    let bytes = if frame_bytes.is_finite() {
+        frame_bytes as usize * chunks
+    } else {
+        0
+    };
 
-            RecordWorker {
-                capturer,
-                encoder,
-                width,
-                height,
-                data_buf,
-                timebase,
-                record_start_time: Instant::now(),
-                buffered_frames,
-            }
-        };
+    (bytes, chunks)
+}
 
-        // the rate is infinity because it's gonna be limited by the capturer
-        let thread_loop = ThreadLoop::new(worker_factory, f64::INFINITY);
+// generic over the worker so it can be tested without capturing anything
+fn wait_for_flush_timeout<W>(thread_loop: &ThreadLoop<W>, timeout: Duration) -> Result<bool, RecordError>
+where
+    W: ThreadWork<WorkResult = Result<EncodeStatus, RecordError>>,
+{
+    let deadline = Instant::now() + timeout;
 
-        // waiting for headers from the thread with the encoder
-        let (headers_lock, condvar) = &*headers_dest;
-        let mut headers = headers_lock.lock();
-
-        let headers = match headers.take() {
-            Some(h) => h,
-            None => {
-                condvar.wait(&mut headers);
-                headers.take().unwrap()
-            }
-        };
+    // same as in block_until_next_flush
+    let mut found_flush = false;
+    for i in thread_loop.work_try_iter() {
+        found_flush |= i? == EncodeStatus::Flushed;
+    }
 
-        Self {
-            thread_loop,
-            data_buf: data_buf_view,
-            headers,
-        }
+    if found_flush {
+        return Ok(true);
     }
 
-    #[inline]
-    pub fn data_buffer(&self) -> Result<EncodedDataGuard<'_>, RecordError> {
-        // bubble up errors
-        for i in self.thread_loop.work_try_iter() {
-            i?;
-        }
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
 
-        Ok(self.data_buf.get())
+        match thread_loop.work_recv_timeout(remaining) {
+            Ok(result) => {
+                if result? == EncodeStatus::Flushed {
+                    return Ok(true);
+                }
+            }
+            Err(RecvTimeoutError::Timeout) => return Ok(false),
+            // finds out why the worker is gone
+            Err(RecvTimeoutError::Disconnected) => {
+                return thread_loop
+                    .work_recv()?
+                    .map(|status| status == EncodeStatus::Flushed);
+            }
+        }
     }
+}
 
-    #[inline]
-    pub fn data_buffer_arc(&self) -> Result<ArcEncodedDataGuard, RecordError> {
-        for i in self.thread_loop.work_try_iter() {
-            i?;
-        }
+// converts the recording time into the timebase,
+// nudging it forward if two frames are closer together than one tick of the timebase
+fn next_pts(elapsed: Duration, timebase: f64, last_pts: Option<i64>) -> i64 {
+    let pts = (elapsed.as_secs_f64() * timebase) as i64;
 
-        Ok(self.data_buf.get_arc())
+    match last_pts {
+        Some(last) if pts <= last => last + 1,
+        _ => pts,
     }
+}
 
-    /// Allows one to have read-only access to the encoded buffer
-    /// while not having access to the recorder itself.
-    ///
-    /// Useful when sharing the recorder across threads as
-    /// Recorder is not `Sync`
-    #[inline]
-    pub fn data_buffer_view(&self) -> EncodedBufferView {
-        self.data_buf.clone()
-    }
+// the planes of a tightly packed frame, only the first `plane_count` of them are used
+fn frame_planes(format: FrameFormat, width: i32, height: i32, data: &[u8]) -> ([Plane<'_>; 3], usize) {
+    let w = width as usize;
+    let h = height as usize;
+    let (chroma_width, chroma_height) = frame::i420_chroma_size(w, h);
 
-    #[inline]
-    pub fn headers(&self) -> &[u8] {
-        &self.headers
-    }
+    let plane = |stride: usize, data| Plane {
+        stride: stride as i32,
+        data,
+    };
+    let unused = plane(0, &[][..]);
 
-    #[inline]
-    pub fn wait_for_frame(&self) -> Result<EncodeStatus, RecordError> {
-        let backlog = self.thread_loop.work_try_iter();
+    match format {
+        FrameFormat::Bgra => ([plane(w * 4, data), unused, unused], 1),
+        FrameFormat::I420 => {
+            let (y, chroma) = data.split_at(w * h);
+            let (u, v) = chroma.split_at(chroma_width * chroma_height);
 
-        if let Some(last_message) = backlog.last() {
-            return last_message;
+            ([plane(w, y), plane(chroma_width, u), plane(chroma_width, v)], 3)
         }
+        FrameFormat::Nv12 => {
+            let (y, uv) = data.split_at(w * h);
 
-        self.thread_loop.work_recv().unwrap()
+            // the U and V samples are interleaved, so the stride covers both
+            ([plane(w, y), plane(chroma_width * 2, uv), unused], 2)
+        }
     }
+}
 
-    #[inline]
-    pub fn block_until_next_flush(&self) -> Result<(), RecordError> {
-        let backlog = self.thread_loop.work_try_iter();
+// Frames in the wrong colorspace get encoded as garbage rather than failing, so this is checked up front
+fn build_encoder(encoder_factory: &mut impl FnMut(EncoderConfig) -> Encoder, config: EncoderConfig) -> Encoder {
+    let encoder = encoder_factory(config);
+    let colorspace = encoder.encoding().colorspace();
 
-        // iterate over the backlog
-        // propagate the first message and return Ok if one of the messages was a flush
-        let mut found_flush = false;
-        for i in backlog {
-            found_flush |= i? == EncodeStatus::Flushed;
-        }
+    assert_eq!(
+        colorspace, config.colorspace,
+        "the encoder has to be built with the colorspace in `EncoderConfig::colorspace`"
+    );
 
-        if found_flush {
-            return Ok(());
+    encoder
+}
+
+impl ThreadWork for RecordWorker {
+    type WorkResult = Result<EncodeStatus, RecordError>;
+
+    fn work(&mut self) -> Option<Self::WorkResult> {
+        let result = self.update();
+        // even a failed flush can get some of the chunks into the ring buffer
+        self.sinks.dispatch();
+
+        match result {
+            // nobody waits for these, see `Recorder::wait_for_frame`
+            Ok(EncodeStatus::Skipped | EncodeStatus::PreBuffered) => None,
+            result => Some(result),
         }
+    }
+}
 
-        for i in self.thread_loop.work_iter() {
-            if let EncodeStatus::Flushed = i? {
-                return Ok(());
-            }
+#[derive(Debug, Error)]
+pub enum RecordError {
+    #[error(transparent)]
+    FrameError(#[from] io::Error),
+    /// x264::Error is zero sized and doesn't even implement the Error trait,
+    /// so where it happened is all there is to go on.
+    ///
+    /// The context is best-effort, errors that don't come from encoding a frame
+    /// get the timestamp of the last frame and the index of the next one.
+    #[error("x264 {kind} failed at frame {frame_index} with timestamp {timestamp}")]
+    EncodeError {
+        kind: EncodeErrorKind,
+        timestamp: i64,
+        /// Counts the frames handed to the encoder since the recording started, from 0
+        frame_index: u64,
+    },
+
+    #[error(transparent)]
+    WriteDataError(#[from] WriteDataError),
+
+    #[error("capture {0}")]
+    CaptureWorker(WorkerError),
+    /// The capturer kept failing, see `CapturerSettings::restart`
+    #[error("gave up restarting the capturer after {attempts} attempts")]
+    CaptureRestartsExhausted { attempts: u32 },
+    #[error("encoder {0}")]
+    EncoderWorker(#[from] WorkerError),
+
+    #[error("couldn't save the replay: {0}")]
+    SaveReplay(io::Error),
+    #[error("there's no keyframe in the buffer to start the replay at")]
+    NoKeyframe,
+    /// See `Recorder::flushed_chunks`, `to` is exclusive
+    #[error("chunks {from} to {to} got evicted before they were read")]
+    ChunksEvicted { from: FrameId, to: FrameId },
+    #[error("frames can't be captured as {0:?}")]
+    UnsupportedColorspace(Colorspace),
+    /// See `Recorder::with_source`
+    #[error("the frame source produces {frames:?} frames, the encoder expects {expected:?}")]
+    SourceFormatMismatch { frames: FrameFormat, expected: FrameFormat },
+}
+
+impl RecordError {
+    // a `From` impl wouldn't have the context, this is meant for `map_err`
+    fn encode(kind: EncodeErrorKind, timestamp: i64, frame_index: u64) -> impl Fn(x264::Error) -> Self + Copy {
+        move |_| Self::EncodeError {
+            kind,
+            timestamp,
+            frame_index,
         }
-        // technically unreachable unless something nasty happens
-        Ok(())
     }
 }
 
-#[derive(Debug)]
-pub struct CapturerSettings<F>
-where
-    F: FnMut() -> Display + Send + 'static,
-{
-    pub display_factory: F,
-    pub target_rate: f64,
+/// What the encoder was doing when it failed, see `RecordError::EncodeError`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EncodeErrorKind {
+    /// Encoding a frame, or getting the frames out of an encoder that's being replaced
+    Encode,
+    /// Getting the SPS/PPS headers
+    Headers,
 }
 
-#[derive(Debug)]
-pub struct BufferingSettings {
-    pub buffer_capacity: usize,
-    pub buffered_frames: usize,
+impl fmt::Display for EncodeErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Encode => f.write_str("encoding"),
+            Self::Headers => f.write_str("getting the headers"),
+        }
+    }
 }
 
-pub struct EncoderSettings<F>
-where
-    F: FnOnce() -> Encoder + Send + 'static,
-{
-    pub encoder_factory: F,
-    pub timebase: f64,
+/// A snapshot of the recorder's counters, see `Recorder::stats`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RecordStats {
+    pub frames_encoded: u64,
+    /// Frames the capturer had nothing new for, see `EncodeStatus::Skipped`
+    pub frames_skipped: u64,
+    pub keyframes: u64,
+    /// Bytes that made it into the shared ring buffer
+    pub bytes_flushed: u64,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub enum EncodeStatus {
-    Skipped,
-    PreBuffered,
-    Flushed,
+// updated by the worker, read by the recorder
+#[derive(Debug, Default)]
+struct RecordCounters {
+    frames_encoded: AtomicU64,
+    frames_skipped: AtomicU64,
+    keyframes: AtomicU64,
+    bytes_flushed: AtomicU64,
+}
+
+impl RecordCounters {
+    fn frame_encoded(&self, is_key: bool) {
+        self.frames_encoded.fetch_add(1, Ordering::Relaxed);
+
+        if is_key {
+            self.keyframes.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    fn frame_skipped(&self) {
+        self.frames_skipped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn bytes_flushed(&self, bytes: usize) {
+        self.bytes_flushed.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    // the counters are independent, so the snapshot isn't guaranteed to be consistent between them
+    fn snapshot(&self) -> RecordStats {
+        RecordStats {
+            frames_encoded: self.frames_encoded.load(Ordering::Relaxed),
+            frames_skipped: self.frames_skipped.load(Ordering::Relaxed),
+            keyframes: self.keyframes.load(Ordering::Relaxed),
+            bytes_flushed: self.bytes_flushed.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// The SPS/PPS headers of the stream.
+///
+/// They change when the encoder gets rebuilt with a different config,
+/// see `EncodeStatus::Reconfigured` and `EncodeStatus::HeadersChanged`.
+#[derive(Debug, Clone, Default)]
+pub struct SharedHeaders {
+    inner: Arc<(MutexHeaders, Condvar)>,
+}
+
+// None until the encoder thread has produced the first headers,
+// along with the id of the first chunk encoded with them
+type MutexHeaders = Mutex<Option<(Arc<[u8]>, FrameId)>>;
+
+impl SharedHeaders {
+    /// Already holding `headers`, so `get` doesn't block
+    pub fn new(headers: Arc<[u8]>) -> Self {
+        let shared = Self::default();
+        shared.set(headers, FrameId::default());
+        shared
+    }
+
+    fn set(&self, headers: Arc<[u8]>, first_id: FrameId) {
+        let (lock, condvar) = &*self.inner;
+
+        *lock.lock() = Some((headers, first_id));
+        condvar.notify_all();
+    }
+
+    /// The current headers, blocks until the encoder thread has produced the first ones
+    pub fn get(&self) -> Arc<[u8]> {
+        self.get_since().0
+    }
+
+    /// The current headers along with the id of the first chunk encoded with them, blocks like `get`.
+    ///
+    /// The chunks before that id need the previous headers, so a consumer that has been sending out the chunks
+    /// has to send these headers again right before that chunk, or before the first keyframe after it.
+    pub fn get_since(&self) -> (Arc<[u8]>, FrameId) {
+        let (lock, condvar) = &*self.inner;
+
+        let mut headers = lock.lock();
+        condvar.wait_while(&mut headers, |headers| headers.is_none());
+
+        headers.clone().unwrap()
+    }
+}
+
+/// What the recorder is producing, see `Recorder::video_info`
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct VideoInfo {
+    /// Of the encoded pictures, the size changes along with the display's, see `EncodeStatus::Reconfigured`
+    pub width: i32,
+    pub height: i32,
+    /// Ticks per second of the timestamps, `EncoderSettings::timebase`
+    pub timebase: f64,
+    /// The capture rate that was asked for, see `Recorder::measured_rate` for the real one
+    pub target_rate: f64,
+    /// `EncoderSettings::keyframe_interval`, or x264's default if it isn't set
+    pub keyframe_interval: KeyframeInterval,
+}
+
+impl VideoInfo {
+    /// The longest a viewer joining at a random point has to wait for a keyframe at the target rate.
+    ///
+    /// Along with the bitrate, this also tells how many bytes a buffer needs to always hold a keyframe.
+    /// `None` if the target rate isn't a positive, finite number.
+    pub fn max_keyframe_gap(&self) -> Option<Duration> {
+        if !self.target_rate.is_finite() || self.target_rate <= 0.0 {
+            return None;
+        }
+
+        Duration::try_from_secs_f64(f64::from(self.keyframe_interval.max) / self.target_rate).ok()
+    }
+}
+
+/// The `VideoInfo` of a recorder, stays up to date after the recorder has been moved somewhere else
+#[derive(Debug, Clone, Default)]
+pub struct SharedVideoInfo {
+    inner: Arc<Mutex<VideoInfo>>,
+}
+
+impl SharedVideoInfo {
+    pub fn new(info: VideoInfo) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(info)),
+        }
+    }
+
+    #[inline]
+    pub fn get(&self) -> VideoInfo {
+        *self.inner.lock()
+    }
+
+    fn set_size(&self, width: usize, height: usize) {
+        let mut info = self.inner.lock();
+        info.width = width as i32;
+        info.height = height as i32;
+    }
+
+    fn set_target_rate(&self, target_rate: f64) {
+        self.inner.lock().target_rate = target_rate;
+    }
+}
+
+// the latest bitrate set by the recorder that the worker hasn't picked up yet
+#[derive(Debug, Default)]
+struct BitrateRequest {
+    // 0 if there's nothing new, not a valid bitrate anyway
+    kbps: AtomicI32,
+}
+
+impl BitrateRequest {
+    fn request(&self, kbps: i32) {
+        // a 0 would get lost, the lowest bitrate x264 accepts is 1 anyway
+        self.kbps.store(kbps.max(1), Ordering::Release);
+    }
+
+    fn take(&self) -> Option<i32> {
+        match self.kbps.swap(0, Ordering::AcqRel) {
+            0 => None,
+            kbps => Some(kbps),
+        }
+    }
+}
+
+// keeps track of how long the recording has been paused for
+#[derive(Debug, Default)]
+struct PauseClock {
+    clock: RecordClock,
+    paused_at: Option<Instant>,
+    paused_before: Duration,
+}
+
+impl PauseClock {
+    fn new(clock: RecordClock) -> Self {
+        Self {
+            clock,
+            ..Self::default()
+        }
+    }
+
+    #[inline]
+    fn now(&self) -> Instant {
+        self.clock.now()
+    }
+
+    fn pause(&mut self) {
+        let now = self.now();
+        self.paused_at.get_or_insert(now);
+    }
+
+    fn resume(&mut self) {
+        if let Some(paused_at) = self.paused_at.take() {
+            self.paused_before += self.now().saturating_duration_since(paused_at);
+        }
+    }
+
+    fn paused_total(&self) -> Duration {
+        let current = self
+            .paused_at
+            .map(|t| self.now().saturating_duration_since(t))
+            .unwrap_or_default();
+
+        self.paused_before + current
+    }
+
+    // how long it's been since `start`, leaving out the time spent paused
+    fn recording_time(&self, start: Instant) -> Duration {
+        let elapsed = self.now().saturating_duration_since(start);
+
+        elapsed.saturating_sub(self.paused_total())
+    }
+}
+
+/// Watches a recorder from elsewhere, e.g. for a stats page, see `Recorder::monitor`
+#[derive(Debug, Clone)]
+pub struct RecorderMonitor {
+    counters: Arc<RecordCounters>,
+    control: ThreadLoopControl,
+    data_buf: EncodedBufferView,
+}
+
+impl RecorderMonitor {
+    /// See `Recorder::stats`
+    #[inline]
+    pub fn stats(&self) -> RecordStats {
+        self.counters.snapshot()
+    }
+
+    /// See `Recorder::measured_rate`
+    #[inline]
+    pub fn measured_rate(&self) -> f64 {
+        self.control.measured_rate()
+    }
+
+    /// Whether the encoder thread is gone, either because the recorder was dropped or because it panicked
+    #[inline]
+    pub fn worker_exited(&self) -> bool {
+        self.control.exited()
+    }
+
+    /// How much of the ring buffer is taken up, as `(bytes_used, capacity)`
+    #[inline]
+    pub fn buffer_usage(&self) -> (usize, usize) {
+        (self.data_buf.bytes_used(), self.data_buf.capacity())
+    }
+}
+
+pub struct Recorder {
+    thread_loop: ThreadLoop<RecordWorker>,
+    data_buf: EncodedBufferView,
+    headers: SharedHeaders,
+    video_info: SharedVideoInfo,
+    raw_frames: TripleBufferView<CapturedFrame>,
+    capture_control: Option<ThreadLoopControl>,
+    pause_clock: Arc<Mutex<PauseClock>>,
+    flush_requested: Arc<AtomicBool>,
+    keyframe_requested: Arc<AtomicBool>,
+    bitrate_request: Arc<BitrateRequest>,
+    reconfig_request: Arc<Mutex<Option<EncoderReconfig>>>,
+    counters: Arc<RecordCounters>,
+    sinks: SinkSet,
+}
+
+impl Recorder {
+    /// Starts capturing and encoding right away.
+    ///
+    /// Fails if `CapturerSettings::region` doesn't fit inside the display
+    /// or if the target size of `CapturerSettings::scaling` is empty.
+    pub fn new<F, G>(
+        capturer_settings: CapturerSettings<F>,
+        buffering_settings: BufferingSettings,
+        encoder_settings: EncoderSettings<G>,
+    ) -> Result<Self, RecordError>
+    where
+        F: FnMut() -> io::Result<Display> + Send + 'static,
+        G: FnMut(EncoderConfig) -> Encoder + Send + 'static,
+    {
+        // destructuring arguments arguments
+        let CapturerSettings {
+            display_factory,
+            target_rate,
+            scene_cut_keyframe_threshold,
+            region,
+            scaling,
+            restart,
+            thread_scheduling,
+            max_frame_age,
+        } = capturer_settings;
+
+        let colorspace = encoder_settings.colorspace;
+        let frame_format =
+            FrameFormat::from_colorspace(colorspace).ok_or(RecordError::UnsupportedColorspace(colorspace))?;
+
+        let mut capturer = ThreadedCapturer::with_options(
+            display_factory,
+            target_rate,
+            frame_format,
+            region,
+            scaling,
+            restart,
+            thread_scheduling,
+        )?;
+        // the frame ages are measured on the same clock as the timestamps
+        capturer.set_clock(encoder_settings.clock.clone());
+        capturer.set_max_frame_age(max_frame_age);
+
+        let info = SourceInfo {
+            target_rate,
+            scene_cut_threshold: scene_cut_keyframe_threshold,
+            raw_frames: Some(capturer.frame_view()),
+            control: Some(capturer.control()),
+        };
+
+        Self::start(capturer, info, buffering_settings, encoder_settings)
+    }
+
+    /// Same as `new`, except the frames come from `source` instead of a capturer of its own,
+    /// e.g. a virtual camera, a test pattern or a different capture library.
+    ///
+    /// The frames get encoded as fast as `source` produces them, `set_capture_rate` caps that.
+    /// Pausing stops taking frames from `source` for the time being.
+    /// `raw_frames` never sees any frames.
+    /// Also fails if the frames aren't in the format `EncoderSettings::colorspace` asks for.
+    pub fn with_source<S, G>(
+        source: S,
+        buffering_settings: BufferingSettings,
+        encoder_settings: EncoderSettings<G>,
+    ) -> Result<Self, RecordError>
+    where
+        S: FrameSource + Send + 'static,
+        G: FnMut(EncoderConfig) -> Encoder + Send + 'static,
+    {
+        let info = SourceInfo {
+            target_rate: f64::INFINITY,
+            scene_cut_threshold: None,
+            raw_frames: None,
+            control: None,
+        };
+
+        Self::start(source, info, buffering_settings, encoder_settings)
+    }
+
+    fn start<S, G>(
+        source: S,
+        info: SourceInfo,
+        buffering_settings: BufferingSettings,
+        encoder_settings: EncoderSettings<G>,
+    ) -> Result<Self, RecordError>
+    where
+        S: FrameSource + Send + 'static,
+        G: FnMut(EncoderConfig) -> Encoder + Send + 'static,
+    {
+        let SourceInfo {
+            target_rate,
+            scene_cut_threshold,
+            raw_frames,
+            control: capture_control,
+        } = info;
+
+        let BufferingSettings {
+            buffer_capacity,
+            buffered_frames,
+            flush_policy,
+            overflow_policy,
+            checksums,
+            growable,
+        } = buffering_settings;
+
+        let EncoderSettings {
+            mut encoder_factory,
+            bitrate,
+            timebase,
+            colorspace,
+            keyframe_interval,
+            clock,
+            thread_scheduling,
+            stall_filler,
+        } = encoder_settings;
+
+        let keyframe_interval = keyframe_interval.unwrap_or_default();
+        keyframe_interval.validate()?;
+
+        let frame_format =
+            FrameFormat::from_colorspace(colorspace).ok_or(RecordError::UnsupportedColorspace(colorspace))?;
+        if source.format() != frame_format {
+            return Err(RecordError::SourceFormatMismatch {
+                frames: source.format(),
+                expected: frame_format,
+            });
+        }
+
+        let (width, height) = source.size();
+        // nothing ever gets written into it if the source doesn't have one
+        let raw_frames = raw_frames.unwrap_or_else(|| TripleBuffer::new(CapturedFrame::default()).view());
+        let source: Box<dyn DynFrameSource> = Box::new(source);
+
+        let mut data_buf = EncodedBuffer::with_policy(buffer_capacity, overflow_policy);
+        if growable {
+            data_buf = data_buf.growable();
+        }
+        if checksums {
+            data_buf = data_buf.with_checksums();
+        }
+        let (write_bytes, write_chunks) = write_buf_capacity(buffered_frames, bitrate, target_rate);
+        data_buf.reserve(write_bytes, write_chunks);
+        let data_buf_view = data_buf.view();
+
+        let video_info = SharedVideoInfo::new(VideoInfo {
+            width: width as i32,
+            height: height as i32,
+            timebase,
+            target_rate,
+            keyframe_interval,
+        });
+        let worker_video_info = video_info.clone();
+
+        // getting the headers from the thread with the encoder
+        let headers = SharedHeaders::default();
+        let worker_headers = headers.clone();
+
+        let flush_requested = Arc::new(AtomicBool::new(false));
+        let worker_flush_requested = flush_requested.clone();
+
+        let keyframe_requested = Arc::new(AtomicBool::new(false));
+        let worker_keyframe_requested = keyframe_requested.clone();
+
+        let bitrate_request = Arc::new(BitrateRequest::default());
+        let worker_bitrate_request = bitrate_request.clone();
+
+        let reconfig_request = Arc::new(Mutex::new(None));
+        let worker_reconfig_request = reconfig_request.clone();
+
+        let clock = RecordClock::new(clock);
+        let record_start_time = clock.now();
+
+        let pause_clock = Arc::new(Mutex::new(PauseClock::new(clock)));
+        let worker_pause_clock = pause_clock.clone();
+
+        let counters = Arc::new(RecordCounters::default());
+        let worker_counters = counters.clone();
+
+        let sinks = SinkSet::default();
+        let worker_sinks = SinkDispatcher::new(sinks.clone(), data_buf_view.clone());
+
+        let worker_factory = move || {
+            let config = EncoderConfig {
+                width,
+                height,
+                bitrate,
+                colorspace,
+                keyframe_interval,
+                preset: None,
+                tune: None,
+            };
+            let mut encoder = build_encoder(&mut encoder_factory, config);
+
+            worker_headers.set(
+                encoder
+                    .headers()
+                    .expect("Couldn't get x264 headers")
+                    .entirety()
+                    .into(),
+                FrameId::default(),
+            );
+
+            RecordWorker {
+                source,
+                encoder,
+                encoder_factory: Box::new(encoder_factory),
+                config,
+                frame_format,
+                data_buf,
+                timebase,
+                record_start_time,
+                last_pts: None,
+                frame_index: 0,
+                pause_clock: worker_pause_clock,
+                buffered_frames,
+                flush_policy,
+                last_flush: record_start_time,
+                flush_requested: worker_flush_requested,
+                keyframe_requested: worker_keyframe_requested,
+                bitrate_request: worker_bitrate_request,
+                reconfig_request: worker_reconfig_request,
+                scene_cut_threshold,
+                change_detector: ChangeDetector::new(),
+                pack_buf: Vec::new(),
+                counters: worker_counters,
+                headers: worker_headers,
+                video_info: worker_video_info,
+                sinks: worker_sinks,
+                filler: Filler::new(stall_filler, record_start_time),
+                restart_too_large: None,
+            }
+        };
+
+        // the rate is infinity because it's gonna be limited by the source
+        let thread_loop = ThreadLoopBuilder::new(worker_factory)
+            .name("h264-encoder")
+            .scheduling(thread_scheduling)
+            .start_loop(f64::INFINITY)
+            .expect("failed to spawn the encoder thread");
+
+        // waiting for headers from the thread with the encoder
+        headers.get();
+
+        Ok(Self {
+            thread_loop,
+            data_buf: data_buf_view,
+            headers,
+            video_info,
+            raw_frames,
+            capture_control,
+            pause_clock,
+            flush_requested,
+            keyframe_requested,
+            bitrate_request,
+            reconfig_request,
+            counters,
+            sinks,
+        })
+    }
+
+    #[inline]
+    pub fn data_buffer(&self) -> Result<EncodedDataGuard<'_>, RecordError> {
+        self.drain_results()?;
+
+        Ok(self.data_buf.get())
+    }
+
+    #[inline]
+    pub fn data_buffer_arc(&self) -> Result<ArcEncodedDataGuard, RecordError> {
+        self.drain_results()?;
+
+        Ok(self.data_buf.get_arc())
+    }
+
+    // clears the worker's backlog without blocking, bubbling up the oldest error in it
+    fn drain_results(&self) -> Result<(), RecordError> {
+        match self.thread_loop.drain_collect_errors().errors.into_iter().next() {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+
+    /// Allows one to have read-only access to the encoded buffer
+    /// while not having access to the recorder itself.
+    ///
+    /// Useful for handing the buffer to code that shouldn't control the recording,
+    /// or that has to keep reading it after the recorder is gone
+    #[inline]
+    pub fn data_buffer_view(&self) -> EncodedBufferView {
+        self.data_buf.clone()
+    }
+
+    /// Gives access to the same captured frames the encoder sees, before they're encoded.
+    ///
+    /// The frames are in the format matching `EncoderSettings::colorspace`, see `FrameFormat::from_colorspace`.
+    /// Holding the front buffer doesn't stall the capture thread, but the frame it holds gets stale.
+    #[inline]
+    pub fn raw_frames(&self) -> TripleBufferView<CapturedFrame> {
+        self.raw_frames.clone()
+    }
+
+    /// The current SPS/PPS headers, they change after `EncodeStatus::Reconfigured` and `EncodeStatus::HeadersChanged`
+    #[inline]
+    pub fn headers(&self) -> Arc<[u8]> {
+        self.headers.get()
+    }
+
+    /// The current headers along with the id of the first chunk they apply to, see `SharedHeaders::get_since`
+    pub fn headers_since(&self) -> (Arc<[u8]>, FrameId) {
+        self.headers.get_since()
+    }
+
+    /// Saves what's in the ring buffer into a new file at `path`, e.g. for an instant replay.
+    ///
+    /// The file is a playable H.264 stream, starting with the headers and the oldest keyframe in the buffer.
+    /// How far back that goes depends on the bitrate, since `BufferingSettings::buffer_capacity`
+    /// bounds the bytes kept, not the time.
+    /// The buffer is copied out first, so the encoder isn't held up while the file gets written.
+    pub fn save_replay(&self, path: &Path) -> Result<(), RecordError> {
+        self.data_buf.snapshot().save_replay(&self.headers(), path)
+    }
+
+    /// The headers followed by the keyframe chunk `id`, a self-contained blob a consumer joining mid-stream
+    /// can start decoding from, e.g. the first thing a new viewer gets.
+    ///
+    /// `None` if the chunk isn't a keyframe, has been evicted,
+    /// or was encoded with headers that have been replaced since, since only the current ones are kept.
+    pub fn keyframe_with_headers(&self, id: FrameId) -> Option<Vec<u8>> {
+        // the headers have to be read after locking the buffer, so they can't be older than its chunks
+        let buf = self.data_buf.get();
+        let chunk = buf.get(id).filter(|chunk| chunk.metadata().is_key)?;
+
+        let (headers, first_id) = self.headers_since();
+        if id < first_id {
+            return None;
+        }
+
+        Some([&headers[..], &chunk.data()].concat())
+    }
+
+    /// Changes the capture rate, which also limits how often frames get encoded.
+    ///
+    /// Meant for throttling down while nobody is watching.
+    #[inline]
+    pub fn set_capture_rate(&self, target_rate: f64) {
+        self.pacing_control().set_rate(target_rate);
+        self.video_info.set_target_rate(target_rate);
+    }
+
+    /// The size of the video along with its timebase and capture rate,
+    /// e.g. for building an fMP4 init segment
+    #[inline]
+    pub fn video_info(&self) -> VideoInfo {
+        self.video_info.get()
+    }
+
+    /// The video info that stays up to date after the recorder has been moved somewhere else
+    #[inline]
+    pub fn shared_video_info(&self) -> SharedVideoInfo {
+        self.video_info.clone()
+    }
+
+    /// The headers that stay up to date after the recorder has been moved somewhere else
+    #[inline]
+    pub fn shared_headers(&self) -> SharedHeaders {
+        self.headers.clone()
+    }
+
+    /// Frame and byte counts since the recorder was created
+    #[inline]
+    pub fn stats(&self) -> RecordStats {
+        self.counters.snapshot()
+    }
+
+    /// How many frames per second the encoder has actually been handling, skipped ones included
+    #[inline]
+    pub fn measured_rate(&self) -> f64 {
+        self.thread_loop.measured_rate()
+    }
+
+    /// Stats and liveness that can be checked without access to the recorder itself
+    pub fn monitor(&self) -> RecorderMonitor {
+        RecorderMonitor {
+            counters: self.counters.clone(),
+            control: self.thread_loop.control(),
+            data_buf: self.data_buf.clone(),
+        }
+    }
+
+    /// Blocks until the worker has something to report and returns it,
+    /// every report gets returned exactly once and in order.
+    ///
+    /// Only flushes, reconfigurations and errors get reported,
+    /// frames that were skipped or pre-buffered only show up in `stats`.
+    ///
+    /// Encode errors are propagated the same way as in `block_until_next_flush`.
+    #[inline]
+    pub fn wait_for_frame(&self) -> Result<EncodeStatus, RecordError> {
+        self.thread_loop.work_recv()?
+    }
+
+    /// Same as `wait_for_frame`, except it returns `None` right away if the worker hasn't reported anything yet.
+    ///
+    /// Takes the oldest report the same as `wait_for_frame`, so the two can be mixed without skipping any.
+    /// Also `None` once the worker is gone, `wait_for_frame` tells why,
+    /// and while another thread is waiting in `wait_for_frame`, which gets the next report then.
+    pub fn try_next_frame(&self) -> Option<Result<EncodeStatus, RecordError>> {
+        self.thread_loop.work_try_iter().next()
+    }
+
+    /// Same as `wait_for_frame`, but gives up after `timeout` and returns `None`
+    pub fn wait_for_frame_timeout(
+        &self,
+        timeout: Duration,
+    ) -> Option<Result<EncodeStatus, RecordError>> {
+        match self.thread_loop.work_recv_timeout(timeout) {
+            Ok(result) => Some(result),
+            Err(RecvTimeoutError::Timeout) => None,
+            // finds out why the worker is gone
+            Err(RecvTimeoutError::Disconnected) => Some(self.wait_for_frame()),
+        }
+    }
+
+    /// Returns right away if a flush happened since the last call, otherwise blocks until the next one.
+    ///
+    /// Only results that are still queued count, so with a bounded results channel that drops
+    /// the oldest ones this can miss a flush that happened during a stall and wait for the next one instead.
+    #[inline]
+    pub fn block_until_next_flush(&self) -> Result<(), RecordError> {
+        let backlog = self.thread_loop.work_try_iter();
+
+        // iterate over the backlog
+        // propagate the first message and return Ok if one of the messages was a flush
+        let mut found_flush = false;
+        for i in backlog {
+            found_flush |= i? == EncodeStatus::Flushed;
+        }
+
+        if found_flush {
+            return Ok(());
+        }
+
+        for i in self.thread_loop.work_iter() {
+            if let EncodeStatus::Flushed = i? {
+                return Ok(());
+            }
+        }
+        // technically unreachable unless something nasty happens
+        Ok(())
+    }
+
+    /// Same as `block_until_next_flush`, but gives up after `timeout`.
+    ///
+    /// Returns `Ok(true)` if there was a flush and `Ok(false)` if the time ran out first,
+    /// so a recording loop can keep checking whether it should stop even if the capture stalls.
+    pub fn block_until_next_flush_timeout(&self, timeout: Duration) -> Result<bool, RecordError> {
+        wait_for_flush_timeout(&self.thread_loop, timeout)
+    }
+
+    /// Makes the next encoded frame a keyframe.
+    ///
+    /// The x264 bindings can't force a single picture to be an IDR,
+    /// so the encoder gets rebuilt with `encoder_factory` instead.
+    /// That makes it rather expensive, so this shouldn't be called on every frame.
+    #[inline]
+    pub fn request_keyframe(&self) {
+        self.keyframe_requested.store(true, Ordering::Release);
+    }
+
+    /// Changes the bitrate of the stream, in kbit/s.
+    ///
+    /// The x264 bindings can't reconfigure a live encoder, so it gets rebuilt with `encoder_factory`
+    /// when the next frame arrives, which makes that frame a keyframe and may change the headers.
+    /// The new bitrate applies from that frame on, so the change takes about one capture interval,
+    /// plus however long the encoder holds on to the pictures it already has.
+    /// Only the last of several calls within one frame has any effect.
+    #[inline]
+    pub fn set_bitrate(&self, kbps: i32) {
+        self.bitrate_request.request(kbps);
+    }
+
+    /// Calls `callback` whenever the ring buffer fills up past `fraction` of its capacity,
+    /// e.g. to log it or to lower the bitrate before chunks nobody has read yet start getting evicted.
+    ///
+    /// See `RingBuffer::set_high_water`. The callback runs on the encoder thread while it's flushing,
+    /// with the ring buffer locked, so it has to be quick and mustn't read the buffer.
+    pub fn set_high_water(&self, fraction: f32, callback: impl Fn() + Send + Sync + 'static) {
+        self.data_buf.set_high_water(fraction, callback);
+    }
+
+    /// Removes the callback set by `set_high_water`
+    pub fn clear_high_water(&self) {
+        self.data_buf.clear_high_water();
+    }
+
+    /// Rebuilds the encoder with different parameters without interrupting the recording.
+    ///
+    /// Picked up by the encoder thread before the next frame, whether or not there is one.
+    /// Whatever the old encoder still had gets flushed, then `wait_for_frame` reports `EncodeStatus::HeadersChanged`
+    /// and the chunks from then on start with a keyframe encoded with the new headers, see `headers_since`.
+    /// Several calls before that get merged, the later ones winning.
+    pub fn reconfigure(&self, reconfig: EncoderReconfig) {
+        let mut request = self.reconfig_request.lock();
+        *request = Some(request.unwrap_or_default().merge(reconfig));
+    }
+
+    /// Hands every chunk flushed from now on to `sink` as well, e.g. to write a file while streaming.
+    ///
+    /// The encoder thread only copies the chunks into the sink's queue,
+    /// the sink runs on its own thread, so a slow one drops chunks instead of stalling the encoder.
+    /// The chunks returned by `finish` don't go through the sinks.
+    pub fn add_sink(&self, sink: Box<dyn ChunkSink + Send>) {
+        self.sinks.add(sink, SINK_QUEUE_CAPACITY);
+    }
+
+    /// Takes why the sinks that failed stopped, one error per sink, oldest first.
+    ///
+    /// A failed sink doesn't get any more chunks, the recording and the other sinks carry on, see `add_sink`.
+    pub fn take_sink_errors(&self) -> Vec<io::Error> {
+        self.sinks.take_errors()
+    }
+
+    /// Blocks until the ring buffer holds at least `n` chunks past `last_id`,
+    /// i.e. until `id_bounds().1 >= last_id + n`, or until the worker stops.
+    ///
+    /// Returns the upper id bound at that moment.
+    /// Only flushes wake this up, so batching consumers don't have to handle every single frame.
+    pub fn wait_for_frames_since(&self, last_id: FrameId, n: usize) -> Result<FrameId, RecordError> {
+        let target_id = last_id + n;
+
+        let mut id_max = self.data_buf.get().id_bounds().1;
+
+        while id_max < target_id {
+            match self.thread_loop.work_recv() {
+                // the bounds only change on flushes
+                Ok(status) => {
+                    if status? != EncodeStatus::Flushed {
+                        continue;
+                    }
+                }
+                Err(e @ WorkerError::Panicked) => return Err(e.into()),
+                // the worker is gone, no more frames are coming
+                Err(WorkerError::Exited) => break,
+            }
+
+            id_max = self.data_buf.get().id_bounds().1;
+        }
+
+        Ok(id_max)
+    }
+
+    /// Stops capturing, keeping the capturer and the encoder around so that recording can resume right away.
+    ///
+    /// The encoder just waits for the next frame in the meantime,
+    /// and the paused duration is left out of the timestamps.
+    pub fn pause_recording(&self) {
+        self.pause_clock.lock().pause();
+        self.pacing_control().pause();
+    }
+
+    pub fn resume_recording(&self) {
+        // the clock has to be resumed first, the next frame's timestamp depends on it
+        self.pause_clock.lock().resume();
+        self.pacing_control().resume();
+    }
+
+    // the thread that decides how often frames come in, the source's if it has one
+    fn pacing_control(&self) -> ThreadLoopControl {
+        match &self.capture_control {
+            Some(control) => control.clone(),
+            None => self.thread_loop.control(),
+        }
+    }
+
+    /// Makes the worker push every pre-buffered frame into the shared ring buffer
+    /// and blocks until it has done so.
+    ///
+    /// After this returns, every frame encoded before the call can be read from the data buffer.
+    /// Resumes the recording if it was paused, since the worker can't get to the flush without a new frame.
+    pub fn finalize(&self) -> Result<(), RecordError> {
+        self.resume_recording();
+
+        // flushes from before the request don't tell us anything
+        self.drain_results()?;
+
+        self.flush_requested.store(true, Ordering::Release);
+
+        for i in self.thread_loop.work_iter() {
+            if let EncodeStatus::Flushed = i? {
+                return Ok(());
+            }
+        }
+        // same as in block_until_next_flush
+        Ok(())
+    }
+
+    /// Stops recording for good and returns the encoded bytes that never made it into the ring buffer:
+    /// the pre-buffered chunks and the pictures x264 was still holding on to.
+    ///
+    /// The ring buffer doesn't change after this returns, so a complete file is
+    /// whatever is left to read through a `data_buffer_view` followed by the returned bytes.
+    pub fn finish(self) -> Result<Box<[u8]>, RecordError> {
+        // a paused capturer would keep the worker waiting for a frame forever
+        self.resume_recording();
+
+        match self.thread_loop.join_with(RecordWorker::finish) {
+            Ok(result) => result,
+            Err(_) => Err(WorkerError::Panicked.into()),
+        }
+    }
+
+    /// Blocks for every flush and yields the chunks it made available, oldest first,
+    /// starting with the oldest one in the ring buffer.
+    ///
+    /// Chunks that got evicted before the iterator got to them come up as `RecordError::ChunksEvicted`,
+    /// after which it carries on with the ones that are left.
+    /// It ends once the worker has stopped and every chunk it flushed has been yielded, or after any other error.
+    /// Pre-buffered chunks only show up after they're flushed, see `FlushedChunks::next_id` for picking up the rest.
+    pub fn flushed_chunks(&self) -> FlushedChunks<'_> {
+        FlushedChunks {
+            recorder: self,
+            next_id: self.data_buf.get().id_bounds().0,
+            pending: VecDeque::new(),
+            finished: false,
+        }
+    }
+
+    /// Finalizes the recorder and returns every chunk with an id of at least `since_id`
+    /// that is still in the ring buffer.
+    ///
+    /// Meant to be called once the recording loop is over so that the tail of the recording doesn't get lost.
+    pub fn drain_remaining(&self, since_id: FrameId) -> Result<DrainedChunks, RecordError> {
+        self.finalize()?;
+
+        Ok(DrainedChunks {
+            guard: self.data_buf.get_arc(),
+            start_id: since_id,
+        })
+    }
+}
+
+/// Chunks left over in the ring buffer after finalizing the recorder.
+///
+/// Holds a read lock on the ring buffer for as long as it's alive.
+#[derive(Debug)]
+pub struct DrainedChunks {
+    guard: ArcEncodedDataGuard,
+    start_id: FrameId,
+}
+
+impl DrainedChunks {
+    pub fn iter(&self) -> impl Iterator<Item = BufferItem<'_, Metadata>> {
+        self.guard.range(self.start_id, self.end_id())
+    }
+
+    /// The id following the last drained chunk
+    #[inline]
+    pub fn end_id(&self) -> FrameId {
+        self.guard.id_bounds().1
+    }
+
+    /// The whole ring buffer, including the chunks before `since_id`,
+    /// e.g. to find out whether the ones right at `since_id` are still there
+    #[inline]
+    pub fn ring_buffer(&self) -> &RingBuffer<Metadata> {
+        &self.guard
+    }
+}
+
+/// The chunks the recorder flushes, copied out of the ring buffer one flush at a time.
+///
+/// See `Recorder::flushed_chunks`.
+pub struct FlushedChunks<'a> {
+    recorder: &'a Recorder,
+    // the first chunk that hasn't been copied into `pending` yet
+    next_id: FrameId,
+    pending: VecDeque<OwnedChunk>,
+    finished: bool,
+}
+
+impl FlushedChunks<'_> {
+    /// The id of the chunk that gets yielded next,
+    /// e.g. for `Recorder::drain_remaining` once the recording loop is over
+    #[inline]
+    pub fn next_id(&self) -> FrameId {
+        self.next_id - self.pending.len()
+    }
+
+    // the chunks get queued even if some were lost before them, the error only has to come first
+    fn collect_new_chunks(&mut self) -> Result<(), RecordError> {
+        let buf = self.recorder.data_buf.get();
+        let (min_id, max_id) = buf.id_bounds();
+
+        let mut result = Ok(());
+        if self.next_id < min_id {
+            result = Err(RecordError::ChunksEvicted {
+                from: self.next_id,
+                to: min_id,
+            });
+            self.next_id = min_id;
+        }
+
+        self.pending.extend(buf.range(self.next_id, max_id).map(|item| item.to_owned()));
+        self.next_id = max_id;
+
+        result
+    }
+}
+
+impl Iterator for FlushedChunks<'_> {
+    type Item = Result<OwnedChunk, RecordError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(chunk) = self.pending.pop_front() {
+                return Some(Ok(chunk));
+            }
+
+            // whatever was flushed before the worker stopped has been yielded by now
+            if self.finished {
+                return None;
+            }
+
+            if let Err(e) = self.collect_new_chunks() {
+                return Some(Err(e));
+            }
+
+            if !self.pending.is_empty() {
+                continue;
+            }
+
+            // the bounds only change on flushes, but any report is a good enough reason to look again
+            match self.recorder.thread_loop.work_recv() {
+                Ok(Ok(_)) => (),
+                Ok(Err(e)) => {
+                    self.finished = true;
+                    return Some(Err(e));
+                }
+                Err(WorkerError::Exited) => {
+                    // one last look, the final flush might've happened since
+                    self.finished = true;
+                    if let Err(e) = self.collect_new_chunks() {
+                        return Some(Err(e));
+                    }
+                }
+                Err(e @ WorkerError::Panicked) => {
+                    self.finished = true;
+                    return Some(Err(e.into()));
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct CapturerSettings<F>
+where
+    F: FnMut() -> io::Result<Display> + Send + 'static,
+{
+    pub display_factory: F,
+    pub target_rate: f64,
+    /// Insert a keyframe when the fraction of the frame that changed since the previous one
+    /// is above this threshold (between 0 and 1), e.g. when a new window opens.
+    ///
+    /// `None` leaves keyframe placement entirely to the encoder.
+    pub scene_cut_keyframe_threshold: Option<f32>,
+    /// Only capture and encode this part of the display, `None` captures all of it
+    pub region: Option<CaptureRegion>,
+    /// Scale the frames down to this size before encoding them, after cropping them to `region`.
+    ///
+    /// `None` encodes them at their captured size.
+    pub scaling: Option<ScalingSettings>,
+    /// Recreate the capturer when it fails, instead of failing the recording.
+    ///
+    /// `None` only recreates it when the display gets disconnected.
+    pub restart: Option<RestartPolicy>,
+    /// Where the capture thread runs, e.g. on different cores than the encoder, see `EncoderSettings::thread_scheduling`
+    pub thread_scheduling: ThreadScheduling,
+    /// Skip frames that were captured longer than this ago by the time the encoder gets to them,
+    /// see `ThreadedCapturer::set_max_frame_age`. `None` encodes every frame however late it is.
+    pub max_frame_age: Option<Duration>,
+}
+
+impl CapturerSettings<BoxedDisplayFactory> {
+    /// Captures the primary display.
+    ///
+    /// Fails if there's no primary display.
+    pub fn for_primary(target_rate: f64) -> io::Result<Self> {
+        // failing here rather than on the worker thread
+        Display::primary()?;
+
+        Ok(Self::with_factory(Box::new(Display::primary), target_rate))
+    }
+
+    /// Captures the display at `index` in `Display::all`.
+    ///
+    /// Fails if there aren't that many displays.
+    pub fn for_display_index(index: usize, target_rate: f64) -> io::Result<Self> {
+        let display_factory = capture::display_index_factory(index, Display::all)?;
+
+        Ok(Self::with_factory(Box::new(display_factory), target_rate))
+    }
+
+    fn with_factory(display_factory: BoxedDisplayFactory, target_rate: f64) -> Self {
+        Self {
+            display_factory,
+            target_rate,
+            scene_cut_keyframe_threshold: None,
+            region: None,
+            scaling: None,
+            restart: None,
+            thread_scheduling: ThreadScheduling::default(),
+            max_frame_age: None,
+        }
+    }
+}
+
+/// When the frames held back by `BufferingSettings::buffered_frames` get pushed into the shared ring buffer
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FlushPolicy {
+    /// Once there are more than `buffered_frames` of them
+    #[default]
+    FrameCount,
+    /// Same as `FrameCount`, or once this long has passed since the last flush, whichever comes first.
+    ///
+    /// Bounds the latency when the frames are tiny and `buffered_frames` of them take a while to come together,
+    /// e.g. on a screen where nothing much moves. Measured on `EncoderSettings::clock`.
+    FrameCountOrDelay(Duration),
+}
+
+impl FlushPolicy {
+    // whether the frames have to go out regardless of how many of them there are
+    fn overdue(self, since_last_flush: Duration) -> bool {
+        match self {
+            FlushPolicy::FrameCount => false,
+            FlushPolicy::FrameCountOrDelay(max_delay) => since_last_flush >= max_delay,
+        }
+    }
+}
+
+/// Keeps the stream going while the capturer has nothing new, e.g. with the laptop lid closed,
+/// by encoding a filler frame every `timeout` until it does.
+///
+/// Players tend to give up on a live stream that stops for too long, the fillers keep the chunks
+/// and their timestamps coming. A repeated frame encodes to a tiny P-frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StallFiller {
+    /// How long to go without encoding anything before a filler, measured on `EncoderSettings::clock`
+    pub timeout: Duration,
+    pub frame: FillerFrame,
+}
+
+/// What `StallFiller` encodes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FillerFrame {
+    /// The last frame that was captured, or a black one if there hasn't been any since the last resize
+    #[default]
+    Repeat,
+    Black,
+}
+
+#[derive(Debug)]
+pub struct BufferingSettings {
+    pub buffer_capacity: usize,
+    pub buffered_frames: usize,
+    /// Doesn't matter if `buffered_frames` is 0, every frame gets flushed right away then
+    pub flush_policy: FlushPolicy,
+    /// With `OverflowPolicy::Reject`, a full buffer makes `update` return `RecordError::WriteDataError`
+    /// instead of evicting old chunks. The chunks that didn't fit get written on a later flush.
+    pub overflow_policy: OverflowPolicy,
+    /// Store a CRC-32 of every chunk in its metadata, see `EncodedBuffer::with_checksums`
+    pub checksums: bool,
+    /// Grow the ring buffer when a single chunk doesn't fit into it, e.g. a keyframe after a scene cut at a high resolution.
+    ///
+    /// Otherwise the chunk gets dropped and reported as `EncodeStatus::ChunkTooLarge`, see `EncodedBuffer::growable`.
+    pub growable: bool,
+}
+
+impl BufferingSettings {
+    /// A ring buffer of `buffer_capacity` bytes, with every frame flushed right away
+    pub fn from_bytes(buffer_capacity: usize) -> Self {
+        Self {
+            buffer_capacity,
+            buffered_frames: 0,
+            flush_policy: FlushPolicy::default(),
+            overflow_policy: OverflowPolicy::default(),
+            checksums: false,
+            growable: false,
+        }
+    }
+
+    /// Like `from_bytes`, but with room for about `secs` seconds of video at `bitrate_kbps` kbit/s.
+    ///
+    /// The encoder only aims for the bitrate, so how far back the buffer actually goes varies with the content.
+    pub fn from_duration(secs: f64, bitrate_kbps: i32) -> Self {
+        let bytes_per_sec = bitrate_kbps.max(0) as f64 * 1000.0 / 8.0;
+        Self::from_bytes((secs.max(0.0) * bytes_per_sec) as usize)
+    }
+}
+
+pub struct EncoderSettings<F>
+where
+    F: FnMut(EncoderConfig) -> Encoder + Send + 'static,
+{
+    /// Called once on startup and again every time the encoder has to be rebuilt,
+    /// e.g. when a keyframe is requested, when the display gets resized, when the bitrate changes
+    /// or after `Recorder::reconfigure`.
+    pub encoder_factory: F,
+    /// The initial bitrate in kbit/s, passed on to `encoder_factory`
+    pub bitrate: i32,
+    pub timebase: f64,
+    /// What the frames get handed to the encoder as, see `FrameFormat::from_colorspace` for the supported ones.
+    ///
+    /// Anything but `Colorspace::BGRA` gets converted on the capture thread instead of by x264 on the encoding thread.
+    /// The color conversion is a sizeable part of the time spent encoding a high resolution frame,
+    /// doing it on the capture thread lets it overlap with encoding.
+    /// The encoder produced by `encoder_factory` must be built with `EncoderConfig::colorspace`,
+    /// the encoding thread panics otherwise.
+    pub colorspace: Colorspace,
+    /// The minimum and maximum number of frames between keyframes, `None` is x264's default.
+    ///
+    /// `encoder_factory` has to apply it, see `EncoderConfig::apply_keyframe_interval`.
+    /// `Recorder::new` fails if it isn't valid, see `KeyframeInterval::validate`.
+    pub keyframe_interval: Option<KeyframeInterval>,
+    /// Where the timestamps come from, `None` is the system's monotonic clock.
+    ///
+    /// Meant for tests, see `clock::ManualClock`.
+    pub clock: Option<Arc<dyn Clock>>,
+    /// Where the encoder thread runs and how eagerly, so it doesn't have to compete with the capture thread.
+    ///
+    /// Needs the `thread-priority` feature, see `ThreadScheduling`.
+    pub thread_scheduling: ThreadScheduling,
+    /// Encode filler frames while the capturer is stalled, `None` leaves a gap in the stream until it recovers
+    pub stall_filler: Option<StallFiller>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EncodeStatus {
+    /// There was no new frame, only used internally, the recorder doesn't report these
+    Skipped,
+    /// The frame went into the write buffer, only used internally like `Skipped`
+    PreBuffered,
+    Flushed,
+    /// The display got resized and the encoder was rebuilt for the new size.
+    ///
+    /// The stream continues with new SPS/PPS, `Recorder::headers` returns them from now on.
+    Reconfigured { width: usize, height: usize },
+    /// The encoded frame was larger than the whole ring buffer and got dropped, see `BufferingSettings::growable`.
+    ///
+    /// Not fatal, but the frames after it can't be decoded until the next keyframe,
+    /// so one gets requested if the dropped frame wasn't a keyframe itself.
+    /// Chunks the old encoder still held when it got rebuilt are reported on the update after the rebuild.
+    ChunkTooLarge { bytes: usize },
+    /// The encoder was rebuilt after `Recorder::reconfigure`.
+    ///
+    /// Every chunk from `first_id` on needs the new headers, see `Recorder::headers_since`,
+    /// the chunk with that id is a keyframe once the next frame has been encoded.
+    HeadersChanged { first_id: FrameId },
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::mpsc, thread};
+
+    use super::*;
+    use crate::{
+        record::clock::ManualClock,
+        testing::{synthetic_source, test_buffering_settings, test_encoder_settings},
+    };
+
+    #[test]
+    fn data_buffer_view_through_shared_ref() {
+        let (source, gate) = synthetic_source(16, 8, 3).held();
+        let recorder = Recorder::with_source(source, test_buffering_settings(), test_encoder_settings()).unwrap();
+
+        // taken through `&Recorder` before anything got flushed, the view still sees what comes after
+        let view = recorder.data_buffer_view();
+        assert!(view.is_empty());
+
+        gate.start();
+        recorder.wait_for_frames_since(FrameId::default(), 3).unwrap();
+
+        let buf = view.get();
+        assert_eq!(buf.id_bounds(), (FrameId::new(0), FrameId::new(3)));
+        assert!(buf.get(FrameId::new(0)).unwrap().metadata().is_key);
+        assert!(buf.iter().all(|item| !item.data().is_empty()));
+    }
+
+    #[test]
+    fn try_next_frame_doesnt_wait_for_another_waiter() {
+        let (source, gate) = synthetic_source(16, 8, usize::MAX).held();
+        let recorder = Recorder::with_source(source, test_buffering_settings(), test_encoder_settings()).unwrap();
+
+        thread::scope(|s| {
+            let waiter = s.spawn(|| recorder.wait_for_frame());
+            thread::sleep(Duration::from_millis(50));
+
+            let start = Instant::now();
+            assert!(recorder.try_next_frame().is_none());
+            assert!(start.elapsed() < Duration::from_millis(100));
+
+            gate.step(1);
+            assert_eq!(waiter.join().unwrap().unwrap(), EncodeStatus::Flushed);
+        });
+    }
+
+    #[test]
+    fn planes_match_format() {
+        let data = vec![0_u8; FrameFormat::I420.frame_len(4, 2)];
+
+        let strides_and_lens = |format: FrameFormat, data: &[u8]| {
+            let (planes, count) = frame_planes(format, 4, 2, data);
+            planes[..count]
+                .iter()
+                .map(|plane| (plane.stride, plane.data.len()))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(strides_and_lens(FrameFormat::Bgra, &[0; 4 * 2 * 4]), [(16, 32)]);
+        assert_eq!(strides_and_lens(FrameFormat::I420, &data), [(4, 8), (2, 2), (2, 2)]);
+        assert_eq!(strides_and_lens(FrameFormat::Nv12, &data), [(4, 8), (4, 4)]);
+
+        assert_eq!(FrameFormat::from_colorspace(Colorspace::NV12), Some(FrameFormat::Nv12));
+        assert_eq!(FrameFormat::from_colorspace(Colorspace::RGB), None);
+    }
+
+    #[test]
+    fn skipped_frames_only_count_as_skipped() {
+        let counters = RecordCounters::default();
+
+        counters.frame_skipped();
+        counters.frame_skipped();
+
+        assert_eq!(
+            counters.snapshot(),
+            RecordStats {
+                frames_skipped: 2,
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn keyframes_count_as_encoded() {
+        let counters = RecordCounters::default();
+
+        counters.frame_encoded(true);
+        counters.frame_encoded(false);
+        counters.bytes_flushed(100);
+
+        assert_eq!(
+            counters.snapshot(),
+            RecordStats {
+                frames_encoded: 2,
+                frames_skipped: 0,
+                keyframes: 1,
+                bytes_flushed: 100,
+            }
+        );
+    }
+
+    #[test]
+    fn video_info_follows_resizes() {
+        let video_info = SharedVideoInfo::new(VideoInfo {
+            width: 1920,
+            height: 1080,
+            timebase: 1000.0,
+            target_rate: 60.0,
+            keyframe_interval: KeyframeInterval::default(),
+        });
+        let worker_video_info = video_info.clone();
+
+        worker_video_info.set_size(1280, 720);
+        video_info.set_target_rate(5.0);
+
+        let expected = VideoInfo {
+            width: 1280,
+            height: 720,
+            timebase: 1000.0,
+            target_rate: 5.0,
+            keyframe_interval: KeyframeInterval::default(),
+        };
+        assert_eq!(video_info.get(), expected);
+    }
+
+    #[test]
+    fn keyframe_interval_bounds() {
+        assert!(KeyframeInterval::X264_DEFAULT.validate().is_ok());
+        assert!(KeyframeInterval { min: 5, max: 5 }.validate().is_ok());
+
+        assert!(KeyframeInterval { min: 0, max: 5 }.validate().is_err());
+        assert!(KeyframeInterval { min: 6, max: 5 }.validate().is_err());
+        assert!(KeyframeInterval { min: 0, max: 0 }.validate().is_err());
+
+        let info = VideoInfo {
+            target_rate: 30.0,
+            keyframe_interval: KeyframeInterval { min: 15, max: 60 },
+            ..VideoInfo::default()
+        };
+        assert_eq!(info.max_keyframe_gap(), Some(Duration::from_secs(2)));
+
+        let unlimited = VideoInfo {
+            target_rate: f64::INFINITY,
+            ..info
+        };
+        assert_eq!(unlimited.max_keyframe_gap(), None);
+    }
+
+    #[test]
+    #[ignore = "needs a display to capture and the real x264"]
+    fn keyframes_follow_the_interval() {
+        let keyframe_interval = KeyframeInterval { min: 5, max: 5 };
+
+        let capturer_settings = CapturerSettings {
+            display_factory: Display::primary,
+            target_rate: 60.0,
+            scene_cut_keyframe_threshold: None,
+            region: None,
+            scaling: None,
+            restart: None,
+            thread_scheduling: ThreadScheduling::default(),
+            max_frame_age: None,
+        };
+        let buffering_settings = BufferingSettings {
+            buffer_capacity: 64 * 1024 * 1024,
+            buffered_frames: 1,
+            flush_policy: FlushPolicy::FrameCount,
+            overflow_policy: OverflowPolicy::Reject,
+            checksums: false,
+            growable: false,
+        };
+        let encoder_settings = EncoderSettings {
+            encoder_factory: |config: EncoderConfig| {
+                let setup = Setup::preset(x264::Preset::Ultrafast, x264::Tune::None, false, true);
+
+                config
+                    .apply_keyframe_interval(setup)
+                    // only the interval decides where the keyframes go
+                    .scenecut_threshold(0)
+                    .bitrate(config.bitrate)
+                    .timebase(1, 1000)
+                    .build(config.colorspace, config.width as _, config.height as _)
+                    .unwrap()
+            },
+            bitrate: 1000,
+            timebase: 1000.0,
+            colorspace: Colorspace::BGRA,
+            keyframe_interval: Some(keyframe_interval),
+            clock: None,
+            thread_scheduling: ThreadScheduling::default(),
+            stall_filler: None,
+        };
+
+        let recorder = Recorder::new(capturer_settings, buffering_settings, encoder_settings).unwrap();
+        assert_eq!(recorder.video_info().keyframe_interval, keyframe_interval);
+
+        while recorder.data_buffer_view().len() < 16 {
+            recorder.wait_for_frame().unwrap();
+        }
+
+        let snapshot = recorder.data_buffer_view().snapshot();
+        let keys: Vec<_> = snapshot.iter().map(|(item, _)| item.metadata.is_key).collect();
+        let expected: Vec<_> = (0..keys.len()).map(|i| i % 5 == 0).collect();
+        assert_eq!(keys, expected);
+    }
+
+    #[test]
+    fn requested_keyframe_comes_next() {
+        let (source, gate) = synthetic_source(16, 8, usize::MAX).held();
+        let defaults = test_encoder_settings();
+        // without the request there'd be no keyframe after the first one for a long while
+        let encoder_settings = EncoderSettings {
+            encoder_factory: |config: EncoderConfig| {
+                config
+                    .apply_keyframe_interval(Setup::preset(x264::Preset::Ultrafast, x264::Tune::None, false, true))
+                    .scenecut_threshold(0)
+                    .bitrate(config.bitrate)
+                    .timebase(1, 1000)
+                    .build(config.colorspace, config.width as _, config.height as _)
+                    .unwrap()
+            },
+            bitrate: defaults.bitrate,
+            timebase: defaults.timebase,
+            colorspace: defaults.colorspace,
+            keyframe_interval: Some(KeyframeInterval { min: 1000, max: 1000 }),
+            clock: defaults.clock,
+            thread_scheduling: defaults.thread_scheduling,
+            stall_filler: defaults.stall_filler,
+        };
+
+        let recorder = Recorder::with_source(source, test_buffering_settings(), encoder_settings).unwrap();
+        let mut chunks = recorder.flushed_chunks();
+        for _ in 0..2 {
+            gate.step(1);
+            chunks.next().unwrap().unwrap();
+        }
+
+        recorder.request_keyframe();
+        gate.step(1);
+        assert!(chunks.next().unwrap().unwrap().metadata.is_key);
+        assert_eq!(chunks.next_id(), FrameId::new(3));
+
+        gate.step(1);
+        assert!(!chunks.next().unwrap().unwrap().metadata.is_key);
+    }
+
+    #[test]
+    fn records_from_a_custom_source() {
+        let source = synthetic_source(16, 8, 5);
+
+        let recorder = Recorder::with_source(source, test_buffering_settings(), test_encoder_settings()).unwrap();
+        assert_eq!((recorder.video_info().width, recorder.video_info().height), (16, 8));
+
+        while recorder.stats().frames_encoded < 5 {
+            recorder.wait_for_frame().unwrap();
+        }
+
+        let snapshot = recorder.data_buffer_view().snapshot();
+        let metadata: Vec<_> = snapshot.iter().map(|(item, _)| item.metadata).collect();
+        assert_eq!(metadata.len(), 5);
+        assert!(metadata[0].is_key);
+        assert!(metadata.windows(2).all(|pair| pair[0].pts < pair[1].pts));
+    }
+
+    #[test]
+    fn flushed_chunks_in_order() {
+        let source = synthetic_source(16, 8, 5);
+
+        let recorder = Recorder::with_source(source, test_buffering_settings(), test_encoder_settings()).unwrap();
+
+        // every frame is its own flush, the source runs dry after 5 of them
+        let mut chunks = recorder.flushed_chunks();
+        let metadata: Vec<_> = chunks.by_ref().take(5).map(|chunk| chunk.unwrap().metadata).collect();
+
+        assert!(metadata[0].is_key);
+        assert!(metadata.windows(2).all(|pair| pair[0].pts < pair[1].pts));
+        assert_eq!(chunks.next_id(), FrameId::new(5));
+        assert_eq!(recorder.data_buffer().unwrap().id_bounds().1, FrameId::new(5));
+    }
+
+    #[test]
+    fn chunk_larger_than_buffer_is_skipped() {
+        let source = synthetic_source(16, 8, 2);
+        // no encoded frame fits into 4 bytes
+        let buffering_settings = BufferingSettings {
+            buffer_capacity: 4,
+            ..test_buffering_settings()
+        };
+
+        let recorder = Recorder::with_source(source, buffering_settings, test_encoder_settings()).unwrap();
+
+        // both frames get dropped and the recording keeps going
+        for _ in 0..2 {
+            let status = recorder.wait_for_frame().unwrap();
+            assert!(matches!(status, EncodeStatus::ChunkTooLarge { bytes } if bytes > 4));
+        }
+        assert!(recorder.data_buffer_view().is_empty());
+        assert_eq!(recorder.data_buffer_view().capacity(), 4);
+        assert!(!recorder.monitor().worker_exited());
+    }
+
+    #[test]
+    fn source_format_has_to_match() {
+        let source = synthetic_source(16, 8, 0);
+        let buffering_settings = BufferingSettings {
+            buffer_capacity: 1024,
+            buffered_frames: 1,
+            ..test_buffering_settings()
+        };
+        let encoder_settings = EncoderSettings {
+            colorspace: Colorspace::I420,
+            ..test_encoder_settings()
+        };
+
+        let result = Recorder::with_source(source, buffering_settings, encoder_settings);
+        assert!(matches!(
+            result,
+            Err(RecordError::SourceFormatMismatch {
+                frames: FrameFormat::Bgra,
+                expected: FrameFormat::I420
+            })
+        ));
+    }
+
+    #[test]
+    fn flush_after_delay() {
+        let clock = ManualClock::new();
+        let (source, gate) = synthetic_source(16, 8, usize::MAX).held();
+        let buffering_settings = BufferingSettings {
+            buffered_frames: 10,
+            flush_policy: FlushPolicy::FrameCountOrDelay(Duration::from_millis(100)),
+            ..test_buffering_settings()
+        };
+        let encoder_settings = EncoderSettings {
+            clock: Some(Arc::new(clock.clone())),
+            ..test_encoder_settings()
+        };
+
+        let recorder = Recorder::with_source(source, buffering_settings, encoder_settings).unwrap();
+        // pre-buffered frames don't get reported, so the counters are the only way to tell they've been encoded
+        let encode_frame = || {
+            let encoded = recorder.stats().frames_encoded;
+            gate.step(1);
+
+            while recorder.stats().frames_encoded == encoded {
+                std::thread::sleep(Duration::from_millis(1));
+            }
+        };
+
+        encode_frame();
+        clock.advance(Duration::from_millis(50));
+        encode_frame();
+        assert_eq!(recorder.data_buffer_view().len(), 0);
+
+        // two frames are a lot less than 10, they still go out once they've waited long enough,
+        // even with no new frames coming in
+        clock.advance(Duration::from_millis(50));
+        assert_eq!(recorder.wait_for_frame().unwrap(), EncodeStatus::Flushed);
+        assert_eq!(recorder.data_buffer_view().len(), 2);
+
+        // the delay starts over after the flush
+        encode_frame();
+        assert_eq!(recorder.data_buffer_view().len(), 2);
+        assert!(recorder.wait_for_frame_timeout(Duration::from_millis(20)).is_none());
+    }
+
+    #[test]
+    fn padded_and_unpadded_frames_get_encoded() {
+        // 16x8, the padded rows are 20 pixels long like scrap can produce on some platforms
+        for stride in [16, 20] {
+            let (source, gate) = synthetic_source(16, 8, usize::MAX).with_stride(stride).held();
+
+            let recorder = Recorder::with_source(source, test_buffering_settings(), test_encoder_settings()).unwrap();
+
+            for _ in 0..2 {
+                gate.step(1);
+                assert_eq!(recorder.wait_for_frame().unwrap(), EncodeStatus::Flushed);
+            }
+            assert_eq!(recorder.stats().frames_encoded, 2);
+        }
+    }
+
+    #[test]
+    fn keyframes_come_with_their_headers() {
+        let (source, gate) = synthetic_source(16, 8, usize::MAX).held();
+
+        let recorder = Recorder::with_source(source, test_buffering_settings(), test_encoder_settings()).unwrap();
+
+        for _ in 0..2 {
+            gate.step(1);
+            assert_eq!(recorder.wait_for_frame().unwrap(), EncodeStatus::Flushed);
+        }
+
+        let keyframe = recorder.data_buffer_view().latest_keyframe_id().unwrap();
+        let chunk = recorder.data_buffer().unwrap().get(keyframe).unwrap().data().into_owned();
+        let headers = recorder.headers();
+
+        let blob = recorder.keyframe_with_headers(keyframe).unwrap();
+        assert!(blob.starts_with(&headers));
+        assert_eq!(blob[headers.len()..], chunk[..]);
+
+        // the chunk after it isn't a keyframe, and the one after that doesn't exist yet
+        assert_eq!(recorder.keyframe_with_headers(keyframe + 1), None);
+        assert_eq!(recorder.keyframe_with_headers(keyframe + 2), None);
+    }
+
+    #[test]
+    fn fillers_while_the_capturer_starves() {
+        let clock = ManualClock::new();
+        let (source, gate) = synthetic_source(16, 8, usize::MAX).held();
+        let encoder_settings = EncoderSettings {
+            clock: Some(Arc::new(clock.clone())),
+            stall_filler: Some(StallFiller {
+                timeout: Duration::from_millis(100),
+                frame: FillerFrame::Repeat,
+            }),
+            ..test_encoder_settings()
+        };
+
+        let recorder = Recorder::with_source(source, test_buffering_settings(), encoder_settings).unwrap();
+
+        gate.step(1);
+        assert_eq!(recorder.wait_for_frame().unwrap(), EncodeStatus::Flushed);
+
+        // not stalled for long enough yet
+        clock.advance(Duration::from_millis(60));
+        assert!(recorder.wait_for_frame_timeout(Duration::from_millis(20)).is_none());
+
+        // one filler per timeout, not one per skipped frame
+        for _ in 0..3 {
+            clock.advance(Duration::from_millis(40));
+            assert_eq!(recorder.wait_for_frame().unwrap(), EncodeStatus::Flushed);
+            clock.advance(Duration::from_millis(60));
+            assert!(recorder.wait_for_frame_timeout(Duration::from_millis(20)).is_none());
+        }
+
+        let pts: Vec<i64> = recorder.data_buffer_view().get().iter().map(|item| item.metadata().pts).collect();
+        assert_eq!(pts, [0, 100, 200, 300]);
+        assert_eq!(recorder.stats().frames_encoded, 4);
+
+        // a captured frame starts the timeout over
+        gate.step(1);
+        assert_eq!(recorder.wait_for_frame().unwrap(), EncodeStatus::Flushed);
+        clock.advance(Duration::from_millis(60));
+        assert!(recorder.wait_for_frame_timeout(Duration::from_millis(20)).is_none());
+        assert_eq!(recorder.data_buffer_view().len(), 5);
+    }
+
+    #[test]
+    fn set_bitrate_rebuilds_the_encoder() {
+        let (source, gate) = synthetic_source(16, 8, usize::MAX).held();
+        let bitrates = Arc::new(Mutex::new(Vec::new()));
+        let factory_bitrates = bitrates.clone();
+        let defaults = test_encoder_settings();
+        let encoder_settings = EncoderSettings {
+            encoder_factory: move |config: EncoderConfig| {
+                factory_bitrates.lock().push(config.bitrate);
+
+                config
+                    .setup(x264::Preset::Ultrafast, x264::Tune::None, false, true)
+                    .bitrate(config.bitrate)
+                    .timebase(1, 1000)
+                    .build(config.colorspace, config.width as _, config.height as _)
+                    .unwrap()
+            },
+            bitrate: defaults.bitrate,
+            timebase: defaults.timebase,
+            colorspace: defaults.colorspace,
+            keyframe_interval: defaults.keyframe_interval,
+            clock: defaults.clock,
+            thread_scheduling: defaults.thread_scheduling,
+            stall_filler: defaults.stall_filler,
+        };
+
+        let recorder = Recorder::with_source(source, test_buffering_settings(), encoder_settings).unwrap();
+        let mut chunks = recorder.flushed_chunks();
+        for _ in 0..2 {
+            gate.step(1);
+            chunks.next().unwrap().unwrap();
+        }
+        assert_eq!(recorder.headers_since().1, FrameId::new(0));
+
+        recorder.set_bitrate(2000);
+        // the encoder only gets rebuilt along with the next frame
+        assert_eq!(*bitrates.lock(), [defaults.bitrate]);
+
+        gate.step(1);
+        assert!(chunks.next().unwrap().unwrap().metadata.is_key);
+        assert_eq!(*bitrates.lock(), [defaults.bitrate, 2000]);
+        assert_eq!(recorder.headers_since().1, FrameId::new(2));
+
+        gate.step(1);
+        assert!(!chunks.next().unwrap().unwrap().metadata.is_key);
+        assert_eq!(bitrates.lock().len(), 2);
+    }
+
+    #[test]
+    fn reconfigure_changes_the_headers() {
+        let (source, gate) = synthetic_source(16, 8, usize::MAX).held();
+        let configs = Arc::new(Mutex::new(Vec::new()));
+        let factory_configs = configs.clone();
+        let defaults = test_encoder_settings();
+        // the factory has to record the configs, so it's the one thing that can't come from the defaults
+        let encoder_settings = EncoderSettings {
+            encoder_factory: move |config: EncoderConfig| {
+                factory_configs.lock().push(config);
+
+                config
+                    .setup(x264::Preset::Ultrafast, x264::Tune::None, false, true)
+                    .bitrate(config.bitrate)
+                    .timebase(1, 1000)
+                    .build(config.colorspace, config.width as _, config.height as _)
+                    .unwrap()
+            },
+            bitrate: defaults.bitrate,
+            timebase: defaults.timebase,
+            colorspace: defaults.colorspace,
+            keyframe_interval: defaults.keyframe_interval,
+            clock: defaults.clock,
+            thread_scheduling: defaults.thread_scheduling,
+            stall_filler: defaults.stall_filler,
+        };
+
+        let recorder = Recorder::with_source(source, test_buffering_settings(), encoder_settings).unwrap();
+        for _ in 0..2 {
+            gate.step(1);
+            assert_eq!(recorder.wait_for_frame().unwrap(), EncodeStatus::Flushed);
+        }
+
+        recorder.reconfigure(EncoderReconfig {
+            preset: Some(x264::Preset::Slow),
+            bitrate: Some(2000),
+            ..EncoderReconfig::default()
+        });
+        // doesn't wait for a frame
+        let first_id = match recorder.wait_for_frame().unwrap() {
+            EncodeStatus::HeadersChanged { first_id } => first_id,
+            other => panic!("expected the headers to change, got {other:?}"),
+        };
+        assert_eq!(first_id, FrameId::new(2));
+
+        let config = *configs.lock().last().unwrap();
+        assert_eq!((config.preset, config.tune, config.bitrate), (Some(x264::Preset::Slow), None, 2000));
+
+        let expected_headers = Setup::preset(x264::Preset::Slow, x264::Tune::None, false, true)
+            .bitrate(2000)
+            .timebase(1, 1000)
+            .build(Colorspace::BGRA, 16, 8)
+            .unwrap()
+            .headers()
+            .unwrap()
+            .entirety()
+            .to_vec();
+        let (headers, since) = recorder.headers_since();
+        assert_eq!((&headers[..], since), (&expected_headers[..], first_id));
+
+        gate.step(1);
+        assert_eq!(recorder.wait_for_frame().unwrap(), EncodeStatus::Flushed);
+        assert!(recorder.data_buffer_view().get_owned(first_id).unwrap().metadata.is_key);
+    }
+
+    #[test]
+    fn high_water_callback_on_the_encoder_thread() {
+        let (source, gate) = synthetic_source(16, 8, usize::MAX).held();
+        let buffering_settings = BufferingSettings {
+            buffer_capacity: 4096,
+            ..test_buffering_settings()
+        };
+
+        let recorder = Recorder::with_source(source, buffering_settings, test_encoder_settings()).unwrap();
+        let (callback_tx, callbacks) = mpsc::channel();
+        recorder.set_high_water(0.5, move || {
+            callback_tx.send(std::thread::current().name().map(String::from)).unwrap();
+        });
+
+        let view = recorder.data_buffer_view();
+        while view.bytes_used() <= 2048 {
+            assert!(callbacks.try_recv().is_err());
+
+            gate.step(1);
+            assert_eq!(recorder.wait_for_frame().unwrap(), EncodeStatus::Flushed);
+        }
+
+        assert_eq!(callbacks.try_recv().unwrap().as_deref(), Some("h264-encoder"));
+        assert!(callbacks.try_recv().is_err());
+    }
+
+    #[test]
+    fn reconfigure_requests_get_merged() {
+        let first = EncoderReconfig {
+            preset: Some(x264::Preset::Slow),
+            bitrate: Some(2000),
+            ..EncoderReconfig::default()
+        };
+        let second = EncoderReconfig {
+            tune: Some(x264::Tune::Film),
+            bitrate: Some(0),
+            ..EncoderReconfig::default()
+        };
+
+        let mut config = EncoderConfig {
+            width: 16,
+            height: 8,
+            bitrate: 1000,
+            colorspace: Colorspace::BGRA,
+            keyframe_interval: KeyframeInterval::default(),
+            preset: None,
+            tune: None,
+        };
+        first.merge(second).apply(&mut config);
+
+        assert_eq!(config.preset, Some(x264::Preset::Slow));
+        assert_eq!(config.tune, Some(x264::Tune::Film));
+        assert_eq!(config.bitrate, 1);
+    }
+
+    #[test]
+    fn write_buf_sized_for_buffered_frames() {
+        assert_eq!(write_buf_capacity(0, 4000, 60.0), (0, 0));
+        // 4 Mbit/s at 50 fps is 10 kB per frame
+        assert_eq!(write_buf_capacity(3, 4000, 50.0), (40_000, 4));
+        assert_eq!(write_buf_capacity(3, 4000, f64::INFINITY), (0, 4));
+        assert_eq!(write_buf_capacity(3, 4000, 0.0), (0, 4));
+    }
+
+    // reports a status at a fixed rate, flushing every `flush_every` frames if that's set
+    struct FakeEncoder {
+        frames: usize,
+        flush_every: Option<usize>,
+    }
+
+    impl ThreadWork for FakeEncoder {
+        type WorkResult = Result<EncodeStatus, RecordError>;
+
+        fn work(&mut self) -> Option<Self::WorkResult> {
+            self.frames += 1;
+
+            // the skips don't get reported, same as with the real one
+            match self.flush_every {
+                Some(n) if self.frames.is_multiple_of(n) => Some(Ok(EncodeStatus::Flushed)),
+                _ => None,
+            }
+        }
+    }
+
+    #[test]
+    fn flush_timeout() {
+        let idle = ThreadLoop::new(|| FakeEncoder { frames: 0, flush_every: None }, 200.0);
+        let flushing = ThreadLoop::new(|| FakeEncoder { frames: 0, flush_every: Some(3) }, 200.0);
+
+        let timeout = Duration::from_millis(100);
+
+        let start = Instant::now();
+        assert!(!wait_for_flush_timeout(&idle, timeout).unwrap());
+        assert!(start.elapsed() >= timeout);
+
+        assert!(wait_for_flush_timeout(&flushing, timeout).unwrap());
+    }
+
+    #[test]
+    fn encode_error_has_frame_context() {
+        let result: Result<(), x264::Error> = Err(x264::Error);
+        let error = result
+            .map_err(RecordError::encode(EncodeErrorKind::Encode, 1234, 41))
+            .unwrap_err();
+
+        assert!(matches!(
+            error,
+            RecordError::EncodeError {
+                kind: EncodeErrorKind::Encode,
+                timestamp: 1234,
+                frame_index: 41,
+            }
+        ));
+        assert_eq!(error.to_string(), "x264 encoding failed at frame 41 with timestamp 1234");
+    }
+
+    #[test]
+    fn headers_get_replaced() {
+        let headers = SharedHeaders::default();
+        let reader = headers.clone();
+
+        headers.set(Arc::from(&b"old"[..]), FrameId::default());
+        assert_eq!(&*reader.get(), b"old");
+
+        headers.set(Arc::from(&b"new"[..]), FrameId::new(5));
+        assert_eq!(&*reader.get(), b"new");
+        assert_eq!(reader.get_since(), (Arc::from(&b"new"[..]), FrameId::new(5)));
+    }
+
+    #[test]
+    fn bitrate_request_is_taken_once() {
+        let request = Arc::new(BitrateRequest::default());
+        let worker_request = request.clone();
+
+        assert_eq!(worker_request.take(), None);
+
+        request.request(2000);
+        request.request(1000);
+
+        // only the latest one counts
+        assert_eq!(worker_request.take(), Some(1000));
+        assert_eq!(worker_request.take(), None);
+    }
+
+    #[test]
+    fn pts_increases_monotonically() {
+        let mut buf = EncodedBuffer::new(1024);
+        let view = buf.view();
+
+        let mut last_pts = None;
+        // the second and third frames land on the same millisecond
+        for elapsed_us in [0, 16_600, 16_900, 33_300] {
+            let pts = next_pts(Duration::from_micros(elapsed_us), 1000.0, last_pts);
+            last_pts = Some(pts);
+
+            buf.write_flush(&[0; 4], Metadata::new(false, pts)).unwrap();
+        }
+
+        let guard = view.get();
+        let stored: Vec<i64> = guard.iter().map(|item| item.metadata().pts).collect();
+
+        assert_eq!(stored, [0, 16, 17, 33]);
+        assert!(stored.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+
+    #[test]
+    fn pts_follow_the_clock() {
+        let clock = ManualClock::new();
+        let pause_clock = PauseClock::new(RecordClock::new(Some(Arc::new(clock.clone()))));
+        let start = clock.now();
+
+        let mut last_pts = None;
+        let mut pts_after = |advance_ms: u64| {
+            clock.advance(Duration::from_millis(advance_ms));
+            let pts = next_pts(pause_clock.recording_time(start), 90_000.0, last_pts);
+            last_pts = Some(pts);
+            pts
+        };
+
+        // steps that are exact in binary, the conversion truncates
+        assert_eq!(pts_after(0), 0);
+        assert_eq!(pts_after(125), 11_250);
+        assert_eq!(pts_after(1000), 101_250);
+        assert_eq!(pts_after(250), 123_750);
+    }
+
+    #[test]
+    fn pause_leaves_a_gapless_pts() {
+        let clock = ManualClock::new();
+        let mut pause_clock = PauseClock::new(RecordClock::new(Some(Arc::new(clock.clone()))));
+        let start = clock.now();
+        let pts = |pause_clock: &PauseClock| next_pts(pause_clock.recording_time(start), 1000.0, None);
+
+        clock.advance(Duration::from_millis(100));
+        assert_eq!(pts(&pause_clock), 100);
+
+        pause_clock.pause();
+        clock.advance(Duration::from_secs(5));
+        // the time doesn't move while paused
+        assert_eq!(pts(&pause_clock), 100);
+
+        pause_clock.resume();
+        clock.advance(Duration::from_millis(20));
+        assert_eq!(pts(&pause_clock), 120);
+
+        // pausing twice counts once
+        pause_clock.pause();
+        clock.advance(Duration::from_millis(30));
+        pause_clock.pause();
+        clock.advance(Duration::from_millis(30));
+        pause_clock.resume();
+        assert_eq!(pts(&pause_clock), 120);
+    }
+
+    #[test]
+    fn buffer_capacity_from_duration() {
+        // 4000 kbit/s is 500 kB/s
+        let settings = BufferingSettings::from_duration(10.0, 4000);
+        assert_eq!(settings.buffer_capacity, 5_000_000);
+        assert_eq!(settings.buffered_frames, 0);
+
+        assert_eq!(BufferingSettings::from_duration(0.5, 8).buffer_capacity, 500);
+        assert_eq!(BufferingSettings::from_bytes(1234).buffer_capacity, 1234);
+    }
 }
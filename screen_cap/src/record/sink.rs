@@ -0,0 +1,418 @@
+use std::{
+    collections::VecDeque,
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+    sync::Arc,
+    thread::{self, JoinHandle},
+};
+
+use parking_lot::{Condvar, Mutex};
+use utils::contiguous::{BufferItem, FrameId};
+
+use super::encoded_buffer::{EncodedBufferView, Metadata};
+
+/// How many chunks a sink can fall behind before its oldest queued chunks get dropped
+pub const SINK_QUEUE_CAPACITY: usize = 256;
+
+/// Gets every chunk the recorder flushes, see `Recorder::add_sink`.
+///
+/// Unlike a `ReaderCursor`, which pulls the chunks out of the ring buffer itself,
+/// this one gets them pushed, so it can't miss chunks by reading too late.
+/// It can still miss some by being too slow though, every sink has a bounded queue
+/// and once it's full the oldest chunk in it gets dropped to make room,
+/// so the ids are increasing but not necessarily consecutive.
+pub trait ChunkSink {
+    /// Called on a thread owned by the sink, so blocking here only holds up this sink.
+    ///
+    /// An error stops the sink, it doesn't get any more chunks, see `Recorder::take_sink_errors`.
+    fn write_chunk(&mut self, id: FrameId, item: BufferItem<'_, Metadata>) -> io::Result<()>;
+
+    /// Makes a blocked `write_chunk` return, called once the recorder and its encoder thread are both gone.
+    ///
+    /// Dropping them waits for the sink to write out what's still queued, so a sink that can block for good,
+    /// e.g. on a consumer that stopped reading, has to stop waiting once this has been called.
+    /// `None` for sinks that never block for long.
+    fn stop_handle(&self) -> Option<StopHandle> {
+        None
+    }
+}
+
+/// See `ChunkSink::stop_handle`
+pub type StopHandle = Box<dyn FnOnce() + Send>;
+
+// the data is shared between the queues of all the sinks
+struct QueuedChunk {
+    id: FrameId,
+    data: Arc<[u8]>,
+    metadata: Metadata,
+}
+
+#[derive(Default)]
+struct QueueState {
+    chunks: VecDeque<QueuedChunk>,
+    closed: bool,
+}
+
+struct ChunkQueue {
+    state: Mutex<QueueState>,
+    condvar: Condvar,
+    capacity: usize,
+}
+
+impl ChunkQueue {
+    fn new(capacity: usize) -> Self {
+        Self {
+            state: Mutex::default(),
+            condvar: Condvar::new(),
+            // a queue that can't hold anything would drop every chunk
+            capacity: capacity.max(1),
+        }
+    }
+
+    fn push(&self, chunk: QueuedChunk) {
+        let mut state = self.state.lock();
+
+        // the sink is gone
+        if state.closed {
+            return;
+        }
+
+        if state.chunks.len() >= self.capacity {
+            state.chunks.pop_front();
+        }
+        state.chunks.push_back(chunk);
+
+        self.condvar.notify_one();
+    }
+
+    // None once the queue is closed and everything in it has been taken
+    fn pop(&self) -> Option<QueuedChunk> {
+        let mut state = self.state.lock();
+        self.condvar
+            .wait_while(&mut state, |state| state.chunks.is_empty() && !state.closed);
+
+        state.chunks.pop_front()
+    }
+
+    fn close(&self) {
+        self.state.lock().closed = true;
+        self.condvar.notify_one();
+    }
+}
+
+// a sink along with the thread that feeds it from its queue
+struct SinkThread {
+    queue: Arc<ChunkQueue>,
+    stop: Option<StopHandle>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl SinkThread {
+    fn spawn(mut sink: Box<dyn ChunkSink + Send>, capacity: usize, errors: SinkErrors) -> Self {
+        let queue = Arc::new(ChunkQueue::new(capacity));
+        let thread_queue = queue.clone();
+        let stop = sink.stop_handle();
+
+        let handle = thread::Builder::new()
+            .name("chunk-sink".to_string())
+            .spawn(move || {
+                while let Some(chunk) = thread_queue.pop() {
+                    let item = BufferItem::from_parts(&chunk.data, &chunk.metadata);
+
+                    if let Err(e) = sink.write_chunk(chunk.id, item) {
+                        errors.lock().push(e);
+                        thread_queue.close();
+                        break;
+                    }
+                }
+            })
+            .expect("failed to spawn a sink thread");
+
+        Self {
+            queue,
+            stop,
+            handle: Some(handle),
+        }
+    }
+}
+
+impl Drop for SinkThread {
+    // the sink still gets whatever is queued, so e.g. a file doesn't lose its tail,
+    // one that's waiting on something else gets woken up first so this doesn't wait forever
+    fn drop(&mut self) {
+        self.queue.close();
+
+        if let Some(stop) = self.stop.take() {
+            stop();
+        }
+
+        if let Some(handle) = self.handle.take() {
+            // a panicking sink only takes itself down
+            let _ = handle.join();
+        }
+    }
+}
+
+/// The sinks of one recorder, shared between the `Recorder` and the encoder thread.
+///
+/// The sink threads finish once both sides are gone.
+#[derive(Clone, Default)]
+pub(super) struct SinkSet {
+    sinks: Arc<Mutex<Vec<SinkThread>>>,
+    errors: SinkErrors,
+}
+
+// why the sinks that failed stopped, oldest first
+type SinkErrors = Arc<Mutex<Vec<io::Error>>>;
+
+impl SinkSet {
+    pub(super) fn add(&self, sink: Box<dyn ChunkSink + Send>, capacity: usize) {
+        let sink = SinkThread::spawn(sink, capacity, self.errors.clone());
+        self.sinks.lock().push(sink);
+    }
+
+    pub(super) fn take_errors(&self) -> Vec<io::Error> {
+        std::mem::take(&mut *self.errors.lock())
+    }
+}
+
+/// Lives on the encoder thread and copies every newly flushed chunk into the queues of the sinks
+pub(super) struct SinkDispatcher {
+    sinks: SinkSet,
+    view: EncodedBufferView,
+    next_id: FrameId,
+}
+
+impl SinkDispatcher {
+    pub(super) fn new(sinks: SinkSet, view: EncodedBufferView) -> Self {
+        let (_, next_id) = view.get().id_bounds();
+
+        Self {
+            sinks,
+            view,
+            next_id,
+        }
+    }
+
+    /// Queues the chunks flushed since the last call, sinks added in the meantime only get the new ones
+    pub(super) fn dispatch(&mut self) {
+        let buf = self.view.get();
+        let (min_id, max_id) = buf.id_bounds();
+
+        // chunks can only get evicted this quickly if a single flush overflows the buffer
+        let start_id = self.next_id.max(min_id);
+        self.next_id = max_id;
+
+        let sinks = self.sinks.sinks.lock();
+        if sinks.is_empty() {
+            return;
+        }
+
+        for (id, item) in FrameId::range(start_id, max_id).zip(buf.range(start_id, max_id)) {
+            let data: Arc<[u8]> = item.data().into();
+
+            for sink in sinks.iter() {
+                sink.queue.push(QueuedChunk {
+                    id,
+                    data: data.clone(),
+                    metadata: *item.metadata(),
+                });
+            }
+        }
+    }
+}
+
+/// Writes the chunks into a file back to back.
+///
+/// A failed write stops the sink, the file is useless after it anyway, see `Recorder::take_sink_errors`.
+pub struct FileSink {
+    writer: BufWriter<File>,
+}
+
+impl FileSink {
+    /// Creates the file at `path` and writes `headers` into it first,
+    /// so the file is a playable H.264 stream, see `Recorder::headers`.
+    ///
+    /// Chunks the sink dropped for being too slow leave the picture broken until the next keyframe.
+    pub fn create(path: impl AsRef<Path>, headers: &[u8]) -> io::Result<Self> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writer.write_all(headers)?;
+
+        Ok(Self { writer })
+    }
+}
+
+impl ChunkSink for FileSink {
+    fn write_chunk(&mut self, _id: FrameId, item: BufferItem<'_, Metadata>) -> io::Result<()> {
+        item.copy_to(&mut self.writer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            mpsc,
+        },
+        time::{Duration, Instant},
+    };
+
+    use super::*;
+    use crate::record::encoded_buffer::EncodedBuffer;
+
+    struct CollectingSink(mpsc::Sender<(FrameId, Vec<u8>)>);
+
+    impl ChunkSink for CollectingSink {
+        fn write_chunk(&mut self, id: FrameId, item: BufferItem<'_, Metadata>) -> io::Result<()> {
+            self.0.send((id, item.data().into_owned())).unwrap();
+            Ok(())
+        }
+    }
+
+    // fails every write, counting them
+    struct FailingSink(Arc<AtomicUsize>);
+
+    impl ChunkSink for FailingSink {
+        fn write_chunk(&mut self, _id: FrameId, _item: BufferItem<'_, Metadata>) -> io::Result<()> {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            Err(io::Error::new(io::ErrorKind::WriteZero, "disk full"))
+        }
+    }
+
+    // blocks in its first write until it's stopped
+    struct StuckSink {
+        entered: mpsc::Sender<()>,
+        // handed over to the stop handle, so that's the only sender left
+        release: Mutex<Option<mpsc::Sender<()>>>,
+        released: mpsc::Receiver<()>,
+    }
+
+    impl ChunkSink for StuckSink {
+        fn write_chunk(&mut self, _id: FrameId, _item: BufferItem<'_, Metadata>) -> io::Result<()> {
+            let _ = self.entered.send(());
+            // only fails once the stop handle has dropped the sender
+            let _ = self.released.recv();
+            Ok(())
+        }
+
+        fn stop_handle(&self) -> Option<StopHandle> {
+            let release = self.release.lock().take()?;
+            Some(Box::new(move || drop(release)))
+        }
+    }
+
+    fn write_chunk(buf: &mut EncodedBuffer, data: u8) {
+        buf.write_flush(&[data; 4], Metadata::new(false, 0)).unwrap();
+    }
+
+    #[test]
+    fn sinks_get_the_same_chunks() {
+        let mut buf = EncodedBuffer::new(64);
+        let sinks = SinkSet::default();
+        let mut dispatcher = SinkDispatcher::new(sinks.clone(), buf.view());
+
+        // from before the sinks were added
+        write_chunk(&mut buf, 0);
+        dispatcher.dispatch();
+
+        let (tx_a, rx_a) = mpsc::channel();
+        let (tx_b, rx_b) = mpsc::channel();
+        sinks.add(Box::new(CollectingSink(tx_a)), SINK_QUEUE_CAPACITY);
+        sinks.add(Box::new(CollectingSink(tx_b)), SINK_QUEUE_CAPACITY);
+
+        for i in 1..4 {
+            write_chunk(&mut buf, i);
+        }
+        dispatcher.dispatch();
+        write_chunk(&mut buf, 4);
+        dispatcher.dispatch();
+
+        // joins the sink threads
+        drop((sinks, dispatcher));
+
+        let expected: Vec<_> = (1..5).map(|i| (FrameId::new(i), vec![i as u8; 4])).collect();
+        assert_eq!(rx_a.iter().collect::<Vec<_>>(), expected);
+        assert_eq!(rx_b.iter().collect::<Vec<_>>(), expected);
+    }
+
+    #[test]
+    fn full_queue_drops_oldest() {
+        let queue = ChunkQueue::new(2);
+
+        for i in 0..5 {
+            queue.push(QueuedChunk {
+                id: FrameId::new(i),
+                data: Arc::new([]),
+                metadata: Metadata::new(false, 0),
+            });
+        }
+        queue.close();
+
+        assert_eq!(queue.pop().unwrap().id, FrameId::new(3));
+        assert_eq!(queue.pop().unwrap().id, FrameId::new(4));
+        assert!(queue.pop().is_none());
+    }
+
+    #[test]
+    fn failed_sink_stops_and_reports() {
+        let mut buf = EncodedBuffer::new(64);
+        let sinks = SinkSet::default();
+        let mut dispatcher = SinkDispatcher::new(sinks.clone(), buf.view());
+
+        let writes = Arc::new(AtomicUsize::new(0));
+        sinks.add(Box::new(FailingSink(writes.clone())), SINK_QUEUE_CAPACITY);
+
+        for i in 0..3 {
+            write_chunk(&mut buf, i);
+        }
+        dispatcher.dispatch();
+
+        let deadline = Instant::now() + Duration::from_secs(1);
+        let errors = loop {
+            let errors = sinks.take_errors();
+            if !errors.is_empty() || Instant::now() > deadline {
+                break errors;
+            }
+            thread::sleep(Duration::from_millis(1));
+        };
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].kind(), io::ErrorKind::WriteZero);
+
+        write_chunk(&mut buf, 3);
+        dispatcher.dispatch();
+        drop((sinks, dispatcher));
+
+        // nothing after the first error
+        assert_eq!(writes.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn dropping_wakes_a_blocked_sink() {
+        let mut buf = EncodedBuffer::new(64);
+        let sinks = SinkSet::default();
+        let mut dispatcher = SinkDispatcher::new(sinks.clone(), buf.view());
+
+        let (entered_tx, entered_rx) = mpsc::channel();
+        let (release_tx, release_rx) = mpsc::channel();
+        let sink = StuckSink {
+            entered: entered_tx,
+            release: Mutex::new(Some(release_tx)),
+            released: release_rx,
+        };
+        sinks.add(Box::new(sink), SINK_QUEUE_CAPACITY);
+
+        write_chunk(&mut buf, 0);
+        dispatcher.dispatch();
+        entered_rx.recv_timeout(Duration::from_secs(1)).unwrap();
+
+        let (dropped_tx, dropped_rx) = mpsc::channel();
+        thread::spawn(move || {
+            drop((sinks, dispatcher));
+            dropped_tx.send(()).unwrap();
+        });
+
+        assert_eq!(dropped_rx.recv_timeout(Duration::from_secs(1)), Ok(()));
+    }
+}
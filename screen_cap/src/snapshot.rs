@@ -0,0 +1,139 @@
+use thiserror::Error;
+
+/// Encodes captured BGRA frames into still images.
+///
+/// Holds on to its scratch buffers between calls,
+/// so encoding frames one after another (e.g. for MJPEG) doesn't allocate on every frame.
+#[derive(Debug, Default)]
+pub struct SnapshotEncoder {
+    // BGRA -> RGB conversion target, only needed for PNG since the JPEG encoder reads BGRA directly
+    rgb_buf: Vec<u8>,
+    output: Vec<u8>,
+}
+
+impl SnapshotEncoder {
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Encodes the frame as a JPEG image.
+    ///
+    /// `quality` ranges from 1 to 100, values outside of that range get clamped by the encoder.
+    ///
+    /// The returned slice is only valid until the next call to one of the `encode_*` methods.
+    pub fn encode_jpeg(
+        &mut self,
+        frame: &[u8],
+        width: usize,
+        height: usize,
+        quality: u8,
+    ) -> Result<&[u8], SnapshotError> {
+        let (width, height) = check_dimensions(frame, width, height)?;
+
+        self.output.clear();
+
+        let encoder = jpeg_encoder::Encoder::new(&mut self.output, quality);
+        encoder.encode(frame, width, height, jpeg_encoder::ColorType::Bgra)?;
+
+        Ok(&self.output)
+    }
+
+    /// Encodes the frame as a PNG image. The alpha channel is dropped.
+    ///
+    /// The returned slice is only valid until the next call to one of the `encode_*` methods.
+    pub fn encode_png(
+        &mut self,
+        frame: &[u8],
+        width: usize,
+        height: usize,
+        compression: png::Compression,
+    ) -> Result<&[u8], SnapshotError> {
+        let (w, h) = check_dimensions(frame, width, height)?;
+        let pixel_count = width * height;
+
+        self.rgb_buf.clear();
+        self.rgb_buf.reserve(pixel_count * 3);
+        for pixel in frame[..pixel_count * 4].chunks_exact(4) {
+            self.rgb_buf.extend_from_slice(&[pixel[2], pixel[1], pixel[0]]);
+        }
+
+        self.output.clear();
+
+        let mut encoder = png::Encoder::new(&mut self.output, w as u32, h as u32);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.set_compression(compression);
+
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&self.rgb_buf)?;
+        writer.finish()?;
+
+        Ok(&self.output)
+    }
+}
+
+// frames can be longer than width * height * 4 because of the stride on macos,
+// the trailing bytes are ignored
+fn check_dimensions(frame: &[u8], width: usize, height: usize) -> Result<(u16, u16), SnapshotError> {
+    let w = u16::try_from(width).map_err(|_| SnapshotError::TooLarge { width, height })?;
+    let h = u16::try_from(height).map_err(|_| SnapshotError::TooLarge { width, height })?;
+
+    let required = width * height * 4;
+    if frame.len() < required {
+        return Err(SnapshotError::FrameTooShort {
+            length: frame.len(),
+            required,
+        });
+    }
+
+    Ok((w, h))
+}
+
+#[derive(Debug, Error)]
+pub enum SnapshotError {
+    #[error("frame is {length} bytes long, but at least {required} are required")]
+    FrameTooShort { length: usize, required: usize },
+    #[error("{width}x{height} is too large for a still image")]
+    TooLarge { width: usize, height: usize },
+    #[error(transparent)]
+    Jpeg(#[from] jpeg_encoder::EncodingError),
+    #[error(transparent)]
+    Png(#[from] png::EncodingError),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn jpeg_output() {
+        let frame = [255_u8; 4 * 4 * 4];
+        let mut encoder = SnapshotEncoder::new();
+
+        let jpeg = encoder.encode_jpeg(&frame, 4, 4, 80).unwrap();
+
+        assert_eq!(&jpeg[..2], &[0xFF, 0xD8]);
+    }
+
+    #[test]
+    fn png_output() {
+        let frame = [255_u8; 4 * 4 * 4];
+        let mut encoder = SnapshotEncoder::new();
+
+        let png = encoder.encode_png(&frame, 4, 4, png::Compression::Fast).unwrap();
+
+        assert_eq!(&png[..8], &[0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A]);
+    }
+
+    #[test]
+    fn short_frame() {
+        let frame = [0_u8; 10];
+        let mut encoder = SnapshotEncoder::new();
+
+        assert!(matches!(
+            encoder.encode_jpeg(&frame, 4, 4, 80),
+            Err(SnapshotError::FrameTooShort { .. })
+        ));
+    }
+}
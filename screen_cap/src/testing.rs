@@ -0,0 +1,286 @@
+use std::{
+    io,
+    ops::Deref,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    thread,
+    time::Duration,
+};
+
+use parking_lot::Mutex;
+use utils::{
+    contiguous::{BufferItem, FrameId, OverflowPolicy},
+    threading::ThreadScheduling,
+};
+use x264::{Colorspace, Encoder, Preset, Setup, Tune};
+
+use crate::{
+    frame::{FrameError, FrameFormat, FrameSource},
+    record::{
+        encoded_buffer::Metadata, sink::ChunkSink, BufferingSettings, EncoderConfig, EncoderSettings,
+        FlushPolicy,
+    },
+};
+
+// how long an exhausted or held source waits before reporting a skipped frame, so the recorder doesn't spin
+const IDLE_WAIT: Duration = Duration::from_millis(1);
+
+// how many frames a gate lets through once it's started, i.e. all of them
+const UNGATED: usize = usize::MAX;
+
+/// An ultrafast x264 with zero latency, encoding BGRA at 1000 kbit/s with the timestamps in milliseconds.
+///
+/// With zero latency every frame comes out of the encoder right away, so one frame in is one chunk out.
+/// Everything but the factory can be changed with struct update syntax.
+pub fn test_encoder_settings() -> EncoderSettings<fn(EncoderConfig) -> Encoder> {
+    EncoderSettings {
+        encoder_factory: |config| {
+            Setup::preset(Preset::Ultrafast, Tune::None, false, true)
+                .bitrate(config.bitrate)
+                .timebase(1, 1000)
+                .build(config.colorspace, config.width as _, config.height as _)
+                .unwrap()
+        },
+        bitrate: 1000,
+        timebase: 1000.0,
+        colorspace: Colorspace::BGRA,
+        keyframe_interval: None,
+        clock: None,
+        thread_scheduling: ThreadScheduling::default(),
+        stall_filler: None,
+    }
+}
+
+/// A 1 MiB ring buffer that gets every frame right away and rejects chunks instead of evicting old ones,
+/// so nothing disappears from under a test
+pub fn test_buffering_settings() -> BufferingSettings {
+    BufferingSettings {
+        overflow_policy: OverflowPolicy::Reject,
+        flush_policy: FlushPolicy::FrameCount,
+        ..BufferingSettings::from_bytes(1024 * 1024)
+    }
+}
+
+/// `frames` BGRA frames of `width`x`height` pixels, each one different from the one before,
+/// and the same ones on every run, for running a `Recorder` without a display, see `Recorder::with_source`.
+///
+/// Every frame after those is skipped. Along with a `VecSink` the whole encode path runs in memory.
+pub fn synthetic_source(width: usize, height: usize, frames: usize) -> SyntheticSource {
+    SyntheticSource {
+        width,
+        height,
+        stride: width,
+        remaining: frames,
+        produced: 0,
+        gate: None,
+        frame: Vec::new(),
+    }
+}
+
+/// See `synthetic_source`
+#[derive(Debug)]
+pub struct SyntheticSource {
+    width: usize,
+    height: usize,
+    // in pixels
+    stride: usize,
+    remaining: usize,
+    produced: usize,
+    gate: Option<Arc<AtomicUsize>>,
+    frame: Vec<u8>,
+}
+
+impl SyntheticSource {
+    /// Skips the frames until the gate lets them through, see `SourceGate`.
+    ///
+    /// A sink only gets the chunks flushed after it's added, so this is how it gets the first one,
+    /// see `Recorder::add_sink`.
+    pub fn held(self) -> (Self, SourceGate) {
+        let gate = Arc::new(AtomicUsize::new(0));
+
+        let source = Self {
+            gate: Some(gate.clone()),
+            ..self
+        };
+
+        (source, SourceGate(gate))
+    }
+
+    /// Pads every row to `stride` pixels, like scrap does on some platforms.
+    ///
+    /// The padding is black, `size` stays the same.
+    pub fn with_stride(self, stride: usize) -> Self {
+        Self {
+            stride: stride.max(self.width),
+            ..self
+        }
+    }
+
+    // takes a frame from the gate if there is one
+    fn let_through(&self) -> bool {
+        let Some(gate) = &self.gate else {
+            return true;
+        };
+
+        gate.fetch_update(Ordering::AcqRel, Ordering::Acquire, |allowed| match allowed {
+            0 => None,
+            UNGATED => Some(UNGATED),
+            allowed => Some(allowed - 1),
+        })
+        .is_ok()
+    }
+
+    fn fill_frame(&mut self) {
+        let n = self.produced;
+        self.frame.clear();
+
+        for y in 0..self.height {
+            for x in 0..self.width {
+                // a gradient moving a pixel every frame
+                self.frame.extend_from_slice(&[
+                    (x + n) as u8,
+                    (y + n) as u8,
+                    ((x ^ y) + 2 * n) as u8,
+                    u8::MAX,
+                ]);
+            }
+            self.frame.resize(self.frame.len() + (self.stride - self.width) * 4, 0);
+        }
+    }
+}
+
+impl FrameSource for SyntheticSource {
+    fn next_frame(&mut self) -> Result<impl Deref<Target = [u8]> + '_, FrameError> {
+        if self.remaining == 0 || !self.let_through() {
+            thread::sleep(IDLE_WAIT);
+            return Err(FrameError::Skipped);
+        }
+
+        self.fill_frame();
+        self.remaining -= 1;
+        self.produced += 1;
+
+        Ok(&self.frame[..])
+    }
+
+    fn size(&self) -> (usize, usize) {
+        (self.width, self.height)
+    }
+
+    fn format(&self) -> FrameFormat {
+        FrameFormat::Bgra
+    }
+}
+
+/// Lets a held `SyntheticSource` hand out its frames, see `SyntheticSource::held`
+#[derive(Debug, Clone)]
+pub struct SourceGate(Arc<AtomicUsize>);
+
+impl SourceGate {
+    /// Lets every frame through from now on
+    pub fn start(&self) {
+        self.0.store(UNGATED, Ordering::Release);
+    }
+
+    /// Lets `n` more frames through, e.g. to encode one frame at a time
+    pub fn step(&self, n: usize) {
+        let _ = self.0.fetch_update(Ordering::AcqRel, Ordering::Acquire, |allowed| {
+            Some(allowed.saturating_add(n))
+        });
+    }
+}
+
+/// A chunk as a `VecSink` got it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CollectedChunk {
+    pub id: FrameId,
+    pub data: Vec<u8>,
+    pub metadata: Metadata,
+}
+
+/// Keeps every chunk it gets, the clones share them.
+///
+/// Hand a clone to `Recorder::add_sink` and read the chunks through another one.
+/// The sink thread may still be catching up until the recorder is gone, e.g. after `Recorder::finish`.
+#[derive(Debug, Clone, Default)]
+pub struct VecSink {
+    chunks: Arc<Mutex<Vec<CollectedChunk>>>,
+}
+
+impl VecSink {
+    /// The chunks so far, oldest first
+    pub fn chunks(&self) -> Vec<CollectedChunk> {
+        self.chunks.lock().clone()
+    }
+
+    /// The data of the chunks so far, back to back, i.e. the stream without the headers
+    pub fn concatenated(&self) -> Vec<u8> {
+        self.chunks
+            .lock()
+            .iter()
+            .flat_map(|chunk| &chunk.data)
+            .copied()
+            .collect()
+    }
+}
+
+impl ChunkSink for VecSink {
+    fn write_chunk(&mut self, id: FrameId, item: BufferItem<'_, Metadata>) -> io::Result<()> {
+        self.chunks.lock().push(CollectedChunk {
+            id,
+            data: item.data().into_owned(),
+            metadata: *item.metadata(),
+        });
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn synthetic_frames_repeat_across_runs() {
+        let frames = |source: &mut SyntheticSource| {
+            let mut frames = Vec::new();
+            while let Ok(frame) = source.next_frame() {
+                frames.push(frame.to_vec());
+            }
+            frames
+        };
+
+        let first = frames(&mut synthetic_source(4, 2, 3));
+        let second = frames(&mut synthetic_source(4, 2, 3));
+
+        assert_eq!(first.len(), 3);
+        assert_eq!(first, second);
+        assert_eq!(first[0].len(), FrameFormat::Bgra.frame_len(4, 2));
+        assert_ne!(first[0], first[1]);
+    }
+
+    #[test]
+    fn held_source_waits_for_start() {
+        let (mut source, start) = synthetic_source(4, 2, 1).held();
+
+        assert!(matches!(source.next_frame(), Err(FrameError::Skipped)));
+        start.start();
+        assert!(source.next_frame().is_ok());
+        assert!(matches!(source.next_frame(), Err(FrameError::Skipped)));
+    }
+
+    #[test]
+    fn gate_steps_one_frame_at_a_time() {
+        let (mut source, gate) = synthetic_source(4, 2, 3).with_stride(5).held();
+
+        gate.step(2);
+        assert_eq!(source.next_frame().unwrap().len(), 5 * 2 * 4);
+        assert!(source.next_frame().is_ok());
+        assert!(matches!(source.next_frame(), Err(FrameError::Skipped)));
+
+        gate.step(1);
+        assert!(source.next_frame().is_ok());
+    }
+}
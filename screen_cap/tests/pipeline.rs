@@ -0,0 +1,40 @@
+use screen_cap::{
+    record::Recorder,
+    testing::{synthetic_source, test_buffering_settings, test_encoder_settings, VecSink},
+};
+use utils::contiguous::FrameId;
+
+const FRAMES: usize = 10;
+
+fn starts_with_start_code(data: &[u8]) -> bool {
+    data.starts_with(&[0, 0, 0, 1]) || data.starts_with(&[0, 0, 1])
+}
+
+#[test]
+fn synthetic_frames_come_out_as_an_annex_b_stream() {
+    let (source, gate) = synthetic_source(64, 32, FRAMES).held();
+    // zero latency, so every frame comes out as a chunk of its own right away
+    let recorder = Recorder::with_source(source, test_buffering_settings(), test_encoder_settings()).unwrap();
+
+    let sink = VecSink::default();
+    recorder.add_sink(Box::new(sink.clone()));
+    let headers = recorder.headers();
+    gate.start();
+
+    recorder.wait_for_frames_since(FrameId::default(), FRAMES).unwrap();
+    // joins the sink threads, so the sink has everything
+    recorder.finish().unwrap();
+
+    let chunks = sink.chunks();
+    assert_eq!(chunks.len(), FRAMES);
+    assert!(chunks[0].metadata.is_key);
+    assert!(chunks.windows(2).all(|pair| pair[1].id == pair[0].id + 1));
+
+    let mut stream = headers.to_vec();
+    stream.extend(sink.concatenated());
+
+    assert!(starts_with_start_code(&stream));
+    // the headers start with the sequence parameter set
+    let nal_start = stream.iter().position(|&byte| byte == 1).unwrap() + 1;
+    assert_eq!(stream[nal_start] & 0x1f, 7);
+}
@@ -1,9 +1,88 @@
-use std::{
-    collections::VecDeque,
-    io::Write,
+//! Only needs `alloc`, so it builds without the `std` feature, unlike the rest of the crate.
+
+use alloc::{
+    borrow::Cow,
+    boxed::Box,
+    collections::{vec_deque, VecDeque},
+    sync::Arc,
+    vec,
+    vec::Vec,
+};
+use core::{
+    fmt, iter,
+    ops::{Add, AddAssign, Sub},
 };
+#[cfg(feature = "std")]
+use std::io::{self, Write};
+
+/// Id of a data chunk in a `RingBuffer`.
+///
+/// Ids are assigned in write order and stay the same for as long as the chunk is in the buffer,
+/// unlike indices into the buffer or byte offsets, which is why they get their own type.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct FrameId(usize);
+
+impl FrameId {
+    #[inline]
+    pub const fn new(id: usize) -> Self {
+        Self(id)
+    }
+
+    #[inline]
+    pub const fn get(self) -> usize {
+        self.0
+    }
+
+    /// Iterates over all ids from `start` up to, but not including, `end`
+    #[inline]
+    pub fn range(
+        start: FrameId,
+        end: FrameId,
+    ) -> impl DoubleEndedIterator<Item = FrameId> + ExactSizeIterator + Clone {
+        (start.0..end.0).map(FrameId)
+    }
+}
+
+impl Add<usize> for FrameId {
+    type Output = FrameId;
 
-use thiserror::Error;
+    #[inline]
+    fn add(self, rhs: usize) -> Self::Output {
+        FrameId(self.0 + rhs)
+    }
+}
+
+impl AddAssign<usize> for FrameId {
+    #[inline]
+    fn add_assign(&mut self, rhs: usize) {
+        self.0 += rhs;
+    }
+}
+
+impl Sub<usize> for FrameId {
+    type Output = FrameId;
+
+    #[inline]
+    fn sub(self, rhs: usize) -> Self::Output {
+        FrameId(self.0 - rhs)
+    }
+}
+
+/// The number of chunks between two ids
+impl Sub<FrameId> for FrameId {
+    type Output = usize;
+
+    #[inline]
+    fn sub(self, rhs: FrameId) -> Self::Output {
+        self.0 - rhs.0
+    }
+}
+
+impl fmt::Display for FrameId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
 
 /// Used for defining data chunks' boundaries in contiguous buffers as well as its metadata
 #[derive(Debug, Clone, Copy)]
@@ -13,25 +92,149 @@ struct ItemData<M> {
     metadata: M,
 }
 
+/// A chunk borrowed from a buffer.
+///
+/// Chunks in a `RingBuffer` can wrap around the end of the buffer,
+/// so the data may be split in two slices.
 #[derive(Debug, Clone, Copy)]
 pub struct BufferItem<'a, M> {
-    data: &'a [u8],
+    first: &'a [u8],
+    second: &'a [u8],
     metadata: &'a M,
 }
 
 impl<'a, M> BufferItem<'a, M> {
     #[inline]
-    pub fn data(&self) -> &'a [u8] {
-        self.data
+    fn new(buf: &'a [u8], item: &'a ItemData<M>) -> Self {
+        let (first, second) = item_slices(buf, item);
+
+        Self {
+            first,
+            second,
+            metadata: &item.metadata,
+        }
     }
 
+    /// An item for a chunk that's already contiguous, e.g. one copied out of a buffer
     #[inline]
-    pub fn metadata(&self) -> &M {
+    pub fn from_parts(data: &'a [u8], metadata: &'a M) -> Self {
+        Self {
+            first: data,
+            second: &[],
+            metadata,
+        }
+    }
+
+    /// The chunk's data, only copied if the chunk wraps around the end of the buffer
+    #[inline]
+    pub fn data(&self) -> Cow<'a, [u8]> {
+        if self.second.is_empty() {
+            Cow::Borrowed(self.first)
+        } else {
+            Cow::Owned([self.first, self.second].concat())
+        }
+    }
+
+    /// The chunk's data in order, the second slice is empty unless the chunk wraps around
+    #[inline]
+    pub fn as_slices(&self) -> (&'a [u8], &'a [u8]) {
+        (self.first, self.second)
+    }
+
+    /// Writes the data out without copying it into a contiguous buffer first
+    #[cfg(feature = "std")]
+    pub fn copy_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writer.write_all(self.first)?;
+        writer.write_all(self.second)
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.first.len() + self.second.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    #[inline]
+    pub fn metadata(&self) -> &'a M {
         self.metadata
     }
+
+    /// Copies the chunk out, so it can outlive the buffer's lock, e.g. to send it somewhere
+    pub fn to_owned(&self) -> OwnedBufferItem<M>
+    where
+        M: Clone,
+    {
+        OwnedBufferItem {
+            data: self.data().into_owned(),
+            metadata: self.metadata.clone(),
+        }
+    }
+}
+
+/// A chunk copied out of a buffer, see `BufferItem::to_owned`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OwnedBufferItem<M> {
+    pub data: Vec<u8>,
+    pub metadata: M,
+}
+
+impl<M> OwnedBufferItem<M> {
+    /// Borrows it as a `BufferItem` again, e.g. to hand it to code that takes one
+    #[inline]
+    pub fn as_item(&self) -> BufferItem<'_, M> {
+        BufferItem::from_parts(&self.data, &self.metadata)
+    }
+}
+
+// splits the item at the end of the buffer
+fn item_slices<'a, M>(buf: &'a [u8], item: &ItemData<M>) -> (&'a [u8], &'a [u8]) {
+    let end = item.start_index + item.length;
+
+    if end > buf.len() {
+        (&buf[item.start_index..], &buf[..end - buf.len()])
+    } else {
+        (&buf[item.start_index..end], &[])
+    }
+}
+
+/// What a `RingBuffer` does when a new chunk doesn't fit without evicting old ones
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// Evict the oldest chunks to make room
+    #[default]
+    Overwrite,
+    /// Fail the write with `WriteDataError::WouldEvict` and leave the buffer as is
+    Reject,
+}
+
+// calls `callback` whenever `bytes_used` goes above `fraction` of the capacity, see `RingBuffer::set_high_water`
+#[derive(Clone)]
+struct HighWater {
+    fraction: f32,
+    callback: Arc<dyn Fn() + Send + Sync>,
+}
+
+impl HighWater {
+    // follows the capacity when the buffer grows
+    fn threshold(&self, capacity: usize) -> usize {
+        (capacity as f64 * f64::from(self.fraction)) as usize
+    }
+}
+
+impl fmt::Debug for HighWater {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HighWater").field("fraction", &self.fraction).finish_non_exhaustive()
+    }
 }
 
-/// A Ring buffer holding arbitrary sized byte chunks contiguously.
+/// A Ring buffer holding arbitrary sized byte chunks.
+///
+/// Chunks are stored back to back, one that doesn't fit before the end of the buffer
+/// wraps around to its start, so all of the capacity is usable.
 #[derive(Debug, Clone)]
 pub struct RingBuffer<M> {
     buf: Box<[u8]>,
@@ -40,8 +243,14 @@ pub struct RingBuffer<M> {
     write_head_position: usize,
     // used for preserving indices even after overwriting elements 
     // and popping items from the front of the queue
-    id_offset: usize
+    id_offset: usize,
     // max id is just id_offset + items.len()
+    pin: Option<FrameId>,
+    max_pinned: Option<usize>,
+    policy: OverflowPolicy,
+    // sum of the live items' lengths
+    used: usize,
+    high_water: Option<HighWater>,
 }
 
 impl<M> RingBuffer<M> {
@@ -55,47 +264,141 @@ impl<M> RingBuffer<M> {
             items,
             write_head_position: 0,
             id_offset: 0,
+            pin: None,
+            max_pinned: None,
+            policy: OverflowPolicy::default(),
+            used: 0,
+            high_water: None,
         }
     }
 
-    pub fn write(&mut self, data: &[u8], metadata: M) -> Result<(), WriteDataError> {
-        if data.len() > self.buf.len() {
-            return Err(WriteDataError::DataTooLarge);
+    #[inline]
+    pub fn with_policy(cap: usize, policy: OverflowPolicy) -> Self {
+        Self {
+            policy,
+            ..Self::new(cap)
         }
+    }
 
-        // reset the write head if there isn't enough space in front of it
-        let free_space = self.buf.len() - self.write_head_position;
-        if free_space < data.len() {
-            self.write_head_position = 0;
-        }
+    #[inline]
+    pub fn policy(&self) -> OverflowPolicy {
+        self.policy
+    }
 
-        // write the data at head position
-        let start_index = self.write_head_position;
-        let end_index = start_index + data.len();
+    /// Protects the item with id `pin` and everything after it from being overwritten.
+    ///
+    /// Writes that would evict a pinned item grow the buffer instead,
+    /// so the buffer can only shrink back by being recreated.
+    /// `None` removes the pin, see also `set_max_pinned`.
+    #[inline]
+    pub fn set_pin(&mut self, pin: Option<FrameId>) {
+        self.pin = pin;
+    }
 
-        let mut write_slice = &mut self.buf[start_index..end_index];
-        // Safety: cannot fail since we've done the bounds check already
-        write_slice.write_all(data).unwrap();
+    #[inline]
+    pub fn pin(&self) -> Option<FrameId> {
+        self.pin
+    }
 
-        self.write_head_position = end_index;
+    /// Limits how far behind the newest item the pin can hold the buffer.
+    ///
+    /// Once the pin is more than `max` items behind, writes evict past it as if there was no pin,
+    /// so a pin that never moves can't grow the buffer forever. `None` removes the limit.
+    #[inline]
+    pub fn set_max_pinned(&mut self, max: Option<usize>) {
+        self.max_pinned = max;
+    }
 
-        // invalidate any overwritten items
-        for _ in 0..self.items.len() {
-            let other_item = match self.items.front() {
-                Some(item) => item,
-                None => break,
-            };
-            
-            let other_item_end = other_item.start_index + other_item.length;
+    /// Size of the underlying buffer in bytes
+    #[inline]
+    pub fn capacity(&self) -> usize {
+        self.buf.len()
+    }
 
-            if other_item.start_index < end_index && other_item_end > start_index {
-                self.items.pop_front().unwrap();
-                self.id_offset = self.id_offset.checked_add(1).expect("DataRingBuffer ids overflowed");
-            } else {
-                break;
-            }
+    /// Total length of the chunks currently in the buffer
+    #[inline]
+    pub fn bytes_used(&self) -> usize {
+        self.used
+    }
+
+    /// How many bytes can be written before the oldest chunks start getting evicted.
+    ///
+    /// Chunks wrap around the end of the buffer, so this is all of the space the live chunks don't take up,
+    /// not just what's left before the end.
+    #[inline]
+    pub fn free_space(&self) -> usize {
+        self.buf.len() - self.used
+    }
+
+    /// Whether the next write of any size evicts something, or fails with `OverflowPolicy::Reject`
+    #[inline]
+    pub fn is_full(&self) -> bool {
+        self.free_space() == 0
+    }
+
+    /// Calls `callback` whenever a write takes `bytes_used` above `fraction` of the capacity,
+    /// e.g. to lower the bitrate before chunks that haven't been read yet start getting evicted.
+    ///
+    /// It's called once per crossing, it has to drop back to or below the threshold before it gets called again.
+    /// The callback runs synchronously within `write`, on whichever thread is writing
+    /// and while the caller likely holds a lock on the buffer, so it has to be quick
+    /// and mustn't touch the buffer itself.
+    /// Replaces the previous callback, `fraction` is clamped to `0.0..=1.0`.
+    pub fn set_high_water(&mut self, fraction: f32, callback: impl Fn() + Send + Sync + 'static) {
+        self.high_water = Some(HighWater {
+            fraction: fraction.clamp(0.0, 1.0),
+            callback: Arc::new(callback),
+        });
+    }
+
+    /// Removes the callback set by `set_high_water`
+    #[inline]
+    pub fn clear_high_water(&mut self) {
+        self.high_water = None;
+    }
+
+    /// Writes the chunk and returns the id it got assigned
+    pub fn write(&mut self, data: &[u8], metadata: M) -> Result<FrameId, WriteDataError> {
+        self.check_write(data.len())?;
+        let used_before = self.used;
+
+        let evicted = self.evicted_count(data.len());
+        // a pin that's fallen too far behind doesn't protect anything anymore
+        let max_id = self.id_offset + self.items.len();
+        let pin = self.pin.filter(|pin| self.max_pinned.is_none_or(|max| max_id.saturating_sub(pin.0) <= max));
+
+        // evicted items have ids id_offset..id_offset + evicted, only the ones before the pin may go
+        let allowed = match pin {
+            Some(pin) => evicted.min(pin.0.saturating_sub(self.id_offset)),
+            None => evicted,
+        };
+
+        // invalidate the items that are going to be overwritten
+        for _ in 0..allowed {
+            let item = self.items.pop_front().unwrap();
+            self.used -= item.length;
+            self.id_offset = self.id_offset.checked_add(1).expect("DataRingBuffer ids overflowed");
         }
 
+        if allowed < evicted {
+            self.grow(data.len());
+        }
+
+        // write the data at head position, wrapping around the end of the buffer if needed
+        let start_index = self.write_head_position;
+        let (first, second) = data.split_at(data.len().min(self.buf.len() - start_index));
+
+        self.buf[start_index..start_index + first.len()].copy_from_slice(first);
+        self.buf[..second.len()].copy_from_slice(second);
+
+        let end_index = start_index + data.len();
+        self.write_head_position = if end_index >= self.buf.len() {
+            end_index - self.buf.len()
+        } else {
+            end_index
+        };
+        self.used += data.len();
+
         // register the new data chunk in the item deque
         let new_item =  ItemData {
                 start_index,
@@ -105,45 +408,183 @@ impl<M> RingBuffer<M> {
         
         self.items.push_back(new_item);
 
+        if let Some(high_water) = &self.high_water {
+            let threshold = high_water.threshold(self.buf.len());
+            if used_before <= threshold && self.used > threshold {
+                (high_water.callback)();
+            }
+        }
+
+        Ok(self.id_bounds().1 - 1)
+    }
+
+    /// Checks whether writing `length` bytes would fail, without writing anything
+    pub fn check_write(&self, length: usize) -> Result<(), WriteDataError> {
+        if length > self.buf.len() {
+            return Err(WriteDataError::DataTooLarge);
+        }
+
+        if self.evicted_count(length) > 0 && self.policy == OverflowPolicy::Reject {
+            return Err(WriteDataError::WouldEvict);
+        }
+
         Ok(())
     }
+
+    // how many of the oldest items have to go to make room for `length` bytes
+    fn evicted_count(&self, length: usize) -> usize {
+        let mut free_space = self.buf.len() - self.used;
+
+        self.items
+            .iter()
+            .take_while(|item| {
+                let evict = free_space < length;
+                free_space += item.length;
+                evict
+            })
+            .count()
+    }
+
+    /// Grows the buffer to at least `cap` bytes, keeping every item,
+    /// e.g. so that a chunk that's larger than the whole buffer can be written after all.
+    ///
+    /// Does nothing if the buffer is that large already, it never shrinks.
+    pub fn ensure_capacity(&mut self, cap: usize) {
+        if cap > self.buf.len() {
+            self.reallocate(cap);
+        }
+    }
+
+    // reallocates the buffer with all items packed at the start
+    // and at least `min_free` bytes of free space after them
+    fn grow(&mut self, min_free: usize) {
+        let new_cap = (self.buf.len() * 2).max(self.used + min_free);
+        self.reallocate(new_cap);
+    }
+
+    // `new_cap` has to fit all the items
+    fn reallocate(&mut self, new_cap: usize) {
+        let mut new_buf = vec![0; new_cap].into_boxed_slice();
+
+        let mut position = 0;
+        for item in &mut self.items {
+            let (first, second) = item_slices(&self.buf, item);
+            
+            new_buf[position..position + first.len()].copy_from_slice(first);
+            new_buf[position + first.len()..position + item.length].copy_from_slice(second);
+            
+            item.start_index = position;
+            position += item.length;
+        }
+
+        self.buf = new_buf;
+        self.write_head_position = position;
+    }
     
-    pub fn get(&self, id: usize) -> Option<BufferItem<M>> {
-        let end = self.id_offset + self.items.len();
+    pub fn get(&self, id: FrameId) -> Option<BufferItem<M>> {
+        let (min, max) = self.id_bounds();
         // bounds check
-        if id < self.id_offset || id >= end {
-            // dbg!(self.index_offset);
-            // dbg!(end);
+        if id < min || id >= max {
             return None;
         }
         
-        let index = id - self.id_offset;
-        let item_data = &self.items[index];
+        let index = id - min;
         
-        let slice_start = item_data.start_index;
-        let slice_end = slice_start + item_data.length;
+        Some(BufferItem::new(&self.buf, &self.items[index]))
+    }
+    
+    /// Same as `get`, but copies the chunk out, see `BufferItem::to_owned`
+    pub fn get_owned(&self, id: FrameId) -> Option<OwnedBufferItem<M>>
+    where
+        M: Clone,
+    {
+        self.get(id).map(|item| item.to_owned())
+    }
+    
+    /// Id of the oldest item the predicate returns `true` for
+    pub fn find_id<P>(&self, mut predicate: P) -> Option<FrameId>
+    where
+        P: FnMut(&BufferItem<'_, M>) -> bool,
+    {
+        let index = self
+            .items
+            .iter()
+            .position(|item| predicate(&BufferItem::new(&self.buf, item)))?;
         
-        let item = BufferItem {
-            data: &self.buf[slice_start..slice_end],
-            metadata: &item_data.metadata,
-        };
+        Some(FrameId(self.id_offset + index))
+    }
+    
+    /// Id of the newest item the predicate returns `true` for
+    pub fn rfind_id<P>(&self, mut predicate: P) -> Option<FrameId>
+    where
+        P: FnMut(&BufferItem<'_, M>) -> bool,
+    {
+        let index = self
+            .items
+            .iter()
+            .rposition(|item| predicate(&BufferItem::new(&self.buf, item)))?;
         
-        Some(item)
+        Some(FrameId(self.id_offset + index))
+    }
+    
+    /// Like `id_bounds`, except the range starts at the oldest item `is_start` returns `true` for,
+    /// e.g. the oldest keyframe, since nothing before it can be decoded.
+    ///
+    /// `None` if there's no such item.
+    pub fn decodable_bounds<P>(&self, is_start: P) -> Option<(FrameId, FrameId)>
+    where
+        P: Fn(&M) -> bool,
+    {
+        let start = self.find_id(|item| is_start(item.metadata()))?;
+        
+        Some((start, self.id_bounds().1))
     }
     
     #[inline]
-    pub fn id_bounds(&self) -> (usize, usize) {
-        let min = self.id_offset;
-        let max = self.id_offset + self.items.len();
+    pub fn id_bounds(&self) -> (FrameId, FrameId) {
+        let min = FrameId(self.id_offset);
+        let max = min + self.items.len();
         
         (min, max)
     }
     
     #[inline]
-    pub fn iter(&self) -> impl Iterator<Item = BufferItem<M>>{
-        Iter {
+    pub fn iter(&self) -> RingBufferIter<'_, M> {
+        RingBufferIter(Iter {
             buf: &self.buf,
             items: self.items.iter(),
+        })
+    }
+    
+    /// Like `iter`, but pairs every item with its id
+    #[inline]
+    pub fn iter_ids(&self) -> impl Iterator<Item = (FrameId, BufferItem<'_, M>)> {
+        let (min, _) = self.id_bounds();
+        
+        self.iter()
+            .enumerate()
+            .map(move |(index, item)| (min + index, item))
+    }
+    
+    /// The id and metadata of every item, oldest first, without touching the data
+    pub fn metadata_index(&self) -> Vec<(FrameId, &M)> {
+        self.iter_ids()
+            .map(|(id, item)| (id, item.metadata()))
+            .collect()
+    }
+    
+    /// Iterates over the items with ids from `start_id` up to, but not including, `end_id`.
+    ///
+    /// The range gets clamped to the ids currently in the buffer,
+    /// so evicted ids are skipped and the iterator is empty if the whole range is gone.
+    pub fn range(&self, start_id: FrameId, end_id: FrameId) -> impl Iterator<Item = BufferItem<'_, M>> {
+        let (min, max) = self.id_bounds();
+        let start = start_id.clamp(min, max) - min;
+        let end = end_id.clamp(min, max) - min;
+        
+        Iter {
+            buf: &self.buf,
+            items: self.items.range(start..end.max(start)),
         }
     }
     
@@ -158,12 +599,25 @@ impl<M> RingBuffer<M> {
     }
 }
 
-#[derive(Debug, Clone, Copy, Error)]
+#[derive(Debug, Clone, Copy)]
 pub enum WriteDataError {
-    #[error("data too large")]
     DataTooLarge,
+    WouldEvict,
+}
+
+// by hand rather than with thiserror, which needs `std`
+impl fmt::Display for WriteDataError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WriteDataError::DataTooLarge => f.write_str("data too large"),
+            WriteDataError::WouldEvict => f.write_str("writing the data would evict live items"),
+        }
+    }
 }
 
+#[cfg(feature = "std")]
+impl std::error::Error for WriteDataError {}
+
 #[derive(Debug, Clone, Default)]
 pub struct GrowableBuffer<M> {
     buf: Vec<u8>,
@@ -178,6 +632,20 @@ impl<M> GrowableBuffer<M> {
         }
     }
     
+    /// Room for `items` items with `bytes` bytes of data between them before anything gets reallocated
+    pub fn with_capacity(bytes: usize, items: usize) -> Self {
+        Self {
+            buf: Vec::with_capacity(bytes),
+            items: Vec::with_capacity(items),
+        }
+    }
+    
+    /// Makes room for at least `bytes` more bytes in `items` more items, see `Vec::reserve`
+    pub fn reserve(&mut self, bytes: usize, items: usize) {
+        self.buf.reserve(bytes);
+        self.items.reserve(items);
+    }
+    
     pub fn write(&mut self, data: &[u8], metadata: M) {
         let start_index = self.buf.len();
         let length = data.len();
@@ -192,28 +660,59 @@ impl<M> GrowableBuffer<M> {
         self.items.push(item);
     }
     
-    pub fn dump_into_ring_buffer(&mut self, ring_buf: &mut RingBuffer<M>) -> Result<(), WriteDataError> {
-        for item in self.items.drain(..) {
+    /// Moves all the items into the ring buffer.
+    ///
+    /// Returns the range of ids the items got assigned, the end being exclusive like in `id_bounds`.
+    /// If an item can't be written, it and all the items after it stay in this buffer,
+    /// so the dump can be retried later.
+    ///
+    /// The buffer keeps its allocations, so writing the next batch doesn't reallocate.
+    pub fn dump_into_ring_buffer(&mut self, ring_buf: &mut RingBuffer<M>) -> Result<(FrameId, FrameId), WriteDataError> {
+        let (_, start_id) = ring_buf.id_bounds();
+        let mut items = self.items.drain(..);
+        
+        while let Some(item) = items.next() {
             let end_index = item.start_index + item.length;
             let data = &self.buf[item.start_index..end_index];
             
+            // checking first so the metadata isn't lost if the write fails
+            if let Err(e) = ring_buf.check_write(data.len()) {
+                let remaining: Vec<_> = iter::once(item).chain(items).collect();
+                self.items.extend(remaining);
+                return Err(e);
+            }
+            
             ring_buf.write(data, item.metadata)?;
         }
         
         self.buf.clear();
         
-        Ok(())
+        Ok((start_id, ring_buf.id_bounds().1))
+    }
+    
+    /// Removes every item, keeping the allocations like `dump_into_ring_buffer` does
+    pub fn clear(&mut self) {
+        self.buf.clear();
+        self.items.clear();
+    }
+    
+    /// Moves the items out as owned data along with their metadata, oldest first,
+    /// e.g. to write them somewhere other than a ring buffer.
+    ///
+    /// The buffer is empty afterwards, even if the iterator is dropped before it's done.
+    /// Like with `dump_into_ring_buffer`, it keeps its allocations.
+    pub fn drain(&mut self) -> Drain<'_, M> {
+        Drain {
+            buf: &mut self.buf,
+            items: self.items.drain(..),
+        }
     }
     
     #[inline]
     pub fn get(&self, index: usize) -> Option<BufferItem<M>> {
         let item = self.items.get(index)?;
-        let end = item.start_index + item.length;
         
-        Some(BufferItem {
-            data: &self.buf[item.start_index..end],
-            metadata: &item.metadata,
-        })
+        Some(BufferItem::new(&self.buf, item))
     }
     
     #[inline]
@@ -253,21 +752,75 @@ where
 
     fn next(&mut self) -> Option<Self::Item> {
         let next_item = self.items.next()?;
-        let end = next_item.start_index + next_item.length;
         
-        let data = &self.buf[next_item.start_index..end];
+        Some(BufferItem::new(self.buf, next_item))
+    }
+    
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.items.size_hint()
+    }
+}
+
+/// Iterator moving the items out of a `GrowableBuffer`, see `GrowableBuffer::drain`
+pub struct Drain<'a, M> {
+    buf: &'a mut Vec<u8>,
+    items: vec::Drain<'a, ItemData<M>>,
+}
+
+impl<M> Iterator for Drain<'_, M> {
+    type Item = (Vec<u8>, M);
+    
+    fn next(&mut self) -> Option<Self::Item> {
+        let item = self.items.next()?;
+        let data = self.buf[item.start_index..item.start_index + item.length].to_vec();
         
-        Some(BufferItem {
-            data,
-            metadata: &next_item.metadata,
-        })
+        Some((data, item.metadata))
     }
     
+    #[inline]
     fn size_hint(&self) -> (usize, Option<usize>) {
         self.items.size_hint()
     }
 }
 
+impl<M> ExactSizeIterator for Drain<'_, M> {}
+
+impl<M> Drop for Drain<'_, M> {
+    fn drop(&mut self) {
+        // the items that weren't taken get dropped along with `items`
+        self.buf.clear();
+    }
+}
+
+/// Iterator over the items of a `RingBuffer`, from the oldest to the newest
+pub struct RingBufferIter<'a, M>(Iter<'a, M, vec_deque::Iter<'a, ItemData<M>>>);
+
+impl<'a, M> Iterator for RingBufferIter<'a, M> {
+    type Item = BufferItem<'a, M>;
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next()
+    }
+    
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.0.size_hint()
+    }
+}
+
+impl<M> ExactSizeIterator for RingBufferIter<'_, M> {}
+
+impl<'a, M> IntoIterator for &'a RingBuffer<M> {
+    type Item = BufferItem<'a, M>;
+    type IntoIter = RingBufferIter<'a, M>;
+
+    #[inline]
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -279,7 +832,24 @@ mod tests {
         let mut rb = RingBuffer::new(10);
         rb.write(chunk, ()).unwrap();
         
-        assert_eq!(rb.get(0).unwrap().data, chunk);
+        assert_eq!(rb.get(FrameId::new(0)).unwrap().data(), chunk);
+    }
+    
+    #[test]
+    fn owned_item_outlives_eviction() {
+        let mut rb = RingBuffer::new(10);
+        rb.write(&[1, 2, 3], 'a').unwrap();
+        rb.write(&[4, 5, 6], 'b').unwrap();
+        
+        let owned = rb.get_owned(FrameId::new(0)).unwrap();
+        
+        // pushes the first chunk out and writes over its bytes
+        rb.write(&[7, 8, 9, 10, 11, 12], 'c').unwrap();
+        assert!(rb.get(FrameId::new(0)).is_none());
+        
+        assert_eq!(owned, OwnedBufferItem { data: vec![1, 2, 3], metadata: 'a' });
+        assert_eq!(owned.as_item().data(), [1, 2, 3].as_slice());
+        assert_eq!(rb.get_owned(FrameId::new(3)), None);
     }
     
     #[test]
@@ -291,8 +861,8 @@ mod tests {
         rb.write(chunk1, ()).unwrap();
         rb.write(chunk2, ()).unwrap();
         
-        assert_eq!(rb.get(0).unwrap().data, chunk1);
-        assert_eq!(rb.get(1).unwrap().data, chunk2);
+        assert_eq!(rb.get(FrameId::new(0)).unwrap().data(), chunk1);
+        assert_eq!(rb.get(FrameId::new(1)).unwrap().data(), chunk2);
     }
     
     #[test]
@@ -305,9 +875,52 @@ mod tests {
         rb.write(chunk2, ()).unwrap();
         rb.write(chunk1, ()).unwrap();
         
-        assert!(rb.get(0).is_none());
-        assert_eq!(rb.get(1).unwrap().data, chunk2);
-        assert_eq!(rb.get(2).unwrap().data, chunk1);
+        assert!(rb.get(FrameId::new(0)).is_none());
+        assert_eq!(rb.get(FrameId::new(1)).unwrap().data(), chunk2);
+        assert_eq!(rb.get(FrameId::new(2)).unwrap().data(), chunk1);
+    }
+    
+    #[test]
+    fn ring_buffer_pin_grows() {
+        let chunk1: &[u8] = &[1, 2, 3];
+        let chunk2: &[u8] = &[4, 5, 6, 7, 8, 9, 10];
+        
+        let mut rb = RingBuffer::new(10);
+        rb.write(chunk1, ()).unwrap();
+        rb.write(chunk2, ()).unwrap();
+        rb.set_pin(Some(FrameId::new(0)));
+        rb.write(chunk1, ()).unwrap();
+        
+        assert!(rb.capacity() > 10);
+        assert_eq!(rb.get(FrameId::new(0)).unwrap().data(), chunk1);
+        assert_eq!(rb.get(FrameId::new(1)).unwrap().data(), chunk2);
+        assert_eq!(rb.get(FrameId::new(2)).unwrap().data(), chunk1);
+        
+        // items before the pin get overwritten as usual
+        rb.set_pin(Some(FrameId::new(1)));
+        let capacity = rb.capacity();
+        while rb.get(FrameId::new(0)).is_some() {
+            rb.write(chunk1, ()).unwrap();
+        }
+        
+        assert_eq!(rb.capacity(), capacity);
+        assert_eq!(rb.get(FrameId::new(1)).unwrap().data(), chunk2);
+    }
+    
+    #[test]
+    fn ring_buffer_pin_too_far_behind_is_ignored() {
+        let mut rb = RingBuffer::new(10);
+        rb.set_pin(Some(FrameId::new(0)));
+        rb.set_max_pinned(Some(4));
+        
+        for i in 0..100 {
+            rb.write(&[i; 3], ()).unwrap();
+        }
+        
+        // grows while the pin is close enough, then wraps around like there's no pin
+        assert!(rb.capacity() < 100);
+        assert!(rb.get(FrameId::new(0)).is_none());
+        assert_eq!(&*rb.get(FrameId::new(99)).unwrap().data(), &[99; 3]);
     }
     
     #[test]
@@ -320,9 +933,9 @@ mod tests {
         rb.write(chunk2, ()).unwrap();
         rb.write(chunk2, ()).unwrap();
         
-        assert!(rb.get(0).is_none());
-        assert!(rb.get(1).is_none());
-        assert_eq!(rb.get(2).unwrap().data, chunk2);
+        assert!(rb.get(FrameId::new(0)).is_none());
+        assert!(rb.get(FrameId::new(1)).is_none());
+        assert_eq!(rb.get(FrameId::new(2)).unwrap().data(), chunk2);
     }
     
     #[test]
@@ -352,7 +965,7 @@ mod tests {
         rb.write(chunk2, ()).unwrap();
         
         let bounds = rb.id_bounds();
-        assert_eq!(bounds, (0, 2));
+        assert_eq!(bounds, (FrameId::new(0), FrameId::new(2)));
     }
     
     #[test]
@@ -380,6 +993,352 @@ mod tests {
         assert!(rb.get(bounds.1).is_none());
     }
     
+    #[test]
+    fn ring_buffer_range_partially_evicted() {
+        let mut rb = RingBuffer::new(10);
+        
+        for i in 0..5_u8 {
+            rb.write(&[i; 3], ()).unwrap();
+        }
+        
+        assert_eq!(rb.id_bounds(), (FrameId::new(2), FrameId::new(5)));
+        
+        let data: Vec<_> = rb.range(FrameId::new(0), FrameId::new(4)).map(|item| item.data()[0]).collect();
+        assert_eq!(data, [2, 3]);
+        
+        let data: Vec<_> = rb.range(FrameId::new(3), FrameId::new(100)).map(|item| item.data()[0]).collect();
+        assert_eq!(data, [3, 4]);
+    }
+    
+    #[test]
+    fn ring_buffer_range_fully_evicted() {
+        let mut rb = RingBuffer::new(10);
+        
+        for i in 0..5_u8 {
+            rb.write(&[i; 3], ()).unwrap();
+        }
+        
+        assert_eq!(rb.range(FrameId::new(0), FrameId::new(2)).count(), 0);
+        assert_eq!(rb.range(FrameId::new(4), FrameId::new(1)).count(), 0);
+    }
+    
+    #[test]
+    fn reject_exact_fit() {
+        let mut rb = RingBuffer::with_policy(10, OverflowPolicy::Reject);
+        rb.write(&[1; 4], ()).unwrap();
+        rb.write(&[2; 6], ()).unwrap();
+        
+        assert_eq!(rb.len(), 2);
+        assert!(matches!(rb.write(&[3; 1], ()), Err(WriteDataError::WouldEvict)));
+    }
+    
+    #[test]
+    fn reject_one_byte_over() {
+        let mut rb = RingBuffer::with_policy(10, OverflowPolicy::Reject);
+        rb.write(&[1; 4], ()).unwrap();
+        
+        assert!(matches!(rb.write(&[2; 7], ()), Err(WriteDataError::WouldEvict)));
+        
+        // the buffer is left untouched
+        assert_eq!(rb.id_bounds(), (FrameId::new(0), FrameId::new(1)));
+        assert_eq!(&*rb.get(FrameId::new(0)).unwrap().data(), &[1; 4]);
+        rb.write(&[2; 6], ()).unwrap();
+    }
+    
+    #[test]
+    fn rejected_dump_keeps_items() {
+        let mut rb = RingBuffer::with_policy(10, OverflowPolicy::Reject);
+        let mut gb = GrowableBuffer::new();
+        
+        for i in 0..4_u8 {
+            gb.write(&[i; 3], ());
+        }
+        
+        assert!(gb.dump_into_ring_buffer(&mut rb).is_err());
+        assert_eq!(rb.len(), 3);
+        assert_eq!(gb.len(), 1);
+        assert_eq!(&*gb.get(0).unwrap().data(), &[3; 3]);
+    }
+    
+    #[test]
+    fn drain_empties_the_buffer() {
+        let mut gb = GrowableBuffer::with_capacity(64, 8);
+        
+        for i in 0..4_u8 {
+            gb.write(&vec![i; i as usize + 1], i * 10);
+        }
+        let allocation = gb.buf.as_ptr();
+        
+        let drained: Vec<_> = gb.drain().collect();
+        let expected: Vec<_> = (0..4_u8).map(|i| (vec![i; i as usize + 1], i * 10)).collect();
+        
+        assert_eq!(drained, expected);
+        assert!(gb.is_empty());
+        assert!(gb.buf.is_empty());
+        
+        // stopping halfway still empties it
+        gb.write(&[5; 3], 50);
+        gb.write(&[6; 3], 60);
+        assert_eq!(gb.drain().next(), Some((vec![5; 3], 50)));
+        assert!(gb.is_empty());
+        
+        // and it's ready to be written again without reallocating
+        gb.write(&[7; 2], 70);
+        assert_eq!(gb.buf.as_ptr(), allocation);
+        assert_eq!(&*gb.get(0).unwrap().data(), &[7; 2]);
+    }
+    
+    #[test]
+    fn write_returns_id() {
+        let mut rb = RingBuffer::new(10);
+        
+        for i in 0..5 {
+            let id = rb.write(&[i; 3], ()).unwrap();
+            
+            assert_eq!(id, FrameId::new(i as usize));
+            assert_eq!(&*rb.get(id).unwrap().data(), &[i; 3]);
+        }
+    }
+    
+    #[test]
+    fn dump_returns_ids() {
+        let mut rb = RingBuffer::new(10);
+        rb.write(&[0; 3], ()).unwrap();
+        
+        let mut gb = GrowableBuffer::new();
+        gb.write(&[1; 3], ());
+        gb.write(&[2; 3], ());
+        
+        let (start, end) = gb.dump_into_ring_buffer(&mut rb).unwrap();
+        
+        assert_eq!((start, end), (FrameId::new(1), FrameId::new(3)));
+        assert_eq!(&*rb.get(start).unwrap().data(), &[1; 3]);
+        assert_eq!(gb.dump_into_ring_buffer(&mut rb).unwrap(), (end, end));
+    }
+    
+    #[test]
+    fn ensure_capacity_fits_oversized_chunk() {
+        let mut rb = RingBuffer::new(10);
+        // wraps around, so the items have to be moved into place
+        for fill in 0..4 {
+            rb.write(&[fill; 3], ()).unwrap();
+        }
+        assert!(matches!(rb.write(&[9; 12], ()), Err(WriteDataError::DataTooLarge)));
+        
+        rb.ensure_capacity(12);
+        assert_eq!(rb.capacity(), 12);
+        let data: Vec<_> = rb.iter().map(|item| item.data().into_owned()).collect();
+        assert_eq!(data, [[1; 3], [2; 3], [3; 3]]);
+        
+        // evicts everything, but it does fit now
+        let id = rb.write(&[9; 12], ()).unwrap();
+        assert_eq!(rb.id_bounds(), (id, id + 1));
+        assert_eq!(&*rb.get(id).unwrap().data(), &[9; 12]);
+        
+        // never shrinks
+        rb.ensure_capacity(4);
+        assert_eq!(rb.capacity(), 12);
+    }
+    
+    #[test]
+    fn free_space_across_wraps() {
+        let mut rb = RingBuffer::new(10);
+        assert_eq!(rb.free_space(), 10);
+        
+        rb.write(&[0; 4], ()).unwrap();
+        rb.write(&[1; 4], ()).unwrap();
+        assert_eq!(rb.free_space(), 2);
+        
+        // evicts the first chunk and wraps around the end
+        rb.write(&[2; 4], ()).unwrap();
+        assert_eq!(rb.free_space(), 2);
+        assert!(!rb.is_full());
+        
+        // what's left before the end doesn't matter
+        rb.write(&[3; 2], ()).unwrap();
+        assert_eq!(rb.free_space(), 0);
+        assert!(rb.is_full());
+        
+        rb.write(&[4; 6], ()).unwrap();
+        assert_eq!(rb.bytes_used(), 8);
+        assert_eq!(rb.free_space(), 2);
+    }
+    
+    #[test]
+    fn high_water_fires_once_per_crossing() {
+        use core::sync::atomic::{AtomicUsize, Ordering};
+        
+        let crossings = Arc::new(AtomicUsize::new(0));
+        let counter = crossings.clone();
+        
+        let mut rb = RingBuffer::new(10);
+        rb.set_high_water(0.5, move || {
+            counter.fetch_add(1, Ordering::Relaxed);
+        });
+        let crossings = || crossings.load(Ordering::Relaxed);
+        
+        // exactly at the threshold doesn't count
+        rb.write(&[0; 5], ()).unwrap();
+        assert_eq!(crossings(), 0);
+        
+        rb.write(&[1; 2], ()).unwrap();
+        assert_eq!(crossings(), 1);
+        // staying above it, even with evictions in between
+        rb.write(&[2; 2], ()).unwrap();
+        rb.write(&[3; 3], ()).unwrap();
+        rb.write(&[4; 10], ()).unwrap();
+        assert_eq!((rb.bytes_used(), crossings()), (10, 1));
+        
+        // evicts everything and ends up below, then goes back up
+        rb.write(&[5; 1], ()).unwrap();
+        assert_eq!((rb.bytes_used(), crossings()), (1, 1));
+        rb.write(&[6; 5], ()).unwrap();
+        assert_eq!(crossings(), 2);
+        
+        rb.clear_high_water();
+        rb.write(&[8; 9], ()).unwrap();
+        rb.write(&[9; 1], ()).unwrap();
+        rb.write(&[10; 9], ()).unwrap();
+        assert_eq!(crossings(), 2);
+    }
+    
+    #[test]
+    fn ring_buffer_uses_full_capacity() {
+        let mut rb = RingBuffer::new(10);
+        
+        // 9 bytes in total
+        for len in [4, 3, 2] {
+            rb.write(&vec![1; len], ()).unwrap();
+        }
+        
+        assert_eq!(rb.id_bounds(), (FrameId::new(0), FrameId::new(3)));
+        
+        // the old write head reset would've evicted the first item here
+        rb.write(&[2; 1], ()).unwrap();
+        assert_eq!(rb.id_bounds(), (FrameId::new(0), FrameId::new(4)));
+    }
+    
+    #[test]
+    fn ring_buffer_wrapped_item() {
+        let mut rb = RingBuffer::new(10);
+        rb.write(&[1; 6], ()).unwrap();
+        rb.write(&[2; 2], ()).unwrap();
+        
+        // 2 bytes at the end and 3 at the start
+        let id = rb.write(&[3, 4, 5, 6, 7], ()).unwrap();
+        
+        assert_eq!(rb.id_bounds(), (FrameId::new(1), FrameId::new(3)));
+        
+        let item = rb.get(id).unwrap();
+        assert_eq!(item.as_slices(), (&[3, 4][..], &[5, 6, 7][..]));
+        assert_eq!(&*item.data(), &[3, 4, 5, 6, 7]);
+        
+        #[cfg(feature = "std")]
+        {
+            let mut copied = Vec::new();
+            item.copy_to(&mut copied).unwrap();
+            assert_eq!(copied, [3, 4, 5, 6, 7]);
+        }
+    }
+    
+    #[test]
+    fn bytes_used_after_overwrite() {
+        let mut rb = RingBuffer::new(10);
+        rb.write(&[1; 4], ()).unwrap();
+        rb.write(&[2; 5], ()).unwrap();
+        
+        assert_eq!(rb.bytes_used(), 9);
+        assert_eq!(rb.capacity(), 10);
+        
+        // evicts the first item
+        rb.write(&[3; 2], ()).unwrap();
+        
+        assert_eq!(rb.bytes_used(), 7);
+    }
+    
+    #[test]
+    fn find_id_full_buffer() {
+        let mut rb = RingBuffer::new(10);
+        
+        for len in [1, 4, 2, 3] {
+            rb.write(&vec![0; len], len).unwrap();
+        }
+        
+        assert_eq!(rb.bytes_used(), rb.capacity());
+        
+        assert_eq!(rb.find_id(|item| item.len() > 1), Some(FrameId::new(1)));
+        assert_eq!(rb.rfind_id(|item| item.len() > 1), Some(FrameId::new(3)));
+        assert_eq!(rb.find_id(|item| *item.metadata() == 2), Some(FrameId::new(2)));
+        assert_eq!(rb.find_id(|item| item.len() > 4), None);
+    }
+    
+    #[test]
+    fn find_id_evicted_front() {
+        let mut rb = RingBuffer::new(10);
+        
+        for i in 0..6 {
+            rb.write(&[0; 3], i).unwrap();
+        }
+        
+        assert_eq!(rb.id_bounds(), (FrameId::new(3), FrameId::new(6)));
+        
+        let first = rb.find_id(|_| true).unwrap();
+        let last = rb.rfind_id(|_| true).unwrap();
+        
+        assert_eq!((first, last), (FrameId::new(3), FrameId::new(5)));
+        assert_eq!(*rb.get(first).unwrap().metadata(), 3);
+        assert_eq!(*rb.get(last).unwrap().metadata(), 5);
+        
+        // item 1 has been evicted
+        assert_eq!(rb.find_id(|item| *item.metadata() == 1), None);
+    }
+    
+    #[test]
+    fn decodable_bounds_start_at_oldest_marker() {
+        let mut rb = RingBuffer::new(10);
+        let is_key = |key: &bool| *key;
+        
+        assert_eq!(rb.decodable_bounds(is_key), None);
+        
+        for key in [false, true, false, true] {
+            rb.write(&[0; 2], key).unwrap();
+        }
+        assert_eq!(rb.decodable_bounds(is_key), Some((FrameId::new(1), FrameId::new(4))));
+        
+        // evicts the first two, the other keyframe is the oldest one now
+        rb.write(&[0; 6], false).unwrap();
+        assert_eq!(rb.id_bounds(), (FrameId::new(2), FrameId::new(5)));
+        assert_eq!(rb.decodable_bounds(is_key), Some((FrameId::new(3), FrameId::new(5))));
+        
+        // nothing left to start from
+        rb.write(&[0; 8], false).unwrap();
+        assert_eq!(rb.id_bounds(), (FrameId::new(5), FrameId::new(6)));
+        assert_eq!(rb.decodable_bounds(is_key), None);
+        
+        // the newest chunk alone
+        rb.write(&[0; 1], true).unwrap();
+        assert_eq!(rb.decodable_bounds(is_key), Some((FrameId::new(6), FrameId::new(7))));
+    }
+    
+    #[test]
+    fn iter_ids_after_overwrites() {
+        let mut rb = RingBuffer::new(10);
+        
+        for i in 0..7_u8 {
+            rb.write(&[i; 3], ()).unwrap();
+        }
+        
+        let ids: Vec<_> = rb.iter_ids().map(|(id, _)| id).collect();
+        assert_eq!(ids, FrameId::range(FrameId::new(4), FrameId::new(7)).collect::<Vec<_>>());
+        
+        for (id, item) in rb.iter_ids() {
+            assert_eq!(rb.get(id).unwrap().data(), item.data());
+            assert_eq!(item.data()[0] as usize, id.get());
+        }
+        
+        assert_eq!((&rb).into_iter().len(), rb.len());
+    }
+    
     #[test]
     fn growable_dump() {
         let chunk: &[u8] = &[1, 2, 3];
@@ -396,8 +1355,8 @@ mod tests {
         
         let (min, max) = rb.id_bounds();
         
-        (min..max).for_each(|id| {
-            assert_eq!(rb.get(id).unwrap().data(), &[1, 2, 3]);
+        FrameId::range(min, max).for_each(|id| {
+            assert_eq!(&*rb.get(id).unwrap().data(), &[1, 2, 3]);
         });
     }
     
@@ -416,7 +1375,7 @@ mod tests {
         gb.dump_into_ring_buffer(&mut rb).unwrap();
         
         for i in rb.iter() {
-            assert_eq!(i.data(), &[1, 2, 3]);
+            assert_eq!(&*i.data(), &[1, 2, 3]);
         }
     }
     
@@ -439,4 +1398,49 @@ mod tests {
             assert_eq!(i.data(), chunk);
         }
     }
-}
\ No newline at end of file
+    
+    #[test]
+    fn metadata_index_matches_get() {
+        // room for 3 chunks, so the first two get evicted
+        let mut rb = RingBuffer::new(9);
+        
+        for i in 0..5u8 {
+            rb.write(&[i; 3], i).unwrap();
+        }
+        
+        let index = rb.metadata_index();
+        assert_eq!(index.len(), 3);
+        
+        for (id, metadata) in index {
+            let item = rb.get(id).unwrap();
+            assert_eq!(item.metadata(), metadata);
+            assert_eq!(&*item.data(), &[*metadata; 3]);
+        }
+        assert_eq!(rb.metadata_index()[0].0, FrameId::new(2));
+    }
+    
+    #[test]
+    fn growable_buffer_with_capacity() {
+        let chunk: &[u8] = &[1, 2, 3, 4];
+        
+        let mut gb = GrowableBuffer::with_capacity(chunk.len() * 8, 8);
+        let capacity = (gb.buf.capacity(), gb.items.capacity());
+        
+        for _ in 0..8 {
+            gb.write(chunk, ());
+        }
+        assert_eq!((gb.buf.capacity(), gb.items.capacity()), capacity);
+        
+        // the allocations survive the dump
+        let mut rb = RingBuffer::new(1024);
+        gb.dump_into_ring_buffer(&mut rb).unwrap();
+        assert!(gb.is_empty());
+        assert_eq!((gb.buf.capacity(), gb.items.capacity()), capacity);
+        
+        // and clearing
+        gb.write(chunk, ());
+        gb.clear();
+        assert!(gb.is_empty());
+        assert_eq!((gb.buf.capacity(), gb.items.capacity()), capacity);
+    }
+}
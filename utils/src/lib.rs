@@ -1,3 +1,13 @@
+//! Everything but `contiguous` needs the `std` feature, which is on by default.
+//!
+//! `cargo test --no-default-features` builds `contiguous` and runs its tests as part of a `no_std` crate.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+#[cfg(feature = "std")]
 pub mod multibuffer;
+#[cfg(feature = "std")]
 pub mod threading;
-pub mod contiguous;
\ No newline at end of file
+pub mod contiguous;
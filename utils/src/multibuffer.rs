@@ -1,10 +1,139 @@
+#[cfg(debug_assertions)]
+use std::cell::RefCell;
 use std::{
+    hint,
+    marker::PhantomData,
     mem,
     ops::{Deref, DerefMut},
-    sync::Arc,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
 };
 
-use parking_lot::RwLock;
+use parking_lot::{
+    lock_api::ArcRwLockWriteGuard, Condvar, Mutex, RawRwLock, RwLock, RwLockReadGuard,
+};
+
+/// Counts swaps so readers can wait for new data instead of polling.
+///
+/// The count is a `u64` that only ever goes up,
+/// at a swap per nanosecond it would take centuries to wrap around, so it's treated as if it never does.
+#[derive(Debug, Default)]
+struct Generation {
+    count: Mutex<u64>,
+    condvar: Condvar,
+}
+
+impl Generation {
+    fn bump(&self) {
+        *self.count.lock() += 1;
+        self.condvar.notify_all();
+    }
+
+    fn get(&self) -> u64 {
+        *self.count.lock()
+    }
+
+    fn wait_past(&self, last_seen: u64) -> u64 {
+        let mut count = self.count.lock();
+
+        while *count <= last_seen {
+            self.condvar.wait(&mut count);
+        }
+
+        *count
+    }
+}
+
+#[cfg(debug_assertions)]
+thread_local! {
+    // addresses of the `MultiBuffer` front locks the current thread holds, once per guard
+    static HELD_FRONTS: RefCell<Vec<usize>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Remembers that the current thread holds a front buffer until it's dropped,
+/// so locking the same front buffer again panics instead of deadlocking.
+///
+/// Only does anything in debug builds. It isn't `Send` in any build though, the lock is tracked by the thread
+/// that took it, so a guard that moved to another thread would be forgotten by the wrong one.
+#[derive(Debug)]
+struct HeldFront {
+    #[cfg(debug_assertions)]
+    addr: usize,
+    _not_send: PhantomData<*const ()>,
+}
+
+impl HeldFront {
+    /// For the blocking locks, panics if the current thread already holds `lock`
+    #[track_caller]
+    fn acquire<T>(lock: &Arc<RwLock<T>>) -> Self {
+        #[cfg(debug_assertions)]
+        HELD_FRONTS.with_borrow(|held| {
+            assert!(
+                !held.contains(&Self::addr_of(lock)),
+                "the front buffer is already locked on this thread, locking it again would deadlock"
+            );
+        });
+
+        Self::register(lock)
+    }
+
+    /// For the `try_` locks, they can't deadlock, but the lock they got still counts as held
+    #[cfg_attr(not(debug_assertions), allow(unused_variables))]
+    fn register<T>(lock: &Arc<RwLock<T>>) -> Self {
+        #[cfg(debug_assertions)]
+        {
+            let addr = Self::addr_of(lock);
+            HELD_FRONTS.with_borrow_mut(|held| held.push(addr));
+
+            Self {
+                addr,
+                _not_send: PhantomData,
+            }
+        }
+
+        #[cfg(not(debug_assertions))]
+        Self { _not_send: PhantomData }
+    }
+
+    #[cfg(debug_assertions)]
+    fn addr_of<T>(lock: &Arc<RwLock<T>>) -> usize {
+        Arc::as_ptr(lock) as *const () as usize
+    }
+}
+
+#[cfg(debug_assertions)]
+impl Drop for HeldFront {
+    fn drop(&mut self) {
+        HELD_FRONTS.with_borrow_mut(|held| {
+            if let Some(index) = held.iter().position(|&addr| addr == self.addr) {
+                held.swap_remove(index);
+            }
+        });
+    }
+}
+
+// a front buffer lock guard along with its `HeldFront`, the lock gets released first,
+// it stays on the thread that locked it even where parking_lot's guards could be sent
+struct FrontGuard<G> {
+    guard: G,
+    _held: HeldFront,
+}
+
+impl<G: Deref> Deref for FrontGuard<G> {
+    type Target = G::Target;
+
+    fn deref(&self) -> &Self::Target {
+        &self.guard
+    }
+}
+
+impl<G: DerefMut> DerefMut for FrontGuard<G> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.guard
+    }
+}
 
 /// A data structure that contains a locally stored back buffer for editing
 /// as well as a shared front buffer for access in other parts of the code.
@@ -20,6 +149,7 @@ use parking_lot::RwLock;
 pub struct MultiBuffer<T> {
     back: T,
     front: Arc<RwLock<T>>,
+    generation: Arc<Generation>,
 }
 
 impl<T> MultiBuffer<T> {
@@ -30,10 +160,7 @@ impl<T> MultiBuffer<T> {
     where
         T: Clone,
     {
-        let front = val;
-        let back = Arc::new(RwLock::new(front.clone()));
-
-        Self { back: front, front: back }
+        Self::from_buffers(val.clone(), val)
     }
 
     /// constructs the `MultiBuffer` out of two different buffers.
@@ -43,7 +170,7 @@ impl<T> MultiBuffer<T> {
     pub fn from_buffers(front: T, back: T) -> Self {
         let back = Arc::new(RwLock::new(back));
         
-        Self { back: front, front: back }
+        Self { back: front, front: back, generation: Arc::default() }
     }
     
     /// Swaps the front and back buffers. 
@@ -54,10 +181,13 @@ impl<T> MultiBuffer<T> {
     /// 
     /// May block if the front buffer is currently locked.
     #[inline]
+    #[track_caller]
     pub fn swap(&mut self) {
+        let _held = HeldFront::acquire(&self.front);
         let front = &mut *self.front.write();
 
         mem::swap(&mut self.back, front);
+        self.generation.bump();
     }
     
     /// Swaps the front and back buffers. 
@@ -73,10 +203,26 @@ impl<T> MultiBuffer<T> {
         let front = &mut *self.front.try_write()?;
 
         mem::swap(&mut self.back, front);
+        self.generation.bump();
         
         Some(())
     }
     
+    /// Swaps the buffers like `swap`, but only if `should` returns true for the back buffer,
+    /// e.g. to skip publishing a frame that's the same as the previous one.
+    ///
+    /// Skipping the swap means `front` stays on the old data and the generation doesn't change,
+    /// so readers waiting for an update keep waiting. Returns whether the buffers were swapped.
+    #[inline]
+    pub fn swap_if<F: FnOnce(&T) -> bool>(&mut self, should: F) -> bool {
+        if !should(&self.back) {
+            return false;
+        }
+
+        self.swap();
+        true
+    }
+
     /// Allows to clone this `MultiBuffer` by providing a new back buffer
     /// in case `T` doesn't implement Clone
     #[inline]
@@ -84,6 +230,7 @@ impl<T> MultiBuffer<T> {
         Self {
             back: new_back,
             front: self.front.clone(),
+            generation: self.generation.clone(),
         }
     }
     
@@ -99,7 +246,16 @@ impl<T> MultiBuffer<T> {
     
     #[inline]
     pub fn view(&self) -> MultiBufferView<T> {
-        MultiBufferView { front: self.front.clone() }
+        MultiBufferView {
+            front: self.front.clone(),
+            generation: self.generation.clone(),
+        }
+    }
+    
+    /// How many times the buffers have been swapped
+    #[inline]
+    pub fn generation(&self) -> u64 {
+        self.generation.get()
     }
     
     /// Returns a reference-like object to the front buffer.
@@ -110,10 +266,13 @@ impl<T> MultiBuffer<T> {
     /// 
     /// The return type is a lock guard, but the exact type is implementation defined and can change.
     /// As such, trying to get the front buffer while a reference to it already exists in the current thread, might
-    /// result in a deadlock
+    /// result in a deadlock. Debug builds panic instead.
     #[inline]
+    #[track_caller]
     pub fn front(&self) -> impl Deref<Target = T> + '_ {
-        self.front.read()
+        let _held = HeldFront::acquire(&self.front);
+
+        FrontGuard { guard: self.front.read(), _held }
     }
 
     /// Returns a mutable reference-like object to the front buffer.
@@ -124,10 +283,13 @@ impl<T> MultiBuffer<T> {
     /// 
     /// The returned value is a lock guard, but the exact type is implementation defined and can change.
     /// As such, trying to get the front buffer while a reference to it already exists in the current thread, might
-    /// result in a deadlock
+    /// result in a deadlock. Debug builds panic instead.
     #[inline]
+    #[track_caller]
     pub fn front_mut(&self) -> impl DerefMut<Target = T> + '_ {
-        self.front.write()
+        let _held = HeldFront::acquire(&self.front);
+
+        FrontGuard { guard: self.front.write(), _held }
     }
     
     /// Returns a reference-like object to the front buffer.
@@ -135,7 +297,9 @@ impl<T> MultiBuffer<T> {
     /// Same as `front`, except it will return None if the operation would block.
     #[inline]
     pub fn try_front(&self) -> Option<impl Deref<Target = T> + '_> {
-        self.front.try_read()
+        let guard = self.front.try_read()?;
+
+        Some(FrontGuard { guard, _held: HeldFront::register(&self.front) })
     }
 
     /// Returns a mutable reference-like object to the front buffer.
@@ -143,7 +307,9 @@ impl<T> MultiBuffer<T> {
     /// Same as `front_mut`, except it will return None if the operation would block.
     #[inline]
     pub fn try_front_mut(&self) -> Option<impl DerefMut<Target = T> + '_> {
-        self.front.try_write()
+        let guard = self.front.try_write()?;
+
+        Some(FrontGuard { guard, _held: HeldFront::register(&self.front) })
     }
 }
 
@@ -167,9 +333,25 @@ impl<T> DerefMut for MultiBuffer<T> {
 #[derive(Clone)]
 pub struct MultiBufferView<T> {
     front: Arc<RwLock<T>>,
+    generation: Arc<Generation>,
 }
 
 impl<T> MultiBufferView<T> {
+    /// How many times the buffers have been swapped
+    #[inline]
+    pub fn generation(&self) -> u64 {
+        self.generation.get()
+    }
+    
+    /// Blocks until the buffers get swapped after generation `last_seen`, returns the new generation.
+    ///
+    /// Returns immediately if that has already happened,
+    /// so passing in the result of the previous call doesn't miss any swaps.
+    #[inline]
+    pub fn wait_for_update(&self, last_seen: u64) -> u64 {
+        self.generation.wait_past(last_seen)
+    }
+    
     /// Returns a reference-like object to the front buffer.
     /// 
     /// When using this method, as well as `front_mut`, keep in mind that
@@ -178,10 +360,13 @@ impl<T> MultiBufferView<T> {
     /// 
     /// The return type is a lock guard, but the exact type is implementation defined and can change.
     /// As such, trying to get the front buffer while a reference to it already exists in the current thread, might
-    /// result in a deadlock
+    /// result in a deadlock. Debug builds panic instead.
     #[inline]
+    #[track_caller]
     pub fn front(&self) -> impl Deref<Target = T> + '_ {
-        self.front.read()
+        let _held = HeldFront::acquire(&self.front);
+
+        FrontGuard { guard: self.front.read(), _held }
     }
     
     /// Returns a reference-like object to the front buffer.
@@ -189,6 +374,378 @@ impl<T> MultiBufferView<T> {
     /// Same as `front`, except it will return None if the operation would block.
     #[inline]
     pub fn try_front(&self) -> Option<impl Deref<Target = T> + '_> {
-        self.front.try_read()
+        let guard = self.front.try_read()?;
+
+        Some(FrontGuard { guard, _held: HeldFront::register(&self.front) })
+    }
+}
+/// Like `MultiBuffer`, but with a third buffer, so swapping never has to wait for readers.
+///
+/// # Swapping
+/// The back buffer becomes the front buffer, and one of the other two buffers that isn't being read
+/// becomes the new back buffer, bringing along whatever old data it had.
+/// A reader can only hold on to one buffer at a time, so with a single reader there is always one available.
+/// When there are several readers, holding on to both of the other buffers, `swap` gives up
+/// and the back buffer stays the same.
+///
+/// # Reading
+/// Readers lock whichever buffer was the front one when they called `front`.
+/// A long read doesn't block the producer, it just means the reader is looking at an older frame.
+#[derive(Debug)]
+pub struct TripleBuffer<T> {
+    back: ArcRwLockWriteGuard<RawRwLock, T>,
+    back_index: usize,
+    shared: Arc<TripleBufferShared<T>>,
+}
+
+#[derive(Debug)]
+struct TripleBufferShared<T> {
+    buffers: [Arc<RwLock<T>>; 3],
+    front: AtomicUsize,
+    generation: Generation,
+}
+
+impl<T> TripleBufferShared<T> {
+    fn front(&self) -> RwLockReadGuard<'_, T> {
+        loop {
+            let index = self.front.load(Ordering::Acquire);
+
+            // can only fail if the producer has just taken this buffer over as its back buffer,
+            // and the front index is about to move on
+            if let Some(guard) = self.buffers[index].try_read() {
+                return guard;
+            }
+
+            hint::spin_loop();
+        }
+    }
+
+    fn try_front(&self) -> Option<RwLockReadGuard<'_, T>> {
+        self.buffers[self.front.load(Ordering::Acquire)].try_read()
+    }
+}
+
+impl<T> TripleBuffer<T> {
+    /// Constructs a new `TripleBuffer`. `val` will be cloned to create all three buffers.
+    /// In case `T` doesn't implement `Clone`, `from_buffers` should be used instead.
+    #[inline]
+    pub fn new(val: T) -> Self
+    where
+        T: Clone,
+    {
+        Self::from_buffers(val.clone(), val.clone(), val)
+    }
+
+    /// Constructs the `TripleBuffer` out of three different buffers, `back` is the one written to first.
+    pub fn from_buffers(back: T, front: T, spare: T) -> Self {
+        let buffers = [back, front, spare].map(|buf| Arc::new(RwLock::new(buf)));
+        let back = buffers[0].write_arc();
+
+        let shared = TripleBufferShared {
+            buffers,
+            front: AtomicUsize::new(1),
+            generation: Generation::default(),
+        };
+
+        Self {
+            back,
+            back_index: 0,
+            shared: Arc::new(shared),
+        }
+    }
+
+    /// Makes the back buffer the front buffer, without blocking.
+    ///
+    /// Returns `false` if every other buffer is being read at the moment, nothing gets swapped then.
+    pub fn swap(&mut self) -> bool {
+        // a reader can move from one buffer to the other between the two attempts,
+        // so it's worth trying again a couple of times before giving up
+        (0..Self::SWAP_ATTEMPTS).any(|_| self.try_swap_once())
+    }
+
+    const SWAP_ATTEMPTS: usize = 3;
+
+    /// Same as `swap`, but only if `should` returns true for the back buffer, see `MultiBuffer::swap_if`.
+    ///
+    /// Returns `false` both when the swap was skipped and when it failed.
+    pub fn swap_if<F: FnOnce(&T) -> bool>(&mut self, should: F) -> bool {
+        should(&self.back) && self.swap()
+    }
+
+    fn try_swap_once(&mut self) -> bool {
+        let front = self.shared.front.load(Ordering::Acquire);
+        let spare = 3 - front - self.back_index;
+
+        // the front buffer only gets recycled if the spare one is being read,
+        // so the latest frame stays available for as long as possible
+        for index in [spare, front] {
+            if let Some(guard) = self.shared.buffers[index].try_write_arc() {
+                let old_back = mem::replace(&mut self.back, guard);
+                let old_index = mem::replace(&mut self.back_index, index);
+
+                // unlocking first so readers don't have to spin on the new front buffer
+                drop(old_back);
+                self.shared.front.store(old_index, Ordering::Release);
+                self.shared.generation.bump();
+
+                return true;
+            }
+        }
+
+        false
     }
-}
\ No newline at end of file
+
+    #[inline]
+    pub fn back(&self) -> &T {
+        &self.back
+    }
+
+    #[inline]
+    pub fn back_mut(&mut self) -> &mut T {
+        &mut self.back
+    }
+
+    #[inline]
+    pub fn view(&self) -> TripleBufferView<T> {
+        TripleBufferView {
+            shared: self.shared.clone(),
+        }
+    }
+
+    /// How many successful swaps there have been
+    #[inline]
+    pub fn generation(&self) -> u64 {
+        self.shared.generation.get()
+    }
+
+    /// Returns a reference-like object to the front buffer.
+    ///
+    /// Unlike with `MultiBuffer`, holding on to it doesn't block `swap`.
+    #[inline]
+    pub fn front(&self) -> impl Deref<Target = T> + '_ {
+        self.shared.front()
+    }
+
+    /// Same as `front`, except it will return None if the operation would block.
+    #[inline]
+    pub fn try_front(&self) -> Option<impl Deref<Target = T> + '_> {
+        self.shared.try_front()
+    }
+}
+
+impl<T> Deref for TripleBuffer<T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        self.back()
+    }
+}
+
+impl<T> DerefMut for TripleBuffer<T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.back_mut()
+    }
+}
+
+/// Similar to `TripleBuffer` but only has access to the front buffer
+pub struct TripleBufferView<T> {
+    shared: Arc<TripleBufferShared<T>>,
+}
+
+impl<T> Clone for TripleBufferView<T> {
+    fn clone(&self) -> Self {
+        Self {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<T> TripleBufferView<T> {
+    /// How many successful swaps there have been
+    #[inline]
+    pub fn generation(&self) -> u64 {
+        self.shared.generation.get()
+    }
+
+    /// Blocks until there's a swap after generation `last_seen`, returns the new generation.
+    ///
+    /// See `MultiBufferView::wait_for_update`
+    #[inline]
+    pub fn wait_for_update(&self, last_seen: u64) -> u64 {
+        self.shared.generation.wait_past(last_seen)
+    }
+
+    /// Returns a reference-like object to the front buffer.
+    ///
+    /// Holding on to it doesn't block the producer, and getting the front buffer again while holding it
+    /// doesn't deadlock either, readers only ever share their locks.
+    #[inline]
+    pub fn front(&self) -> impl Deref<Target = T> + '_ {
+        self.shared.front()
+    }
+
+    /// Returns a reference-like object to the front buffer.
+    ///
+    /// Same as `front`, except it will return None if the operation would block.
+    #[inline]
+    pub fn try_front(&self) -> Option<impl Deref<Target = T> + '_> {
+        self.shared.try_front()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{
+            atomic::{AtomicBool, AtomicU64},
+            mpsc,
+        },
+        thread,
+        time::Duration,
+    };
+
+    use super::*;
+
+    #[test]
+    fn swap_notifications() {
+        const SWAPS: u64 = 100;
+
+        let mut buf = MultiBuffer::new(0);
+        let view = buf.view();
+        let (ack_tx, ack_rx) = mpsc::channel();
+
+        let counter = thread::spawn(move || {
+            let mut generation = 0;
+            let mut notifications = 0;
+
+            while generation < SWAPS {
+                generation = view.wait_for_update(generation);
+                notifications += 1;
+                ack_tx.send(()).unwrap();
+            }
+
+            notifications
+        });
+
+        for _ in 0..SWAPS {
+            buf.swap();
+            ack_rx.recv().unwrap();
+        }
+
+        assert_eq!(counter.join().unwrap(), SWAPS);
+        assert_eq!(buf.generation(), SWAPS);
+    }
+
+    #[test]
+    fn swap_if_false_keeps_buffers() {
+        let mut buf = MultiBuffer::from_buffers(1, 2);
+
+        assert!(!buf.swap_if(|&back| back != 1));
+        assert_eq!((*buf.back(), *buf.front()), (1, 2));
+        assert_eq!(buf.generation(), 0);
+
+        assert!(buf.swap_if(|&back| back == 1));
+        assert_eq!((*buf.back(), *buf.front()), (2, 1));
+        assert_eq!(buf.generation(), 1);
+
+        let mut buf = TripleBuffer::new(0);
+        let view = buf.view();
+
+        *buf.back_mut() = 1;
+        assert!(!buf.swap_if(|_| false));
+        assert_eq!(*view.front(), 0);
+        assert_eq!(view.generation(), 0);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "already locked on this thread")]
+    fn front_locked_twice_panics() {
+        let buf = MultiBuffer::new(0);
+        let view = buf.view();
+
+        let _front = buf.front_mut();
+        // a read lock on top of the write lock would hang forever
+        let _ = view.front();
+    }
+
+    #[test]
+    fn front_can_be_locked_again_after_release() {
+        let mut buf = MultiBuffer::new(0);
+        let view = buf.view();
+
+        drop(buf.front_mut());
+        let front = buf.try_front().unwrap();
+        drop(front);
+        buf.swap();
+
+        // other threads aren't affected by what this one holds
+        let _front = buf.front();
+        thread::scope(|s| s.spawn(|| *view.front()).join().unwrap());
+    }
+
+    #[test]
+    fn triple_buffer_front_locked_twice() {
+        let mut buf = TripleBuffer::new(0);
+        let view = buf.view();
+
+        let first = view.front();
+        *buf.back_mut() = 1;
+        assert!(buf.swap());
+
+        // one read lock on the old front buffer and one on the new one
+        let second = view.front();
+        let again = buf.front();
+        assert_eq!((*first, *second, *again), (0, 1, 1));
+    }
+
+    #[test]
+    fn triple_buffer_swap() {
+        let mut buf = TripleBuffer::new(0);
+        let view = buf.view();
+
+        *buf.back_mut() = 1;
+        assert!(buf.swap());
+        assert_eq!(*view.front(), 1);
+
+        *buf.back_mut() = 2;
+        assert!(buf.swap());
+        assert_eq!(*view.front(), 2);
+        assert_eq!(view.wait_for_update(1), 2);
+    }
+
+    #[test]
+    fn triple_buffer_producer_never_blocks() {
+        let mut buf = TripleBuffer::new(0_u64);
+        let view = buf.view();
+        let swaps = Arc::new(AtomicU64::new(0));
+        let stop = Arc::new(AtomicBool::new(false));
+
+        let producer = {
+            let swaps = swaps.clone();
+            let stop = stop.clone();
+
+            thread::spawn(move || {
+                while !stop.load(Ordering::Relaxed) {
+                    *buf.back_mut() += 1;
+                    if buf.swap() {
+                        swaps.fetch_add(1, Ordering::Relaxed);
+                    }
+                }
+            })
+        };
+
+        for _ in 0..5 {
+            let front = view.front();
+            let before = swaps.load(Ordering::Relaxed);
+
+            thread::sleep(Duration::from_millis(20));
+
+            assert!(swaps.load(Ordering::Relaxed) > before);
+            drop(front);
+        }
+
+        stop.store(true, Ordering::Relaxed);
+        producer.join().unwrap();
+    }
+}
@@ -1,49 +1,212 @@
 use std::{
-    sync::mpsc::{self, Receiver, RecvError, RecvTimeoutError, Sender, SyncSender},
+    any::Any,
+    io, iter,
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        mpsc::{self, Receiver, RecvTimeoutError, Sender, SyncSender, TrySendError},
+        Arc,
+    },
     thread::{self, JoinHandle},
     time::Duration,
 };
 
+use parking_lot::Mutex;
 use spin_sleep::LoopHelper;
+use thiserror::Error;
+
+/// What a panicking thread was called with, see `std::thread::Result`
+pub type PanicPayload = Box<dyn Any + Send + 'static>;
+
+// called with the worker on its thread once the loop is over, see `join_with`
+type HandBack<W> = Box<dyn FnOnce(W) + Send>;
 
 pub trait ThreadWork {
     type WorkResult: Send + 'static;
 
-    fn work(&mut self) -> Self::WorkResult;
+    /// Does one iteration's worth of work.
+    ///
+    /// `None` means there's nothing worth reporting this time, nothing gets sent on the results channel then,
+    /// so consumers don't have to wade through non-events.
+    fn work(&mut self) -> Option<Self::WorkResult>;
+}
+
+// how often the measured rate gets updated
+const RATE_REPORT_INTERVAL_S: f64 = 1.0;
+
+// how long `join` waits for a result before trying to send the join message again
+const JOIN_RETRY_INTERVAL: Duration = Duration::from_millis(1);
+
+/// What the worker does with a result when the results channel is full,
+/// see `ThreadLoopBuilder::result_capacity`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OnFull {
+    /// Waits for the consumer to take a result, stalling the loop in the meantime
+    #[default]
+    Block,
+    /// Throws away the oldest result nobody has taken yet, see `ThreadLoop::dropped_results`
+    DropOldest,
+}
+
+// the receiving end is shared with the worker, so it can make room by dropping the oldest result.
+// Whoever waits for a result holds the lock while waiting, so everything that mustn't block only tries to take it,
+// a waiter gets whatever arrives before anyone else would have anyway
+type SharedReceiver<T> = Arc<Mutex<Receiver<T>>>;
+
+enum ResultSender<T> {
+    Unbounded(Sender<T>),
+    Block(SyncSender<T>),
+    DropOldest {
+        tx: SyncSender<T>,
+        rx: SharedReceiver<T>,
+        dropped: Arc<AtomicU64>,
+    },
+}
+
+// derive would want T: Clone
+impl<T> Clone for ResultSender<T> {
+    fn clone(&self) -> Self {
+        match self {
+            Self::Unbounded(tx) => Self::Unbounded(tx.clone()),
+            Self::Block(tx) => Self::Block(tx.clone()),
+            Self::DropOldest { tx, rx, dropped } => Self::DropOldest {
+                tx: tx.clone(),
+                rx: rx.clone(),
+                dropped: dropped.clone(),
+            },
+        }
+    }
+}
+
+impl<T> ResultSender<T> {
+    // false once the consumer is gone
+    fn send(&self, result: T) -> bool {
+        match self {
+            Self::Unbounded(tx) => tx.send(result).is_ok(),
+            Self::Block(tx) => tx.send(result).is_ok(),
+            Self::DropOldest { tx, rx, dropped } => {
+                let mut result = result;
+
+                loop {
+                    match tx.try_send(result) {
+                        Ok(()) => return true,
+                        Err(TrySendError::Full(returned)) => {
+                            // the consumer may have taken something in the meantime, then there's room anyway,
+                            // same if it's waiting for a result, it's about to take one
+                            if rx.try_lock().is_some_and(|rx| rx.try_recv().is_ok()) {
+                                dropped.fetch_add(1, Ordering::Relaxed);
+                            }
+                            result = returned;
+                        }
+                        Err(TrySendError::Disconnected(_)) => return false,
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// How the loop waits out the rest of every iteration, see `ThreadScheduling::sleep`.
+///
+/// Native sleeps overshoot, so the loop sleeps natively for most of the wait and spins for the rest of it.
+/// The longer it spins, the closer it sticks to the target rate and the more CPU it burns,
+/// a background recorder is usually better off trading a bit of jitter for a core that isn't pinned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SleepStrategy {
+    /// Spins as long as `spin_sleep` thinks the platform's native sleeps need
+    #[default]
+    Default,
+    /// Never spins, the least CPU but a frame can come late by however much the OS overshoots
+    Native,
+    /// Spins for the last this many nanoseconds of every wait, see `spin_sleep::LoopHelperBuilder::native_accuracy_ns`
+    NativeAccuracyNs(u32),
+}
+
+impl SleepStrategy {
+    fn configure<B: NativeAccuracy>(self, builder: B) -> B {
+        match self {
+            Self::Default => builder,
+            Self::Native => builder.native_accuracy_ns(0),
+            Self::NativeAccuracyNs(accuracy) => builder.native_accuracy_ns(accuracy),
+        }
+    }
+}
+
+// the one setting of `LoopHelperBuilder` the sleep strategy touches, so the tests can check what it gets
+trait NativeAccuracy {
+    fn native_accuracy_ns(self, accuracy: u32) -> Self;
+}
+
+// deprecated along with `LoopHelper`, which is already warned about
+#[allow(deprecated)]
+impl NativeAccuracy for spin_sleep::LoopHelperBuilder {
+    fn native_accuracy_ns(self, accuracy: u32) -> Self {
+        spin_sleep::LoopHelperBuilder::native_accuracy_ns(self, accuracy)
+    }
 }
 
 enum MessageToWorker {
     StartLoop { target_rate: f64 },
+    SetRate { target_rate: f64 },
+    Pause,
+    Resume,
     Join,
 }
 
 struct ThreadLoopWorker<W: ThreadWork> {
     worker: W,
-    tx: Sender<W::WorkResult>,
+    tx: ResultSender<W::WorkResult>,
     rx: Receiver<MessageToWorker>,
+    // bits of the f64, there's no AtomicF64
+    measured_rate: Arc<AtomicU64>,
+    sleep: SleepStrategy,
 }
 
 // struct that will be running its code on another thread
 impl<W: ThreadWork> ThreadLoopWorker<W> {
-    fn new(worker: W, tx: Sender<W::WorkResult>, rx: Receiver<MessageToWorker>) -> Self {
-        Self { worker, tx, rx }
+    fn new(
+        worker: W,
+        tx: ResultSender<W::WorkResult>,
+        rx: Receiver<MessageToWorker>,
+        measured_rate: Arc<AtomicU64>,
+        sleep: SleepStrategy,
+    ) -> Self {
+        Self {
+            worker,
+            tx,
+            rx,
+            measured_rate,
+            sleep,
+        }
     }
 
     fn run(&mut self) {
         let target_rate = match self.rx.recv().unwrap() {
             MessageToWorker::StartLoop { target_rate } => target_rate,
             MessageToWorker::Join => return,
+            // safety: there's no way to send anything else before the loop is started
+            _ => unreachable!(),
         };
 
-        let mut loop_helper = LoopHelper::builder()
-            // .report_interval_s(1.0)      // for debugging
-            .build_with_target_rate(target_rate);
+        // an infinite rate makes the loop never sleep
+        let sleep = self.sleep;
+        let build_loop_helper = |target_rate| {
+            sleep
+                .configure(LoopHelper::builder())
+                .report_interval_s(RATE_REPORT_INTERVAL_S)
+                .build_with_target_rate(target_rate)
+        };
+
+        let mut loop_helper = build_loop_helper(target_rate);
 
         loop {
             loop_helper.loop_start();
 
+            if let Some(rate) = loop_helper.report_rate() {
+                self.measured_rate.store(rate.to_bits(), Ordering::Relaxed);
+            }
+
             // handle incoming messages
-            #[allow(clippy::never_loop)]
             for message in self.rx.try_iter() {
                 match message {
                     // safety: start can only be called once per worker
@@ -51,13 +214,38 @@ impl<W: ThreadWork> ThreadLoopWorker<W> {
                     MessageToWorker::StartLoop { .. } => {
                         unreachable!()
                     }
+                    MessageToWorker::SetRate { target_rate } => {
+                        loop_helper = build_loop_helper(target_rate);
+                        loop_helper.loop_start();
+                    }
+                    MessageToWorker::Pause => {
+                        // blocking instead of spinning until we're resumed
+                        loop {
+                            match self.rx.recv() {
+                                Ok(MessageToWorker::Resume) => break,
+                                Ok(MessageToWorker::SetRate { target_rate }) => {
+                                    loop_helper = build_loop_helper(target_rate);
+                                }
+                                Ok(MessageToWorker::Pause) => (),
+                                Ok(MessageToWorker::StartLoop { .. }) => unreachable!(),
+                                Ok(MessageToWorker::Join) | Err(_) => return,
+                            }
+                        }
+
+                        // the time spent paused shouldn't count towards this iteration
+                        loop_helper.loop_start();
+                    }
+                    MessageToWorker::Resume => (),
                     MessageToWorker::Join => return,
                 }
             }
 
-            let result = self.worker.work();
-
-            self.tx.send(result).unwrap();
+            // nobody is going to read the results anymore
+            if let Some(result) = self.worker.work() {
+                if !self.tx.send(result) {
+                    return;
+                }
+            }
 
             loop_helper.loop_sleep();
         }
@@ -66,60 +254,283 @@ impl<W: ThreadWork> ThreadLoopWorker<W> {
 
 // this is here so we don't need to implement drop twice
 struct ThreadLoopInner<W: ThreadWork> {
-    worker_join_handle: JoinHandle<()>,
+    // only None after being joined
+    worker_join_handle: Option<JoinHandle<()>>,
     tx: SyncSender<MessageToWorker>,
-    rx: Receiver<W::WorkResult>,
+    rx: SharedReceiver<W::WorkResult>,
+    panic: Arc<Mutex<Option<PanicPayload>>>,
+    hand_back: Arc<Mutex<Option<HandBack<W>>>>,
+    measured_rate: Arc<AtomicU64>,
+    dropped_results: Arc<AtomicU64>,
+    exited: Arc<AtomicBool>,
+    scheduling_errors: Arc<Mutex<Vec<SchedulingError>>>,
 }
 
 impl<W: ThreadWork> Drop for ThreadLoopInner<W> {
     fn drop(&mut self) {
-        // intentionally silencing the error if there is one
-        let _ = self.tx.send(MessageToWorker::Join);
+        // intentionally silencing the error if there is one,
+        // a worker that's stuck on a full message channel stops once it can't send its results either
+        let _ = self.tx.try_send(MessageToWorker::Join);
         // not joining the handle to keep the drop low cost
     }
 }
 
+/// Which cores a worker thread runs on, how eagerly the OS schedules it and how it sleeps between iterations,
+/// see `ThreadLoopBuilder::scheduling`.
+///
+/// The cores and the priority are only supported on Linux with the `thread-priority` feature.
+/// Anywhere else, or if the OS refuses, the thread just runs as it would have without them,
+/// see `ThreadLoop::take_scheduling_errors` for why.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ThreadScheduling {
+    /// The indices of the cores the thread may run on, `None` leaves it up to the OS
+    pub cores: Option<Vec<usize>>,
+    /// The thread's nice value, from -20 (scheduled the most eagerly) to 19, `None` keeps the inherited one.
+    ///
+    /// Going below the current value usually takes elevated privileges.
+    pub nice: Option<i32>,
+    /// Works everywhere, unlike the other two
+    pub sleep: SleepStrategy,
+}
+
+impl ThreadScheduling {
+    // called on the thread it's meant for, failures aren't worth taking the thread down for
+    fn apply(&self) -> Vec<SchedulingError> {
+        let affinity = self.apply_cores().err().map(SchedulingError::Affinity);
+        let priority = self.apply_nice().err().map(SchedulingError::Priority);
+
+        affinity.into_iter().chain(priority).collect()
+    }
+
+    #[cfg(all(feature = "thread-priority", target_os = "linux"))]
+    fn apply_cores(&self) -> io::Result<()> {
+        let Some(cores) = &self.cores else {
+            return Ok(());
+        };
+
+        // SAFETY: cpu_set_t is a plain bitmask, all zeroes is an empty set
+        let mut set: libc::cpu_set_t = unsafe { std::mem::zeroed() };
+        for &core in cores {
+            if core >= libc::CPU_SETSIZE as usize {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("core {core} is past the largest one that can be set"),
+                ));
+            }
+            // SAFETY: the index was checked to be in the set
+            unsafe { libc::CPU_SET(core, &mut set) };
+        }
+
+        // SAFETY: the set outlives the call and the size is its own, 0 is the calling thread
+        let result = unsafe { libc::sched_setaffinity(0, std::mem::size_of_val(&set), &set) };
+        if result != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(())
+    }
+
+    #[cfg(all(feature = "thread-priority", target_os = "linux"))]
+    fn apply_nice(&self) -> io::Result<()> {
+        let Some(nice) = self.nice else {
+            return Ok(());
+        };
+
+        // unlike POSIX says, on Linux every thread has a nice value of its own
+        // SAFETY: only the calling thread is affected
+        let result = unsafe { libc::setpriority(libc::PRIO_PROCESS, libc::gettid() as libc::id_t, nice) };
+        if result != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(())
+    }
+
+    #[cfg(not(all(feature = "thread-priority", target_os = "linux")))]
+    fn apply_cores(&self) -> io::Result<()> {
+        match self.cores {
+            Some(_) => Err(Self::unsupported()),
+            None => Ok(()),
+        }
+    }
+
+    #[cfg(not(all(feature = "thread-priority", target_os = "linux")))]
+    fn apply_nice(&self) -> io::Result<()> {
+        match self.nice {
+            Some(_) => Err(Self::unsupported()),
+            None => Ok(()),
+        }
+    }
+
+    #[cfg(not(all(feature = "thread-priority", target_os = "linux")))]
+    fn unsupported() -> io::Error {
+        io::Error::new(
+            io::ErrorKind::Unsupported,
+            "only supported on Linux with the thread-priority feature",
+        )
+    }
+}
+
+type WorkerFactory<W> = Box<dyn FnOnce() -> W + Send>;
+
 pub struct ThreadLoopBuilder<W: ThreadWork> {
-    inner: ThreadLoopInner<W>,
+    worker_factory: WorkerFactory<W>,
+    name: Option<String>,
+    result_capacity: Option<(usize, OnFull)>,
+    scheduling: ThreadScheduling,
 }
 
-impl<W: ThreadWork> ThreadLoopBuilder<W> {
+impl<W: ThreadWork + 'static> ThreadLoopBuilder<W> {
     pub fn new<F>(worker_factory: F) -> Self
     where
         F: FnOnce() -> W,
         F: Send + 'static,
     {
-        // technically there will only ever be 2 messages sent at most
-        // I'm just generous setting the value to 8
+        Self {
+            worker_factory: Box::new(worker_factory),
+            name: None,
+            result_capacity: None,
+            scheduling: ThreadScheduling::default(),
+        }
+    }
+
+    /// Names the worker thread, so it can be told apart in debuggers, profilers and panic messages
+    #[inline]
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    /// Caps how many results can pile up when the consumer doesn't keep up with the worker,
+    /// `on_full` decides what happens once they do.
+    ///
+    /// Without a cap, a stalled consumer makes the results grow without bound.
+    /// The capacity is at least 1.
+    #[inline]
+    pub fn result_capacity(mut self, cap: usize, on_full: OnFull) -> Self {
+        self.result_capacity = Some((cap, on_full));
+        self
+    }
+
+    /// Pins the worker thread to some cores and/or changes its priority, before the worker gets created.
+    ///
+    /// Not being able to doesn't fail `start_loop`, see `ThreadScheduling`.
+    #[inline]
+    pub fn scheduling(mut self, scheduling: ThreadScheduling) -> Self {
+        self.scheduling = scheduling;
+        self
+    }
+
+    /// Spawns the worker thread and starts the loop.
+    ///
+    /// Fails if the OS couldn't create the thread.
+    pub fn start_loop(self, target_rate: f64) -> io::Result<ThreadLoop<W>> {
+        let Self {
+            worker_factory,
+            name,
+            result_capacity,
+            scheduling,
+        } = self;
+
+        // the worker drains the messages on every iteration,
+        // so this only fills up if they're sent faster than the loop runs
         let (tx, worker_rx) = mpsc::sync_channel::<MessageToWorker>(8);
 
-        let (worker_tx, rx) = mpsc::channel::<W::WorkResult>();
+        let dropped_results = Arc::new(AtomicU64::new(0));
 
-        let worker_join_handle = thread::spawn(move || {
-            let inner_worker = worker_factory();
+        let (worker_tx, rx) = match result_capacity {
+            None => {
+                let (tx, rx) = mpsc::channel::<W::WorkResult>();
+                (ResultSender::Unbounded(tx), Arc::new(Mutex::new(rx)))
+            }
+            Some((cap, on_full)) => {
+                // a zero sized channel only hands results over while the consumer is waiting for one
+                let (tx, rx) = mpsc::sync_channel::<W::WorkResult>(cap.max(1));
+                let rx = Arc::new(Mutex::new(rx));
 
-            let mut loop_worker = ThreadLoopWorker::new(inner_worker, worker_tx, worker_rx);
+                let tx = match on_full {
+                    OnFull::Block => ResultSender::Block(tx),
+                    OnFull::DropOldest => ResultSender::DropOldest {
+                        tx,
+                        rx: rx.clone(),
+                        dropped: dropped_results.clone(),
+                    },
+                };
 
-            loop_worker.run();
-        });
+                (tx, rx)
+            }
+        };
 
-        Self {
-            inner: ThreadLoopInner {
-                worker_join_handle,
-                tx,
-                rx,
-            },
+        let panic = Arc::new(Mutex::new(None));
+        let worker_panic = panic.clone();
+
+        let hand_back: Arc<Mutex<Option<HandBack<W>>>> = Arc::default();
+        let worker_hand_back = hand_back.clone();
+
+        let measured_rate = Arc::new(AtomicU64::new(0.0_f64.to_bits()));
+        let worker_measured_rate = measured_rate.clone();
+
+        let exited = Arc::new(AtomicBool::new(false));
+        let worker_exited = exited.clone();
+
+        let scheduling_errors = Arc::new(Mutex::new(Vec::new()));
+        let worker_scheduling_errors = scheduling_errors.clone();
+
+        let mut thread_builder = thread::Builder::new();
+        if let Some(name) = name {
+            thread_builder = thread_builder.name(name);
         }
-    }
 
-    #[inline]
-    pub fn start_loop(self, target_rate: f64) -> ThreadLoop<W> {
-        self.inner
+        let worker_join_handle = thread_builder.spawn(move || {
+            // keeps the channel open until the panic is stored,
+            // so a consumer that sees it closed can tell whether the worker panicked
+            let _result_channel = worker_tx.clone();
+
+            *worker_scheduling_errors.lock() = scheduling.apply();
+
+            let result = panic::catch_unwind(AssertUnwindSafe(move || {
+                let inner_worker = worker_factory();
+
+                let mut loop_worker = ThreadLoopWorker::new(
+                    inner_worker,
+                    worker_tx,
+                    worker_rx,
+                    worker_measured_rate,
+                    scheduling.sleep,
+                );
+
+                loop_worker.run();
+
+                if let Some(hand_back) = worker_hand_back.lock().take() {
+                    hand_back(loop_worker.worker);
+                }
+            }));
+
+            if let Err(payload) = result {
+                *worker_panic.lock() = Some(payload);
+            }
+
+            worker_exited.store(true, Ordering::Release);
+        })?;
+
+        let inner = ThreadLoopInner {
+            worker_join_handle: Some(worker_join_handle),
+            tx,
+            rx,
+            panic,
+            hand_back,
+            measured_rate,
+            dropped_results,
+            exited,
+            scheduling_errors,
+        };
+
+        inner
             .tx
             .send(MessageToWorker::StartLoop { target_rate })
             .unwrap();
 
-        ThreadLoop { inner: self.inner }
+        Ok(ThreadLoop { inner })
     }
 }
 
@@ -128,24 +539,93 @@ pub struct ThreadLoop<W: ThreadWork> {
 }
 
 impl<W: ThreadWork> ThreadLoop<W> {
+    /// Spawns an unnamed worker thread and starts the loop.
+    ///
+    /// Panics if the thread couldn't be spawned, same as `thread::spawn`.
+    /// `ThreadLoopBuilder` allows handling that instead.
     pub fn new<F>(worker_factory: F, target_rate: f64) -> Self
     where
         F: FnOnce() -> W,
         F: Send + 'static,
+        W: 'static,
     {
-        let builder = ThreadLoopBuilder::new(worker_factory);
+        ThreadLoopBuilder::new(worker_factory)
+            .start_loop(target_rate)
+            .expect("failed to spawn thread")
+    }
 
-        builder.start_loop(target_rate)
+    /// Changes how often `work` gets called, starting from the next iteration.
+    ///
+    /// `f64::INFINITY` makes the loop run as fast as it can.
+    #[inline]
+    pub fn set_rate(&self, target_rate: f64) {
+        self.control().set_rate(target_rate);
     }
 
+    /// Stops calling `work` until `resume` is called, without dropping the worker.
+    ///
+    /// Takes effect at the start of the next iteration, the current one still finishes.
+    /// While paused the thread blocks, so it doesn't use any CPU.
+    #[inline]
+    pub fn pause(&self) {
+        self.control().pause();
+    }
+
+    #[inline]
+    pub fn resume(&self) {
+        self.control().resume();
+    }
+
+    /// How many times per second `work` actually got called, averaged over the last second.
+    ///
+    /// `0.0` until the first second of the loop is over.
+    /// Unlike the target rate, this includes the time `work` itself takes.
+    #[inline]
+    pub fn measured_rate(&self) -> f64 {
+        f64::from_bits(self.inner.measured_rate.load(Ordering::Relaxed))
+    }
+
+    /// A handle for controlling the loop from elsewhere, e.g. when the `ThreadLoop` itself
+    /// lives inside another worker
+    #[inline]
+    pub fn control(&self) -> ThreadLoopControl {
+        ThreadLoopControl {
+            tx: self.inner.tx.clone(),
+            measured_rate: self.inner.measured_rate.clone(),
+            exited: self.inner.exited.clone(),
+        }
+    }
+
+    /// How many results got thrown away because the consumer didn't keep up,
+    /// always 0 unless the loop was built with `OnFull::DropOldest`
+    #[inline]
+    pub fn dropped_results(&self) -> u64 {
+        self.inner.dropped_results.load(Ordering::Relaxed)
+    }
+
+    /// Takes the results that are queued up right now, without blocking.
+    ///
+    /// Ends early while another thread is waiting for a result, e.g. in `work_recv`,
+    /// the queue is empty then anyway, or about to be.
     #[inline]
     pub fn work_try_iter(&self) -> impl Iterator<Item = W::WorkResult> + '_ {
-        self.inner.rx.try_iter()
+        iter::from_fn(|| self.inner.rx.try_lock()?.try_recv().ok())
+    }
+
+    /// Takes every result that's queued up right now and returns the newest one, `None` if there were none.
+    ///
+    /// Never blocks, results the worker produces while this runs may or may not be included.
+    /// Takes nothing while another thread is waiting for a result, see `work_try_iter`.
+    pub fn drain_latest(&self) -> Option<W::WorkResult> {
+        self.work_try_iter().last()
     }
 
+    /// Blocks until the worker produces a result.
+    ///
+    /// Fails once the worker thread is gone, the error tells whether it has panicked.
     #[inline]
-    pub fn work_recv(&self) -> Result<<W as ThreadWork>::WorkResult, RecvError> {
-        self.inner.rx.recv()
+    pub fn work_recv(&self) -> Result<<W as ThreadWork>::WorkResult, WorkerError> {
+        self.inner.rx.lock().recv().map_err(|_| self.worker_error())
     }
 
     #[inline]
@@ -153,16 +633,745 @@ impl<W: ThreadWork> ThreadLoop<W> {
         &self,
         timeout: Duration,
     ) -> Result<<W as ThreadWork>::WorkResult, RecvTimeoutError> {
-        self.inner.rx.recv_timeout(timeout)
+        self.inner.rx.lock().recv_timeout(timeout)
     }
 
     #[inline]
     pub fn work_iter(&self) -> impl Iterator<Item = W::WorkResult> + '_ {
-        self.inner.rx.iter()
+        iter::from_fn(|| self.inner.rx.lock().recv().ok())
     }
 
     #[inline]
     pub fn exited(&mut self) -> bool {
-        self.inner.worker_join_handle.is_finished()
+        self.inner
+            .worker_join_handle
+            .as_ref()
+            .is_none_or(JoinHandle::is_finished)
+    }
+
+    /// Stops the loop and waits for the worker thread to finish.
+    ///
+    /// Unlike dropping the `ThreadLoop`, this waits for the current `work` call to return
+    /// and for the worker to be dropped, so any cleanup it does is done by the time this returns.
+    /// Returns what the worker has panicked with, if it has.
+    pub fn join(mut self) -> thread::Result<()> {
+        // a worker blocked on a full results channel doesn't take its messages,
+        // so with the message channel full as well, sending has to wait for it while making room for the results
+        let mut message = MessageToWorker::Join;
+        loop {
+            match self.inner.tx.try_send(message) {
+                // the worker may have exited already
+                Ok(()) | Err(TrySendError::Disconnected(_)) => break,
+                Err(TrySendError::Full(returned)) => {
+                    message = returned;
+                    let _ = self.work_recv_timeout(JOIN_RETRY_INTERVAL);
+                }
+            }
+        }
+        // the worker only gets to the message once there's room for its result too,
+        // this ends when the worker thread does
+        self.work_iter().for_each(drop);
+
+        if let Some(handle) = self.inner.worker_join_handle.take() {
+            // the worker's panics are caught on the thread, this can only fail if storing them panics
+            handle.join()?;
+        }
+
+        match self.take_panic() {
+            Some(payload) => Err(payload),
+            None => Ok(()),
+        }
+    }
+
+    /// Same as `join`, but hands the worker back instead of dropping it.
+    pub fn join_into(self) -> thread::Result<W>
+    where
+        W: Send + 'static,
+    {
+        self.join_with(|worker| worker)
+    }
+
+    /// Same as `join`, but calls `f` with the worker on the worker thread once the loop is over
+    /// and returns what it returned.
+    ///
+    /// Unlike `join_into`, this works for workers that can't leave their thread.
+    /// A panic in `f` is returned the same way as a panic in the worker.
+    pub fn join_with<F, R>(self, f: F) -> thread::Result<R>
+    where
+        F: FnOnce(W) -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        let (result_tx, result_rx) = mpsc::channel();
+        *self.inner.hand_back.lock() = Some(Box::new(move |worker| {
+            let _ = result_tx.send(f(worker));
+        }));
+
+        self.join()?;
+
+        result_rx
+            .try_recv()
+            .map_err(|_| Box::new("the worker exited without being handed back") as PanicPayload)
+    }
+
+    /// Takes what the worker thread has panicked with, if it has.
+    ///
+    /// Can be used for resuming the panic on this thread with `std::panic::resume_unwind`.
+    #[inline]
+    pub fn take_panic(&self) -> Option<PanicPayload> {
+        self.inner.panic.lock().take()
+    }
+
+    /// Takes why the worker thread couldn't be given its cores or priority, see `ThreadScheduling`.
+    ///
+    /// The thread applies them before it creates the worker, so they're all in by the time the first result is.
+    #[inline]
+    pub fn take_scheduling_errors(&self) -> Vec<SchedulingError> {
+        std::mem::take(&mut *self.inner.scheduling_errors.lock())
+    }
+
+    fn worker_error(&self) -> WorkerError {
+        if self.inner.panic.lock().is_some() {
+            WorkerError::Panicked
+        } else {
+            WorkerError::Exited
+        }
+    }
+}
+
+impl<W, T, E> ThreadLoop<W>
+where
+    W: ThreadWork<WorkResult = Result<T, E>>,
+{
+    /// Same as `drain_latest`, except every error in the queue is kept, not just a newer one.
+    ///
+    /// Never blocks either, and takes nothing while another thread is waiting for a result.
+    pub fn drain_collect_errors(&self) -> Drained<T, E> {
+        let mut drained = Drained::default();
+
+        for result in self.work_try_iter() {
+            match result {
+                Ok(value) => {
+                    drained.latest = Some(value);
+                    drained.successes += 1;
+                }
+                Err(e) => drained.errors.push(e),
+            }
+        }
+
+        drained
+    }
+}
+
+/// The results `ThreadLoop::drain_collect_errors` took out of the queue
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Drained<T, E> {
+    /// The newest successful result
+    pub latest: Option<T>,
+    /// How many successful results there were, `latest` included
+    pub successes: u64,
+    /// Every error, oldest first
+    pub errors: Vec<E>,
+}
+
+impl<T, E> Default for Drained<T, E> {
+    fn default() -> Self {
+        Self {
+            latest: None,
+            successes: 0,
+            errors: Vec::new(),
+        }
+    }
+}
+
+/// Why a `ThreadLoop` can't produce any more results
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+pub enum WorkerError {
+    #[error("worker thread panicked")]
+    Panicked,
+    #[error("worker thread has exited")]
+    Exited,
+}
+
+/// Why a worker thread runs without some of its `ThreadScheduling`
+#[derive(Debug, Error)]
+pub enum SchedulingError {
+    #[error("couldn't set the thread's affinity, leaving it as it is")]
+    Affinity(#[source] io::Error),
+    #[error("couldn't set the thread's priority, leaving it as it is")]
+    Priority(#[source] io::Error),
+}
+
+/// Controls a running `ThreadLoop` without having access to its results
+#[derive(Debug, Clone)]
+pub struct ThreadLoopControl {
+    tx: SyncSender<MessageToWorker>,
+    measured_rate: Arc<AtomicU64>,
+    exited: Arc<AtomicBool>,
+}
+
+impl ThreadLoopControl {
+    /// See `ThreadLoop::set_rate`
+    pub fn set_rate(&self, target_rate: f64) {
+        // the worker has exited already, there's nothing to adjust
+        let _ = self.tx.send(MessageToWorker::SetRate { target_rate });
+    }
+
+    /// See `ThreadLoop::pause`
+    pub fn pause(&self) {
+        let _ = self.tx.send(MessageToWorker::Pause);
+    }
+
+    /// See `ThreadLoop::resume`
+    pub fn resume(&self) {
+        let _ = self.tx.send(MessageToWorker::Resume);
+    }
+
+    /// See `ThreadLoop::measured_rate`
+    #[inline]
+    pub fn measured_rate(&self) -> f64 {
+        f64::from_bits(self.measured_rate.load(Ordering::Relaxed))
+    }
+
+    /// Whether the worker thread is done, either because the loop was stopped or because it panicked.
+    ///
+    /// Same as `ThreadLoop::exited`, but without needing the `ThreadLoop` itself.
+    #[inline]
+    pub fn exited(&self) -> bool {
+        self.exited.load(Ordering::Acquire)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::VecDeque,
+        sync::atomic::{AtomicUsize, Ordering},
+        time::Instant,
+    };
+
+    use super::*;
+
+    struct Counter;
+
+    impl ThreadWork for Counter {
+        type WorkResult = Instant;
+
+        fn work(&mut self) -> Option<Self::WorkResult> {
+            Some(Instant::now())
+        }
+    }
+
+    // iterations per second over `duration`
+    fn observed_rate(thread_loop: &ThreadLoop<Counter>, duration: Duration) -> f64 {
+        // whatever piled up before doesn't count
+        thread_loop.work_try_iter().for_each(drop);
+
+        let start = Instant::now();
+        let mut count = 0;
+
+        while start.elapsed() < duration {
+            if thread_loop.work_recv_timeout(duration).is_ok() {
+                count += 1;
+            }
+        }
+
+        count as f64 / start.elapsed().as_secs_f64()
+    }
+
+    struct PanicsOnThird(usize);
+
+    impl ThreadWork for PanicsOnThird {
+        type WorkResult = usize;
+
+        fn work(&mut self) -> Option<Self::WorkResult> {
+            self.0 += 1;
+
+            if self.0 == 3 {
+                panic!("third time's the charm");
+            }
+
+            Some(self.0)
+        }
+    }
+
+    #[test]
+    fn worker_panic() {
+        let thread_loop = ThreadLoop::new(|| PanicsOnThird(0), f64::INFINITY);
+
+        assert_eq!(thread_loop.work_recv(), Ok(1));
+        assert_eq!(thread_loop.work_recv(), Ok(2));
+        assert_eq!(thread_loop.work_recv(), Err(WorkerError::Panicked));
+
+        let payload = thread_loop.take_panic().unwrap();
+        assert_eq!(
+            payload.downcast_ref::<&str>(),
+            Some(&"third time's the charm")
+        );
+        assert!(thread_loop.take_panic().is_none());
+    }
+
+    #[test]
+    fn control_sees_exit() {
+        let thread_loop = ThreadLoop::new(|| PanicsOnThird(0), f64::INFINITY);
+        let control = thread_loop.control();
+
+        assert_eq!(thread_loop.work_recv(), Ok(1));
+        assert_eq!(thread_loop.work_recv(), Ok(2));
+        // the flag is set before the results channel closes
+        assert_eq!(thread_loop.work_recv(), Err(WorkerError::Panicked));
+        assert!(control.exited());
+
+        let thread_loop = ThreadLoop::new(|| Sequence(0), 200.0);
+        let control = thread_loop.control();
+        thread_loop.work_recv().unwrap();
+        assert!(!control.exited());
+    }
+
+    struct Sequence(usize);
+
+    impl ThreadWork for Sequence {
+        type WorkResult = usize;
+
+        fn work(&mut self) -> Option<Self::WorkResult> {
+            self.0 += 1;
+            Some(self.0)
+        }
+    }
+
+    // only reports every `every`th iteration, but counts all of them
+    struct Sparse {
+        iterations: Arc<AtomicUsize>,
+        every: usize,
+    }
+
+    impl ThreadWork for Sparse {
+        type WorkResult = usize;
+
+        fn work(&mut self) -> Option<Self::WorkResult> {
+            let iteration = self.iterations.fetch_add(1, Ordering::SeqCst) + 1;
+
+            iteration.is_multiple_of(self.every).then_some(iteration)
+        }
+    }
+
+    #[test]
+    fn nothing_to_report_sends_nothing() {
+        let iterations = Arc::new(AtomicUsize::new(0));
+        let worker_iterations = iterations.clone();
+        let thread_loop = ThreadLoop::new(
+            move || Sparse {
+                iterations: worker_iterations,
+                every: usize::MAX,
+            },
+            1000.0,
+        );
+
+        thread::sleep(Duration::from_millis(50));
+
+        assert!(iterations.load(Ordering::SeqCst) > 0);
+        assert_eq!(thread_loop.work_try_iter().count(), 0);
+        assert_eq!(
+            thread_loop.work_recv_timeout(Duration::from_millis(20)),
+            Err(RecvTimeoutError::Timeout)
+        );
+
+        let iterations = Arc::new(AtomicUsize::new(0));
+        let worker_iterations = iterations.clone();
+        let thread_loop = ThreadLoop::new(
+            move || Sparse {
+                iterations: worker_iterations,
+                every: 3,
+            },
+            f64::INFINITY,
+        );
+
+        // only the ones that had something to say, still in order
+        let results: Vec<_> = (0..3).map(|_| thread_loop.work_recv().unwrap()).collect();
+        assert_eq!(results, [3, 6, 9]);
+    }
+
+    #[test]
+    fn work_recv_returns_every_iteration() {
+        let thread_loop = ThreadLoop::new(|| Sequence(0), 200.0);
+
+        // let a few results pile up, none of them get skipped
+        thread::sleep(Duration::from_millis(50));
+
+        for expected in 1..=5 {
+            assert_eq!(thread_loop.work_recv(), Ok(expected));
+        }
+    }
+
+    #[test]
+    fn stalled_consumer_drops_oldest() {
+        let thread_loop = ThreadLoopBuilder::new(|| Sequence(0))
+            .result_capacity(4, OnFull::DropOldest)
+            .start_loop(f64::INFINITY)
+            .unwrap();
+
+        // plenty of time for an unbounded channel to pile up thousands of results
+        thread::sleep(Duration::from_millis(50));
+        thread_loop.pause();
+        thread::sleep(Duration::from_millis(20));
+
+        let results: Vec<_> = thread_loop.work_try_iter().collect();
+        let dropped = thread_loop.dropped_results();
+
+        assert_eq!(results.len(), 4);
+        assert!(dropped > 0);
+        // only the newest ones were kept
+        assert_eq!(results[0] as u64, dropped + 1);
+        assert_eq!(results[3] as u64, dropped + 4);
+    }
+
+    #[test]
+    fn try_iter_doesnt_wait_behind_a_waiter() {
+        let thread_loop = ThreadLoop::new(
+            || Sparse {
+                iterations: Arc::default(),
+                every: usize::MAX,
+            },
+            1000.0,
+        );
+
+        thread::scope(|s| {
+            let waiter = s.spawn(|| thread_loop.work_recv_timeout(Duration::from_millis(500)));
+            thread::sleep(Duration::from_millis(50));
+
+            let start = Instant::now();
+            assert_eq!(thread_loop.work_try_iter().count(), 0);
+            assert!(start.elapsed() < Duration::from_millis(100));
+
+            assert_eq!(waiter.join().unwrap(), Err(RecvTimeoutError::Timeout));
+        });
+    }
+
+    #[test]
+    fn stalled_consumer_blocks_worker() {
+        let thread_loop = ThreadLoopBuilder::new(|| Sequence(0))
+            .result_capacity(4, OnFull::Block)
+            .start_loop(f64::INFINITY)
+            .unwrap();
+
+        thread::sleep(Duration::from_millis(50));
+
+        // the worker waited instead of throwing anything away
+        let results: Vec<_> = thread_loop.work_try_iter().take(4).collect();
+        assert_eq!(results, [1, 2, 3, 4]);
+        assert_eq!(thread_loop.dropped_results(), 0);
+
+        // joining doesn't get stuck on the worker waiting for room
+        thread_loop.join().unwrap();
+    }
+
+    #[test]
+    fn join_with_full_message_channel() {
+        let thread_loop = ThreadLoopBuilder::new(|| Sequence(0))
+            .result_capacity(1, OnFull::Block)
+            .start_loop(f64::INFINITY)
+            .unwrap();
+
+        // the worker is stuck on the full results channel, so nothing takes these
+        thread::sleep(Duration::from_millis(50));
+        for _ in 0..8 {
+            thread_loop.inner.tx.try_send(MessageToWorker::Resume).unwrap();
+        }
+
+        let (joined_tx, joined_rx) = mpsc::channel();
+        thread::spawn(move || joined_tx.send(thread_loop.join().is_ok()).unwrap());
+
+        assert_eq!(joined_rx.recv_timeout(Duration::from_secs(1)), Ok(true));
+    }
+
+    // hands out `script`, then holds off until `release` is dropped, so the results stay put while they're looked at
+    struct Scripted<R> {
+        script: VecDeque<R>,
+        filler: R,
+        done: Sender<()>,
+        release: Receiver<()>,
+    }
+
+    impl<R: Clone + Send + 'static> ThreadWork for Scripted<R> {
+        type WorkResult = R;
+
+        fn work(&mut self) -> Option<Self::WorkResult> {
+            if let Some(result) = self.script.pop_front() {
+                return Some(result);
+            }
+
+            // every result from the script has been sent by the time the next iteration starts
+            _ = self.done.send(());
+            _ = self.release.recv();
+            Some(self.filler.clone())
+        }
+    }
+
+    // the loop along with the sender that has to be dropped before it to let it stop,
+    // which bindings in the order `let (thread_loop, _release)` are, even if the test panics
+    fn scripted<R>(script: Vec<R>, filler: R) -> (ThreadLoop<Scripted<R>>, Sender<()>)
+    where
+        R: Clone + Send + 'static,
+    {
+        let (done_tx, done_rx) = mpsc::channel();
+        let (release_tx, release_rx) = mpsc::channel();
+
+        let thread_loop = ThreadLoop::new(
+            move || Scripted {
+                script: script.into(),
+                filler,
+                done: done_tx,
+                release: release_rx,
+            },
+            f64::INFINITY,
+        );
+        done_rx.recv().unwrap();
+
+        (thread_loop, release_tx)
+    }
+
+    #[test]
+    fn drain_latest_takes_everything() {
+        let (thread_loop, _release) = scripted(vec![1, 2, 3, 4, 5], 0);
+
+        assert_eq!(thread_loop.drain_latest(), Some(5));
+        assert_eq!(thread_loop.drain_latest(), None);
+    }
+
+    #[test]
+    fn drain_keeps_every_error() {
+        let script = vec![Ok(1), Err("first"), Ok(2), Err("second"), Ok(3), Ok(4)];
+        let (thread_loop, _release) = scripted(script, Ok(0));
+
+        let expected = Drained {
+            latest: Some(4),
+            successes: 4,
+            errors: vec!["first", "second"],
+        };
+        assert_eq!(thread_loop.drain_collect_errors(), expected);
+        assert_eq!(thread_loop.drain_collect_errors(), Drained::default());
+    }
+
+    #[test]
+    fn drains_dont_wait_for_a_concurrent_recv() {
+        let (thread_loop, release) = scripted(vec![Ok(1)], Err("filler"));
+        assert_eq!(thread_loop.work_recv(), Ok(Ok(1)));
+
+        thread::scope(|s| {
+            // waits for the filler, which only comes once the worker is released
+            let waiter = s.spawn(|| thread_loop.work_recv());
+            thread::sleep(Duration::from_millis(50));
+
+            let start = Instant::now();
+            assert_eq!(thread_loop.drain_latest(), None);
+            assert_eq!(thread_loop.drain_collect_errors(), Drained::default());
+            assert!(start.elapsed() < Duration::from_millis(100));
+
+            drop(release);
+            assert_eq!(waiter.join().unwrap(), Ok(Err("filler")));
+        });
+    }
+
+    struct SlowCounter {
+        count: Arc<AtomicUsize>,
+    }
+
+    impl ThreadWork for SlowCounter {
+        type WorkResult = ();
+
+        fn work(&mut self) -> Option<Self::WorkResult> {
+            thread::sleep(Duration::from_millis(50));
+            self.count.fetch_add(1, Ordering::SeqCst);
+            Some(())
+        }
+    }
+
+    #[test]
+    fn join_waits_for_work() {
+        let count = Arc::new(AtomicUsize::new(0));
+        let worker_count = count.clone();
+
+        let thread_loop = ThreadLoop::new(
+            || SlowCounter {
+                count: worker_count,
+            },
+            f64::INFINITY,
+        );
+
+        // the second call to work has started by now
+        thread_loop.work_recv().unwrap();
+        thread_loop.join().unwrap();
+
+        assert_eq!(count.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn join_into_returns_worker() {
+        let thread_loop = ThreadLoop::new(|| PanicsOnThird(0), 100.0);
+        thread_loop.work_recv().unwrap();
+
+        let worker = thread_loop.join_into().unwrap();
+
+        assert!(worker.0 >= 1);
+    }
+
+    struct ThreadName;
+
+    impl ThreadWork for ThreadName {
+        type WorkResult = Option<String>;
+
+        fn work(&mut self) -> Option<Self::WorkResult> {
+            Some(thread::current().name().map(str::to_owned))
+        }
+    }
+
+    #[test]
+    fn named_thread() {
+        let thread_loop = ThreadLoopBuilder::new(|| ThreadName)
+            .name("test-worker")
+            .start_loop(100.0)
+            .unwrap();
+
+        assert_eq!(
+            thread_loop.work_recv().unwrap().as_deref(),
+            Some("test-worker")
+        );
+    }
+
+    // what the worker thread's scheduling looks like from the inside
+    #[cfg(all(feature = "thread-priority", target_os = "linux"))]
+    struct Scheduling;
+
+    #[cfg(all(feature = "thread-priority", target_os = "linux"))]
+    impl ThreadWork for Scheduling {
+        type WorkResult = (Vec<usize>, i32);
+
+        fn work(&mut self) -> Option<Self::WorkResult> {
+            // SAFETY: all of these only look at the calling thread
+            unsafe {
+                let mut set: libc::cpu_set_t = std::mem::zeroed();
+                libc::sched_getaffinity(0, std::mem::size_of_val(&set), &mut set);
+                let cores = (0..libc::CPU_SETSIZE as usize)
+                    .filter(|&core| libc::CPU_ISSET(core, &set))
+                    .collect();
+
+                let nice = libc::getpriority(libc::PRIO_PROCESS, libc::gettid() as libc::id_t);
+                Some((cores, nice))
+            }
+        }
+    }
+
+    #[cfg(all(feature = "thread-priority", target_os = "linux"))]
+    #[test]
+    fn scheduling_applied_on_worker_thread() {
+        // lowering the priority doesn't need any privileges, unlike raising it
+        let thread_loop = ThreadLoopBuilder::new(|| Scheduling)
+            .scheduling(ThreadScheduling {
+                cores: Some(vec![0]),
+                nice: Some(10),
+                sleep: SleepStrategy::default(),
+            })
+            .start_loop(100.0)
+            .unwrap();
+
+        assert_eq!(thread_loop.work_recv().unwrap(), (vec![0], 10));
+        assert!(thread_loop.take_scheduling_errors().is_empty());
+    }
+
+    #[test]
+    fn unsupported_scheduling_is_not_fatal() {
+        // there's no such core, so it fails even where it's supported
+        let thread_loop = ThreadLoopBuilder::new(|| ThreadName)
+            .name("unscheduled-worker")
+            .scheduling(ThreadScheduling {
+                cores: Some(vec![usize::MAX]),
+                nice: None,
+                sleep: SleepStrategy::default(),
+            })
+            .start_loop(100.0)
+            .unwrap();
+
+        assert_eq!(
+            thread_loop.work_recv().unwrap().as_deref(),
+            Some("unscheduled-worker")
+        );
+
+        let errors = thread_loop.take_scheduling_errors();
+        assert!(matches!(errors[..], [SchedulingError::Affinity(_)]));
+        assert!(thread_loop.take_scheduling_errors().is_empty());
+    }
+
+    // stands in for `LoopHelperBuilder`, which doesn't tell what it's been given
+    #[derive(Debug, Default, PartialEq)]
+    struct RecordedAccuracy(Option<u32>);
+
+    impl NativeAccuracy for RecordedAccuracy {
+        fn native_accuracy_ns(self, accuracy: u32) -> Self {
+            Self(Some(accuracy))
+        }
+    }
+
+    #[test]
+    fn sleep_strategy_sets_native_accuracy() {
+        let configured = |sleep: SleepStrategy| sleep.configure(RecordedAccuracy::default());
+
+        assert_eq!(configured(SleepStrategy::Default), RecordedAccuracy(None));
+        assert_eq!(configured(SleepStrategy::Native), RecordedAccuracy(Some(0)));
+        assert_eq!(configured(SleepStrategy::NativeAccuracyNs(500_000)), RecordedAccuracy(Some(500_000)));
+
+        // the loop runs with it all the same
+        let thread_loop = ThreadLoopBuilder::new(|| ThreadName)
+            .scheduling(ThreadScheduling {
+                sleep: SleepStrategy::Native,
+                ..ThreadScheduling::default()
+            })
+            .start_loop(100.0)
+            .unwrap();
+        assert!(thread_loop.work_recv().is_ok());
+    }
+
+    #[test]
+    fn join_with_runs_on_worker_thread() {
+        let thread_loop = ThreadLoopBuilder::new(|| ThreadName)
+            .name("joined-worker")
+            .start_loop(100.0)
+            .unwrap();
+        thread_loop.work_recv().unwrap();
+
+        let name = thread_loop
+            .join_with(|_| thread::current().name().map(str::to_owned))
+            .unwrap();
+
+        assert_eq!(name.as_deref(), Some("joined-worker"));
+    }
+
+    #[test]
+    fn rate_change() {
+        let thread_loop = ThreadLoop::new(|| Counter, 200.0);
+        let fast = observed_rate(&thread_loop, Duration::from_millis(300));
+
+        thread_loop.set_rate(25.0);
+        let slow = observed_rate(&thread_loop, Duration::from_millis(300));
+
+        assert!(fast > 100.0, "fast rate was {fast}");
+        assert!(slow < 50.0, "slow rate was {slow}");
+    }
+
+    #[test]
+    fn no_work_while_paused() {
+        let thread_loop = ThreadLoop::new(|| Counter, 200.0);
+        thread_loop.work_recv().unwrap();
+
+        thread_loop.pause();
+        // letting the iteration that was already running finish
+        thread::sleep(Duration::from_millis(50));
+        thread_loop.work_try_iter().for_each(drop);
+
+        thread::sleep(Duration::from_millis(100));
+        assert_eq!(thread_loop.work_try_iter().count(), 0);
+
+        thread_loop.resume();
+        assert!(thread_loop
+            .work_recv_timeout(Duration::from_millis(100))
+            .is_ok());
     }
 }